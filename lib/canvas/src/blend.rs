@@ -0,0 +1,408 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Color;
+use crate::color::Channel;
+
+/// Converts a straight alpha color into a premultiplied pixel
+pub fn premultiply<T: Channel>(color: Color<T>) -> [T; 4] {
+    let alpha = color.a;
+    [
+        color.r.premultiplied(alpha),
+        color.g.premultiplied(alpha),
+        color.b.premultiplied(alpha),
+        alpha,
+    ]
+}
+
+/// Converts a premultiplied pixel back into a straight alpha color
+pub fn unpremultiply<T: Channel>(pixel: [T; 4]) -> Color<T> {
+    let alpha = pixel[3];
+    if alpha.to_f32() <= 0.0 {
+        return Color::new(T::default(), T::default(), T::default(), T::default());
+    }
+
+    Color::new(
+        pixel[0].unpremultiplied(alpha),
+        pixel[1].unpremultiplied(alpha),
+        pixel[2].unpremultiplied(alpha),
+        alpha,
+    )
+}
+
+/// Converts a premultiplied pixel into another working format
+pub fn convert_pixel<S: Channel, D: Channel>(pixel: [S; 4]) -> [D; 4] {
+    /* the encoding only changes the color, so it is undone on straight alpha */
+    if S::LINEAR == D::LINEAR {
+        return pixel.map(|c| D::from_normalized(c.normalized()));
+    }
+    premultiply(unpremultiply(pixel).convert())
+}
+
+/// Source-over of a premultiplied pixel onto a premultiplied `dst`,
+/// `opacity` scales the whole source pixel
+pub fn source_over<T: Channel>(dst: &mut [T], src: [T; 4], opacity: f32) {
+    let opacity = opacity.clamp(0.0, 1.0);
+    let src_alpha = src[3].normalized() * opacity;
+    if src_alpha <= 0.0 {
+        return;
+    }
+
+    for i in 0..4 {
+        let value = src[i].to_f32() * opacity + dst[i].to_f32() * (1.0 - src_alpha);
+        dst[i] = T::from_f32(value);
+    }
+}
+
+/// Rescales a premultiplied pixel so it ends up with `alpha`, keeping its color
+pub(crate) fn with_alpha<T: Channel>(pixel: &mut [T], alpha: T) {
+    let current = pixel[3].to_f32();
+    if current <= 0.0 {
+        return;
+    }
+
+    for channel in pixel.iter_mut().take(3) {
+        *channel = T::from_f32(channel.to_f32() * alpha.to_f32() / current);
+    }
+    pixel[3] = alpha;
+}
+
+/// How a source color is combined with the backdrop it is drawn on,
+/// following the W3C compositing and blending spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 16] = [
+        BlendMode::Normal,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Overlay,
+        BlendMode::Darken,
+        BlendMode::Lighten,
+        BlendMode::ColorDodge,
+        BlendMode::ColorBurn,
+        BlendMode::HardLight,
+        BlendMode::SoftLight,
+        BlendMode::Difference,
+        BlendMode::Exclusion,
+        BlendMode::Hue,
+        BlendMode::Saturation,
+        BlendMode::Color,
+        BlendMode::Luminosity,
+    ];
+
+    /// Whether the mode blends each channel on its own
+    pub fn is_separable(&self) -> bool {
+        !matches!(
+            self,
+            BlendMode::Hue | BlendMode::Saturation | BlendMode::Color | BlendMode::Luminosity
+        )
+    }
+
+    /// The blend function `B(Cb, Cs)` for straight alpha colors in the `0.0..=1.0` range
+    pub fn blend_color(&self, backdrop: [f32; 3], source: [f32; 3]) -> [f32; 3] {
+        match self {
+            BlendMode::Hue => set_lum(set_sat(source, sat(backdrop)), lum(backdrop)),
+            BlendMode::Saturation => set_lum(set_sat(backdrop, sat(source)), lum(backdrop)),
+            BlendMode::Color => set_lum(source, lum(backdrop)),
+            BlendMode::Luminosity => set_lum(backdrop, lum(source)),
+            _ => [
+                self.blend_channel(backdrop[0], source[0]),
+                self.blend_channel(backdrop[1], source[1]),
+                self.blend_channel(backdrop[2], source[2]),
+            ],
+        }
+    }
+
+    /// The blend function of a separable mode for a single channel,
+    /// non-separable modes fall back to normal
+    pub fn blend_channel(&self, cb: f32, cs: f32) -> f32 {
+        match self {
+            BlendMode::Multiply => cb * cs,
+            BlendMode::Screen => cb + cs - cb * cs,
+            BlendMode::Overlay => BlendMode::HardLight.blend_channel(cs, cb),
+            BlendMode::Darken => cb.min(cs),
+            BlendMode::Lighten => cb.max(cs),
+            BlendMode::ColorDodge => {
+                if cb <= 0.0 {
+                    0.0
+                } else if cs >= 1.0 {
+                    1.0
+                } else {
+                    (cb / (1.0 - cs)).min(1.0)
+                }
+            }
+            BlendMode::ColorBurn => {
+                if cb >= 1.0 {
+                    1.0
+                } else if cs <= 0.0 {
+                    0.0
+                } else {
+                    1.0 - ((1.0 - cb) / cs).min(1.0)
+                }
+            }
+            BlendMode::HardLight => {
+                if cs <= 0.5 {
+                    cb * 2.0 * cs
+                } else {
+                    BlendMode::Screen.blend_channel(cb, 2.0 * cs - 1.0)
+                }
+            }
+            BlendMode::SoftLight => {
+                if cs <= 0.5 {
+                    cb - (1.0 - 2.0 * cs) * cb * (1.0 - cb)
+                } else {
+                    let d = if cb <= 0.25 {
+                        ((16.0 * cb - 12.0) * cb + 4.0) * cb
+                    } else {
+                        cb.sqrt()
+                    };
+                    cb + (2.0 * cs - 1.0) * (d - cb)
+                }
+            }
+            BlendMode::Difference => (cb - cs).abs(),
+            BlendMode::Exclusion => cb + cs - 2.0 * cb * cs,
+            _ => cs,
+        }
+    }
+}
+
+pub(crate) fn lum(c: [f32; 3]) -> f32 {
+    0.3 * c[0] + 0.59 * c[1] + 0.11 * c[2]
+}
+
+fn clip_color(c: [f32; 3]) -> [f32; 3] {
+    let l = lum(c);
+    let n = c[0].min(c[1]).min(c[2]);
+    let x = c[0].max(c[1]).max(c[2]);
+
+    c.map(|channel| {
+        let mut channel = channel;
+        if n < 0.0 {
+            channel = l + (channel - l) * l / (l - n);
+        }
+        if x > 1.0 {
+            channel = l + (channel - l) * (1.0 - l) / (x - l);
+        }
+        channel
+    })
+}
+
+pub(crate) fn set_lum(c: [f32; 3], l: f32) -> [f32; 3] {
+    let d = l - lum(c);
+    clip_color([c[0] + d, c[1] + d, c[2] + d])
+}
+
+fn sat(c: [f32; 3]) -> f32 {
+    c[0].max(c[1]).max(c[2]) - c[0].min(c[1]).min(c[2])
+}
+
+fn set_sat(c: [f32; 3], s: f32) -> [f32; 3] {
+    let mut order = [0, 1, 2];
+    order.sort_by(|a, b| c[*a].total_cmp(&c[*b]));
+    let [min, mid, max] = order;
+
+    let mut output = [0.0; 3];
+    if c[max] > c[min] {
+        output[mid] = (c[mid] - c[min]) * s / (c[max] - c[min]);
+        output[max] = s;
+    }
+    output
+}
+
+/// Composites a premultiplied pixel onto a premultiplied `dst` with the given blend mode,
+/// `opacity` scales the whole source pixel
+pub fn blend<T: Channel>(dst: &mut [T], src: [T; 4], opacity: f32, mode: BlendMode) {
+    if mode == BlendMode::Normal {
+        source_over(dst, src, opacity);
+        return;
+    }
+
+    let opacity = opacity.clamp(0.0, 1.0);
+    let src_alpha = src[3].normalized() * opacity;
+    if src_alpha <= 0.0 {
+        return;
+    }
+    let dst_alpha = dst[3].normalized();
+
+    let straight = |pixel: &[T]| {
+        let alpha = pixel[3].to_f32();
+        if alpha <= 0.0 {
+            return [0.0; 3];
+        }
+        [
+            pixel[0].to_f32() / alpha,
+            pixel[1].to_f32() / alpha,
+            pixel[2].to_f32() / alpha,
+        ]
+    };
+    let cs = straight(&src);
+    let cb = straight(dst);
+    let mixed = mode.blend_color(cb, cs);
+
+    /* co = cs * as * (1 - ab) + B(cb, cs) * as * ab + cb * ab * (1 - as), premultiplied */
+    let out_alpha = src_alpha + dst_alpha * (1.0 - src_alpha);
+    for i in 0..3 {
+        let value = src_alpha * (1.0 - dst_alpha) * cs[i]
+            + src_alpha * dst_alpha * mixed[i].clamp(0.0, 1.0)
+            + (1.0 - src_alpha) * dst_alpha * cb[i];
+        dst[i] = T::from_normalized(value);
+    }
+    dst[3] = T::from_normalized(out_alpha);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn separable_modes_match_spec() {
+        let cases = [
+            (BlendMode::Normal, 0.25, 0.75, 0.75),
+            (BlendMode::Multiply, 0.5, 0.5, 0.25),
+            (BlendMode::Screen, 0.5, 0.5, 0.75),
+            (BlendMode::Overlay, 0.25, 0.5, 0.25),
+            (BlendMode::Overlay, 0.75, 0.5, 0.75),
+            (BlendMode::Darken, 0.25, 0.75, 0.25),
+            (BlendMode::Lighten, 0.25, 0.75, 0.75),
+            (BlendMode::ColorDodge, 0.25, 0.5, 0.5),
+            (BlendMode::ColorDodge, 0.0, 1.0, 0.0),
+            (BlendMode::ColorDodge, 0.5, 1.0, 1.0),
+            (BlendMode::ColorBurn, 0.5, 0.75, 1.0 / 3.0),
+            (BlendMode::ColorBurn, 1.0, 0.0, 1.0),
+            (BlendMode::ColorBurn, 0.5, 0.0, 0.0),
+            (BlendMode::HardLight, 0.5, 0.25, 0.25),
+            (BlendMode::HardLight, 0.5, 0.75, 0.75),
+            (BlendMode::SoftLight, 0.5, 0.25, 0.375),
+            (BlendMode::SoftLight, 0.25, 0.75, 0.375),
+            (BlendMode::SoftLight, 0.64, 0.75, 0.72),
+            (BlendMode::Difference, 0.25, 0.75, 0.5),
+            (BlendMode::Exclusion, 0.25, 0.75, 0.625),
+        ];
+
+        for (mode, cb, cs, expected) in cases {
+            assert_close(mode.blend_channel(cb, cs), expected);
+        }
+    }
+
+    #[test]
+    fn non_separable_modes_match_spec() {
+        let gray = [0.5, 0.5, 0.5];
+        let red = [1.0, 0.0, 0.0];
+        let orange = [0.8, 0.4, 0.2];
+
+        let cases = [
+            (BlendMode::Hue, gray, red, [0.5, 0.5, 0.5]),
+            (BlendMode::Color, gray, red, [1.0, 0.2857, 0.2857]),
+            (BlendMode::Luminosity, gray, red, [0.3, 0.3, 0.3]),
+            (BlendMode::Saturation, orange, red, [1.0, 0.3351, 0.0027]),
+            (
+                BlendMode::Hue,
+                orange,
+                [0.2, 0.4, 0.8],
+                [0.314, 0.514, 0.914],
+            ),
+        ];
+
+        for (mode, cb, cs, expected) in cases {
+            let result = mode.blend_color(cb, cs);
+            for i in 0..3 {
+                assert_close(result[i], expected[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn blending_onto_transparent_keeps_source() {
+        let mut dst = [0u8, 0, 0, 0];
+        blend(&mut dst, [100, 50, 25, 200], 1.0, BlendMode::Multiply);
+        assert_eq!(dst, [100, 50, 25, 200]);
+    }
+
+    #[test]
+    fn multiply_onto_opaque_backdrop() {
+        let mut dst = [128u8, 255, 64, 255];
+        blend(&mut dst, [255, 128, 255, 255], 1.0, BlendMode::Multiply);
+        assert_eq!(dst, [128, 128, 64, 255]);
+    }
+
+    #[test]
+    fn premultiply_round_trips_partial_alpha() {
+        let color = Color::new(200u8, 100, 50, 128);
+        assert_eq!(premultiply(color), [100, 50, 25, 128]);
+        assert_eq!(
+            unpremultiply(premultiply(color)),
+            Color::new(199, 100, 50, 128)
+        );
+
+        let color = Color::new(0.8f32, 0.4, 0.2, 0.25);
+        let pixel = premultiply(color);
+        for (actual, expected) in pixel.into_iter().zip([0.2, 0.1, 0.05, 0.25]) {
+            assert_close(actual, expected);
+        }
+        let back = unpremultiply(pixel);
+        for (actual, expected) in [back.r, back.g, back.b, back.a]
+            .into_iter()
+            .zip([0.8, 0.4, 0.2, 0.25])
+        {
+            assert_close(actual, expected);
+        }
+
+        /* fully transparent pixels carry no color */
+        assert_eq!(unpremultiply([0u8, 0, 0, 0]), Color::new(0, 0, 0, 0));
+    }
+
+    #[test]
+    fn partial_alpha_source_over() {
+        /* half transparent red over opaque white */
+        let mut dst = [255u8, 255, 255, 255];
+        source_over(&mut dst, premultiply(Color::new(255, 0, 0, 128)), 1.0);
+        assert_eq!(dst, [255, 127, 127, 255]);
+
+        /* onto a transparent backdrop the source is kept as it is */
+        let mut dst = [0u8, 0, 0, 0];
+        source_over(&mut dst, premultiply(Color::new(255, 0, 0, 128)), 1.0);
+        assert_eq!(dst, [128, 0, 0, 128]);
+
+        /* two half transparent layers build up to three quarters */
+        let mut dst = [0.0f32; 4];
+        let src = premultiply(Color::new(0.0f32, 0.0, 1.0, 0.5));
+        source_over(&mut dst, src, 1.0);
+        source_over(&mut dst, src, 1.0);
+        for (actual, expected) in dst.into_iter().zip([0.0, 0.0, 0.75, 0.75]) {
+            assert_close(actual, expected);
+        }
+    }
+
+    #[test]
+    fn half_opacity_source_over() {
+        let mut dst = [255u8, 255, 255, 255];
+        source_over(&mut dst, premultiply(Color::new(0, 0, 0, 255)), 0.5);
+        assert_eq!(dst, [128, 128, 128, 255]);
+    }
+}
@@ -1,4 +1,91 @@
+mod airbrush;
+mod dab;
+pub mod dynamics;
+mod eraser;
+mod image;
+mod mypaint;
+mod round;
+mod smudge;
+pub mod stabilizer;
 pub mod stroke;
 
-#[allow(dead_code)]
-trait BrushEngine {}
+pub use airbrush::Airbrush;
+pub use dab::Dab;
+pub use dynamics::{PressureDynamics, PressureMapping, ResponseCurve};
+pub use eraser::Eraser;
+pub use image::{Grain, GrayImage, ImageBrush};
+pub use mypaint::{MyPaintBrush, MyPaintSetting};
+pub use round::RoundBrush;
+pub use smudge::{MixMode, SmudgeBrush};
+pub use stabilizer::{Interpolation, Stabilizer};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Canvas;
+use crate::color::Channel;
+use stroke::StrokePositionalData;
+
+/// Turns the interpolated points of a stroke into pixel writes on a canvas working in `T`
+pub trait BrushEngine<T: Channel = u8>: Send {
+    /// Distance between two dabs of a stroke as a fraction of the dab size
+    fn spacing(&self) -> f32;
+
+    /// Diameter in canvas pixels of a dab drawn at `pressure`, the spacing is measured
+    /// against it
+    fn dab_size(&self, pressure: f32) -> f32;
+
+    /// Called before the first dab of a new stroke
+    fn begin_stroke(&mut self) {}
+
+    /// Draws a single dab centered at `point`, which is already in canvas coordinates
+    fn dab(&mut self, point: &StrokePositionalData, canvas: &mut Canvas<T>);
+
+    /// Called after the last dab of a stroke, engines holding back a dab draw it here
+    fn end_stroke(&mut self, _canvas: &mut Canvas<T>) {}
+}
+
+/// Settings of one of the built in brush engines, used to restore the active brush
+/// and to store it in project files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "engine", rename_all = "camelCase")]
+pub enum BrushSettings {
+    Round(RoundBrush),
+    Airbrush(Airbrush),
+    Eraser(Eraser),
+    Smudge(SmudgeBrush),
+    Image(ImageBrush),
+    MyPaint(MyPaintBrush),
+}
+
+impl Default for BrushSettings {
+    fn default() -> Self {
+        BrushSettings::Round(RoundBrush::default())
+    }
+}
+
+impl BrushSettings {
+    /// Creates an engine painting with these settings
+    pub fn engine<T: Channel>(&self) -> Box<dyn BrushEngine<T>> {
+        match self {
+            BrushSettings::Round(brush) => Box::new(brush.clone()),
+            BrushSettings::Airbrush(brush) => Box::new(brush.clone()),
+            BrushSettings::Eraser(brush) => Box::new(brush.clone()),
+            BrushSettings::Smudge(brush) => Box::new(smudge::SmudgeEngine::new(brush.clone())),
+            BrushSettings::Image(brush) => Box::new(image::ImageEngine::new(brush.clone())),
+            BrushSettings::MyPaint(brush) => Box::new(mypaint::MyPaintEngine::new(brush)),
+        }
+    }
+
+    /// Pressure dynamics of the brush, MyPaint brushes map pressure through their own
+    /// settings and have none
+    pub fn dynamics_mut(&mut self) -> Option<&mut PressureDynamics> {
+        match self {
+            BrushSettings::Round(brush) => Some(&mut brush.dynamics),
+            BrushSettings::Airbrush(brush) => Some(&mut brush.dynamics),
+            BrushSettings::Eraser(brush) => Some(&mut brush.dynamics),
+            BrushSettings::Smudge(brush) => Some(&mut brush.dynamics),
+            BrushSettings::Image(brush) => Some(&mut brush.dynamics),
+            BrushSettings::MyPaint(_) => None,
+        }
+    }
+}
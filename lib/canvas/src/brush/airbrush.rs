@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+
+use super::stroke::StrokePositionalData;
+use super::{BrushEngine, Dab, PressureDynamics, PressureMapping};
+use crate::Canvas;
+use crate::blend::BlendMode;
+use crate::color::Channel;
+
+/// Soft round brush that slowly builds up color with every dab
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Airbrush {
+    /// Diameter of a dab in canvas pixels
+    pub size: f32,
+    /// How far the solid centre reaches before fading out, see [`Dab::hardness`]
+    pub hardness: f32,
+    /// Opacity at the centre of a single dab
+    pub flow: f32,
+    /// How pen pressure scales the size and flow of each dab
+    pub dynamics: PressureDynamics,
+    /// How each dab is blended onto the active layer
+    pub blend_mode: BlendMode,
+}
+
+impl Default for Airbrush {
+    fn default() -> Self {
+        Self {
+            size: 40.0,
+            hardness: 0.0,
+            flow: 0.1,
+            dynamics: PressureDynamics {
+                size: PressureMapping::disabled(),
+                opacity: PressureMapping::linear(0.0),
+            },
+            blend_mode: BlendMode::Normal,
+        }
+    }
+}
+
+impl<T: Channel> BrushEngine<T> for Airbrush {
+    fn spacing(&self) -> f32 {
+        0.05
+    }
+
+    fn dab_size(&self, pressure: f32) -> f32 {
+        self.dynamics.dab_size(self.size, pressure)
+    }
+
+    fn dab(&mut self, point: &StrokePositionalData, canvas: &mut Canvas<T>) {
+        let dab = Dab {
+            blend_mode: self.blend_mode,
+            ..Dab::from_point(point, self.size, self.hardness, self.flow, &self.dynamics)
+        };
+        dab.draw(canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    #[test]
+    fn builds_up_color_over_repeated_dabs() {
+        let mut canvas: Canvas = Canvas::new(20, 20);
+        canvas.add_layer();
+        let mut brush = Airbrush {
+            size: 10.0,
+            ..Default::default()
+        };
+        let point = StrokePositionalData {
+            x: 10.5,
+            y: 10.5,
+            pressure: 1.0,
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            time: 0.0,
+            color: Color::new(0, 0, 255, 255),
+        };
+
+        let mut alphas = vec![];
+        for _ in 0..10 {
+            brush.dab(&point, &mut canvas);
+            alphas.push(canvas.layers().active().pixel(10, 10).unwrap().a);
+        }
+        assert!((20..=30).contains(&alphas[0]), "{alphas:?}");
+        assert!(
+            alphas.windows(2).all(|pair| pair[0] < pair[1]),
+            "{alphas:?}"
+        );
+        assert!(alphas[9] < 255, "{alphas:?}");
+    }
+}
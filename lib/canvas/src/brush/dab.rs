@@ -0,0 +1,201 @@
+use super::PressureDynamics;
+use super::stroke::StrokePositionalData;
+use crate::blend::BlendMode;
+use crate::canvas::Rect;
+use crate::color::Channel;
+use crate::{Canvas, Color};
+
+/// Dabs smaller than this radius are drawn at this radius with their opacity
+/// scaled down by the lost area, so tiny brushes fade out instead of flickering
+const MIN_RADIUS: f32 = 0.5;
+
+/// A single round brush mark placed at a sub-pixel position
+#[derive(Debug, Clone, Copy)]
+pub struct Dab {
+    /// Centre of the dab in canvas pixels, pixel centres lie at `n + 0.5`
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    /// `1.0` gives a hard edge, lower values fade out from `radius * hardness` to the edge
+    pub hardness: f32,
+    pub opacity: f32,
+    /// sRGB color of the dab, converted to the working format when drawn
+    pub color: Color<u8>,
+    /// How the dab is blended onto the active layer
+    pub blend_mode: BlendMode,
+}
+
+impl Dab {
+    /// Dab of a round brush `size` wide at a stroke point, with its size and `opacity`
+    /// scaled by the pressure of the point. It blends normally
+    pub fn from_point(
+        point: &StrokePositionalData,
+        size: f32,
+        hardness: f32,
+        opacity: f32,
+        dynamics: &PressureDynamics,
+    ) -> Self {
+        Self {
+            x: point.x,
+            y: point.y,
+            radius: dynamics.dab_size(size, point.pressure) / 2.0,
+            hardness,
+            opacity: opacity * dynamics.opacity_multiplier(point.pressure),
+            color: point.color,
+            blend_mode: BlendMode::Normal,
+        }
+    }
+
+    /// Smallest rect of canvas pixels the dab can touch
+    pub fn bounds(&self) -> Option<Rect> {
+        let radius = self.radius.max(MIN_RADIUS) + 0.5;
+        let right = (self.x + radius).ceil();
+        let bottom = (self.y + radius).ceil();
+        if !right.is_finite() || !bottom.is_finite() || right <= 0.0 || bottom <= 0.0 {
+            return None;
+        }
+
+        let left = (self.x - radius).floor().max(0.0) as usize;
+        let top = (self.y - radius).floor().max(0.0) as usize;
+        Some(Rect::new(
+            left,
+            top,
+            right as usize - left,
+            bottom as usize - top,
+        ))
+    }
+
+    /// Fraction of the pixel at `(x, y)` covered by the dab, including the soft falloff
+    pub fn coverage(&self, x: usize, y: usize) -> f32 {
+        let (radius, opacity) = self.effective_radius();
+        let dx = x as f32 + 0.5 - self.x;
+        let dy = y as f32 + 0.5 - self.y;
+        let distance = (dx * dx + dy * dy).sqrt();
+
+        /* approximates the area of the pixel inside the circle for a one pixel wide edge */
+        let edge = (radius - distance + 0.5).clamp(0.0, 1.0);
+        if edge <= 0.0 {
+            return 0.0;
+        }
+
+        let hardness = self.hardness.clamp(0.0, 1.0);
+        let t = distance / radius;
+        let falloff = if hardness >= 1.0 || t <= hardness {
+            1.0
+        } else {
+            let s = ((1.0 - t) / (1.0 - hardness)).clamp(0.0, 1.0);
+            s * s * (3.0 - 2.0 * s)
+        };
+
+        edge * falloff * opacity
+    }
+
+    /// Radius and opacity used for rasterizing, after accounting for tiny dabs
+    fn effective_radius(&self) -> (f32, f32) {
+        let opacity = self.opacity.clamp(0.0, 1.0);
+        if self.radius >= MIN_RADIUS {
+            return (self.radius, opacity);
+        }
+
+        let ratio = self.radius.max(0.0) / MIN_RADIUS;
+        (MIN_RADIUS, opacity * ratio * ratio)
+    }
+
+    /// Calls `f` with every pixel the dab touches and its coverage
+    pub fn rasterize(&self, mut f: impl FnMut(usize, usize, f32)) {
+        let Some(bounds) = self.bounds() else {
+            return;
+        };
+
+        for y in bounds.y..bounds.bottom() {
+            for x in bounds.x..bounds.right() {
+                let coverage = self.coverage(x, y);
+                if coverage > 0.0 {
+                    f(x, y, coverage);
+                }
+            }
+        }
+    }
+
+    /// Composites the dab onto the active layer using its blend mode
+    pub fn draw<T: Channel>(&self, canvas: &mut Canvas<T>) {
+        let color = self.color.convert();
+        self.rasterize(|x, y, coverage| canvas.blend_pixel(x, y, color, coverage, self.blend_mode));
+    }
+
+    /// Removes alpha from the active layer in the shape of the dab
+    pub fn erase<T: Channel>(&self, canvas: &mut Canvas<T>) {
+        self.rasterize(|x, y, coverage| canvas.erase_pixel(x, y, coverage));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dab(radius: f32, hardness: f32, opacity: f32) -> Dab {
+        Dab {
+            x: 5.5,
+            y: 5.5,
+            radius,
+            hardness,
+            opacity,
+            color: Color::new(255, 0, 0, 255),
+            blend_mode: BlendMode::Normal,
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn hard_dabs_antialias_their_edge() {
+        let dab = dab(3.0, 1.0, 1.0);
+        assert_close(dab.coverage(5, 5), 1.0);
+        assert_close(dab.coverage(7, 5), 1.0);
+        /* the pixel centre sits exactly on the circle */
+        assert_close(dab.coverage(8, 5), 0.5);
+        assert_close(dab.coverage(8, 6), 0.338);
+        assert_close(dab.coverage(9, 5), 0.0);
+    }
+
+    #[test]
+    fn soft_dabs_fade_out() {
+        let dab = dab(4.0, 0.5, 0.5);
+        /* inside the hard core only the opacity applies */
+        assert_close(dab.coverage(5, 5), 0.5);
+        assert_close(dab.coverage(7, 5), 0.5);
+        /* halfway through the falloff the smoothstep is at one half */
+        assert_close(dab.coverage(8, 5), 0.25);
+        assert_close(dab.coverage(9, 5), 0.0);
+    }
+
+    #[test]
+    fn tiny_dabs_lose_opacity_instead_of_size() {
+        let tiny = dab(0.25, 1.0, 1.0);
+        assert_close(tiny.coverage(5, 5), 0.25);
+        assert_eq!(tiny.bounds(), Some(Rect::new(4, 4, 3, 3)));
+    }
+
+    #[test]
+    fn partial_alpha_dabs_build_up() {
+        let mut canvas: Canvas = Canvas::new(10, 10);
+        let mut dab = dab(3.0, 1.0, 1.0);
+        dab.color = Color::new(255, 0, 0, 128);
+
+        dab.draw(&mut canvas);
+        assert_eq!(
+            canvas.layers().active().pixel(5, 5),
+            Some(Color::new(255, 127, 127, 255))
+        );
+        dab.draw(&mut canvas);
+        assert_eq!(
+            canvas.layers().active().pixel(5, 5),
+            Some(Color::new(255, 63, 63, 255))
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use serde::{Deserialize, Serialize};
+
+/// Curve used to reshape an input in the `0.0..=1.0` range before it is applied
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum ResponseCurve {
+    /// Output equals the input
+    #[default]
+    Linear,
+    /// Straight segments between `(input, output)` points, sorted by input
+    PiecewiseLinear(Vec<(f32, f32)>),
+    /// Cubic Bézier from `(0, 0)` to `(1, 1)` with two control points,
+    /// the same way css `cubic-bezier()` timing functions work
+    CubicBezier { p1: (f32, f32), p2: (f32, f32) },
+}
+
+impl ResponseCurve {
+    /// Evaluates the curve, the input is clamped to `0.0..=1.0`
+    pub fn evaluate(&self, input: f32) -> f32 {
+        let input = if input.is_nan() {
+            0.0
+        } else {
+            input.clamp(0.0, 1.0)
+        };
+
+        let output = match self {
+            ResponseCurve::Linear => input,
+            ResponseCurve::PiecewiseLinear(points) => Self::evaluate_piecewise(points, input),
+            ResponseCurve::CubicBezier { p1, p2 } => Self::evaluate_bezier(*p1, *p2, input),
+        };
+        output.clamp(0.0, 1.0)
+    }
+
+    fn evaluate_piecewise(points: &[(f32, f32)], input: f32) -> f32 {
+        let (Some(first), Some(last)) = (points.first(), points.last()) else {
+            return input;
+        };
+        if input <= first.0 {
+            return first.1;
+        }
+        if input >= last.0 {
+            return last.1;
+        }
+
+        for window in points.windows(2) {
+            let (x0, y0) = window[0];
+            let (x1, y1) = window[1];
+            if input <= x1 {
+                if x1 - x0 <= f32::EPSILON {
+                    return y1;
+                }
+                return y0 + (y1 - y0) * (input - x0) / (x1 - x0);
+            }
+        }
+        last.1
+    }
+
+    fn evaluate_bezier(p1: (f32, f32), p2: (f32, f32), input: f32) -> f32 {
+        /* keeping the control points inside the unit range keeps x(t) monotonic */
+        let x1 = p1.0.clamp(0.0, 1.0);
+        let x2 = p2.0.clamp(0.0, 1.0);
+        let bezier = |a: f32, b: f32, t: f32| {
+            let mt = 1.0 - t;
+            3.0 * mt * mt * t * a + 3.0 * mt * t * t * b + t * t * t
+        };
+
+        /* solve x(t) = input by bisection, then sample y(t) */
+        let (mut low, mut high) = (0.0, 1.0);
+        let mut t = input;
+        for _ in 0..24 {
+            let x = bezier(x1, x2, t);
+            if (x - input).abs() < 1e-5 {
+                break;
+            }
+            if x < input {
+                low = t;
+            } else {
+                high = t;
+            }
+            t = (low + high) / 2.0;
+        }
+
+        bezier(p1.1, p2.1, t)
+    }
+}
+
+/// Maps pen pressure onto a multiplier for a brush setting
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PressureMapping {
+    pub enabled: bool,
+    /// Multiplier used at zero pressure, full pressure always maps to `1.0`
+    pub minimum: f32,
+    pub curve: ResponseCurve,
+}
+
+impl PressureMapping {
+    /// A mapping that always returns `1.0`
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            minimum: 0.0,
+            curve: ResponseCurve::Linear,
+        }
+    }
+
+    /// A linear mapping from `minimum` at zero pressure to `1.0` at full pressure
+    pub fn linear(minimum: f32) -> Self {
+        Self {
+            enabled: true,
+            minimum,
+            curve: ResponseCurve::Linear,
+        }
+    }
+
+    pub fn apply(&self, pressure: f32) -> f32 {
+        if !self.enabled {
+            return 1.0;
+        }
+
+        let minimum = self.minimum.clamp(0.0, 1.0);
+        minimum + (1.0 - minimum) * self.curve.evaluate(pressure)
+    }
+}
+
+/// How pen pressure affects the size and opacity of each dab
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PressureDynamics {
+    pub size: PressureMapping,
+    pub opacity: PressureMapping,
+}
+
+impl PressureDynamics {
+    pub fn size_multiplier(&self, pressure: f32) -> f32 {
+        self.size.apply(pressure)
+    }
+
+    /// Diameter of a dab of a brush `size` wide drawn at `pressure`
+    pub fn dab_size(&self, size: f32, pressure: f32) -> f32 {
+        size * self.size_multiplier(pressure)
+    }
+
+    pub fn opacity_multiplier(&self, pressure: f32) -> f32 {
+        self.opacity.apply(pressure)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn linear_clamps_its_input() {
+        let curve = ResponseCurve::Linear;
+        assert_close(curve.evaluate(0.25), 0.25);
+        assert_close(curve.evaluate(-1.0), 0.0);
+        assert_close(curve.evaluate(2.0), 1.0);
+        assert_close(curve.evaluate(f32::NAN), 0.0);
+    }
+
+    #[test]
+    fn piecewise_interpolates_between_points() {
+        let curve = ResponseCurve::PiecewiseLinear(vec![(0.2, 0.0), (0.6, 0.8), (1.0, 1.0)]);
+        assert_close(curve.evaluate(0.0), 0.0);
+        assert_close(curve.evaluate(0.4), 0.4);
+        assert_close(curve.evaluate(0.6), 0.8);
+        assert_close(curve.evaluate(0.8), 0.9);
+        assert_close(curve.evaluate(1.0), 1.0);
+
+        /* an empty curve behaves like a linear one */
+        assert_close(ResponseCurve::PiecewiseLinear(vec![]).evaluate(0.3), 0.3);
+    }
+
+    #[test]
+    fn piecewise_handles_vertical_steps() {
+        let curve = ResponseCurve::PiecewiseLinear(vec![(0.0, 0.0), (0.5, 0.0), (0.5, 1.0)]);
+        assert_close(curve.evaluate(0.25), 0.0);
+        assert_close(curve.evaluate(0.75), 1.0);
+    }
+
+    #[test]
+    fn bezier_matches_css_timing_functions() {
+        /* css `linear` written as a bezier */
+        let linear = ResponseCurve::CubicBezier {
+            p1: (0.0, 0.0),
+            p2: (1.0, 1.0),
+        };
+        for input in [0.0, 0.3, 0.5, 0.9, 1.0] {
+            assert_close(linear.evaluate(input), input);
+        }
+
+        /* css `ease-in-out` is symmetric around the middle */
+        let ease = ResponseCurve::CubicBezier {
+            p1: (0.42, 0.0),
+            p2: (0.58, 1.0),
+        };
+        assert_close(ease.evaluate(0.5), 0.5);
+        assert_close(ease.evaluate(0.25) + ease.evaluate(0.75), 1.0);
+        assert!(ease.evaluate(0.25) < 0.25);
+
+        /* overshooting control points still stay in range */
+        let overshoot = ResponseCurve::CubicBezier {
+            p1: (0.3, 2.0),
+            p2: (0.7, 2.0),
+        };
+        assert_close(overshoot.evaluate(0.5), 1.0);
+    }
+
+    #[test]
+    fn mappings_scale_from_the_minimum() {
+        let mapping = PressureMapping::linear(0.2);
+        assert_close(mapping.apply(0.0), 0.2);
+        assert_close(mapping.apply(0.5), 0.6);
+        assert_close(mapping.apply(1.0), 1.0);
+
+        let disabled = PressureMapping::disabled();
+        assert_close(disabled.apply(0.0), 1.0);
+
+        let curved = PressureMapping {
+            enabled: true,
+            minimum: 0.0,
+            curve: ResponseCurve::PiecewiseLinear(vec![(0.0, 0.0), (0.5, 1.0)]),
+        };
+        assert_close(curved.apply(0.25), 0.5);
+        assert_close(curved.apply(0.75), 1.0);
+    }
+}
@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+
+use super::stroke::StrokePositionalData;
+use super::{BrushEngine, Dab, PressureDynamics, PressureMapping};
+use crate::Canvas;
+use crate::color::Channel;
+
+/// Round brush that removes alpha from the active layer instead of adding color
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Eraser {
+    /// Diameter of a dab in canvas pixels
+    pub size: f32,
+    /// How far the solid centre reaches before fading out, see [`Dab::hardness`]
+    pub hardness: f32,
+    /// Amount of alpha a single dab removes
+    pub strength: f32,
+    /// How pen pressure scales the size and strength of each dab
+    pub dynamics: PressureDynamics,
+}
+
+impl Default for Eraser {
+    fn default() -> Self {
+        Self {
+            size: 20.0,
+            hardness: 1.0,
+            strength: 1.0,
+            dynamics: PressureDynamics {
+                size: PressureMapping::linear(0.1),
+                opacity: PressureMapping::disabled(),
+            },
+        }
+    }
+}
+
+impl<T: Channel> BrushEngine<T> for Eraser {
+    fn spacing(&self) -> f32 {
+        0.1
+    }
+
+    fn dab_size(&self, pressure: f32) -> f32 {
+        self.dynamics.dab_size(self.size, pressure)
+    }
+
+    fn dab(&mut self, point: &StrokePositionalData, canvas: &mut Canvas<T>) {
+        Dab::from_point(
+            point,
+            self.size,
+            self.hardness,
+            self.strength,
+            &self.dynamics,
+        )
+        .erase(canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    #[test]
+    fn removes_alpha_and_leaves_color_alone() {
+        let mut canvas: Canvas = Canvas::new(20, 20);
+        canvas.add_layer();
+        canvas.draw_pixel(10, 10, Color::new(200, 100, 50, 255));
+        let mut eraser = Eraser {
+            size: 6.0,
+            strength: 0.5,
+            ..Default::default()
+        };
+        let point = StrokePositionalData {
+            x: 10.5,
+            y: 10.5,
+            pressure: 1.0,
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            time: 0.0,
+            color: Color::new(0, 0, 0, 255),
+        };
+        eraser.dab(&point, &mut canvas);
+
+        let erased = canvas.layers().active().pixel(10, 10).unwrap();
+        assert_eq!(erased.a, 128);
+        for (channel, original) in [(erased.r, 200), (erased.g, 100), (erased.b, 50)] {
+            assert!(channel.abs_diff(original) <= 1, "{erased:?}");
+        }
+    }
+}
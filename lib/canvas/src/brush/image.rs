@@ -0,0 +1,510 @@
+use std::f32::consts::TAU;
+use std::io::Read;
+
+use serde::{Deserialize, Serialize};
+
+use super::stroke::StrokePositionalData;
+use super::{BrushEngine, PressureDynamics, PressureMapping};
+use crate::blend::BlendMode;
+use crate::color::Channel;
+use crate::format::{self, png};
+use crate::random::next_random;
+use crate::{Canvas, Color};
+
+/// A grayscale bitmap used as a brush tip or a paper grain, every pixel holds the amount
+/// of paint it lets through from 0 to 255
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "StoredGrayImage")]
+pub struct GrayImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+/// A [`GrayImage`] as stored in project files, checked by [`GrayImage::new`] when loaded
+#[derive(Deserialize)]
+struct StoredGrayImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl TryFrom<StoredGrayImage> for GrayImage {
+    type Error = String;
+
+    fn try_from(stored: StoredGrayImage) -> Result<Self, Self::Error> {
+        let (width, height) = (stored.width, stored.height);
+        Self::new(width, height, stored.pixels).ok_or_else(|| {
+            format!("brush image of {width}x{height} pixels does not match its data")
+        })
+    }
+}
+
+impl GrayImage {
+    /// A bitmap of `width` by `height` pixels, `None` if `pixels` doesn't hold exactly
+    /// one value for each of them
+    pub fn new(width: usize, height: usize, pixels: Vec<u8>) -> Option<Self> {
+        (width > 0 && height > 0 && pixels.len() == width * height).then_some(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Reads a png as a bitmap. Images with transparency paint with their alpha, opaque
+    /// ones with their darkness so black on white tips work as well
+    pub fn from_png(reader: impl Read) -> format::Result<Self> {
+        let (width, height, rgba) = png::decode(reader)?;
+        let transparent = rgba.chunks_exact(4).any(|p| p[3] < 255);
+        let pixels = rgba
+            .chunks_exact(4)
+            .map(|p| {
+                if transparent {
+                    p[3]
+                } else {
+                    let luma = 0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32;
+                    255 - luma.round() as u8
+                }
+            })
+            .collect();
+        Self::new(width, height, pixels)
+            .ok_or_else(|| format::Error::Unsupported("empty brush image".into()))
+    }
+
+    /// A hard round tip with an anti-aliased edge
+    pub fn round(diameter: usize) -> Self {
+        let diameter = diameter.max(1);
+        let radius = diameter as f32 / 2.0;
+        let pixels = (0..diameter * diameter)
+            .map(|i| {
+                let dx = (i % diameter) as f32 + 0.5 - radius;
+                let dy = (i / diameter) as f32 + 0.5 - radius;
+                let edge = (radius - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0);
+                (edge * 255.0).round() as u8
+            })
+            .collect();
+        Self {
+            width: diameter,
+            height: diameter,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn get(&self, x: usize, y: usize) -> f32 {
+        self.pixels[y * self.width + x] as f32 / 255.0
+    }
+
+    /// Bilinearly interpolated value from 0 to 1 at a position in pixels, where pixel
+    /// centres lie at `n + 0.5`. Everything outside of the bitmap is empty
+    pub fn sample(&self, x: f32, y: f32) -> f32 {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (left, top) = (x.floor(), y.floor());
+        let (fx, fy) = (x - left, y - top);
+        let value = |dx: f32, dy: f32| {
+            let (px, py) = (left + dx, top + dy);
+            if px < 0.0 || py < 0.0 || px >= self.width as f32 || py >= self.height as f32 {
+                return 0.0;
+            }
+            self.get(px as usize, py as usize)
+        };
+
+        let top_row = value(0.0, 0.0) * (1.0 - fx) + value(1.0, 0.0) * fx;
+        let bottom_row = value(0.0, 1.0) * (1.0 - fx) + value(1.0, 1.0) * fx;
+        top_row * (1.0 - fy) + bottom_row * fy
+    }
+
+    /// Value of the pixel the position falls into with the bitmap repeating endlessly
+    pub fn sample_wrapped(&self, x: f32, y: f32) -> f32 {
+        let x = x.floor().rem_euclid(self.width as f32) as usize;
+        let y = y.floor().rem_euclid(self.height as f32) as usize;
+        self.get(x.min(self.width - 1), y.min(self.height - 1))
+    }
+}
+
+/// A paper texture repeated across the canvas, so every stroke over the same spot
+/// reveals the same grain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Grain {
+    pub texture: GrayImage,
+    /// Canvas pixels covered by one pixel of the texture
+    pub scale: f32,
+    /// How much paint the empty parts of the texture hold back, from 0 to 1
+    pub strength: f32,
+}
+
+impl Grain {
+    /// Share of the paint that reaches canvas pixel `x`, `y`
+    fn coverage(&self, x: usize, y: usize) -> f32 {
+        let scale = self.scale.max(0.01);
+        let value = self
+            .texture
+            .sample_wrapped(x as f32 / scale, y as f32 / scale);
+        1.0 - self.strength.clamp(0.0, 1.0) * (1.0 - value)
+    }
+}
+
+/// Brush stamping a grayscale bitmap, turned and scattered along the stroke. Missing
+/// fields take their default
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageBrush {
+    pub tip: GrayImage,
+    /// Length of the longer side of the tip in canvas pixels
+    pub size: f32,
+    /// Distance between two dabs as a fraction of their size
+    pub spacing: f32,
+    pub opacity: f32,
+    /// Angle of the tip in degrees, relative to the stroke direction if `follow_direction`
+    pub angle: f32,
+    /// Turns every dab along the direction the stroke is heading
+    pub follow_direction: bool,
+    /// Largest distance a dab is moved off the stroke, as a fraction of its size
+    pub scatter: f32,
+    /// Largest share a dab randomly shrinks by, from 0 to 1
+    pub size_jitter: f32,
+    /// Largest random turn of a dab in degrees, either way
+    pub angle_jitter: f32,
+    /// Largest share of the opacity a dab randomly loses, from 0 to 1
+    pub opacity_jitter: f32,
+    /// Paper texture the dabs are drawn through
+    pub grain: Option<Grain>,
+    /// How pen pressure scales the size and opacity of each dab
+    pub dynamics: PressureDynamics,
+    /// How each dab is blended onto the active layer
+    pub blend_mode: BlendMode,
+}
+
+impl Default for ImageBrush {
+    fn default() -> Self {
+        Self {
+            tip: GrayImage::round(64),
+            size: 24.0,
+            spacing: 0.25,
+            opacity: 1.0,
+            angle: 0.0,
+            follow_direction: false,
+            scatter: 0.0,
+            size_jitter: 0.0,
+            angle_jitter: 0.0,
+            opacity_jitter: 0.0,
+            grain: None,
+            dynamics: PressureDynamics {
+                size: PressureMapping::linear(0.1),
+                opacity: PressureMapping::disabled(),
+            },
+            blend_mode: BlendMode::Normal,
+        }
+    }
+}
+
+/// An [`ImageBrush`] along with the state it keeps during a stroke
+pub(super) struct ImageEngine {
+    brush: ImageBrush,
+    /// position of the previous dab, to find the stroke direction
+    previous: Option<(f32, f32)>,
+    /// stroke direction in radians
+    direction: f32,
+    /// dabs at the start of a stroke following its direction, held back until the
+    /// stroke moves
+    pending: Vec<StrokePositionalData>,
+    /// state of the random jitter, carried over between strokes
+    random: u64,
+}
+
+impl ImageEngine {
+    pub fn new(brush: ImageBrush) -> Self {
+        Self {
+            brush,
+            previous: None,
+            direction: 0.0,
+            pending: vec![],
+            random: 0,
+        }
+    }
+
+    /// Draws a dab turned by the current stroke direction
+    fn stamp<T: Channel>(&mut self, point: &StrokePositionalData, canvas: &mut Canvas<T>) {
+        let size_jitter =
+            1.0 - self.brush.size_jitter.clamp(0.0, 1.0) * next_random(&mut self.random);
+        let size =
+            self.brush.size * self.brush.dynamics.size_multiplier(point.pressure) * size_jitter;
+        let opacity_jitter =
+            1.0 - self.brush.opacity_jitter.clamp(0.0, 1.0) * next_random(&mut self.random);
+        let opacity = self.brush.opacity
+            * self.brush.dynamics.opacity_multiplier(point.pressure)
+            * opacity_jitter;
+
+        let mut angle = self.brush.angle.to_radians();
+        if self.brush.follow_direction {
+            angle += self.direction;
+        }
+        angle += self.brush.angle_jitter.to_radians() * (next_random(&mut self.random) * 2.0 - 1.0);
+
+        /* scatter in a random direction, up to the full distance */
+        let distance = self.brush.scatter * size * next_random(&mut self.random);
+        let heading = next_random(&mut self.random) * TAU;
+        let stamp = Stamp {
+            x: point.x + distance * heading.cos(),
+            y: point.y + distance * heading.sin(),
+            size,
+            angle,
+            opacity,
+        };
+        stamp.draw(&self.brush, point.color.convert(), canvas);
+    }
+}
+
+impl<T: Channel> BrushEngine<T> for ImageEngine {
+    fn spacing(&self) -> f32 {
+        self.brush.spacing
+    }
+
+    fn dab_size(&self, pressure: f32) -> f32 {
+        self.brush.dynamics.dab_size(self.brush.size, pressure)
+    }
+
+    fn begin_stroke(&mut self) {
+        self.previous = None;
+        self.direction = 0.0;
+        self.pending.clear();
+    }
+
+    fn dab(&mut self, point: &StrokePositionalData, canvas: &mut Canvas<T>) {
+        match self.previous {
+            Some((x, y)) if point.x != x || point.y != y => {
+                self.direction = (point.y - y).atan2(point.x - x);
+                for pending in std::mem::take(&mut self.pending) {
+                    self.stamp(&pending, canvas);
+                }
+            }
+            /* the direction is only known once the stroke moves */
+            None if self.brush.follow_direction => self.pending.push(point.clone()),
+            _ if !self.pending.is_empty() => self.pending.push(point.clone()),
+            _ => {}
+        }
+        self.previous = Some((point.x, point.y));
+
+        if self.pending.is_empty() {
+            self.stamp(point, canvas);
+        }
+    }
+
+    fn end_stroke(&mut self, canvas: &mut Canvas<T>) {
+        /* a stroke that never moved is drawn at the angle of the tip alone */
+        for pending in std::mem::take(&mut self.pending) {
+            self.stamp(&pending, canvas);
+        }
+        self.previous = None;
+    }
+}
+
+/// One placement of the tip of an [`ImageBrush`] on the canvas
+struct Stamp {
+    /// Centre of the stamp in canvas pixels
+    x: f32,
+    y: f32,
+    /// Length of the longer side of the tip
+    size: f32,
+    /// Clockwise turn in radians
+    angle: f32,
+    opacity: f32,
+}
+
+impl Stamp {
+    fn draw<T: Channel>(&self, brush: &ImageBrush, color: Color<T>, canvas: &mut Canvas<T>) {
+        let tip = &brush.tip;
+        let scale = self.size / tip.width().max(tip.height()) as f32;
+        let opacity = self.opacity.clamp(0.0, 1.0);
+        if !scale.is_finite() || scale <= 0.0 || opacity <= 0.0 {
+            return;
+        }
+
+        let half = (tip.width() as f32, tip.height() as f32);
+        let half = (half.0 / 2.0, half.1 / 2.0);
+        let reach = (half.0 * half.0 + half.1 * half.1).sqrt() * scale + 1.0;
+        let right = (self.x + reach).ceil().min(canvas.width() as f32);
+        let bottom = (self.y + reach).ceil().min(canvas.height() as f32);
+        if !right.is_finite() || !bottom.is_finite() || right <= 0.0 || bottom <= 0.0 {
+            return;
+        }
+        let left = (self.x - reach).floor().max(0.0) as usize;
+        let top = (self.y - reach).floor().max(0.0) as usize;
+
+        let (sin, cos) = self.angle.sin_cos();
+        for y in top..bottom as usize {
+            for x in left..right as usize {
+                /* turn the pixel centre back into the unrotated tip */
+                let dx = x as f32 + 0.5 - self.x;
+                let dy = y as f32 + 0.5 - self.y;
+                let u = (dx * cos + dy * sin) / scale + half.0;
+                let v = (dy * cos - dx * sin) / scale + half.1;
+
+                let mut coverage = tip.sample(u, v) * opacity;
+                if let Some(grain) = &brush.grain {
+                    coverage *= grain.coverage(x, y);
+                }
+                if coverage > 0.0 {
+                    canvas.blend_pixel(x, y, color, coverage, brush.blend_mode);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brush::BrushSettings;
+    use crate::brush::stroke::StrokeManager;
+
+    const WHITE: Color<u8> = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 255,
+    };
+
+    fn paint(brush: ImageBrush, points: &[(f32, f32)]) -> Canvas {
+        let mut canvas: Canvas = Canvas::new(40, 40);
+        let mut strokes = StrokeManager::new();
+        strokes.set_brush(BrushSettings::Image(brush));
+        stroke(&mut strokes, &mut canvas, points);
+        canvas
+    }
+
+    fn stroke(strokes: &mut StrokeManager, canvas: &mut Canvas, points: &[(f32, f32)]) {
+        let point = |&(x, y): &(f32, f32)| StrokePositionalData {
+            x,
+            y,
+            pressure: 1.0,
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            time: 0.0,
+            color: Color::new(0, 0, 0, 255),
+        };
+        let (last, rest) = points.split_last().unwrap();
+        strokes.begin_stroke(point(&rest[0]), canvas);
+        for p in &rest[1..] {
+            strokes.continue_stroke(point(p), canvas);
+        }
+        strokes.end_stroke(point(last), canvas);
+    }
+
+    /// A wide flat tip turning with the stroke, 16 by 4 pixels when lying flat
+    fn flat_tip() -> ImageBrush {
+        ImageBrush {
+            tip: GrayImage::new(8, 2, vec![255; 16]).unwrap(),
+            size: 16.0,
+            follow_direction: true,
+            dynamics: PressureDynamics {
+                size: PressureMapping::disabled(),
+                opacity: PressureMapping::disabled(),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reads_tips_from_png() {
+        /* opaque images paint with their darkness */
+        let mut file = vec![];
+        png::encode(2, 1, &[0, 0, 0, 255, 255, 255, 255, 255], &mut file).unwrap();
+        let tip = GrayImage::from_png(file.as_slice()).unwrap();
+        assert_eq!(tip, GrayImage::new(2, 1, vec![255, 0]).unwrap());
+
+        let mut file = vec![];
+        png::encode(2, 1, &[255, 255, 255, 0, 255, 255, 255, 200], &mut file).unwrap();
+        let tip = GrayImage::from_png(file.as_slice()).unwrap();
+        assert_eq!(tip, GrayImage::new(2, 1, vec![0, 200]).unwrap());
+    }
+
+    #[test]
+    fn tips_turn_with_the_stroke() {
+        /* a wide flat tip, drawn along a vertical stroke, stands upright */
+        let canvas = paint(flat_tip(), &[(20.0, 5.0), (20.0, 30.0), (20.0, 30.0)]);
+        let layer = canvas.layers().active();
+        assert_ne!(layer.pixel(20, 30), Some(WHITE));
+        assert_eq!(layer.pixel(27, 30), Some(WHITE));
+        assert_ne!(layer.pixel(20, 35), Some(WHITE));
+
+        /* the first dab waits for the second sample to know which way to turn */
+        assert_eq!(layer.pixel(27, 5), Some(WHITE));
+        assert_ne!(layer.pixel(20, 0), Some(WHITE));
+    }
+
+    #[test]
+    fn direction_starts_over_with_every_stroke() {
+        let mut canvas: Canvas = Canvas::new(40, 40);
+        let mut strokes = StrokeManager::new();
+        strokes.set_brush(BrushSettings::Image(flat_tip()));
+        stroke(&mut strokes, &mut canvas, &[(10.0, 5.0), (10.0, 35.0)]);
+
+        /* a click never moves, so the tip keeps lying flat */
+        stroke(&mut strokes, &mut canvas, &[(30.0, 20.0), (30.0, 20.0)]);
+        let layer = canvas.layers().active();
+        assert_ne!(layer.pixel(36, 20), Some(WHITE));
+        assert_eq!(layer.pixel(30, 25), Some(WHITE));
+    }
+
+    #[test]
+    fn settings_default_their_missing_fields() {
+        let brush: ImageBrush =
+            serde_json::from_str(r#"{"size": 40.0, "follow_direction": true}"#).unwrap();
+        assert_eq!(brush.size, 40.0);
+        assert!(brush.follow_direction);
+        assert_eq!(brush.tip, ImageBrush::default().tip);
+        assert!(brush.grain.is_none());
+    }
+
+    #[test]
+    fn rejects_images_not_matching_their_size() {
+        let image: GrayImage =
+            serde_json::from_str(r#"{"width": 2, "height": 1, "pixels": [0, 255]}"#).unwrap();
+        assert_eq!(image, GrayImage::new(2, 1, vec![0, 255]).unwrap());
+
+        for json in [
+            r#"{"width": 2, "height": 2, "pixels": [0, 255]}"#,
+            r#"{"width": 0, "height": 1, "pixels": []}"#,
+        ] {
+            assert!(serde_json::from_str::<GrayImage>(json).is_err(), "{json}");
+        }
+    }
+
+    #[test]
+    fn huge_tips_stop_at_the_canvas() {
+        let brush = ImageBrush {
+            size: 1e7,
+            ..Default::default()
+        };
+        let canvas = paint(brush, &[(20.0, 20.0), (20.0, 20.0)]);
+        assert_ne!(canvas.layers().active().pixel(39, 39), Some(WHITE));
+    }
+
+    #[test]
+    fn grain_stays_in_canvas_space() {
+        /* columns alternate between holding all of the paint and none of it */
+        let brush = ImageBrush {
+            size: 10.0,
+            grain: Some(Grain {
+                texture: GrayImage::new(2, 1, vec![255, 0]).unwrap(),
+                scale: 1.0,
+                strength: 1.0,
+            }),
+            ..Default::default()
+        };
+        let canvas = paint(brush, &[(9.0, 20.0), (31.0, 20.0)]);
+        let layer = canvas.layers().active();
+        for x in 10..30 {
+            let painted = layer.pixel(x, 20) != Some(WHITE);
+            assert_eq!(painted, x % 2 == 0, "column {x}");
+        }
+    }
+}
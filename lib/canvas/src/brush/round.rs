@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+
+use super::stroke::StrokePositionalData;
+use super::{BrushEngine, Dab, PressureDynamics, PressureMapping};
+use crate::Canvas;
+use crate::blend::BlendMode;
+use crate::color::Channel;
+
+/// Round brush with an anti-aliased outline and adjustable hardness
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundBrush {
+    /// Diameter of a dab in canvas pixels
+    pub size: f32,
+    /// How far the solid centre reaches before fading out, see [`Dab::hardness`]
+    pub hardness: f32,
+    /// Opacity of a single dab
+    pub opacity: f32,
+    /// How pen pressure scales the size and opacity of each dab
+    pub dynamics: PressureDynamics,
+    /// How each dab is blended onto the active layer
+    pub blend_mode: BlendMode,
+}
+
+impl Default for RoundBrush {
+    fn default() -> Self {
+        Self {
+            size: 12.0,
+            hardness: 1.0,
+            opacity: 1.0,
+            dynamics: PressureDynamics {
+                size: PressureMapping::linear(0.1),
+                opacity: PressureMapping::disabled(),
+            },
+            blend_mode: BlendMode::Normal,
+        }
+    }
+}
+
+impl<T: Channel> BrushEngine<T> for RoundBrush {
+    fn spacing(&self) -> f32 {
+        0.1
+    }
+
+    fn dab_size(&self, pressure: f32) -> f32 {
+        self.dynamics.dab_size(self.size, pressure)
+    }
+
+    fn dab(&mut self, point: &StrokePositionalData, canvas: &mut Canvas<T>) {
+        let dab = Dab {
+            blend_mode: self.blend_mode,
+            ..Dab::from_point(
+                point,
+                self.size,
+                self.hardness,
+                self.opacity,
+                &self.dynamics,
+            )
+        };
+        dab.draw(canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    fn point(x: f32, y: f32, pressure: f32) -> StrokePositionalData {
+        StrokePositionalData {
+            x,
+            y,
+            pressure,
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            time: 0.0,
+            color: Color::new(255, 0, 0, 255),
+        }
+    }
+
+    #[test]
+    fn covers_its_radius() {
+        let mut canvas: Canvas = Canvas::new(20, 20);
+        canvas.add_layer();
+        let mut brush = RoundBrush {
+            size: 10.0,
+            ..Default::default()
+        };
+        brush.dab(&point(10.0, 10.0, 1.0), &mut canvas);
+
+        let layer = canvas.layers().active();
+        for (x, y) in [(10, 10), (6, 10), (13, 10), (10, 6), (10, 13), (12, 12)] {
+            assert_eq!(layer.pixel(x, y), Some(Color::new(255, 0, 0, 255)));
+        }
+        for (x, y) in [(4, 10), (15, 10), (10, 15), (14, 14)] {
+            assert_eq!(layer.pixel(x, y), Some(Color::new(0, 0, 0, 0)));
+        }
+    }
+
+    #[test]
+    fn pressure_shrinks_the_dab() {
+        let brush = RoundBrush {
+            size: 10.0,
+            ..Default::default()
+        };
+        assert_eq!(BrushEngine::<u8>::dab_size(&brush, 1.0), 10.0);
+        assert_eq!(BrushEngine::<u8>::dab_size(&brush, 0.0), 1.0);
+    }
+}
@@ -0,0 +1,266 @@
+use serde::{Deserialize, Serialize};
+
+use super::stroke::StrokePositionalData;
+use super::{BrushEngine, Dab, PressureDynamics, PressureMapping};
+use crate::color::{Channel, srgb_to_linear};
+use crate::{Canvas, Color};
+
+/// Reflectance a channel is clamped to before pigment mixing, keeps black finite
+const MIN_REFLECTANCE: f32 = 0.001;
+
+/// Straight alpha RGBA in linear light, the paint carried along a smudge stroke
+pub(super) type Paint = [f32; 4];
+
+/// How a [`SmudgeBrush`] mixes the paint it carries with the canvas and the brush color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MixMode {
+    /// Averages the colors in linear light, like two lights adding up
+    #[default]
+    Additive,
+    /// Mixes the colors like paint, absorbing light with an approximation of the
+    /// Kubelka-Munk model on every channel. Mixed colors turn darker and duller
+    Pigment,
+}
+
+impl MixMode {
+    /// Mixes `amount` of `other` into `paint`, transparent paint brings no color along
+    pub(super) fn mix(self, paint: Paint, other: Paint, amount: f32) -> Paint {
+        let amount = amount.clamp(0.0, 1.0);
+        let alpha = paint[3] + (other[3] - paint[3]) * amount;
+        if alpha <= 0.0 {
+            return [0.0; 4];
+        }
+
+        let t = other[3] * amount / alpha;
+        let mut mixed = [0.0, 0.0, 0.0, alpha];
+        for (channel, (&from, &to)) in mixed.iter_mut().zip(paint.iter().zip(&other)).take(3) {
+            *channel = match self {
+                MixMode::Additive => from + (to - from) * t,
+                MixMode::Pigment => reflectance(absorption(from) * (1.0 - t) + absorption(to) * t),
+            };
+        }
+        mixed
+    }
+}
+
+/// Ratio of absorption to scattering of a layer of paint reflecting `reflectance`
+fn absorption(reflectance: f32) -> f32 {
+    let reflectance = reflectance.clamp(MIN_REFLECTANCE, 1.0);
+    (1.0 - reflectance).powi(2) / (2.0 * reflectance)
+}
+
+/// Reflectance of a thick layer of paint, the inverse of [`absorption`]
+fn reflectance(absorption: f32) -> f32 {
+    1.0 + absorption - (absorption * absorption + 2.0 * absorption).sqrt()
+}
+
+/// Brush that picks up the color under it and drags it along the stroke, mixed with
+/// the brush color. Missing fields take their default
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SmudgeBrush {
+    /// Diameter of a dab in canvas pixels
+    pub size: f32,
+    /// How far the solid centre reaches before fading out, see [`Dab::hardness`]
+    pub hardness: f32,
+    /// How far a single dab moves the canvas towards the carried paint
+    pub opacity: f32,
+    /// Share of the carried paint kept on every dab, the rest is picked up from the canvas
+    /// under the dab. `0` barely smears, `1` drags the first color along the whole stroke
+    pub length: f32,
+    /// Share of the carried paint in every dab, the rest is brush color. `1` smudges
+    /// without adding color, `0` paints plain brush color
+    pub wetness: f32,
+    /// How the carried paint mixes with the canvas and the brush color
+    pub mix_mode: MixMode,
+    /// How pen pressure scales the size and opacity of each dab
+    pub dynamics: PressureDynamics,
+}
+
+impl Default for SmudgeBrush {
+    fn default() -> Self {
+        Self {
+            size: 30.0,
+            hardness: 0.5,
+            opacity: 1.0,
+            length: 0.8,
+            wetness: 1.0,
+            mix_mode: MixMode::Additive,
+            dynamics: PressureDynamics {
+                size: PressureMapping::disabled(),
+                opacity: PressureMapping::linear(0.0),
+            },
+        }
+    }
+}
+
+/// A [`SmudgeBrush`] along with the paint it picked up during the current stroke
+pub(super) struct SmudgeEngine {
+    brush: SmudgeBrush,
+    reservoir: Option<Paint>,
+}
+
+impl SmudgeEngine {
+    pub fn new(brush: SmudgeBrush) -> Self {
+        Self {
+            brush,
+            reservoir: None,
+        }
+    }
+}
+
+impl<T: Channel> BrushEngine<T> for SmudgeEngine {
+    fn spacing(&self) -> f32 {
+        0.1
+    }
+
+    fn dab_size(&self, pressure: f32) -> f32 {
+        self.brush.dynamics.dab_size(self.brush.size, pressure)
+    }
+
+    fn begin_stroke(&mut self) {
+        self.reservoir = None;
+    }
+
+    fn dab(&mut self, point: &StrokePositionalData, canvas: &mut Canvas<T>) {
+        let brush = &self.brush;
+        let dab = Dab::from_point(
+            point,
+            brush.size,
+            brush.hardness,
+            brush.opacity,
+            &brush.dynamics,
+        );
+        let Some(sample) = sample(&dab, canvas) else {
+            return;
+        };
+
+        /* the first dab of a stroke loads the brush with the paint under it */
+        let reservoir = match self.reservoir {
+            Some(reservoir) => brush.mix_mode.mix(sample, reservoir, brush.length),
+            None => sample,
+        };
+        self.reservoir = Some(reservoir);
+
+        let Color { r, g, b, a } = point.color;
+        let [r, g, b] = [r, g, b].map(|c| srgb_to_linear(c.normalized()));
+        let paint = brush
+            .mix_mode
+            .mix([r, g, b, a.normalized()], reservoir, brush.wetness);
+        let paint = Color::new(
+            T::from_linear(paint[0]),
+            T::from_linear(paint[1]),
+            T::from_linear(paint[2]),
+            T::from_normalized(paint[3]),
+        );
+        dab.rasterize(|x, y, coverage| canvas.smudge_pixel(x, y, paint, coverage));
+    }
+
+    fn end_stroke(&mut self, _canvas: &mut Canvas<T>) {
+        self.reservoir = None;
+    }
+}
+
+/// Average of the active layer under `dab` weighted by its coverage, `None` if the dab
+/// misses the canvas
+pub(super) fn sample<T: Channel>(dab: &Dab, canvas: &Canvas<T>) -> Option<Paint> {
+    let layer = canvas.layers().active();
+    let mut sum = [0.0f32; 4];
+    let mut weight = 0.0;
+    dab.rasterize(|x, y, coverage| {
+        let Some(color) = layer.pixel(x, y) else {
+            return;
+        };
+        let alpha = color.a.normalized() * coverage;
+        for (total, channel) in sum.iter_mut().zip([color.r, color.g, color.b]) {
+            *total += channel.to_linear() * alpha;
+        }
+        sum[3] += alpha;
+        weight += coverage;
+    });
+
+    if weight <= 0.0 {
+        return None;
+    }
+    if sum[3] <= 0.0 {
+        return Some([0.0; 4]);
+    }
+    Some([
+        sum[0] / sum[3],
+        sum[1] / sum[3],
+        sum[2] / sum[3],
+        sum[3] / weight,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brush::BrushSettings;
+    use crate::brush::stroke::StrokeManager;
+
+    #[test]
+    fn pigments_mix_darker_than_light() {
+        let yellow = [1.0, 1.0, 0.0, 1.0];
+        let blue = [0.0, 0.0, 1.0, 1.0];
+        let light = MixMode::Additive.mix(yellow, blue, 0.5);
+        let pigment = MixMode::Pigment.mix(yellow, blue, 0.5);
+        assert_eq!(light, [0.5, 0.5, 0.5, 1.0]);
+        assert!(pigment[..3].iter().all(|&c| c < 0.1));
+
+        /* mixing a color with itself keeps it */
+        let grey = [0.3, 0.3, 0.3, 1.0];
+        let same = MixMode::Pigment.mix(grey, grey, 0.5);
+        assert!(same.iter().zip(grey).all(|(a, b)| (a - b).abs() < 1e-4));
+
+        /* transparent paint thins the mix out without tinting it */
+        let clear = MixMode::Additive.mix(yellow, [0.0; 4], 0.5);
+        assert_eq!(clear, [1.0, 1.0, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn drags_color_along_the_stroke() {
+        let mut canvas: Canvas = Canvas::new(60, 10);
+        for y in 0..10 {
+            for x in 0..10 {
+                canvas.draw_pixel(x, y, Color::new(255, 0, 0, 255));
+            }
+        }
+
+        let mut strokes = StrokeManager::new();
+        strokes.set_brush(BrushSettings::Smudge(SmudgeBrush {
+            size: 8.0,
+            dynamics: PressureDynamics {
+                size: PressureMapping::disabled(),
+                opacity: PressureMapping::disabled(),
+            },
+            ..Default::default()
+        }));
+        let point = |x| StrokePositionalData {
+            x,
+            y: 5.0,
+            pressure: 1.0,
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            time: 0.0,
+            color: Color::new(0, 0, 255, 255),
+        };
+        strokes.begin_stroke(point(5.0), &mut canvas);
+        strokes.continue_stroke(point(30.0), &mut canvas);
+        strokes.end_stroke(point(30.0), &mut canvas);
+
+        /* red is smeared onto the white canvas without any of the blue brush color */
+        let smeared = canvas.layers().active().pixel(20, 5).unwrap();
+        assert!(smeared.r == 255 && smeared.g < 200 && smeared.b == smeared.g);
+        assert_eq!(
+            canvas.layers().active().pixel(50, 5),
+            Some(Color::new(255, 255, 255, 255))
+        );
+        assert!(canvas.undo());
+        assert_eq!(
+            canvas.layers().active().pixel(20, 5),
+            Some(Color::new(255, 255, 255, 255))
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use super::stroke::StrokePositionalData;
+
+/// Filters raw pointer samples before dabs are placed, to steady shaky hands
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "camelCase")]
+pub enum Stabilizer {
+    /// Samples are used as they arrive
+    #[default]
+    None,
+    /// Averages the last `samples` pointer positions, newer samples weigh more
+    MovingAverage { samples: usize },
+    /// The brush trails the pointer on a string of `radius` canvas pixels and only
+    /// moves once the string is pulled tight, also known as a lazy nuzzle
+    PulledString { radius: f32 },
+}
+
+/// How the path between two stabilized samples is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Interpolation {
+    /// Straight lines between samples
+    #[default]
+    Linear,
+    /// Uniform Catmull-Rom spline through the samples
+    CatmullRom,
+    /// Centripetal Catmull-Rom spline, which avoids loops and cusps where
+    /// samples are unevenly spaced
+    Centripetal,
+}
+
+impl Interpolation {
+    /// Knot parameterization of the Catmull-Rom variants, `None` for straight lines
+    pub(crate) fn alpha(self) -> Option<f32> {
+        match self {
+            Interpolation::Linear => None,
+            Interpolation::CatmullRom => Some(0.0),
+            Interpolation::Centripetal => Some(0.5),
+        }
+    }
+}
+
+/// Stabilizer state of the stroke in progress
+pub(crate) struct StabilizerState {
+    settings: Stabilizer,
+    samples: VecDeque<StrokePositionalData>,
+}
+
+impl StabilizerState {
+    pub fn new(settings: Stabilizer) -> Self {
+        Self {
+            settings,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Takes the next raw sample, returning the stabilized one or `None` if the
+    /// brush should not move yet
+    pub fn push(&mut self, point: StrokePositionalData) -> Option<StrokePositionalData> {
+        match self.settings {
+            Stabilizer::None => Some(point),
+            Stabilizer::MovingAverage { samples } => {
+                self.samples.push_back(point);
+                while self.samples.len() > samples.max(1) {
+                    self.samples.pop_front();
+                }
+                Some(self.average())
+            }
+            Stabilizer::PulledString { radius } => {
+                let Some(brush) = self.samples.back_mut() else {
+                    self.samples.push_back(point.clone());
+                    return Some(point);
+                };
+
+                let position = Vec2::new(brush.x, brush.y);
+                let pointer = Vec2::new(point.x, point.y);
+                let length = position.distance(pointer);
+                if length <= radius {
+                    return None;
+                }
+
+                let moved = pointer + (position - pointer) / length * radius;
+                *brush = StrokePositionalData {
+                    x: moved.x,
+                    y: moved.y,
+                    ..point
+                };
+                Some(brush.clone())
+            }
+        }
+    }
+
+    /// Catches the brush up with the final pointer position, returning the
+    /// remaining stabilized samples, the last one always lies on `point`
+    pub fn finish(&mut self, point: StrokePositionalData) -> Vec<StrokePositionalData> {
+        match self.settings {
+            Stabilizer::MovingAverage { samples } => {
+                /* feeding the final position again until it fills the window eases into it */
+                let mut points: Vec<_> = (0..samples.max(1) - 1)
+                    .map(|_| {
+                        self.samples.push_back(point.clone());
+                        while self.samples.len() > samples.max(1) {
+                            self.samples.pop_front();
+                        }
+                        self.average()
+                    })
+                    .collect();
+                points.push(point);
+                points
+            }
+            Stabilizer::None | Stabilizer::PulledString { .. } => vec![point],
+        }
+    }
+
+    /// Weighted average of the kept samples, the `n`th oldest sample has weight `n`
+    fn average(&self) -> StrokePositionalData {
+        let mut total = 0.0;
+        let (mut x, mut y, mut pressure) = (0.0, 0.0, 0.0);
+        let (mut tilt_x, mut tilt_y) = (0.0, 0.0);
+        for (index, sample) in self.samples.iter().enumerate() {
+            let weight = (index + 1) as f32;
+            x += sample.x * weight;
+            y += sample.y * weight;
+            pressure += sample.pressure * weight;
+            tilt_x += sample.tilt_x * weight;
+            tilt_y += sample.tilt_y * weight;
+            total += weight;
+        }
+
+        let newest = self.samples.back().expect("average of an empty window");
+        StrokePositionalData {
+            x: x / total,
+            y: y / total,
+            pressure: pressure / total,
+            tilt_x: tilt_x / total,
+            tilt_y: tilt_y / total,
+            time: newest.time,
+            color: newest.color,
+        }
+    }
+}
+
+/// Point at `t` in `0.0..=1.0` on the Catmull-Rom segment between `points[1]` and
+/// `points[2]`, `alpha` is 0 for the uniform and 0.5 for the centripetal variant
+pub(crate) fn catmull_rom(points: [Vec2; 4], alpha: f32, t: f32) -> Vec2 {
+    let [p0, p1, p2, p3] = points;
+    /* coincident samples would give zero length knot intervals */
+    let knot = |a: Vec2, b: Vec2, t: f32| t + a.distance(b).powf(alpha).max(1e-4);
+    let t0 = 0.0;
+    let t1 = knot(p0, p1, t0);
+    let t2 = knot(p1, p2, t1);
+    let t3 = knot(p2, p3, t2);
+    let t = t1 + (t2 - t1) * t;
+
+    let a1 = p0 * (t1 - t) / (t1 - t0) + p1 * (t - t0) / (t1 - t0);
+    let a2 = p1 * (t2 - t) / (t2 - t1) + p2 * (t - t1) / (t2 - t1);
+    let a3 = p2 * (t3 - t) / (t3 - t2) + p3 * (t - t2) / (t3 - t2);
+    let b1 = a1 * (t2 - t) / (t2 - t0) + a2 * (t - t0) / (t2 - t0);
+    let b2 = a2 * (t3 - t) / (t3 - t1) + a3 * (t - t1) / (t3 - t1);
+    b1 * (t2 - t) / (t2 - t1) + b2 * (t - t1) / (t2 - t1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    fn sample(x: f32, y: f32) -> StrokePositionalData {
+        StrokePositionalData {
+            x,
+            y,
+            pressure: 1.0,
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            time: 0.0,
+            color: Color::new(0, 0, 0, 255),
+        }
+    }
+
+    #[test]
+    fn moving_average_catches_up_on_finish() {
+        let mut state = StabilizerState::new(Stabilizer::MovingAverage { samples: 4 });
+        state.push(sample(0.0, 0.0));
+        let lagging = state.push(sample(30.0, 0.0)).unwrap();
+        assert_eq!(lagging.x, 20.0);
+
+        let rest = state.finish(sample(30.0, 0.0));
+        assert_eq!(rest.len(), 4);
+        assert!(rest.windows(2).all(|w| w[0].x <= w[1].x));
+        assert_eq!(rest.last().unwrap().x, 30.0);
+    }
+
+    #[test]
+    fn pulled_string_only_moves_when_tight() {
+        let mut state = StabilizerState::new(Stabilizer::PulledString { radius: 10.0 });
+        assert!(state.push(sample(0.0, 0.0)).is_some());
+        assert!(state.push(sample(6.0, 8.0)).is_none());
+
+        let moved = state.push(sample(0.0, 25.0)).unwrap();
+        assert!((moved.y - 15.0).abs() < 1e-4 && moved.x.abs() < 1e-4);
+        assert_eq!(state.finish(sample(0.0, 25.0))[0].y, 25.0);
+    }
+
+    #[test]
+    fn splines_pass_through_their_samples() {
+        let points = [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(0.0, 0.0),
+            Vec2::new(10.0, 5.0),
+            Vec2::new(40.0, 5.0),
+        ];
+        for alpha in [0.0, 0.5] {
+            assert!(catmull_rom(points, alpha, 0.0).distance(points[1]) < 1e-3);
+            assert!(catmull_rom(points, alpha, 1.0).distance(points[2]) < 1e-3);
+            assert!(catmull_rom(points, alpha, 0.5).is_finite());
+        }
+    }
+}
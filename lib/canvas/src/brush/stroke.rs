@@ -1,12 +1,25 @@
 use glam::Vec2;
 
+use super::stabilizer::{Interpolation, Stabilizer, StabilizerState, catmull_rom};
+use super::{BrushEngine, BrushSettings, PressureDynamics};
+use crate::color::Channel;
+use crate::view::ViewTransform;
 use crate::{Canvas, Color};
 
+/// Smallest distance in canvas pixels between two dabs, however small they get
+const MIN_SPACING: f32 = 1.0;
+
 #[derive(Debug)]
 pub struct StrokePositionalData {
     pub x: f32,
     pub y: f32,
     pub pressure: f32,
+    /// Tilt of the pen in degrees towards positive x and y, `0.0` when upright or unknown
+    pub tilt_x: f32,
+    pub tilt_y: f32,
+    /// Time of the sample in seconds, `0.0` on every sample when unknown. Kept at double
+    /// precision as the clock can have been running for days
+    pub time: f64,
     pub color: Color<u8>,
 }
 
@@ -16,73 +29,161 @@ impl Clone for StrokePositionalData {
             x: self.x,
             y: self.y,
             pressure: self.pressure,
+            tilt_x: self.tilt_x,
+            tilt_y: self.tilt_y,
+            time: self.time,
             color: self.color,
         }
     }
 }
 
-pub struct StrokeManager {
+impl StrokePositionalData {
+    /// The sample moved from screen into canvas coordinates. The pen leans towards a
+    /// direction on screen, so its tilt turns and mirrors with the view as well
+    fn into_canvas(self, view: &ViewTransform) -> Self {
+        let (x, y) = view.screen_to_canvas(self.x, self.y);
+        /* the lean as a direction whose length is the tangent of the tilt, so turning it
+         * keeps how far the pen leans over */
+        let (lean_x, lean_y) = view.screen_to_canvas_direction(
+            self.tilt_x.to_radians().tan(),
+            self.tilt_y.to_radians().tan(),
+        );
+        Self {
+            x,
+            y,
+            tilt_x: lean_x.atan().to_degrees(),
+            tilt_y: lean_y.atan().to_degrees(),
+            ..self
+        }
+    }
+
+    /// A sample at `x`, `y` with pressure, tilt and time `t` of the way from `self` to
+    /// `to`, taking the color of `to`
+    fn towards(&self, to: &Self, t: f32, x: f32, y: f32) -> Self {
+        let lerp = |from: f32, to: f32| from + (to - from) * t;
+        Self {
+            x,
+            y,
+            pressure: lerp(self.pressure, to.pressure),
+            tilt_x: lerp(self.tilt_x, to.tilt_x),
+            tilt_y: lerp(self.tilt_y, to.tilt_y),
+            time: self.time + (to.time - self.time) * t as f64,
+            color: to.color,
+        }
+    }
+}
+
+pub struct StrokeManager<T: Channel = u8> {
     current_stroke: Option<Stroke>,
+    engine: Box<dyn BrushEngine<T>>,
+    brush: BrushSettings,
+    stabilizer: Stabilizer,
+    interpolation: Interpolation,
+    stabilizer_state: StabilizerState,
+}
+
+impl<T: Channel> Default for StrokeManager<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Really basic for now, but in the future this should handle
-/// bounding boxes for strokes and handle undo redo states and such
-impl StrokeManager {
+/// bounding boxes for strokes and such.
+/// Strokes are always drawn onto the active layer of the canvas, each one
+/// being recorded as a single step in the canvas history
+impl<T: Channel> StrokeManager<T> {
     pub fn new() -> Self {
+        let brush = BrushSettings::default();
         Self {
             current_stroke: None,
+            engine: brush.engine(),
+            brush,
+            stabilizer: Stabilizer::None,
+            interpolation: Interpolation::Linear,
+            stabilizer_state: StabilizerState::new(Stabilizer::None),
         }
     }
-    /* Temporary, this type of stuff should be seperated in a Brush engine object */
-    fn draw_circle(size: usize, point: &StrokePositionalData, canvas: &mut Canvas) {
-        let color = point.color;
-        let radius = (size / 2) as isize;
-        let cx = point.x as isize;
-        let cy = point.y as isize;
-
-        let r2 = radius * radius;
-
-        for y in (cy - radius)..=(cy + radius) {
-            for x in (cx - radius)..=(cx + radius) {
-                let dx = x - cx;
-                let dy = y - cy;
-
-                if dx * dx + dy * dy <= r2 {
-                    // Bounds check before casting
-                    if x >= 0 && y >= 0 {
-                        canvas.draw_pixel(
-                            x as usize,
-                            y as usize,
-                            Color {
-                                r: color.r,
-                                g: color.g,
-                                b: color.b,
-                                a: color.a,
-                            },
-                        );
-                    }
-                }
-            }
+    /// A stroke manager for another working format with the same brush and smoothing.
+    /// A stroke in progress is dropped and an engine set through
+    /// [`StrokeManager::set_engine`] is replaced by the built in one of the brush
+    pub fn convert<U: Channel>(self) -> StrokeManager<U> {
+        let mut manager = StrokeManager::new();
+        manager.set_brush(self.brush);
+        manager.set_smoothing(self.stabilizer, self.interpolation);
+        manager
+    }
+
+    /// Replaces the brush engine used for the following strokes, [`StrokeManager::brush`]
+    /// keeps reporting the last settings passed to [`StrokeManager::set_brush`]
+    pub fn set_engine(&mut self, engine: Box<dyn BrushEngine<T>>) {
+        self.engine = engine;
+    }
+
+    /// Switches to one of the built in engines configured with `brush`
+    pub fn set_brush(&mut self, brush: BrushSettings) {
+        self.engine = brush.engine();
+        self.brush = brush;
+    }
+
+    /// Settings of the active built in brush
+    pub fn brush(&self) -> &BrushSettings {
+        &self.brush
+    }
+
+    /// Changes how pressure affects the active brush, brushes without pressure dynamics
+    /// are left as they are
+    pub fn set_dynamics(&mut self, dynamics: PressureDynamics) {
+        let mut brush = self.brush.clone();
+        if let Some(current) = brush.dynamics_mut() {
+            *current = dynamics;
+            self.set_brush(brush);
         }
     }
 
+    /// Sets how pointer samples are stabilized and interpolated, applied from the next stroke on
+    pub fn set_smoothing(&mut self, stabilizer: Stabilizer, interpolation: Interpolation) {
+        self.stabilizer = stabilizer;
+        self.interpolation = interpolation;
+    }
+
+    pub fn stabilizer(&self) -> &Stabilizer {
+        &self.stabilizer
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    pub fn engine(&self) -> &dyn BrushEngine<T> {
+        self.engine.as_ref()
+    }
+
+    pub fn engine_mut(&mut self) -> &mut dyn BrushEngine<T> {
+        self.engine.as_mut()
+    }
+
     /// Begin recording positional data for current stroke
-    pub fn begin_stroke(&mut self, point: StrokePositionalData, canvas: &mut Canvas) {
-        let mut new_stroke = Stroke::new();
+    pub fn begin_stroke(&mut self, point: StrokePositionalData, canvas: &mut Canvas<T>) {
+        let mut new_stroke = Stroke::new(self.engine.spacing(), self.interpolation);
+        self.stabilizer_state = StabilizerState::new(self.stabilizer.clone());
+        canvas.begin_paint();
+        self.engine.begin_stroke();
 
         println!("new stroke began at (x: {}, y: {})", point.x, point.y);
-        let (x, y) = canvas.translate_screen_to_canvas(point.x, point.y);
-        let point = StrokePositionalData { x, y, ..point };
-
-        Self::draw_circle(12, &point, canvas);
+        let point = point.into_canvas(canvas.view());
 
-        new_stroke.add_point(point);
+        if let Some(point) = self.stabilizer_state.push(point) {
+            for p in new_stroke.add_point(point, |pressure| self.engine.dab_size(pressure)) {
+                self.engine.dab(p, canvas);
+            }
+        }
 
         self.current_stroke = Some(new_stroke);
     }
 
     /// Add to the positional data
-    pub fn continue_stroke(&mut self, point: StrokePositionalData, canvas: &mut Canvas) {
+    pub fn continue_stroke(&mut self, point: StrokePositionalData, canvas: &mut Canvas<T>) {
         let Some(stroke) = &mut self.current_stroke else {
             eprintln!("Stroke not initialized");
             return;
@@ -90,32 +191,34 @@ impl StrokeManager {
 
         println!("continued stroke: (x: {}, y: {})", point.x, point.y);
 
-        let (x, y) = canvas.translate_screen_to_canvas(point.x, point.y);
-        let point = StrokePositionalData { x, y, ..point };
-        let points = stroke.add_point(point);
+        let point = point.into_canvas(canvas.view());
+        let Some(point) = self.stabilizer_state.push(point) else {
+            return;
+        };
+        let points = stroke.add_point(point, |pressure| self.engine.dab_size(pressure));
         println!("points: {points:?}");
 
-        for p in points.into_iter() {
-            Self::draw_circle(12, &p, canvas);
+        for p in points {
+            self.engine.dab(p, canvas);
         }
     }
 
-    pub fn end_stroke(&mut self, point: StrokePositionalData, canvas: &mut Canvas) {
+    pub fn end_stroke(&mut self, point: StrokePositionalData, canvas: &mut Canvas<T>) {
         println!("ended stroke: (x: {}, y: {})", point.x, point.y);
 
-        let (x, y) = canvas.translate_screen_to_canvas(point.x, point.y);
-        let point = StrokePositionalData { x, y, ..point };
-        // canvas.draw_pixel(
-        //     x as usize,
-        //     y as usize,
-        //     Color {
-        //         r: 0,
-        //         g: 0,
-        //         b: 0,
-        //         a: 0,
-        //     },
-        // );
-        Self::draw_circle(12, &point, canvas);
+        let point = point.into_canvas(canvas.view());
+        if let Some(stroke) = &mut self.current_stroke {
+            for point in self.stabilizer_state.finish(point) {
+                for p in stroke.add_point(point, |pressure| self.engine.dab_size(pressure)) {
+                    self.engine.dab(p, canvas);
+                }
+            }
+            for p in stroke.finish(|pressure| self.engine.dab_size(pressure)) {
+                self.engine.dab(p, canvas);
+            }
+        }
+        self.engine.end_stroke(canvas);
+        canvas.end_paint();
         self.current_stroke = None;
     }
 }
@@ -123,51 +226,285 @@ impl StrokeManager {
 /// Representation of a brush stroke, storing all the raw input data that is received
 struct Stroke {
     position_data: Vec<StrokePositionalData>,
-    accumulated_distance: f32,
+    /// the last few samples, kept as control points for spline interpolation
+    samples: Vec<StrokePositionalData>,
+    /// distance left along the path until the next dab should be placed
+    distance_to_next: f32,
+    /// distance between dabs as a fraction of the size of the last dab
     spacing: f32,
+    interpolation: Interpolation,
 }
 
 impl Stroke {
-    pub fn new() -> Self {
+    pub fn new(spacing: f32, interpolation: Interpolation) -> Self {
         Self {
             position_data: vec![],
-            accumulated_distance: 0.0,
-            spacing: 5.0,
+            samples: vec![],
+            distance_to_next: 0.0,
+            spacing: spacing.max(0.01),
+            interpolation,
         }
     }
-    /// adds a new location point data to the end of the internally stored vec
-    pub fn add_point(&mut self, point: StrokePositionalData) -> &[StrokePositionalData] {
-        let last_point = self.position_data.last();
+
+    /// adds a new stabilized sample, returning the dabs placed along the path from the
+    /// previous sample. Position and pressure are both interpolated between the two
+    /// samples, `size` gives the dab size at a pressure to space the dabs by.
+    /// Splines need the sample after a segment to shape it, so they lag one sample
+    /// behind until [`Stroke::finish`]
+    pub fn add_point(
+        &mut self,
+        point: StrokePositionalData,
+        size: impl Fn(f32) -> f32,
+    ) -> &[StrokePositionalData] {
         let last_index = self.position_data.len();
-        let Some(last_point) = last_point else {
+        if self.samples.is_empty() {
+            self.distance_to_next = self.step(point.pressure, &size);
+            self.position_data.push(point.clone());
+            self.samples.push(point);
+            return &self.position_data[last_index..];
+        }
+
+        self.samples.push(point);
+        if self.samples.len() > 4 {
+            self.samples.remove(0);
+        }
+
+        match self.interpolation.alpha() {
+            None => {
+                let count = self.samples.len();
+                let from = self.samples[count - 2].clone();
+                let to = self.samples[count - 1].clone();
+                self.walk(&from, &to, &size);
+            }
+            Some(alpha) => {
+                let count = self.samples.len();
+                if count >= 3 {
+                    let before = &self.samples[count.saturating_sub(4)..count - 2];
+                    let start = before.first().unwrap().clone();
+                    let from = before.last().unwrap().clone();
+                    let to = self.samples[count - 2].clone();
+                    let after = self.samples[count - 1].clone();
+                    self.walk_spline([&start, &from, &to, &after], alpha, &size);
+                }
+            }
+        }
+
+        &self.position_data[last_index..]
+    }
+
+    /// Draws the segment splines are still holding back, returning its dabs
+    pub fn finish(&mut self, size: impl Fn(f32) -> f32) -> &[StrokePositionalData] {
+        let last_index = self.position_data.len();
+        if let Some(alpha) = self.interpolation.alpha() {
+            let count = self.samples.len();
+            if count >= 2 {
+                let start = self.samples[count.saturating_sub(3)].clone();
+                let from = self.samples[count - 2].clone();
+                let to = self.samples[count - 1].clone();
+                self.walk_spline([&start, &from, &to, &to], alpha, &size);
+            }
+        }
+        &self.position_data[last_index..]
+    }
+
+    /// Places dabs along the spline segment between `points[1]` and `points[2]`,
+    /// following the curve in short straight pieces
+    fn walk_spline(
+        &mut self,
+        points: [&StrokePositionalData; 4],
+        alpha: f32,
+        size: &impl Fn(f32) -> f32,
+    ) {
+        let controls = points.map(|p| Vec2::new(p.x, p.y));
+        let [_, from, to, _] = points;
+        let pieces = (controls[1].distance(controls[2]) / 2.0).ceil().max(1.0) as usize;
+
+        let mut previous = from.clone();
+        for piece in 1..=pieces {
+            let t = piece as f32 / pieces as f32;
+            let position = catmull_rom(controls, alpha, t);
+            let next = from.towards(to, t, position.x, position.y);
+            self.walk(&previous, &next, size);
+            previous = next;
+        }
+    }
+
+    /// Places dabs along the straight line between two points, each one the spacing
+    /// times its size after the previous one. The leftover distance carries over to the
+    /// next line
+    fn walk(
+        &mut self,
+        from: &StrokePositionalData,
+        to: &StrokePositionalData,
+        size: &impl Fn(f32) -> f32,
+    ) {
+        let start = Vec2::new(from.x, from.y);
+        let end = Vec2::new(to.x, to.y);
+        let distance = start.distance(end);
+
+        let mut travelled = self.distance_to_next;
+        while travelled <= distance {
+            let t = travelled / distance;
+            let position = start.lerp(end, t);
+            let point = from.towards(to, t, position.x, position.y);
+            travelled += self.step(point.pressure, size);
             self.position_data.push(point);
-            return &self.position_data[..];
-        };
-        let from = Vec2::new(last_point.x, last_point.y);
-        let to = Vec2::new(point.x, point.y);
-        let distance = from.distance(to);
+        }
+        self.distance_to_next = travelled - distance;
+    }
+
+    /// Distance in canvas pixels to the next dab after one drawn at `pressure`
+    fn step(&self, pressure: f32, size: &impl Fn(f32) -> f32) -> f32 {
+        (self.spacing * size(pressure)).max(MIN_SPACING)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Records the dabs of a stroke instead of drawing them. Its dabs are `size` wide at
+    /// any pressure
+    struct Recorder {
+        spacing: f32,
+        size: f32,
+        dabs: Arc<Mutex<Vec<StrokePositionalData>>>,
+    }
+
+    impl BrushEngine for Recorder {
+        fn spacing(&self) -> f32 {
+            self.spacing
+        }
 
-        self.accumulated_distance += distance;
+        fn dab_size(&self, _pressure: f32) -> f32 {
+            self.size
+        }
 
-        if self.accumulated_distance < self.spacing {
-            return &[];
+        fn dab(&mut self, point: &StrokePositionalData, _canvas: &mut Canvas) {
+            self.dabs.lock().unwrap().push(point.clone());
         }
+    }
 
-        let steps = (self.accumulated_distance / self.spacing).floor() as usize;
+    fn sample(x: f32, y: f32, pressure: f32) -> StrokePositionalData {
+        StrokePositionalData {
+            x,
+            y,
+            pressure,
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            time: 0.0,
+            color: Color::new(0, 0, 0, 255),
+        }
+    }
 
-        for i in 0..steps {
-            let from = from.lerp(to, i as f32 / steps as f32);
+    /// Draws a stroke through `samples` with a [`Recorder`], returning its dabs
+    fn record(
+        manager: &mut StrokeManager,
+        spacing: f32,
+        size: f32,
+        samples: &[StrokePositionalData],
+    ) -> Vec<StrokePositionalData> {
+        let dabs = Arc::new(Mutex::new(vec![]));
+        manager.set_engine(Box::new(Recorder {
+            spacing,
+            size,
+            dabs: dabs.clone(),
+        }));
 
-            let positional_data = StrokePositionalData {
-                x: from.x,
-                y: from.y,
-                pressure: point.pressure,
-                color: point.color,
-            };
-            self.position_data.push(positional_data);
-            self.accumulated_distance -= self.spacing;
+        let mut canvas: Canvas = Canvas::new(200, 200);
+        let (first, rest) = samples.split_first().unwrap();
+        let (last, middle) = rest.split_last().unwrap();
+        manager.begin_stroke(first.clone(), &mut canvas);
+        for sample in middle {
+            manager.continue_stroke(sample.clone(), &mut canvas);
         }
+        manager.end_stroke(last.clone(), &mut canvas);
+
+        dabs.lock().unwrap().clone()
+    }
 
-        return &self.position_data[last_index..];
+    #[test]
+    fn dabs_are_spaced_by_a_fraction_of_their_size() {
+        let samples = [
+            sample(0.0, 10.0, 1.0),
+            sample(50.0, 10.0, 1.0),
+            sample(100.0, 10.0, 1.0),
+        ];
+        for (spacing, size, count) in [(0.25, 8.0, 51), (0.5, 8.0, 26), (0.1, 40.0, 26)] {
+            let dabs = record(&mut StrokeManager::new(), spacing, size, &samples);
+            assert_eq!(dabs.len(), count, "spacing {spacing} of {size}");
+            for pair in dabs.windows(2) {
+                assert!((pair[1].x - pair[0].x - spacing * size).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn pressure_is_interpolated_between_samples() {
+        let samples = [sample(0.0, 10.0, 0.0), sample(100.0, 10.0, 1.0)];
+        let dabs = record(&mut StrokeManager::new(), 0.5, 20.0, &samples);
+
+        assert_eq!(dabs.len(), 11);
+        for dab in dabs {
+            assert!((dab.pressure - dab.x / 100.0).abs() < 1e-5, "{dab:?}");
+        }
+    }
+
+    #[test]
+    fn tiny_dabs_are_spaced_by_at_least_a_pixel() {
+        let samples = [sample(0.0, 10.0, 1.0), sample(20.0, 10.0, 1.0)];
+        for size in [0.0, 0.1] {
+            let dabs = record(&mut StrokeManager::new(), 0.1, size, &samples);
+            assert_eq!(dabs.len(), 21, "size {size}");
+            for pair in dabs.windows(2) {
+                assert!((pair[1].x - pair[0].x - MIN_SPACING).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn smoothed_strokes_end_on_the_pen() {
+        let samples: Vec<_> = [(10.0, 10.0), (40.0, 60.0), (80.0, 20.0), (120.0, 70.0)]
+            .into_iter()
+            .map(|(x, y)| sample(x, y, 1.0))
+            .collect();
+        let end = samples.last().unwrap();
+
+        for stabilizer in [
+            Stabilizer::None,
+            Stabilizer::MovingAverage { samples: 5 },
+            Stabilizer::PulledString { radius: 15.0 },
+        ] {
+            for interpolation in [
+                Interpolation::Linear,
+                Interpolation::CatmullRom,
+                Interpolation::Centripetal,
+            ] {
+                let mut manager = StrokeManager::new();
+                manager.set_smoothing(stabilizer.clone(), interpolation);
+                /* dabs a pixel apart, so the last one is at most a pixel short of the end */
+                let dabs = record(&mut manager, 0.1, 0.0, &samples);
+                let last = dabs.last().unwrap();
+                let gap = Vec2::new(last.x, last.y).distance(Vec2::new(end.x, end.y));
+                assert!(
+                    gap < MIN_SPACING,
+                    "{stabilizer:?} {interpolation:?} ended {gap} short"
+                );
+
+                /* without a stabilizer the splines go through every sample */
+                if stabilizer == Stabilizer::None {
+                    for sample in &samples {
+                        let closest = dabs
+                            .iter()
+                            .map(|dab| {
+                                Vec2::new(dab.x, dab.y).distance(Vec2::new(sample.x, sample.y))
+                            })
+                            .fold(f32::INFINITY, f32::min);
+                        assert!(closest < MIN_SPACING, "{interpolation:?} missed {sample:?}");
+                    }
+                }
+            }
+        }
     }
 }
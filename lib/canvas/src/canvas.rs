@@ -1,65 +1,115 @@
-use glam::{self, Vec2, Vec3};
-
 use crate::Color;
+use crate::blend::BlendMode;
+use crate::color::{Channel, PixelFormat};
+use crate::dirty::DirtyRegion;
+use crate::fill::{self, FillOptions};
+use crate::filter::{Filter, FilterSession};
+use crate::history::{Command, History};
+use crate::layer::{Layer, LayerStack};
+use crate::selection::{Selection, SelectionMode};
+use crate::tile;
+use crate::transform::{Resampling, Transform, TransformSession};
+use crate::view::ViewTransform;
 
-pub struct Canvas {
+/// A stack of layers painted on in the working format `T`, see [`Channel`]
+pub struct Canvas<T: Channel = u8> {
     width: usize,
     height: usize,
-    pixels: Vec<u8>,
-    zoom: f32,
-    rotation: f32,
-    offset: Point,
+    layers: LayerStack<T>,
+    history: History<T>,
+    dirty: DirtyRegion,
+    view: ViewTransform,
+    selection: Option<Selection>,
+    /// bumped on every selection change so the renderer knows when to rebuild its outline
+    selection_revision: u64,
+    transform: Option<TransformSession<T>>,
+    filter: Option<FilterSession>,
 }
 
-impl Default for Canvas {
+impl<T: Channel> Default for Canvas<T> {
     fn default() -> Self {
         const WIDTH: usize = 500;
         const HEIGHT: usize = 500;
 
-        let size = 500 * 500 * 4;
-        let pattern = [97, 152, 219, 255];
-
-        let pixels: Vec<u8> = pattern.iter().cycle().take(size).cloned().collect();
-        let zoom = 1.0;
-        let rotation = 0.0;
-        let offset = Point { x: 0.0, y: 0.0 };
+        let background = Layer::filled("Background", WIDTH, HEIGHT, Color::new(97, 152, 219, 255));
+        let layers = LayerStack::new(background);
 
         Self {
             width: WIDTH,
             height: HEIGHT,
-            pixels,
-            zoom,
-            rotation,
-            offset,
+            layers,
+            history: History::default(),
+            selection: None,
+            selection_revision: 0,
+            transform: None,
+            filter: None,
+            dirty: DirtyRegion::full(WIDTH, HEIGHT),
+            view: ViewTransform::new(WIDTH, HEIGHT),
         }
     }
 }
 
-impl Canvas {
+impl<T: Channel> Canvas<T> {
     /// Creates a new canvas with specified width and height
     pub fn new(width: usize, height: usize) -> Self {
-        // let pattern = [97, 152, 219, 255];
-        let pattern = [255, 255, 255, 255];
-        let pixels: Vec<u8> = pattern
-            .iter()
-            .cycle()
-            .take(width * height * 4)
-            .cloned()
-            .collect();
-        let zoom = 1.0;
-        let rotation = 0.0;
-        let offset = Point { x: 0.0, y: 0.0 };
+        let background = Layer::filled("Background", width, height, Color::new(255, 255, 255, 255));
+        let layers = LayerStack::new(background);
 
         Self {
             width,
             height,
-            pixels,
-            zoom,
-            rotation,
-            offset,
+            layers,
+            history: History::default(),
+            selection: None,
+            selection_revision: 0,
+            transform: None,
+            filter: None,
+            dirty: DirtyRegion::full(width, height),
+            view: ViewTransform::new(width, height),
         }
     }
 
+    /// Creates a canvas the size of `layer`, holding it as its only layer
+    pub fn from_layer(layer: Layer<T>) -> Self {
+        Self::from_layers(LayerStack::new(layer))
+    }
+
+    /// Creates a canvas the size of the layers in `layers`
+    pub fn from_layers(layers: LayerStack<T>) -> Self {
+        let (width, height) = layers.get(0).map_or((0, 0), |l| (l.width(), l.height()));
+
+        Self {
+            width,
+            height,
+            layers,
+            history: History::default(),
+            selection: None,
+            selection_revision: 0,
+            transform: None,
+            filter: None,
+            dirty: DirtyRegion::full(width, height),
+            view: ViewTransform::new(width, height),
+        }
+    }
+
+    /// Converts the canvas into another working format. The history can't be carried
+    /// over and is dropped, a transform or filter in progress is cancelled first
+    pub fn convert<U: Channel>(mut self) -> Canvas<U> {
+        self.cancel_transform();
+        self.cancel_filter();
+
+        let mut canvas = Canvas::from_layers(self.layers.convert());
+        canvas.view = self.view;
+        canvas.selection = self.selection;
+        canvas.selection_revision = self.selection_revision;
+        canvas
+    }
+
+    /// The working format of the layer pixels
+    pub fn format(&self) -> PixelFormat {
+        T::FORMAT
+    }
+
     pub fn width(&self) -> usize {
         self.width
     }
@@ -69,90 +119,650 @@ impl Canvas {
     }
 
     pub fn zoom(&self) -> f32 {
-        self.zoom
+        self.view.zoom()
     }
 
     pub fn rotation(&self) -> f32 {
-        self.rotation
+        self.view.rotation()
+    }
+
+    /// Sets the view rotation in radians, turning around the canvas centre
+    pub fn set_rotation(&mut self, rotation: f32) {
+        self.view.set_rotation(rotation);
+    }
+
+    pub fn offset(&self) -> &Point {
+        self.view.offset()
+    }
+
+    /// How the canvas is placed on screen
+    pub fn view(&self) -> &ViewTransform {
+        &self.view
+    }
+
+    pub fn view_mut(&mut self) -> &mut ViewTransform {
+        &mut self.view
+    }
+
+    pub fn layers(&self) -> &LayerStack<T> {
+        &self.layers
+    }
+
+    /// Direct access to the layer stack, changes made through here are not recorded in the history.
+    /// The whole canvas is marked dirty since anything may change
+    pub fn layers_mut(&mut self) -> &mut LayerStack<T> {
+        self.dirty.mark_all();
+        &mut self.layers
+    }
+
+    pub fn history(&self) -> &History<T> {
+        &self.history
+    }
+
+    /// Areas changed since the renderer last took them
+    pub fn dirty(&self) -> &DirtyRegion {
+        &self.dirty
+    }
+
+    /// Marks an area as changed, so the renderer uploads it on the next redraw
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        self.dirty.mark(rect);
+    }
+
+    /// Marks the whole canvas as changed, e.g. when it is attached to a new renderer
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty.mark_all();
+    }
+
+    /// Hands out the areas changed since the last call, each rect lies inside a single tile
+    pub fn take_dirty_rects(&mut self) -> Vec<Rect> {
+        self.dirty.take()
+    }
+
+    /// Adds a new layer above the active one, returning its index
+    pub fn add_layer(&mut self) -> usize {
+        self.commit_previews();
+        self.history.end_paint();
+        let index = self.layers.add_layer();
+        self.dirty.mark_all();
+        self.history.push(Command::AddLayer { index, layer: None });
+        index
+    }
+
+    /// Removes a layer, the last remaining layer can not be removed
+    pub fn remove_layer(&mut self, index: usize) -> bool {
+        self.commit_previews();
+        self.history.end_paint();
+        let Some(layer) = self.layers.remove(index) else {
+            return false;
+        };
+        self.dirty.mark_all();
+        self.history.push(Command::RemoveLayer {
+            index,
+            layer: Some(layer),
+        });
+        true
+    }
+
+    /// Moves a layer to a new position in the stack, returning the index it ended up at
+    pub fn move_layer(&mut self, from: usize, to: usize) -> Option<usize> {
+        self.commit_previews();
+        self.history.end_paint();
+        let to = self.layers.move_layer(from, to)?;
+        self.dirty.mark_all();
+        self.history.push(Command::MoveLayer { from, to });
+        Some(to)
+    }
+
+    /// Duplicates a layer, returning the index of the copy
+    pub fn duplicate_layer(&mut self, index: usize) -> Option<usize> {
+        self.commit_previews();
+        self.history.end_paint();
+        let index = self.layers.duplicate(index)?;
+        self.dirty.mark_all();
+        self.history.push(Command::AddLayer { index, layer: None });
+        Some(index)
+    }
+
+    /// Merges a layer into the one below it, returning the index of the merged layer
+    pub fn merge_down(&mut self, index: usize) -> Option<usize> {
+        self.commit_previews();
+        self.history.end_paint();
+        let lower = self.layers.get(index.checked_sub(1)?)?.clone();
+        let upper = self.layers.get(index)?.clone();
+        let merged = self.layers.merge_down(index)?;
+        self.dirty.mark_all();
+        self.history.push(Command::MergeDown {
+            index,
+            upper: Some(upper),
+            lower,
+        });
+        Some(merged)
+    }
+
+    /// The active selection, `None` when nothing is selected and painting affects the whole canvas
+    pub fn selection(&self) -> Option<&Selection> {
+        self.selection.as_ref()
+    }
+
+    /// Counter that changes whenever the selection does
+    pub fn selection_revision(&self) -> u64 {
+        self.selection_revision
+    }
+
+    /// How strongly painting affects a pixel, from `0.0` to `1.0`
+    pub fn selection_coverage(&self, x: usize, y: usize) -> f32 {
+        self.selection
+            .as_ref()
+            .map_or(1.0, |s| s.coverage(x, y) as f32 / 255.0)
+    }
+
+    /// Combines `selection` with the active one, a selection that ends up empty is dropped
+    pub fn select(&mut self, selection: Selection, mode: SelectionMode) {
+        if selection.width() != self.width || selection.height() != self.height {
+            return;
+        }
+
+        let combined = match (self.selection.take(), mode) {
+            (_, SelectionMode::Replace) | (None, SelectionMode::Add) => selection,
+            (None, SelectionMode::Subtract | SelectionMode::Intersect) => {
+                let mut all = Selection::all(self.width, self.height);
+                all.combine(&selection, mode);
+                all
+            }
+            (Some(mut current), mode) => {
+                current.combine(&selection, mode);
+                current
+            }
+        };
+        self.set_selection((!combined.is_empty()).then_some(combined));
+    }
+
+    pub fn select_all(&mut self) {
+        self.set_selection(Some(Selection::all(self.width, self.height)));
+    }
+
+    pub fn select_none(&mut self) {
+        self.set_selection(None);
+    }
+
+    /// Inverts the active selection, nothing happens without one
+    pub fn invert_selection(&mut self) {
+        self.modify_selection(Selection::invert);
+    }
+
+    pub fn feather_selection(&mut self, radius: usize) {
+        self.modify_selection(|s| s.feather(radius));
+    }
+
+    /// Grows the selection by `pixels`, negative values shrink it
+    pub fn grow_selection(&mut self, pixels: isize) {
+        let amount = pixels.unsigned_abs();
+        if pixels >= 0 {
+            self.modify_selection(|s| s.grow(amount));
+        } else {
+            self.modify_selection(|s| s.shrink(amount));
+        }
+    }
+
+    fn modify_selection(&mut self, change: impl FnOnce(&mut Selection)) {
+        let Some(mut selection) = self.selection.take() else {
+            return;
+        };
+        change(&mut selection);
+        self.set_selection((!selection.is_empty()).then_some(selection));
+    }
+
+    fn set_selection(&mut self, selection: Option<Selection>) {
+        self.selection = selection;
+        self.selection_revision += 1;
+    }
+
+    /// Starts recording pixel changes on the active layer as a single undo step
+    pub fn begin_paint(&mut self) {
+        self.commit_previews();
+        self.history.begin_paint(self.layers.active_index());
+    }
+
+    /// Finishes the undo step started by [`Canvas::begin_paint`]
+    pub fn end_paint(&mut self) {
+        self.history.end_paint();
+    }
+
+    /// Lifts the selected pixels of the active layer, or all of them without a selection,
+    /// to be transformed. Returns false if the layer is locked or has nothing to move
+    pub fn begin_transform(&mut self) -> bool {
+        self.cancel_transform();
+        self.cancel_filter();
+        self.history.end_paint();
+
+        let layer = self.layers.active();
+        if layer.locked() {
+            return false;
+        }
+        let content = match &self.selection {
+            Some(selection) => selection.bounds(),
+            None => layer.content_bounds(),
+        };
+        let Some(content) = content else {
+            return false;
+        };
+
+        self.transform = Some(TransformSession::new(
+            self.layers.active_index(),
+            layer.pixels(),
+            content,
+            self.selection.as_ref(),
+        ));
+        self.update_transform(Transform::identity(), Resampling::default());
+        true
+    }
+
+    /// Area of the canvas the pixels being transformed came from
+    pub fn transform_source(&self) -> Option<Rect> {
+        self.transform.as_ref().map(|t| t.floating.rect)
+    }
+
+    /// The transform currently previewed
+    pub fn current_transform(&self) -> Option<Transform> {
+        self.transform.as_ref().map(|t| t.transform)
+    }
+
+    /// Previews the lifted pixels with `transform` applied on the layer, the change is
+    /// not recorded until [`Canvas::commit_transform`]
+    pub fn update_transform(&mut self, transform: Transform, resampling: Resampling) {
+        let bounds = Rect::new(0, 0, self.width, self.height);
+        let Some(session) = &mut self.transform else {
+            return;
+        };
+        session.transform = transform;
+        session.resampling = resampling;
+
+        let previous = session.preview.take();
+        let area = session.destination(bounds);
+        let Some(layer) = self.layers.get_mut(session.layer) else {
+            return;
+        };
+        *layer.pixels_mut() = session.base.clone();
+        if let Some(area) = area {
+            let pixels = session.render(&session.base, area);
+            layer.pixels_mut().write_rect(area, &pixels);
+        }
+        session.preview = area;
+
+        /* the lifted pixels vanish from their source as soon as the transform starts */
+        let source = session.floating.rect;
+        for rect in [previous, area, Some(source)].into_iter().flatten() {
+            self.dirty.mark(rect);
+        }
+    }
+
+    /// Applies the transform as a single undo step, moving the selection along with it
+    pub fn commit_transform(&mut self) {
+        let Some(session) = self.transform.take() else {
+            return;
+        };
+        let Some(layer) = self.layers.get_mut(session.layer) else {
+            return;
+        };
+
+        /* the history snapshots tiles before they change, so the untouched pixels go back first */
+        let preview = std::mem::replace(layer.pixels_mut(), session.original.clone());
+        self.history.begin_paint(session.layer);
+        let changed = session
+            .preview
+            .map_or(session.floating.rect, |p| p.union(&session.floating.rect));
+        if let Some(layer) = self.layers.get(session.layer) {
+            self.history.record_paint(changed, layer);
+        }
+        if let Some(layer) = self.layers.get_mut(session.layer) {
+            *layer.pixels_mut() = preview;
+        }
+        self.history.end_paint();
+
+        if session.selection.is_some() {
+            let moved = session.transformed_selection();
+            self.set_selection(moved.filter(|s| !s.is_empty()));
+        }
+        self.dirty.mark(changed);
+    }
+
+    /// Drops the transform, putting the pixels back where they were
+    pub fn cancel_transform(&mut self) {
+        let Some(session) = self.transform.take() else {
+            return;
+        };
+        if let Some(layer) = self.layers.get_mut(session.layer) {
+            *layer.pixels_mut() = session.original;
+        }
+        let changed = session
+            .preview
+            .map_or(session.floating.rect, |p| p.union(&session.floating.rect));
+        self.dirty.mark(changed);
+    }
+
+    pub fn is_transforming(&self) -> bool {
+        self.transform.is_some()
+    }
+
+    /// Shows `filter` applied to the selection of the active layer, or the whole layer
+    /// without one. The change is not recorded until [`Canvas::commit_filter`].
+    /// Returns false if the layer is locked
+    pub fn preview_filter(&mut self, filter: &Filter) -> bool {
+        self.cancel_transform();
+        let active = self.layers.active_index();
+        if self.filter.as_ref().is_some_and(|s| s.layer != active) {
+            self.cancel_filter();
+        }
+
+        if self.filter.is_none() {
+            self.history.end_paint();
+            let layer = self.layers.active();
+            if layer.locked() {
+                return false;
+            }
+            let area = match &self.selection {
+                Some(selection) => selection.bounds(),
+                None => Some(layer.bounds()),
+            };
+            let Some(area) = area else {
+                return false;
+            };
+            self.filter = Some(FilterSession {
+                layer: active,
+                area,
+            });
+        }
+
+        let Some(session) = &self.filter else {
+            return false;
+        };
+        let Some(layer) = self.layers.get(session.layer) else {
+            return false;
+        };
+        let pixels = session.render(filter, layer, self.selection.as_ref());
+        let mut preview = layer.pixels().clone();
+        preview.write_rect(session.area, &pixels);
+        let area = session.area;
+        self.layers.set_preview(session.layer, preview);
+        self.dirty.mark(area);
+        true
+    }
+
+    /// Writes the previewed filter into the layer as a single undo step
+    pub fn commit_filter(&mut self) {
+        let Some(session) = self.filter.take() else {
+            return;
+        };
+        let Some(preview) = self.layers.take_preview() else {
+            return;
+        };
+
+        self.history.begin_paint(session.layer);
+        if let Some(layer) = self.layers.get(session.layer) {
+            self.history.record_paint(session.area, layer);
+        }
+        if let Some(layer) = self.layers.get_mut(session.layer) {
+            *layer.pixels_mut() = preview;
+        }
+        self.history.end_paint();
+        self.dirty.mark(session.area);
+    }
+
+    /// Drops the previewed filter, showing the layer as it was again
+    pub fn cancel_filter(&mut self) {
+        let Some(session) = self.filter.take() else {
+            return;
+        };
+        self.layers.take_preview();
+        self.dirty.mark(session.area);
+    }
+
+    /// Applies `filter` straight away as a single undo step
+    pub fn apply_filter(&mut self, filter: &Filter) -> bool {
+        let applied = self.preview_filter(filter);
+        self.commit_filter();
+        applied
+    }
+
+    pub fn is_filtering(&self) -> bool {
+        self.filter.is_some()
     }
 
-    pub fn pixels(&self) -> &Vec<u8> {
-        &self.pixels
+    /// Commits the transform or filter being previewed. Everything else that changes
+    /// the layers, and saving, calls this first so a preview never outlives the layer
+    /// it was started on
+    pub fn commit_previews(&mut self) {
+        self.commit_transform();
+        self.commit_filter();
+    }
+
+    pub fn undo(&mut self) -> bool {
+        self.cancel_transform();
+        self.cancel_filter();
+        self.history.undo(&mut self.layers, &mut self.dirty)
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.cancel_transform();
+        self.cancel_filter();
+        self.history.redo(&mut self.layers, &mut self.dirty)
+    }
+
+    /// Flattens the layer stack into a straight alpha sRGB RGBA8 buffer
+    pub fn composite(&self) -> Vec<u8> {
+        self.layers.composite()
+    }
+
+    /// Flattens the area `rect` of the layer stack into a tightly packed sRGB RGBA8 buffer
+    pub fn composite_rect(&self, rect: Rect) -> Vec<u8> {
+        self.layers.composite_rect(rect)
+    }
+
+    /// Flattens the area `rect` of the layer stack into tightly packed linear light RGBA,
+    /// for renderers and exports that keep more than 8 bits
+    pub fn composite_rect_linear(&self, rect: Rect) -> Vec<f32> {
+        self.layers.composite_rect_linear(rect)
+    }
+
+    /// Iterates the areas covered by each tile of the canvas, row by row
+    pub fn tile_rects(&self) -> impl Iterator<Item = Rect> + use<T> {
+        tile::tile_rects(self.width, self.height)
     }
 
     pub fn apply_offset(&mut self, dx: f32, dy: f32) {
-        self.offset.x += dx;
-        self.offset.y += dy;
+        self.view.pan(dx, dy);
     }
 
     pub fn set_offset(&mut self, offset_x: f32, offset_y: f32) {
-        self.offset.x = offset_x;
-        self.offset.y = offset_y;
+        self.view.set_offset(offset_x, offset_y);
     }
 
+    /// Sets the zoom, clamped to the limits of the view
     pub fn set_zoom(&mut self, zoom: f32) {
-        self.zoom = zoom;
+        self.view.set_zoom(zoom);
     }
 
     /// creates a tranformation matrix for use to upload to the gpu
     pub fn transform_matrix(&self) -> [[f32; 4]; 4] {
-        let translation =
-            glam::Mat4::from_translation(Vec3::new(-self.offset.x, -self.offset.y, 0.0));
-        let scale = glam::Mat4::from_scale(Vec3::new(self.zoom, self.zoom, 1.0));
-        let rotation = glam::Mat4::from_rotation_z(self.rotation);
-        let pivot = glam::Mat4::from_translation(Vec3::new(
-            self.width as f32 / 2.0,
-            self.height as f32 / 2.0,
-            0.0,
-        ));
-
-        (translation * scale * pivot * rotation * pivot.inverse()).to_cols_array_2d()
+        self.view.matrix().to_cols_array_2d()
     }
 
     pub fn inverse_transform_matrix(&self) -> [[f32; 4]; 4] {
-        glam::Mat4::from_cols_array_2d(&self.transform_matrix())
-            .inverse()
-            .to_cols_array_2d()
+        self.view.matrix().inverse().to_cols_array_2d()
+    }
+
+    /// Replaces a pixel of the active layer with `color`, in the working format, see
+    /// [`Color::convert`]. Partly selected pixels only move towards `color` by their
+    /// coverage, pixels outside the selection are left alone
+    pub fn draw_pixel(&mut self, x: usize, y: usize, color: Color<T>) {
+        let coverage = self.selection_coverage(x, y);
+        if coverage <= 0.0 {
+            return;
+        }
+        self.history
+            .record_paint(Rect::new(x, y, 1, 1), self.layers.active());
+        if coverage >= 1.0 {
+            self.layers.active_mut().draw_pixel(x, y, color);
+        } else {
+            self.layers.active_mut().smudge_pixel(x, y, color, coverage);
+        }
+        self.dirty.mark(Rect::new(x, y, 1, 1));
     }
 
-    pub fn draw_pixel(&mut self, x: usize, y: usize, color: Color<u8>) {
-        if x >= self.width || y >= self.height {
+    /// Composites `color`, in the working format, onto a pixel of the active layer using
+    /// `mode`, `coverage` scales the alpha of the color along with the selection
+    pub fn blend_pixel(
+        &mut self,
+        x: usize,
+        y: usize,
+        color: Color<T>,
+        coverage: f32,
+        mode: BlendMode,
+    ) {
+        let coverage = coverage * self.selection_coverage(x, y);
+        if coverage <= 0.0 {
             return;
         }
+        self.history
+            .record_paint(Rect::new(x, y, 1, 1), self.layers.active());
+        self.layers
+            .active_mut()
+            .blend_pixel(x, y, color, coverage, mode);
+        self.dirty.mark(Rect::new(x, y, 1, 1));
+    }
 
-        let position = ((y * self.width + x) * 4) as usize;
+    /// Moves a pixel of the active layer towards `color`, in the working format, by `amount`
+    /// scaled by the selection. Unlike [`Canvas::blend_pixel`] this can also lower its alpha
+    pub fn smudge_pixel(&mut self, x: usize, y: usize, color: Color<T>, amount: f32) {
+        let amount = amount * self.selection_coverage(x, y);
+        if amount <= 0.0 {
+            return;
+        }
+        self.history
+            .record_paint(Rect::new(x, y, 1, 1), self.layers.active());
+        self.layers.active_mut().smudge_pixel(x, y, color, amount);
+        self.dirty.mark(Rect::new(x, y, 1, 1));
+    }
 
-        self.pixels[position] = color.r;
-        self.pixels[position + 1] = color.g;
-        self.pixels[position + 2] = color.b;
-        self.pixels[position + 3] = color.a;
+    /// Removes `amount` of the alpha of a pixel on the active layer, scaled by the selection
+    pub fn erase_pixel(&mut self, x: usize, y: usize, amount: f32) {
+        let amount = amount * self.selection_coverage(x, y);
+        if amount <= 0.0 {
+            return;
+        }
+        self.history
+            .record_paint(Rect::new(x, y, 1, 1), self.layers.active());
+        self.layers.active_mut().erase_pixel(x, y, amount);
+        self.dirty.mark(Rect::new(x, y, 1, 1));
+    }
+
+    /// Fills the area around canvas pixel `x`, `y` on the active layer with the sRGB `color`
+    /// as a single undo step, returning the bounds of the filled area. The fill is limited
+    /// to the selection
+    pub fn fill(
+        &mut self,
+        x: usize,
+        y: usize,
+        color: Color<u8>,
+        options: &FillOptions,
+    ) -> Option<Rect> {
+        self.commit_previews();
+        let mask = fill::fill_mask(self, x, y, options)?;
+        let bounds = mask.bounds()?;
+        let color = color.convert();
+
+        self.history.end_paint();
+        self.history.begin_paint(self.layers.active_index());
+        self.history.record_paint(bounds, self.layers.active());
+        let layer = self.layers.active_mut();
+        for y in bounds.y..bounds.bottom() {
+            for x in bounds.x..bounds.right() {
+                let coverage = self
+                    .selection
+                    .as_ref()
+                    .map_or(1.0, |s| s.coverage(x, y) as f32 / 255.0);
+                if mask.contains(x, y) && coverage > 0.0 {
+                    layer.blend_pixel(x, y, color, coverage, BlendMode::Normal);
+                }
+            }
+        }
+        self.history.end_paint();
+        self.dirty.mark(bounds);
+        Some(bounds)
     }
 
     pub fn translate_screen_to_canvas(&self, x: f32, y: f32) -> (f32, f32) {
-        let translated_points = glam::Mat4::from_cols_array_2d(&self.inverse_transform_matrix())
-            * glam::Vec4::new(x, y, 0.0, 1.0);
-        (translated_points.x, translated_points.y)
+        self.view.screen_to_canvas(x, y)
+    }
+}
+
+/// Axis aligned rectangle of pixels on the canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
     }
 
-    #[deprecated = "moved logic to frontend"]
-    pub fn zoom_relative_to_point(&mut self, zoom: f32, mouse_x: f32, mouse_y: f32) {
-        let offset: Vec2 = Vec2::new(self.offset.x, self.offset.y);
+    pub fn right(&self) -> usize {
+        self.x + self.width
+    }
 
-        let mouse_pos: Vec2 = Vec2::new(mouse_x, mouse_y);
-        let world_before: Vec2 = (mouse_pos + offset) / self.zoom;
+    pub fn bottom(&self) -> usize {
+        self.y + self.height
+    }
 
-        /* zoom should be clamped in the front end */
-        self.zoom = zoom;
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
 
-        let new_coords = world_before * self.zoom - mouse_pos;
-        self.offset = Point {
-            x: new_coords.x,
-            y: new_coords.y,
-        };
+    /// Smallest rectangle containing both rectangles
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(
+            x,
+            y,
+            self.right().max(other.right()) - x,
+            self.bottom().max(other.bottom()) - y,
+        )
+    }
+
+    /// Overlapping area of both rectangles, `None` if they don't overlap
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        if right <= x || bottom <= y {
+            return None;
+        }
+        Some(Rect::new(x, y, right - x, bottom - y))
     }
 }
 
 /* point type to store canvas location on  */
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Point<U = f32> {
     pub x: U,
     pub y: U,
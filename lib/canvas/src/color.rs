@@ -1,3 +1,7 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, Clone, Copy)]
 pub struct ColorF32(f32);
 
@@ -29,8 +33,204 @@ impl From<ColorF32> for f32 {
     }
 }
 
+/// Storage type of one channel of a layer pixel, picking the working format of a canvas.
+///
+/// `u8` keeps pixels sRGB encoded like the files they come from, `u16` and `f32` store
+/// linear light so blending doesn't darken soft edges and glazes don't band
+pub trait Channel: Copy + Default + PartialEq + std::fmt::Debug + Send + Sync + 'static {
+    /// Raw value of a channel at full intensity
+    const MAX: f32;
+    /// Whether the values are linear light instead of sRGB encoded
+    const LINEAR: bool;
+    const FORMAT: PixelFormat;
+
+    /// The raw value, from 0 to [`Channel::MAX`]
+    fn to_f32(self) -> f32;
+
+    /// Rounds and clamps a raw value from 0 to [`Channel::MAX`]
+    fn from_f32(value: f32) -> Self;
+
+    /// Value of the channel from 0 to 1
+    fn normalized(self) -> f32 {
+        self.to_f32() / Self::MAX
+    }
+
+    fn from_normalized(value: f32) -> Self {
+        Self::from_f32(value * Self::MAX)
+    }
+
+    /// Stores an sRGB encoded value from 0 to 1, linearizing it for linear formats
+    fn from_srgb(value: f32) -> Self {
+        if Self::LINEAR {
+            Self::from_normalized(srgb_to_linear(value))
+        } else {
+            Self::from_normalized(value)
+        }
+    }
+
+    /// The sRGB encoded value of the channel, from 0 to 1
+    fn to_srgb(self) -> f32 {
+        if Self::LINEAR {
+            linear_to_srgb(self.normalized())
+        } else {
+            self.normalized()
+        }
+    }
+
+    /// The linear light value of the channel, from 0 to 1
+    fn to_linear(self) -> f32 {
+        if Self::LINEAR {
+            self.normalized()
+        } else {
+            srgb_to_linear(self.normalized())
+        }
+    }
+
+    /// Stores a linear light value from 0 to 1, encoding it for sRGB formats
+    fn from_linear(value: f32) -> Self {
+        if Self::LINEAR {
+            Self::from_normalized(value)
+        } else {
+            Self::from_normalized(linear_to_srgb(value))
+        }
+    }
+
+    /// The channel scaled by `alpha`, used to premultiply
+    fn premultiplied(self, alpha: Self) -> Self {
+        Self::from_f32(self.to_f32() * alpha.normalized())
+    }
+
+    /// The channel divided by `alpha`, `alpha` must not be zero
+    fn unpremultiplied(self, alpha: Self) -> Self {
+        Self::from_f32(self.to_f32() / alpha.normalized())
+    }
+
+    /// Appends the channel to `out` as little endian bytes, used by project files
+    fn write_le(self, out: &mut Vec<u8>);
+
+    /// Reads a channel written by [`Channel::write_le`], `bytes` holds exactly its size
+    fn read_le(bytes: &[u8]) -> Self;
+}
+
+impl Channel for u8 {
+    const MAX: f32 = 255.0;
+    const LINEAR: bool = false;
+    const FORMAT: PixelFormat = PixelFormat::Srgb8;
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    /* rounds half away from zero like `f32::round`, which is a libm call on baseline
+     * x86_64 and the slowest part of filtering. The subtraction is exact below 2^24 */
+    fn from_f32(value: f32) -> Self {
+        let value = value.clamp(0.0, 255.0);
+        let whole = value as u8;
+        whole + (value - whole as f32 >= 0.5) as u8
+    }
+
+    /* integer math keeps round trips through premultiplied pixels exact */
+    fn premultiplied(self, alpha: Self) -> Self {
+        ((self as u32 * alpha as u32 + 127) / 255) as u8
+    }
+
+    fn unpremultiplied(self, alpha: Self) -> Self {
+        let alpha = alpha as u32;
+        ((self as u32 * 255 + alpha / 2) / alpha).min(255) as u8
+    }
+
+    fn write_le(self, out: &mut Vec<u8>) {
+        out.push(self);
+    }
+
+    fn read_le(bytes: &[u8]) -> Self {
+        bytes[0]
+    }
+}
+
+impl Channel for u16 {
+    const MAX: f32 = 65535.0;
+    const LINEAR: bool = true;
+    const FORMAT: PixelFormat = PixelFormat::Linear16;
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    /* rounds without `f32::round` for the same reason as u8 */
+    fn from_f32(value: f32) -> Self {
+        let value = value.clamp(0.0, 65535.0);
+        let whole = value as u16;
+        whole + (value - whole as f32 >= 0.5) as u16
+    }
+
+    fn write_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Self {
+        u16::from_le_bytes([bytes[0], bytes[1]])
+    }
+}
+
+impl Channel for f32 {
+    const MAX: f32 = 1.0;
+    const LINEAR: bool = true;
+    const FORMAT: PixelFormat = PixelFormat::LinearF32;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(value: f32) -> Self {
+        if value.is_nan() {
+            return 0.0;
+        }
+        value.clamp(0.0, 1.0)
+    }
+
+    fn write_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Self {
+        f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+}
+
+/// Decodes an sRGB encoded value from 0 to 1 into linear light
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes linear light from 0 to 1 as sRGB
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Working formats a canvas can be created in, see [`Channel`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PixelFormat {
+    /// 8 bits per channel, sRGB encoded
+    #[default]
+    Srgb8,
+    /// 16 bits per channel, linear light
+    Linear16,
+    /// 32 bit floats per channel, linear light
+    LinearF32,
+}
+
 #[repr(C)]
-#[derive(Debug, Copy)]
+#[derive(Debug, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Color<T> {
     pub r: T,
     pub g: T,
@@ -44,6 +244,26 @@ impl<T> Color<T> {
     }
 }
 
+impl<T: Channel> Color<T> {
+    /// Converts a straight alpha color into another working format, decoding or encoding
+    /// sRGB as needed. Alpha is never gamma encoded
+    pub fn convert<U: Channel>(self) -> Color<U> {
+        let channel = |c: T| {
+            if T::LINEAR == U::LINEAR {
+                U::from_normalized(c.normalized())
+            } else {
+                U::from_srgb(c.to_srgb())
+            }
+        };
+        Color::new(
+            channel(self.r),
+            channel(self.g),
+            channel(self.b),
+            U::from_normalized(self.a.normalized()),
+        )
+    }
+}
+
 impl Color<u8> {
     pub fn new_f32<C: Into<ColorF32>>(r: C, g: C, b: C, a: C) -> Self {
         Color::<ColorF32>::new(r.into(), g.into(), b.into(), a.into()).into()
@@ -65,11 +285,100 @@ impl From<Color<ColorF32>> for Color<u8> {
 
 impl<T: Copy> Clone for Color<T> {
     fn clone(&self) -> Self {
-        Self {
-            r: self.r,
-            g: self.g,
-            b: self.b,
-            a: self.a,
+        *self
+    }
+}
+
+/// Most recently used colors, newest first
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ColorHistory {
+    colors: VecDeque<Color<u8>>,
+}
+
+impl ColorHistory {
+    /// Amount of colors kept before the oldest ones are dropped
+    pub const CAPACITY: usize = 32;
+
+    /// Moves `color` to the front, adding it if it was not used before
+    pub fn push(&mut self, color: Color<u8>) {
+        self.colors.retain(|c| *c != color);
+        self.colors.push_front(color);
+        self.colors.truncate(Self::CAPACITY);
+    }
+
+    /// Iterates the colors from newest to oldest
+    pub fn iter(&self) -> impl Iterator<Item = &Color<u8>> {
+        self.colors.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blend::{premultiply, source_over};
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn integer_channels_round_like_f32_round(value in proptest::num::f32::ANY) {
+            prop_assert_eq!(u8::from_f32(value), value.round().clamp(0.0, 255.0) as u8);
+            prop_assert_eq!(u16::from_f32(value), value.round().clamp(0.0, 65535.0) as u16);
+        }
+    }
+
+    #[test]
+    fn integer_channels_round_halves_up() {
+        for value in [
+            0.5f32,
+            0.499_999_97,
+            1.5,
+            254.5,
+            255.0,
+            -0.5,
+            f32::NAN,
+            1e30,
+        ] {
+            assert_eq!(u8::from_f32(value), value.round().clamp(0.0, 255.0) as u8);
+            assert_eq!(
+                u16::from_f32(value),
+                value.round().clamp(0.0, 65535.0) as u16
+            );
+        }
+    }
+
+    #[test]
+    fn every_srgb_value_survives_the_linear_formats() {
+        for value in 0..=255u8 {
+            let color = Color::new(value, value, value, value);
+            assert_eq!(color.convert::<u16>().convert::<u8>(), color);
+            assert_eq!(color.convert::<f32>().convert::<u8>(), color);
         }
     }
+
+    #[test]
+    fn linear_formats_blend_in_linear_light() {
+        let white = Color::new(255u8, 255, 255, 255);
+        let half_black = Color::new(0u8, 0, 0, 128);
+
+        let mut srgb = premultiply(white);
+        source_over(&mut srgb, premultiply(half_black), 1.0);
+        assert_eq!(srgb, [127, 127, 127, 255]);
+
+        let mut linear = premultiply(white.convert::<u16>());
+        source_over(&mut linear, premultiply(half_black.convert()), 1.0);
+        let [red, _, _, alpha] = linear;
+        assert_eq!(
+            Color::new(red, red, red, alpha).convert::<u8>(),
+            Color::new(187, 187, 187, 255)
+        );
+    }
 }
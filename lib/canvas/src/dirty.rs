@@ -0,0 +1,104 @@
+use crate::canvas::Rect;
+use crate::tile::{TILE_SIZE, tile_rect};
+
+/// Areas of the canvas that changed since they were last handed to the renderer.
+///
+/// Changes are tracked per tile, so a small dab only dirties the part of the tiles
+/// it touches instead of growing one big bounding box
+#[derive(Debug, Clone)]
+pub struct DirtyRegion {
+    width: usize,
+    height: usize,
+    columns: usize,
+    tiles: Vec<Option<Rect>>,
+    empty: bool,
+}
+
+impl DirtyRegion {
+    /// Creates an empty region for a canvas of the given size
+    pub fn new(width: usize, height: usize) -> Self {
+        let columns = width.div_ceil(TILE_SIZE);
+        let rows = height.div_ceil(TILE_SIZE);
+
+        Self {
+            width,
+            height,
+            columns,
+            tiles: vec![None; columns * rows],
+            empty: true,
+        }
+    }
+
+    /// Creates a region covering the whole canvas
+    pub fn full(width: usize, height: usize) -> Self {
+        let mut region = Self::new(width, height);
+        region.mark_all();
+        region
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.empty
+    }
+
+    /// Adds `rect` to the region, the rect is clipped to the canvas bounds
+    pub fn mark(&mut self, rect: Rect) {
+        let Some(rect) = rect.intersect(&Rect::new(0, 0, self.width, self.height)) else {
+            return;
+        };
+
+        for row in rect.y / TILE_SIZE..=(rect.bottom() - 1) / TILE_SIZE {
+            for column in rect.x / TILE_SIZE..=(rect.right() - 1) / TILE_SIZE {
+                let Some(area) = tile_rect(column, row, self.width, self.height).intersect(&rect)
+                else {
+                    continue;
+                };
+                let dirty = &mut self.tiles[row * self.columns + column];
+                *dirty = Some(dirty.map_or(area, |d| d.union(&area)));
+            }
+        }
+        self.empty = false;
+    }
+
+    /// Marks the whole canvas as changed
+    pub fn mark_all(&mut self) {
+        self.mark(Rect::new(0, 0, self.width, self.height));
+    }
+
+    /// Empties the region, returning the changed area of every dirty tile.
+    /// Every returned rect lies inside a single tile
+    pub fn take(&mut self) -> Vec<Rect> {
+        if self.empty {
+            return vec![];
+        }
+
+        self.empty = true;
+        self.tiles.iter_mut().filter_map(Option::take).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_are_split_and_merged_per_tile() {
+        let mut region = DirtyRegion::new(200, 100);
+        assert!(region.is_empty());
+
+        region.mark(Rect::new(60, 10, 8, 4));
+        region.mark(Rect::new(62, 20, 1, 1));
+        region.mark(Rect::new(180, 90, 50, 50));
+
+        assert_eq!(
+            region.take(),
+            vec![
+                Rect::new(60, 10, 4, 11),
+                Rect::new(64, 10, 4, 4),
+                Rect::new(180, 90, 12, 10),
+                Rect::new(192, 90, 8, 10),
+            ]
+        );
+        assert!(region.is_empty());
+        assert!(region.take().is_empty());
+    }
+}
@@ -0,0 +1,407 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Canvas;
+use crate::blend;
+use crate::canvas::Rect;
+use crate::color::Channel;
+
+/// Which pixels a fill compares against the seed pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FillSource {
+    /// Only the active layer
+    #[default]
+    Layer,
+    /// All visible layers flattened
+    Merged,
+}
+
+/// Settings of the paint bucket, missing fields take their default
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FillOptions {
+    /// Largest per channel difference to the seed pixel that is still filled
+    pub tolerance: u8,
+    /// Fills only the area connected to the seed, otherwise every matching pixel
+    pub contiguous: bool,
+    pub source: FillSource,
+    /// Breaks in the line art up to this many pixels wide are treated as closed
+    pub gap_closing: usize,
+    /// Grows the filled area by this many pixels to cover anti-aliased edges
+    pub expand: usize,
+}
+
+impl Default for FillOptions {
+    fn default() -> Self {
+        Self {
+            tolerance: 0,
+            contiguous: true,
+            source: FillSource::Layer,
+            gap_closing: 0,
+            expand: 0,
+        }
+    }
+}
+
+/// Pixels selected by a fill, one flag per canvas pixel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FillMask {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+impl FillMask {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![false; width * height],
+        }
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.pixels[y * self.width + x]
+    }
+
+    /// Smallest rect containing every filled pixel, `None` if nothing is filled
+    pub fn bounds(&self) -> Option<Rect> {
+        let mut bounds: Option<Rect> = None;
+        for (y, row) in self.pixels.chunks_exact(self.width.max(1)).enumerate() {
+            let Some(left) = row.iter().position(|&p| p) else {
+                continue;
+            };
+            let right = row.iter().rposition(|&p| p).unwrap_or(left);
+            let rect = Rect::new(left, y, right - left + 1, 1);
+            bounds = Some(bounds.map_or(rect, |b| b.union(&rect)));
+        }
+        bounds
+    }
+
+    /// Adds every pixel within `radius` pixels, horizontally and vertically, of a filled one
+    fn dilate(&mut self, radius: usize) {
+        if radius == 0 {
+            return;
+        }
+
+        let (width, height) = (self.width, self.height);
+        let horizontal = dilate_lines(&self.pixels, width, height, 1, width, radius);
+        self.pixels = dilate_lines(&horizontal, height, width, width, 1, radius);
+    }
+}
+
+/// Dilates `count` lines of `length` flags, the `i`th flag of line `l` being at
+/// `l * line_stride + i * step`
+fn dilate_lines(
+    pixels: &[bool],
+    length: usize,
+    count: usize,
+    step: usize,
+    line_stride: usize,
+    radius: usize,
+) -> Vec<bool> {
+    let mut out = vec![false; pixels.len()];
+    let mut prefix = vec![0usize; length + 1];
+    for line in 0..count {
+        for i in 0..length {
+            prefix[i + 1] = prefix[i] + pixels[line * line_stride + i * step] as usize;
+        }
+        for i in 0..length {
+            let start = i.saturating_sub(radius);
+            let end = (i + radius + 1).min(length);
+            out[line * line_stride + i * step] = prefix[end] > prefix[start];
+        }
+    }
+    out
+}
+
+/// Works out which pixels a fill started at canvas pixel `x`, `y` covers
+pub fn fill_mask<T: Channel>(
+    canvas: &Canvas<T>,
+    x: usize,
+    y: usize,
+    options: &FillOptions,
+) -> Option<FillMask> {
+    let (width, height) = (canvas.width(), canvas.height());
+    if x >= width || y >= height {
+        return None;
+    }
+
+    /* compared premultiplied, so fully transparent pixels match regardless of their color */
+    let source = match options.source {
+        FillSource::Layer => canvas
+            .layers()
+            .active()
+            .read_rect(canvas.layers().active().bounds()),
+        FillSource::Merged => canvas
+            .layers()
+            .composite_premultiplied(Rect::new(0, 0, width, height)),
+    };
+    let seed = srgb_steps(&source[(y * width + x) * 4..][..4]);
+    let tolerance = options.tolerance as f32;
+
+    let mut matches = FillMask::new(width, height);
+    for (flag, pixel) in matches.pixels.iter_mut().zip(source.chunks_exact(4)) {
+        *flag = srgb_steps(pixel)
+            .iter()
+            .zip(seed)
+            .all(|(&a, b)| (a - b).abs() <= tolerance);
+    }
+
+    let mut mask = if !options.contiguous {
+        matches
+    } else if options.gap_closing == 0 {
+        flood(&matches, x, y)
+    } else {
+        /* growing the line art by half the gap closes it, the fill is then grown back
+         * by the same amount without leaving the matching pixels */
+        let radius = options.gap_closing.div_ceil(2);
+        let mut walls = FillMask {
+            width,
+            height,
+            pixels: matches.pixels.iter().map(|&m| !m).collect(),
+        };
+        walls.dilate(radius);
+        let open = FillMask {
+            width,
+            height,
+            pixels: walls.pixels.iter().map(|&w| !w).collect(),
+        };
+
+        /* a seed close to the line art is covered by the grown walls, so the fill
+         * starts from the closest open pixel of the area around it */
+        match nearest_open(&matches, &open, x, y) {
+            None => flood(&matches, x, y),
+            Some((x, y)) => {
+                let mut mask = flood(&open, x, y);
+                for _ in 0..radius {
+                    mask.dilate(1);
+                    for (flag, &matching) in mask.pixels.iter_mut().zip(&matches.pixels) {
+                        *flag &= matching;
+                    }
+                }
+                mask
+            }
+        }
+    };
+
+    mask.dilate(options.expand);
+    Some(mask)
+}
+
+/// A premultiplied pixel in 8 bit sRGB steps, the way an 8 bit canvas stores it. The
+/// tolerance is compared in these steps so it picks the same colors in every working
+/// format, in linear light the darks would bunch up and the lights spread out
+fn srgb_steps<T: Channel>(pixel: &[T]) -> [f32; 4] {
+    let color = blend::unpremultiply([pixel[0], pixel[1], pixel[2], pixel[3]]);
+    let alpha = color.a.normalized();
+    [
+        color.r.to_srgb() * alpha * 255.0,
+        color.g.to_srgb() * alpha * 255.0,
+        color.b.to_srgb() * alpha * 255.0,
+        alpha * 255.0,
+    ]
+}
+
+/// Breadth first search through the `matches` pixels connected to `x`, `y` for the
+/// closest one that is also `open`
+fn nearest_open(matches: &FillMask, open: &FillMask, x: usize, y: usize) -> Option<(usize, usize)> {
+    let (width, height) = (matches.width, matches.height);
+    let mut visited = FillMask::new(width, height);
+    let mut queue = VecDeque::from([(x, y)]);
+    visited.pixels[y * width + x] = true;
+
+    while let Some((x, y)) = queue.pop_front() {
+        if open.pixels[y * width + x] {
+            return Some((x, y));
+        }
+
+        let neighbours = [
+            x.checked_sub(1).map(|x| (x, y)),
+            (x + 1 < width).then_some((x + 1, y)),
+            y.checked_sub(1).map(|y| (x, y)),
+            (y + 1 < height).then_some((x, y + 1)),
+        ];
+        for (x, y) in neighbours.into_iter().flatten() {
+            let index = y * width + x;
+            if matches.pixels[index] && !visited.pixels[index] {
+                visited.pixels[index] = true;
+                queue.push_back((x, y));
+            }
+        }
+    }
+    None
+}
+
+/// Scanline fill of the area of `open` pixels connected to `x`, `y`
+fn flood(open: &FillMask, x: usize, y: usize) -> FillMask {
+    let (width, height) = (open.width, open.height);
+    let mut filled = FillMask::new(width, height);
+    let mut stack = vec![(x, y)];
+
+    while let Some((x, y)) = stack.pop() {
+        let row = y * width;
+        if !open.pixels[row + x] || filled.pixels[row + x] {
+            continue;
+        }
+
+        let mut left = x;
+        while left > 0 && open.pixels[row + left - 1] && !filled.pixels[row + left - 1] {
+            left -= 1;
+        }
+        let mut right = x;
+        while right + 1 < width && open.pixels[row + right + 1] && !filled.pixels[row + right + 1] {
+            right += 1;
+        }
+        filled.pixels[row + left..=row + right].fill(true);
+
+        /* seed the start of every open run in the rows above and below */
+        for neighbour in [y.checked_sub(1), Some(y + 1).filter(|&y| y < height)]
+            .into_iter()
+            .flatten()
+        {
+            let row = neighbour * width;
+            let mut in_run = false;
+            for x in left..=right {
+                let fillable = open.pixels[row + x] && !filled.pixels[row + x];
+                if fillable && !in_run {
+                    stack.push((x, neighbour));
+                }
+                in_run = fillable;
+            }
+        }
+    }
+
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    /// A 20x20 canvas with a black square outline from 5 to 14, broken by a
+    /// gap of `gap` pixels on its top edge
+    fn outlined(gap: usize) -> Canvas {
+        let mut canvas: Canvas = Canvas::new(20, 20);
+        canvas.begin_paint();
+        for i in 5..15 {
+            for (x, y) in [(i, 5), (i, 14), (5, i), (14, i)] {
+                if y == 5 && (9..9 + gap).contains(&x) {
+                    continue;
+                }
+                canvas.draw_pixel(x, y, Color::new(0, 0, 0, 255));
+            }
+        }
+        canvas.end_paint();
+        canvas
+    }
+
+    #[test]
+    fn contiguous_fill_stays_inside_lines() {
+        let canvas = outlined(0);
+        let mask = fill_mask(&canvas, 10, 10, &FillOptions::default()).unwrap();
+        assert_eq!(mask.bounds(), Some(Rect::new(6, 6, 8, 8)));
+
+        let global = FillOptions {
+            contiguous: false,
+            ..Default::default()
+        };
+        let mask = fill_mask(&canvas, 10, 10, &global).unwrap();
+        assert!(mask.contains(0, 0) && !mask.contains(5, 5));
+    }
+
+    #[test]
+    fn gaps_are_closed_and_fills_expand() {
+        let canvas = outlined(2);
+        let leaking = fill_mask(&canvas, 10, 10, &FillOptions::default()).unwrap();
+        assert!(leaking.contains(0, 0));
+
+        let options = FillOptions {
+            gap_closing: 2,
+            ..Default::default()
+        };
+        let mask = fill_mask(&canvas, 10, 10, &options).unwrap();
+        assert!(!mask.contains(0, 0));
+        assert!(mask.contains(6, 6) && mask.contains(13, 13));
+
+        let options = FillOptions {
+            expand: 1,
+            ..Default::default()
+        };
+        let mask = fill_mask(&outlined(0), 10, 10, &options).unwrap();
+        assert_eq!(mask.bounds(), Some(Rect::new(5, 5, 10, 10)));
+    }
+
+    #[test]
+    fn gap_closing_works_next_to_the_lines() {
+        let canvas = outlined(2);
+        let options = FillOptions {
+            gap_closing: 4,
+            ..Default::default()
+        };
+
+        /* right inside the corner, where the grown walls cover the seed */
+        let mask = fill_mask(&canvas, 6, 6, &options).unwrap();
+        assert!(!mask.contains(0, 0));
+        assert!(mask.contains(6, 6) && mask.contains(13, 13));
+    }
+
+    #[test]
+    fn tolerance_is_measured_in_srgb_steps() {
+        let gray = |value: u8| Color::new(value, value, value, 255).convert::<u16>();
+        let mut canvas: Canvas<u16> = Canvas::new(3, 1);
+        canvas.begin_paint();
+        canvas.draw_pixel(0, 0, gray(10));
+        canvas.draw_pixel(1, 0, gray(30));
+        canvas.draw_pixel(2, 0, gray(40));
+        canvas.end_paint();
+        let options = FillOptions {
+            tolerance: 12,
+            contiguous: false,
+            ..Default::default()
+        };
+
+        /* 20 steps apart in the darks, only a few hundredths apart in linear light */
+        let mask = fill_mask(&canvas, 0, 0, &options).unwrap();
+        assert!(!mask.contains(1, 0));
+        let mask = fill_mask(&canvas, 1, 0, &options).unwrap();
+        assert!(mask.contains(2, 0) && !mask.contains(0, 0));
+
+        canvas.begin_paint();
+        canvas.draw_pixel(0, 0, gray(200));
+        canvas.draw_pixel(1, 0, gray(210));
+        canvas.end_paint();
+        let mask = fill_mask(&canvas, 0, 0, &options).unwrap();
+        assert!(mask.contains(1, 0));
+    }
+
+    #[test]
+    fn options_default_their_missing_fields() {
+        let options: FillOptions = serde_json::from_str(r#"{"tolerance": 12}"#).unwrap();
+        assert_eq!(
+            options,
+            FillOptions {
+                tolerance: 12,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn canvas_fill_is_a_single_undo_step() {
+        let mut canvas = outlined(0);
+        let red = Color::new(255, 0, 0, 255);
+        let filled = canvas.fill(10, 10, red, &FillOptions::default());
+        assert_eq!(filled, Some(Rect::new(6, 6, 8, 8)));
+        assert_eq!(canvas.layers().active().pixel(13, 6), Some(red));
+
+        assert!(canvas.undo());
+        assert_eq!(
+            canvas.layers().active().pixel(13, 6),
+            Some(Color::new(255, 255, 255, 255))
+        );
+    }
+}
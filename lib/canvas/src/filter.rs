@@ -0,0 +1,388 @@
+mod adjust;
+mod convolve;
+
+pub use adjust::Curve;
+pub(crate) use adjust::{hsl_to_rgb, rgb_to_hsl};
+pub use convolve::RadialBlurKind;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::blend;
+use crate::canvas::Rect;
+use crate::color::Channel;
+use crate::layer::Layer;
+use crate::selection::Selection;
+use crate::tile::TiledImage;
+
+/// Largest radius of a box blur in pixels
+pub const MAX_BOX_RADIUS: usize = 500;
+
+/// Largest distance of a motion blur in pixels
+pub const MAX_MOTION_DISTANCE: f32 = 500.0;
+
+/// Largest radius of a median filter in pixels, every pixel sorts a window this wide
+pub const MAX_MEDIAN_RADIUS: usize = 10;
+
+/// Largest block size of a pixelate filter in pixels
+pub const MAX_PIXELATE_SIZE: usize = 1024;
+
+/// Destructive filters that can be previewed on the active layer and committed as one undo step.
+///
+/// Adjustments work on sRGB encoded values whatever the working format, so their settings
+/// mean the same on every canvas. Spatial filters mix pixels in the working format, which
+/// blurs linear light canvases without dark halos
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "filter", rename_all = "camelCase")]
+pub enum Filter {
+    /// `brightness` shifts every channel, `contrast` stretches them away from middle
+    /// grey. Both range from -1 to 1
+    #[serde(rename_all = "camelCase")]
+    BrightnessContrast {
+        brightness: f32,
+        contrast: f32,
+    },
+    /// Turns the hue by `hue` degrees, `saturation` and `lightness` range from -1 to 1
+    #[serde(rename_all = "camelCase")]
+    HueSaturation {
+        hue: f32,
+        saturation: f32,
+        lightness: f32,
+    },
+    /// Maps the input range onto the output range with a gamma curve in between
+    #[serde(rename_all = "camelCase")]
+    Levels {
+        input_black: u8,
+        input_white: u8,
+        gamma: f32,
+        output_black: u8,
+        output_white: u8,
+    },
+    /// Tone curves, `master` is applied after the curve of each channel
+    #[serde(rename_all = "camelCase")]
+    Curves {
+        #[serde(default)]
+        master: Curve,
+        #[serde(default)]
+        red: Curve,
+        #[serde(default)]
+        green: Curve,
+        #[serde(default)]
+        blue: Curve,
+    },
+    /// Shifts the tonal ranges towards red, green and blue, each from -1 to 1
+    #[serde(rename_all = "camelCase")]
+    ColorBalance {
+        shadows: [f32; 3],
+        midtones: [f32; 3],
+        highlights: [f32; 3],
+        #[serde(default)]
+        preserve_luminosity: bool,
+    },
+    Invert,
+    /// Rounds every channel to one of `levels` evenly spaced values
+    #[serde(rename_all = "camelCase")]
+    Posterize {
+        levels: u8,
+    },
+    /// Pixels with a luminosity of at least `level` turn white, the rest black
+    #[serde(rename_all = "camelCase")]
+    Threshold {
+        level: u8,
+    },
+    Desaturate,
+    /// Gaussian blur, `radius` is the standard deviation in pixels
+    #[serde(rename_all = "camelCase")]
+    GaussianBlur {
+        radius: f32,
+    },
+    /// Box blur, `radius` is clamped to [`MAX_BOX_RADIUS`] when deserialized
+    #[serde(rename_all = "camelCase")]
+    BoxBlur {
+        #[serde(deserialize_with = "at_most::<MAX_BOX_RADIUS, _>")]
+        radius: usize,
+    },
+    /// Smears pixels over `distance` pixels along `angle` degrees, the distance is clamped
+    /// to [`MAX_MOTION_DISTANCE`] when deserialized
+    #[serde(rename_all = "camelCase")]
+    MotionBlur {
+        angle: f32,
+        #[serde(deserialize_with = "motion_distance")]
+        distance: f32,
+    },
+    /// Smears pixels around a canvas position, `amount` is the angle in degrees for a
+    /// spin and the fraction of the distance to the centre for a zoom
+    #[serde(rename_all = "camelCase")]
+    RadialBlur {
+        center_x: f32,
+        center_y: f32,
+        amount: f32,
+        #[serde(default)]
+        kind: RadialBlurKind,
+    },
+    /// Sharpens by `amount` times the difference to a gaussian blur of `radius`
+    #[serde(rename_all = "camelCase")]
+    UnsharpMask {
+        radius: f32,
+        amount: f32,
+        #[serde(default)]
+        threshold: u8,
+    },
+    /// Median filter, `radius` is clamped to [`MAX_MEDIAN_RADIUS`] when deserialized
+    #[serde(rename_all = "camelCase")]
+    Median {
+        #[serde(deserialize_with = "at_most::<MAX_MEDIAN_RADIUS, _>")]
+        radius: usize,
+    },
+    /// Replaces pixels that differ from the median of their neighbours by more than `threshold`
+    #[serde(rename_all = "camelCase")]
+    Despeckle {
+        threshold: u8,
+    },
+    /// Random noise of up to `amount`, from 0 to 1. The same `seed` gives the same noise
+    #[serde(rename_all = "camelCase")]
+    AddNoise {
+        amount: f32,
+        #[serde(default)]
+        monochrome: bool,
+        #[serde(default)]
+        seed: u64,
+    },
+    /// Averages blocks of `size` pixels, aligned to the canvas. The size is clamped to
+    /// [`MAX_PIXELATE_SIZE`] when deserialized
+    #[serde(rename_all = "camelCase")]
+    Pixelate {
+        #[serde(deserialize_with = "at_most::<MAX_PIXELATE_SIZE, _>")]
+        size: usize,
+    },
+}
+
+/* filter settings come straight from the ui, so sizes that would overflow or keep a
+ * preview busy for minutes are clamped as they are read */
+fn at_most<'de, const MAX: usize, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<usize, D::Error> {
+    usize::deserialize(deserializer).map(|value| value.min(MAX))
+}
+
+fn motion_distance<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    f32::deserialize(deserializer).map(|distance| distance.clamp(0.0, MAX_MOTION_DISTANCE))
+}
+
+impl Filter {
+    /// Runs the filter on the `area` of a premultiplied image, returning the filtered pixels
+    pub fn apply<T: Channel>(&self, image: &TiledImage<T>, area: Rect) -> Vec<T> {
+        match convolve::source_rect(self, area, image.bounds()) {
+            Some(source) => convolve::convolve(self, image.read_rect(source), source, area),
+            None => {
+                let mut pixels = image.read_rect(area);
+                adjust::adjust(self, &mut pixels);
+                pixels
+            }
+        }
+    }
+}
+
+/// A filter being previewed on one layer. The filtered pixels are shown in place of the
+/// layer until the filter is committed, the layer itself is left untouched until then
+pub(crate) struct FilterSession {
+    pub layer: usize,
+    /// area the filter is applied to
+    pub area: Rect,
+}
+
+impl FilterSession {
+    /// Filters the session area of `layer`, fading the result into it by the coverage
+    /// of `selection`. Alpha locked layers keep the alpha of every pixel
+    pub fn render<T: Channel>(
+        &self,
+        filter: &Filter,
+        layer: &Layer<T>,
+        selection: Option<&Selection>,
+    ) -> Vec<T> {
+        let mut pixels = filter.apply(layer.pixels(), self.area);
+        if selection.is_none() && !layer.alpha_locked() {
+            return pixels;
+        }
+
+        let original = layer.read_rect(self.area);
+        for (i, (pixel, before)) in pixels
+            .chunks_exact_mut(4)
+            .zip(original.chunks_exact(4))
+            .enumerate()
+        {
+            if let Some(selection) = selection {
+                let x = self.area.x + i % self.area.width;
+                let y = self.area.y + i / self.area.width;
+                let coverage = selection.coverage(x, y) as f32;
+                for (after, &before) in pixel.iter_mut().zip(before) {
+                    let mixed = after.to_f32() * coverage + before.to_f32() * (255.0 - coverage);
+                    *after = T::from_f32(mixed / 255.0);
+                }
+            }
+
+            if layer.alpha_locked() {
+                /* a pixel the filter cleared has no color left to keep */
+                if pixel[3] == T::default() {
+                    pixel.copy_from_slice(before);
+                } else {
+                    blend::with_alpha(pixel, before[3]);
+                }
+            }
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection::SelectionMode;
+    use crate::{Canvas, Color};
+
+    #[test]
+    fn preview_respects_selection_and_commits_once() {
+        let mut canvas: Canvas = Canvas::new(8, 8);
+        canvas.select(
+            Selection::rectangle(8, 8, Rect::new(0, 0, 4, 8)),
+            SelectionMode::Replace,
+        );
+        let white = Color::new(255, 255, 255, 255);
+        let black = Color::new(0, 0, 0, 255);
+
+        assert!(canvas.preview_filter(&Filter::Posterize { levels: 4 }));
+        assert!(canvas.preview_filter(&Filter::Invert));
+        /* the preview is only composited, the layer keeps its pixels until the commit */
+        assert_eq!(canvas.layers().active().pixel(1, 1), Some(white));
+        assert_eq!(canvas.composite_rect(Rect::new(1, 1, 1, 1)), [0, 0, 0, 255]);
+        canvas.commit_filter();
+        assert_eq!(canvas.layers().active().pixel(1, 1), Some(black));
+        assert_eq!(canvas.layers().active().pixel(6, 1), Some(white));
+
+        assert!(canvas.undo());
+        assert_eq!(canvas.layers().active().pixel(1, 1), Some(white));
+
+        canvas.preview_filter(&Filter::Invert);
+        assert_eq!(canvas.composite_rect(Rect::new(1, 1, 1, 1)), [0, 0, 0, 255]);
+        canvas.cancel_filter();
+        assert_eq!(canvas.layers().active().pixel(1, 1), Some(white));
+        assert!(!canvas.history().can_undo());
+    }
+
+    #[test]
+    fn strokes_commit_the_filter_first() {
+        let black = Color::new(0, 0, 0, 255);
+        let mut canvas: Canvas = Canvas::new(4, 4);
+        assert!(canvas.preview_filter(&Filter::Invert));
+        canvas.begin_paint();
+        canvas.draw_pixel(0, 0, Color::new(255, 0, 0, 255));
+        canvas.end_paint();
+
+        /* the stroke survives a cancel that comes too late */
+        canvas.cancel_filter();
+        let layer = canvas.layers().active();
+        assert_eq!(layer.pixel(0, 0), Some(Color::new(255, 0, 0, 255)));
+        assert_eq!(layer.pixel(1, 0), Some(black));
+    }
+
+    #[test]
+    fn clamps_sizes_read_from_the_ui() {
+        let read = |json: &str| serde_json::from_str::<Filter>(json).unwrap();
+        let huge = usize::MAX;
+        assert_eq!(
+            read(&format!(r#"{{"filter": "boxBlur", "radius": {huge}}}"#)),
+            Filter::BoxBlur {
+                radius: MAX_BOX_RADIUS
+            }
+        );
+        assert_eq!(
+            read(r#"{"filter": "motionBlur", "angle": 0, "distance": 1e30}"#),
+            Filter::MotionBlur {
+                angle: 0.0,
+                distance: MAX_MOTION_DISTANCE
+            }
+        );
+        assert_eq!(
+            read(r#"{"filter": "motionBlur", "angle": 0, "distance": -4}"#),
+            Filter::MotionBlur {
+                angle: 0.0,
+                distance: 0.0
+            }
+        );
+        assert_eq!(
+            read(&format!(r#"{{"filter": "median", "radius": {huge}}}"#)),
+            Filter::Median {
+                radius: MAX_MEDIAN_RADIUS
+            }
+        );
+        assert_eq!(
+            read(&format!(r#"{{"filter": "pixelate", "size": {huge}}}"#)),
+            Filter::Pixelate {
+                size: MAX_PIXELATE_SIZE
+            }
+        );
+    }
+
+    #[test]
+    fn largest_sizes_filter_without_overflowing() {
+        let mut canvas: Canvas = Canvas::new(40, 30);
+        canvas.draw_pixel(3, 4, Color::new(255, 0, 0, 255));
+        for filter in [
+            Filter::BoxBlur {
+                radius: MAX_BOX_RADIUS,
+            },
+            Filter::MotionBlur {
+                angle: 45.0,
+                distance: MAX_MOTION_DISTANCE,
+            },
+            Filter::Median {
+                radius: MAX_MEDIAN_RADIUS,
+            },
+            Filter::Pixelate {
+                size: MAX_PIXELATE_SIZE,
+            },
+        ] {
+            assert!(canvas.apply_filter(&filter), "{filter:?}");
+        }
+    }
+
+    #[test]
+    fn alpha_locked_layers_keep_their_alpha() {
+        let mut canvas: Canvas = Canvas::new(9, 9);
+        canvas.add_layer();
+        let layer = canvas.layers_mut().active_mut();
+        for y in 3..6 {
+            for x in 3..6 {
+                layer.draw_pixel(x, y, Color::new(255, 0, 0, 255));
+            }
+        }
+        layer.draw_pixel(0, 0, Color::new(0, 0, 255, 100));
+        layer.set_alpha_locked(true);
+        let before: Vec<u8> = canvas.layers().active().read_rect(Rect::new(0, 0, 9, 9));
+
+        for filter in [
+            Filter::GaussianBlur { radius: 2.0 },
+            Filter::MotionBlur {
+                angle: 30.0,
+                distance: 4.0,
+            },
+            Filter::Pixelate { size: 4 },
+            Filter::Median { radius: 2 },
+        ] {
+            assert!(canvas.apply_filter(&filter));
+            let after = canvas.layers().active().read_rect(Rect::new(0, 0, 9, 9));
+            let alpha = |pixels: &[u8]| {
+                pixels
+                    .iter()
+                    .skip(3)
+                    .step_by(4)
+                    .copied()
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(alpha(&after), alpha(&before), "{filter:?}");
+        }
+
+        /* the color still changes where there is coverage */
+        let corner = canvas.layers().active().pixel(0, 0).unwrap();
+        assert_ne!(corner, Color::new(0, 0, 255, 100));
+    }
+}
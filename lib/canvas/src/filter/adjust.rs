@@ -0,0 +1,358 @@
+use serde::{Deserialize, Serialize};
+
+use super::Filter;
+use crate::blend::{lum, premultiply, set_lum, unpremultiply};
+use crate::color::{Channel, Color};
+
+/// Steps of the lookup tables used on linear canvases, finer than 8 bits so the
+/// adjustments keep the extra precision
+const LINEAR_TABLE_STEPS: usize = 4095;
+
+/// Tone curve through control points given as `(input, output)`, joined by a monotone
+/// cubic so the curve never overshoots between points. Without points it leaves values alone
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Curve(pub Vec<(u8, u8)>);
+
+impl Curve {
+    /// The curve as a function of input values from 0 to 255
+    fn evaluator(&self) -> impl Fn(f32) -> f32 + use<> {
+        let mut points: Vec<(f32, f32)> =
+            self.0.iter().map(|&(x, y)| (x as f32, y as f32)).collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        points.dedup_by(|a, b| a.0 == b.0);
+        let tangents = if points.len() > 1 {
+            monotone_tangents(&points)
+        } else {
+            vec![]
+        };
+
+        move |x| match points.as_slice() {
+            [] => x,
+            [(_, y)] => *y,
+            _ => {
+                let segment = points
+                    .windows(2)
+                    .position(|w| x <= w[1].0)
+                    .unwrap_or(points.len() - 2);
+                let ((x0, y0), (x1, y1)) = (points[segment], points[segment + 1]);
+                if x <= x0 {
+                    y0
+                } else if x >= x1 {
+                    y1
+                } else {
+                    let h = x1 - x0;
+                    let t = (x - x0) / h;
+                    let (t2, t3) = (t * t, t * t * t);
+                    (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+                        + (t3 - 2.0 * t2 + t) * h * tangents[segment]
+                        + (-2.0 * t3 + 3.0 * t2) * y1
+                        + (t3 - t2) * h * tangents[segment + 1]
+                }
+            }
+        }
+    }
+}
+
+/// Fritsch–Carlson tangents of a monotone cubic through `points`
+fn monotone_tangents(points: &[(f32, f32)]) -> Vec<f32> {
+    let slopes: Vec<f32> = points
+        .windows(2)
+        .map(|w| (w[1].1 - w[0].1) / (w[1].0 - w[0].0))
+        .collect();
+
+    let mut tangents = Vec::with_capacity(points.len());
+    tangents.push(slopes[0]);
+    for pair in slopes.windows(2) {
+        tangents.push(if pair[0] * pair[1] <= 0.0 {
+            0.0
+        } else {
+            (pair[0] + pair[1]) / 2.0
+        });
+    }
+    tangents.push(slopes[slopes.len() - 1]);
+
+    for (i, &slope) in slopes.iter().enumerate() {
+        if slope == 0.0 {
+            tangents[i] = 0.0;
+            tangents[i + 1] = 0.0;
+            continue;
+        }
+        let (a, b) = (tangents[i] / slope, tangents[i + 1] / slope);
+        let length = a.hypot(b);
+        if length > 3.0 {
+            tangents[i] = 3.0 * a / length * slope;
+            tangents[i + 1] = 3.0 * b / length * slope;
+        }
+    }
+    tangents
+}
+
+/// Runs an adjustment filter on premultiplied pixels
+pub(super) fn adjust<T: Channel>(filter: &Filter, pixels: &mut [T]) {
+    match *filter {
+        Filter::HueSaturation {
+            hue,
+            saturation,
+            lightness,
+        } => map_colors(pixels, |c| hue_saturation(c, hue, saturation, lightness)),
+        Filter::ColorBalance {
+            shadows,
+            midtones,
+            highlights,
+            preserve_luminosity,
+        } => map_colors(pixels, |c| {
+            let balanced = std::array::from_fn(|i| {
+                let v = c[i];
+                /* weights of the tonal ranges, taken from the value of the channel */
+                let shadow = ((0.333 - v) / 0.25 + 0.5).clamp(0.0, 1.0) * 0.7;
+                let highlight = ((v - 0.667) / 0.25 + 0.5).clamp(0.0, 1.0) * 0.7;
+                let midtone = ((v - 0.333) / 0.25 + 0.5).clamp(0.0, 1.0)
+                    * ((1.0 - v - 0.333) / 0.25 + 0.5).clamp(0.0, 1.0)
+                    * 0.7;
+                (v + shadow * shadows[i] + midtone * midtones[i] + highlight * highlights[i])
+                    .clamp(0.0, 1.0)
+            });
+            if preserve_luminosity {
+                set_lum(balanced, lum(c))
+            } else {
+                balanced
+            }
+        }),
+        Filter::Threshold { level } => map_colors(pixels, |c| {
+            let white = lum(c) * 255.0 >= level as f32;
+            [if white { 1.0 } else { 0.0 }; 3]
+        }),
+        Filter::Desaturate => map_colors(pixels, |c| [lum(c); 3]),
+        _ => {
+            /* 8 bit canvases get an entry for every value they can hold */
+            let steps = if T::LINEAR {
+                LINEAR_TABLE_STEPS
+            } else {
+                T::MAX as usize
+            };
+            if let Some(tables) = channel_tables(filter, steps) {
+                map_channels(pixels, &tables)
+            }
+        }
+    }
+}
+
+/// Lookup tables of the filters that treat every channel on its own, mapping `steps + 1`
+/// evenly spaced sRGB encoded values from 0 to 1
+fn channel_tables(filter: &Filter, steps: usize) -> Option<[Vec<f32>; 3]> {
+    let table = |f: &dyn Fn(f32) -> f32| -> Vec<f32> {
+        (0..=steps)
+            .map(|i| f(i as f32 / steps as f32).clamp(0.0, 1.0))
+            .collect()
+    };
+
+    let tables = match filter {
+        Filter::BrightnessContrast {
+            brightness,
+            contrast,
+        } => {
+            let slope = ((contrast.clamp(-1.0, 0.99) + 1.0) * std::f32::consts::FRAC_PI_4).tan();
+            let t = table(&|v| (v + brightness - 0.5) * slope + 0.5);
+            [t.clone(), t.clone(), t]
+        }
+        Filter::Levels {
+            input_black,
+            input_white,
+            gamma,
+            output_black,
+            output_white,
+        } => {
+            let (in_black, in_white) = (*input_black as f32 / 255.0, *input_white as f32 / 255.0);
+            let (out_black, out_white) =
+                (*output_black as f32 / 255.0, *output_white as f32 / 255.0);
+            let range = (in_white - in_black).max(1.0 / 255.0);
+            let t = table(&|v| {
+                let v = ((v - in_black) / range).clamp(0.0, 1.0);
+                out_black + v.powf(1.0 / gamma.max(0.01)) * (out_white - out_black)
+            });
+            [t.clone(), t.clone(), t]
+        }
+        Filter::Curves {
+            master,
+            red,
+            green,
+            blue,
+        } => {
+            let master = master.evaluator();
+            [red, green, blue].map(|curve| {
+                let curve = curve.evaluator();
+                table(&|v| master(curve(v * 255.0)) / 255.0)
+            })
+        }
+        Filter::Invert => std::array::from_fn(|_| table(&|v| 1.0 - v)),
+        Filter::Posterize { levels } => {
+            let levels = ((*levels).max(2) - 1) as f32;
+            std::array::from_fn(|_| table(&|v| (v * levels).round() / levels))
+        }
+        _ => return None,
+    };
+    Some(tables)
+}
+
+fn map_channels<T: Channel>(pixels: &mut [T], tables: &[Vec<f32>; 3]) {
+    let steps = tables[0].len() - 1;
+    let map = |c: T, table: &[f32]| {
+        let index = ((c.to_srgb() * steps as f32).round() as usize).min(steps);
+        T::from_srgb(table[index])
+    };
+
+    for pixel in pixels.chunks_exact_mut(4) {
+        if pixel[3] == T::default() {
+            continue;
+        }
+        let color = unpremultiply([pixel[0], pixel[1], pixel[2], pixel[3]]);
+        let mapped = Color::new(
+            map(color.r, &tables[0]),
+            map(color.g, &tables[1]),
+            map(color.b, &tables[2]),
+            color.a,
+        );
+        pixel.copy_from_slice(&premultiply(mapped));
+    }
+}
+
+/// Maps the straight sRGB encoded RGB of every pixel, channels range from 0 to 1
+fn map_colors<T: Channel>(pixels: &mut [T], f: impl Fn([f32; 3]) -> [f32; 3]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        if pixel[3] == T::default() {
+            continue;
+        }
+        let color = unpremultiply([pixel[0], pixel[1], pixel[2], pixel[3]]);
+        let [r, g, b] = f([color.r, color.g, color.b].map(Channel::to_srgb))
+            .map(|c| T::from_srgb(c.clamp(0.0, 1.0)));
+        pixel.copy_from_slice(&premultiply(Color::new(r, g, b, color.a)));
+    }
+}
+
+fn hue_saturation(c: [f32; 3], hue: f32, saturation: f32, lightness: f32) -> [f32; 3] {
+    let (h, s, l) = rgb_to_hsl(c);
+    let h = (h + hue / 360.0).rem_euclid(1.0);
+    let s = (s * (1.0 + saturation.clamp(-1.0, 1.0))).clamp(0.0, 1.0);
+    let lightness = lightness.clamp(-1.0, 1.0);
+    let l = if lightness < 0.0 {
+        l * (1.0 + lightness)
+    } else {
+        l + (1.0 - l) * lightness
+    };
+    hsl_to_rgb(h, s, l)
+}
+
+/// Hue, saturation and lightness of a color, all from 0 to 1
+pub(crate) fn rgb_to_hsl([r, g, b]: [f32; 3]) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let d = max - min;
+    if d == 0.0 {
+        return (0.0, 0.0, l);
+    }
+
+    let s = d / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        ((g - b) / d).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / d + 2.0
+    } else {
+        (r - g) / d + 4.0
+    };
+    (h / 6.0, s, l)
+}
+
+pub(crate) fn hsl_to_rgb(h: f32, s: f32, l: f32) -> [f32; 3] {
+    let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let h = h * 6.0;
+    let x = chroma * (1.0 - (h.rem_euclid(2.0) - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = l - chroma / 2.0;
+    [r + m, g + m, b + m]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adjusted(filter: Filter, color: Color<u8>) -> Color<u8> {
+        let mut pixels = premultiply(color).to_vec();
+        adjust(&filter, &mut pixels);
+        unpremultiply(pixels.try_into().unwrap())
+    }
+
+    #[test]
+    fn channel_adjustments() {
+        let color = Color::new(200, 100, 0, 255);
+        assert_eq!(
+            adjusted(Filter::Invert, color),
+            Color::new(55, 155, 255, 255)
+        );
+        assert_eq!(
+            adjusted(Filter::Posterize { levels: 2 }, color),
+            Color::new(255, 0, 0, 255)
+        );
+        assert_eq!(
+            adjusted(
+                Filter::BrightnessContrast {
+                    brightness: 0.0,
+                    contrast: 0.0
+                },
+                color
+            ),
+            color
+        );
+
+        let levels = Filter::Levels {
+            input_black: 100,
+            input_white: 200,
+            gamma: 1.0,
+            output_black: 0,
+            output_white: 255,
+        };
+        assert_eq!(adjusted(levels, color), Color::new(255, 0, 0, 255));
+
+        /* transparent pixels are left alone */
+        assert_eq!(
+            adjusted(Filter::Invert, Color::new(0, 0, 0, 0)),
+            Color::new(0, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn curves_pass_through_their_points() {
+        let curve = Curve(vec![(0, 0), (64, 128), (255, 255)]).evaluator();
+        assert_eq!((curve(0.0), curve(64.0), curve(255.0)), (0.0, 128.0, 255.0));
+        let values: Vec<f32> = (0..=255).map(|x| curve(x as f32)).collect();
+        assert!(values.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(Curve::default().evaluator()(77.0), 77.0);
+    }
+
+    #[test]
+    fn hue_turns_and_desaturate_keeps_luminosity() {
+        let red = Color::new(255, 0, 0, 255);
+        let turned = Filter::HueSaturation {
+            hue: 120.0,
+            saturation: 0.0,
+            lightness: 0.0,
+        };
+        assert_eq!(adjusted(turned, red), Color::new(0, 255, 0, 255));
+        assert_eq!(
+            adjusted(Filter::Desaturate, red),
+            Color::new(77, 77, 77, 255)
+        );
+        assert_eq!(
+            adjusted(Filter::Threshold { level: 128 }, red),
+            Color::new(0, 0, 0, 255)
+        );
+    }
+}
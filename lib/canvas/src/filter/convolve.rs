@@ -0,0 +1,678 @@
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use super::Filter;
+use crate::canvas::Rect;
+use crate::color::Channel;
+use crate::random;
+
+/// Rows below which a pass isn't worth splitting across threads
+const MIN_BAND_ROWS: usize = 16;
+
+/// Gaussian blurs with a larger deviation run as three box blurs instead of a kernel
+const MAX_KERNEL_SIGMA: f32 = 4.0;
+
+/// Most samples taken along the path of a radial blur
+const MAX_RADIAL_SAMPLES: usize = 64;
+
+/// Direction of a radial blur
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RadialBlurKind {
+    /// Smears along circles around the centre
+    #[default]
+    Spin,
+    /// Smears along lines through the centre
+    Zoom,
+}
+
+/// Area of the image a spatial filter reads to produce `area`, `None` for filters that
+/// only look at one pixel at a time
+pub(super) fn source_rect(filter: &Filter, area: Rect, bounds: Rect) -> Option<Rect> {
+    let reach = match *filter {
+        Filter::GaussianBlur { radius } => gaussian_reach(radius),
+        Filter::UnsharpMask { radius, .. } => gaussian_reach(radius),
+        Filter::BoxBlur { radius } | Filter::Median { radius } => radius,
+        Filter::MotionBlur { distance, .. } => (distance.max(0.0) / 2.0).ceil() as usize + 1,
+        Filter::RadialBlur { .. } => return Some(bounds),
+        Filter::Despeckle { .. } => 1,
+        Filter::AddNoise { .. } => 0,
+        Filter::Pixelate { size } => {
+            /* whole blocks are read so every block averages the same pixels */
+            let size = size.max(1);
+            let left = area.x - area.x % size;
+            let top = area.y - area.y % size;
+            let right = area.right().next_multiple_of(size);
+            let bottom = area.bottom().next_multiple_of(size);
+            return Rect::new(left, top, right - left, bottom - top).intersect(&bounds);
+        }
+        _ => return None,
+    };
+
+    let left = area.x.saturating_sub(reach);
+    let top = area.y.saturating_sub(reach);
+    Rect::new(
+        left,
+        top,
+        area.right() + reach - left,
+        area.bottom() + reach - top,
+    )
+    .intersect(&bounds)
+}
+
+/// Runs a spatial filter on the premultiplied pixels read from `source`, returning the
+/// filtered pixels inside `area`
+pub(super) fn convolve<T: Channel>(
+    filter: &Filter,
+    pixels: Vec<T>,
+    source: Rect,
+    area: Rect,
+) -> Vec<T> {
+    let image = Buffer {
+        width: source.width,
+        height: source.height,
+        pixels,
+    };
+
+    let filtered = match *filter {
+        Filter::GaussianBlur { radius } => gaussian_blur(&image, radius),
+        Filter::BoxBlur { radius } => separable(&image, |src, dst| box_line(src, dst, radius)),
+        Filter::MotionBlur { angle, distance } => motion_blur(&image, angle, distance),
+        Filter::RadialBlur {
+            center_x,
+            center_y,
+            amount,
+            kind,
+        } => {
+            let center = (center_x - source.x as f32, center_y - source.y as f32);
+            radial_blur(&image, center, amount, kind)
+        }
+        Filter::UnsharpMask {
+            radius,
+            amount,
+            threshold,
+        } => unsharp_mask(&image, radius, amount, threshold),
+        Filter::Median { radius } => median(&image, radius, None),
+        Filter::Despeckle { threshold } => median(&image, 1, Some(threshold)),
+        Filter::AddNoise {
+            amount,
+            monochrome,
+            seed,
+        } => add_noise(&image, (source.x, source.y), amount, monochrome, seed),
+        Filter::Pixelate { size } => pixelate(&image, (source.x, source.y), size),
+        _ => image,
+    };
+
+    if area == source {
+        return filtered.pixels;
+    }
+    let mut out = Vec::with_capacity(area.width * area.height * 4);
+    for y in area.y..area.bottom() {
+        let start = ((y - source.y) * filtered.width + area.x - source.x) * 4;
+        out.extend_from_slice(&filtered.pixels[start..start + area.width * 4]);
+    }
+    out
+}
+
+/// Tightly packed premultiplied RGBA pixels
+struct Buffer<T> {
+    width: usize,
+    height: usize,
+    pixels: Vec<T>,
+}
+
+impl<T: Channel> Buffer<T> {
+    fn row(&self, y: usize) -> &[T] {
+        &self.pixels[y * self.width * 4..][..self.width * 4]
+    }
+
+    /// Pixel at `x`, `y`, positions outside the buffer repeat its edge
+    fn pixel(&self, x: isize, y: isize) -> &[T] {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        &self.pixels[(y * self.width + x) * 4..][..4]
+    }
+
+    /// Bilinear sample with pixel centres at whole coordinates
+    fn sample(&self, x: f32, y: f32) -> [f32; 4] {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+
+        let mut out = [0.0; 4];
+        for (dx, dy, weight) in [
+            (0, 0, (1.0 - fx) * (1.0 - fy)),
+            (1, 0, fx * (1.0 - fy)),
+            (0, 1, (1.0 - fx) * fy),
+            (1, 1, fx * fy),
+        ] {
+            for (o, &c) in out.iter_mut().zip(self.pixel(x0 + dx, y0 + dy)) {
+                *o += c.to_f32() * weight;
+            }
+        }
+        out
+    }
+
+    /// Builds a buffer of the same size row by row, `f` gets the row index and the
+    /// output row. Rows are spread over all cores
+    fn map_rows(&self, f: impl Fn(usize, &mut [T]) + Sync) -> Buffer<T> {
+        let mut pixels = vec![T::default(); self.width * self.height * 4];
+        let row_len = self.width * 4;
+        if row_len > 0 {
+            let threads = thread::available_parallelism().map_or(1, |n| n.get());
+            let band_rows = self.height.div_ceil(threads).max(MIN_BAND_ROWS);
+            thread::scope(|scope| {
+                for (band, rows) in pixels.chunks_mut(band_rows * row_len).enumerate() {
+                    let f = &f;
+                    scope.spawn(move || {
+                        for (i, row) in rows.chunks_exact_mut(row_len).enumerate() {
+                            f(band * band_rows + i, row);
+                        }
+                    });
+                }
+            });
+        }
+
+        Buffer {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+
+    fn transposed(&self) -> Buffer<T> {
+        let transposed = Buffer {
+            width: self.height,
+            height: self.width,
+            pixels: Vec::new(),
+        };
+        transposed.map_rows(|x, row| {
+            for (y, pixel) in row.chunks_exact_mut(4).enumerate() {
+                pixel.copy_from_slice(&self.pixels[(y * self.width + x) * 4..][..4]);
+            }
+        })
+    }
+}
+
+/// Runs `line` over every row, then over every column
+fn separable<T: Channel>(image: &Buffer<T>, line: impl Fn(&[T], &mut [T]) + Sync) -> Buffer<T> {
+    let horizontal = image.map_rows(|y, row| line(image.row(y), row));
+    let columns = horizontal.transposed();
+    columns
+        .map_rows(|x, column| line(columns.row(x), column))
+        .transposed()
+}
+
+/// Box blur of a line of pixels, the edge pixels repeat
+fn box_line<T: Channel>(src: &[T], dst: &mut [T], radius: usize) {
+    box_lines(src, dst, &[radius]);
+}
+
+/// Runs box blurs of `radii` one after the other over a line of pixels, in floats so only
+/// the result is rounded
+fn box_lines<T: Channel>(src: &[T], dst: &mut [T], radii: &[usize]) {
+    if src.is_empty() {
+        return;
+    }
+    let mut line: Vec<[f32; 4]> = src
+        .chunks_exact(4)
+        .map(|pixel| std::array::from_fn(|c| pixel[c].to_f32()))
+        .collect();
+    let mut scratch = vec![[0.0; 4]; line.len()];
+    for &radius in radii {
+        box_pass(&line, &mut scratch, radius);
+        std::mem::swap(&mut line, &mut scratch);
+    }
+    for (pixel, value) in dst.chunks_exact_mut(4).zip(&line) {
+        for (p, &v) in pixel.iter_mut().zip(value) {
+            *p = T::from_f32(v);
+        }
+    }
+}
+
+/// Box blur of a non-empty line using a running sum
+fn box_pass(src: &[[f32; 4]], dst: &mut [[f32; 4]], radius: usize) {
+    let scale = 1.0 / (2 * radius + 1) as f64;
+    let radius = radius as isize;
+    let at = |x: isize| &src[x.clamp(0, src.len() as isize - 1) as usize];
+
+    let mut sum = [0.0f64; 4];
+    for x in -radius..=radius {
+        for (s, &c) in sum.iter_mut().zip(at(x)) {
+            *s += c as f64;
+        }
+    }
+    for (x, out) in dst.iter_mut().enumerate() {
+        let x = x as isize;
+        let (incoming, outgoing) = (at(x + radius + 1), at(x - radius));
+        for c in 0..4 {
+            out[c] = (sum[c] * scale) as f32;
+            sum[c] += incoming[c] as f64 - outgoing[c] as f64;
+        }
+    }
+}
+
+fn gaussian_reach(sigma: f32) -> usize {
+    (3.0 * sigma.max(0.0)).ceil() as usize
+}
+
+fn gaussian_blur<T: Channel>(image: &Buffer<T>, sigma: f32) -> Buffer<T> {
+    if sigma <= 0.0 {
+        return separable(image, |src, dst| dst.copy_from_slice(src));
+    }
+
+    if sigma > MAX_KERNEL_SIGMA {
+        /* three box blurs come within a few percent of a gaussian at any radius */
+        let radii = box_radii(sigma);
+        return separable(image, |src, dst| box_lines(src, dst, &radii));
+    }
+
+    let reach = gaussian_reach(sigma) as isize;
+    let weights: Vec<f32> = (-reach..=reach)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    let weights: Vec<f32> = weights.iter().map(|w| w / total).collect();
+
+    separable(image, |src, dst| {
+        /* the line as floats with its edge pixels repeated `reach` times on both ends,
+         * so the kernel runs without bounds checks */
+        let length = src.len() as isize / 4;
+        let padded: Vec<[f32; 4]> = (-reach..length + reach)
+            .map(|x| {
+                let at = x.clamp(0, length - 1) as usize * 4;
+                std::array::from_fn(|c| src[at + c].to_f32())
+            })
+            .collect();
+
+        for (pixel, window) in dst.chunks_exact_mut(4).zip(padded.windows(weights.len())) {
+            let mut sum = [0.0f32; 4];
+            for (weight, value) in weights.iter().zip(window) {
+                for c in 0..4 {
+                    sum[c] += value[c] * weight;
+                }
+            }
+            for (p, s) in pixel.iter_mut().zip(sum) {
+                *p = T::from_f32(s);
+            }
+        }
+    })
+}
+
+/// Radii of three box blurs that together approximate a gaussian with deviation `sigma`
+fn box_radii(sigma: f32) -> [usize; 3] {
+    const PASSES: f32 = 3.0;
+    let ideal = (12.0 * sigma * sigma / PASSES + 1.0).sqrt();
+    let mut lower = ideal.floor() as usize;
+    if lower.is_multiple_of(2) {
+        lower -= 1;
+    }
+    let l = lower as f32;
+    let small = ((12.0 * sigma * sigma - PASSES * l * l - 4.0 * PASSES * l - 3.0 * PASSES)
+        / (-4.0 * l - 4.0))
+        .round() as usize;
+
+    std::array::from_fn(|i| if i < small { lower / 2 } else { lower / 2 + 1 })
+}
+
+fn motion_blur<T: Channel>(image: &Buffer<T>, angle: f32, distance: f32) -> Buffer<T> {
+    let samples = distance.max(0.0).ceil() as usize + 1;
+    let (dy, dx) = angle.to_radians().sin_cos();
+    let step = if samples > 1 {
+        distance / (samples - 1) as f32
+    } else {
+        0.0
+    };
+
+    image.map_rows(|y, row| {
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let mut sum = [0.0f32; 4];
+            for i in 0..samples {
+                let t = i as f32 * step - distance / 2.0;
+                let sample = image.sample(x as f32 + t * dx, y as f32 + t * dy);
+                for (s, c) in sum.iter_mut().zip(sample) {
+                    *s += c;
+                }
+            }
+            for (p, s) in pixel.iter_mut().zip(sum) {
+                *p = T::from_f32(s / samples as f32);
+            }
+        }
+    })
+}
+
+fn radial_blur<T: Channel>(
+    image: &Buffer<T>,
+    center: (f32, f32),
+    amount: f32,
+    kind: RadialBlurKind,
+) -> Buffer<T> {
+    image.map_rows(|y, row| {
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let (rx, ry) = (x as f32 - center.0, y as f32 - center.1);
+            let distance = rx.hypot(ry);
+            /* one sample per pixel travelled keeps the smear smooth */
+            let travel = match kind {
+                RadialBlurKind::Spin => distance * amount.to_radians().abs(),
+                RadialBlurKind::Zoom => distance * amount.abs(),
+            };
+            let samples = (travel.ceil() as usize + 1).min(MAX_RADIAL_SAMPLES);
+
+            let mut sum = [0.0f32; 4];
+            for i in 0..samples {
+                let t = if samples > 1 {
+                    i as f32 / (samples - 1) as f32
+                } else {
+                    0.0
+                };
+                let (sx, sy) = match kind {
+                    RadialBlurKind::Spin => {
+                        let (sin, cos) = ((t - 0.5) * amount.to_radians()).sin_cos();
+                        (rx * cos - ry * sin, rx * sin + ry * cos)
+                    }
+                    RadialBlurKind::Zoom => {
+                        let scale = 1.0 - amount * t;
+                        (rx * scale, ry * scale)
+                    }
+                };
+                let sample = image.sample(center.0 + sx, center.1 + sy);
+                for (s, c) in sum.iter_mut().zip(sample) {
+                    *s += c;
+                }
+            }
+            for (p, s) in pixel.iter_mut().zip(sum) {
+                *p = T::from_f32(s / samples as f32);
+            }
+        }
+    })
+}
+
+/// Pushes every pixel away from a blurred copy of itself by `amount`, differences
+/// below `threshold`, in 8 bit steps, are left alone so flat areas don't turn grainy
+fn unsharp_mask<T: Channel>(
+    image: &Buffer<T>,
+    radius: f32,
+    amount: f32,
+    threshold: u8,
+) -> Buffer<T> {
+    let blurred = gaussian_blur(image, radius);
+    let threshold = threshold as f32 * T::MAX / 255.0;
+    image.map_rows(|y, row| {
+        let (original, blurred) = (image.row(y), blurred.row(y));
+        for ((pixel, original), blurred) in row
+            .chunks_exact_mut(4)
+            .zip(original.chunks_exact(4))
+            .zip(blurred.chunks_exact(4))
+        {
+            let alpha = original[3].to_f32();
+            for c in 0..3 {
+                let difference = original[c].to_f32() - blurred[c].to_f32();
+                pixel[c] = if difference.abs() < threshold {
+                    original[c]
+                } else {
+                    T::from_f32((original[c].to_f32() + difference * amount).min(alpha))
+                };
+            }
+            pixel[3] = original[3];
+        }
+    })
+}
+
+/// Median of every channel over a square window. With a `threshold`, in 8 bit steps, only
+/// pixels that stand out from their median by more than it are replaced, which removes specks
+fn median<T: Channel>(image: &Buffer<T>, radius: usize, threshold: Option<u8>) -> Buffer<T> {
+    let radius = radius as isize;
+    let threshold = threshold.map(|t| t as f32 * T::MAX / 255.0);
+
+    image.map_rows(|y, row| {
+        let y = y as isize;
+        let mut window = Vec::with_capacity(((2 * radius + 1) * (2 * radius + 1)) as usize);
+
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let x = x as isize;
+            let original = image.pixel(x, y);
+            let medians: [T; 4] = std::array::from_fn(|c| {
+                window.clear();
+                for dy in -radius..=radius {
+                    for dx in -radius..=radius {
+                        window.push(image.pixel(x + dx, y + dy)[c]);
+                    }
+                }
+                let middle = window.len() / 2;
+                *window
+                    .select_nth_unstable_by(middle, |a, b| a.to_f32().total_cmp(&b.to_f32()))
+                    .1
+            });
+            let replace = threshold.is_none_or(|threshold| {
+                medians
+                    .iter()
+                    .zip(original)
+                    .any(|(m, o)| (m.to_f32() - o.to_f32()).abs() > threshold)
+            });
+            pixel.copy_from_slice(if replace { &medians } else { original });
+        }
+    })
+}
+
+/// Adds random noise of up to `amount`, from 0 to 1, to every channel. The noise only
+/// depends on the canvas position and `seed`, so previews and tiles agree
+fn add_noise<T: Channel>(
+    image: &Buffer<T>,
+    origin: (usize, usize),
+    amount: f32,
+    monochrome: bool,
+    seed: u64,
+) -> Buffer<T> {
+    image.map_rows(|y, row| {
+        let src = image.row(y);
+        for (x, (pixel, original)) in row.chunks_exact_mut(4).zip(src.chunks_exact(4)).enumerate() {
+            let position = (((origin.1 + y) as u64) << 32) | (origin.0 + x) as u64;
+            let alpha = original[3].to_f32();
+            for c in 0..3 {
+                let channel = if monochrome { 0 } else { c as u64 };
+                let noise = noise(seed, position, channel) * amount * alpha;
+                pixel[c] = T::from_f32((original[c].to_f32() + noise).min(alpha));
+            }
+            pixel[3] = original[3];
+        }
+    })
+}
+
+/// Uniform noise between -1 and 1 from a splitmix64 hash
+fn noise(seed: u64, position: u64, channel: u64) -> f32 {
+    let z = seed
+        .wrapping_add(position.wrapping_mul(random::GAMMA))
+        .wrapping_add(channel.wrapping_mul(0xd1b5_4a32_d192_ed03));
+    random::unit(random::mix(z)) * 2.0 - 1.0
+}
+
+/// Replaces every `size` by `size` block, aligned to the canvas, with its average
+fn pixelate<T: Channel>(image: &Buffer<T>, origin: (usize, usize), size: usize) -> Buffer<T> {
+    let size = size.max(1);
+    /* blocks are counted from the canvas origin, the first one may be cut off */
+    let first = (origin.0 % size, origin.1 % size);
+    let columns = (first.0 + image.width).div_ceil(size);
+    let rows = (first.1 + image.height).div_ceil(size);
+
+    let mut sums = vec![[0.0f64; 5]; columns * rows];
+    for y in 0..image.height {
+        let block_row = (first.1 + y) / size;
+        for (x, pixel) in image.row(y).chunks_exact(4).enumerate() {
+            let block = &mut sums[block_row * columns + (first.0 + x) / size];
+            for (s, &c) in block.iter_mut().zip(pixel) {
+                *s += c.to_f32() as f64;
+            }
+            block[4] += 1.0;
+        }
+    }
+    let averages: Vec<[T; 4]> = sums
+        .iter()
+        .map(|s| std::array::from_fn(|c| T::from_f32((s[c] / s[4].max(1.0)) as f32)))
+        .collect();
+
+    image.map_rows(|y, row| {
+        let block_row = (first.1 + y) / size;
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            pixel.copy_from_slice(&averages[block_row * columns + (first.0 + x) / size]);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use crate::tile::TiledImage;
+
+    const SIZE: usize = 32;
+
+    /// An opaque test card: a gradient with a checker board, a few saturated dots and a
+    /// hard diagonal edge
+    fn card() -> TiledImage {
+        let mut image: TiledImage = TiledImage::new(SIZE, SIZE, [0, 0, 0, 255]);
+        let mut pixels = Vec::with_capacity(SIZE * SIZE * 4);
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let checker = if (x / 4 + y / 4) % 2 == 0 { 40 } else { 0 };
+                let pixel = if x > y + 8 {
+                    [250, 240, 30, 255]
+                } else if (x * 7 + y * 3) % 23 == 0 {
+                    [255, 0, 255, 255]
+                } else {
+                    [(x * 6) as u8 + checker, (y * 6) as u8, 120 + checker, 255]
+                };
+                pixels.extend(pixel);
+            }
+        }
+        image.write_rect(image.bounds(), &pixels);
+        image
+    }
+
+    /// Compares the filtered test card with the image in `testdata/filter`, allowing
+    /// one step of slack for float rounding differences between platforms
+    fn assert_golden(name: &str, filter: Filter) {
+        let image = card();
+        let pixels = filter.apply(&image, image.bounds());
+        testing::assert_golden("filter", name, (SIZE, SIZE), &pixels, 1);
+    }
+
+    #[test]
+    fn blurs_match_golden_images() {
+        assert_golden("gaussian_small", Filter::GaussianBlur { radius: 1.5 });
+        assert_golden("gaussian_large", Filter::GaussianBlur { radius: 6.0 });
+        assert_golden("box", Filter::BoxBlur { radius: 2 });
+        assert_golden(
+            "motion",
+            Filter::MotionBlur {
+                angle: 30.0,
+                distance: 8.0,
+            },
+        );
+        assert_golden(
+            "radial_spin",
+            Filter::RadialBlur {
+                center_x: 16.0,
+                center_y: 16.0,
+                amount: 20.0,
+                kind: RadialBlurKind::Spin,
+            },
+        );
+        assert_golden(
+            "radial_zoom",
+            Filter::RadialBlur {
+                center_x: 8.0,
+                center_y: 24.0,
+                amount: 0.2,
+                kind: RadialBlurKind::Zoom,
+            },
+        );
+    }
+
+    #[test]
+    fn detail_filters_match_golden_images() {
+        assert_golden(
+            "unsharp_mask",
+            Filter::UnsharpMask {
+                radius: 2.0,
+                amount: 1.0,
+                threshold: 4,
+            },
+        );
+        assert_golden("median", Filter::Median { radius: 1 });
+        assert_golden("despeckle", Filter::Despeckle { threshold: 32 });
+        assert_golden(
+            "noise",
+            Filter::AddNoise {
+                amount: 0.2,
+                monochrome: false,
+                seed: 7,
+            },
+        );
+        assert_golden("pixelate", Filter::Pixelate { size: 5 });
+    }
+
+    #[test]
+    fn box_radii_approximate_the_deviation() {
+        for sigma in [5.0f32, 12.0, 40.0] {
+            let variance: f32 = box_radii(sigma)
+                .iter()
+                .map(|&r| {
+                    let width = (2 * r + 1) as f32;
+                    (width * width - 1.0) / 12.0
+                })
+                .sum();
+            assert!((variance.sqrt() - sigma).abs() / sigma < 0.05);
+        }
+    }
+
+    #[test]
+    fn filtering_part_of_an_image_matches_the_whole() {
+        let image = card();
+        let area = Rect::new(5, 7, 13, 11);
+        for filter in [
+            Filter::GaussianBlur { radius: 2.0 },
+            Filter::Median { radius: 2 },
+            Filter::Pixelate { size: 4 },
+            Filter::AddNoise {
+                amount: 0.5,
+                monochrome: true,
+                seed: 1,
+            },
+        ] {
+            let whole = filter.apply(&image, image.bounds());
+            let part = filter.apply(&image, area);
+            for y in 0..area.height {
+                let start = ((area.y + y) * SIZE + area.x) * 4;
+                assert_eq!(
+                    &part[y * area.width * 4..][..area.width * 4],
+                    &whole[start..start + area.width * 4],
+                    "{filter:?}"
+                );
+            }
+        }
+    }
+
+    /* timings only mean something in release builds, run with
+     * `cargo test --release -p canvas -- --ignored` */
+    #[test]
+    #[ignore]
+    fn blurs_a_4k_layer_in_under_a_second() {
+        let (width, height) = (3840, 2160);
+        let mut image: TiledImage = TiledImage::new(width, height, [0, 0, 0, 0]);
+        let pixels: Vec<u8> = (0..width * height * 4)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect();
+        image.write_rect(image.bounds(), &pixels);
+
+        for filter in [
+            Filter::GaussianBlur { radius: 4.0 },
+            Filter::GaussianBlur { radius: 50.0 },
+        ] {
+            let start = std::time::Instant::now();
+            filter.apply(&image, image.bounds());
+            let elapsed = start.elapsed();
+            assert!(elapsed.as_secs_f32() < 1.0, "{filter:?} took {elapsed:?}");
+        }
+    }
+}
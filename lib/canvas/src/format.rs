@@ -0,0 +1,85 @@
+//! Reading and writing the canvas in external file formats
+
+pub mod ora;
+pub mod png;
+pub mod project;
+pub mod psd;
+
+use crate::Color;
+use crate::blend::premultiply;
+use crate::canvas::Rect;
+use crate::color::Channel;
+use crate::layer::{Layer, LayerStack};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("failed to decode png: {0}")]
+    PngDecoding(#[from] ::png::DecodingError),
+    #[error("failed to encode png: {0}")]
+    PngEncoding(#[from] ::png::EncodingError),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error("invalid xml: {0}")]
+    Xml(#[from] quick_xml::Error),
+    #[error("invalid manifest: {0}")]
+    Manifest(#[from] serde_json::Error),
+    #[error("invalid brush: {0}")]
+    Brush(serde_json::Error),
+    #[error("unsupported image: {0}")]
+    Unsupported(String),
+}
+
+/// Writes straight alpha sRGB `pixels` onto the layer with their top left corner at `x`, `y`,
+/// clipping everything outside of the layer
+pub(crate) fn place_rgba<T: Channel>(
+    layer: &mut Layer<T>,
+    x: i64,
+    y: i64,
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+) {
+    let left = x.max(0);
+    let top = y.max(0);
+    let right = (x + width as i64).min(layer.width() as i64);
+    let bottom = (y + height as i64).min(layer.height() as i64);
+    if right <= left || bottom <= top {
+        return;
+    }
+
+    let rect = Rect::new(
+        left as usize,
+        top as usize,
+        (right - left) as usize,
+        (bottom - top) as usize,
+    );
+    let mut premultiplied = Vec::with_capacity(rect.width * rect.height * 4);
+    for row in (top - y) as usize..(bottom - y) as usize {
+        let start = (row * width + (left - x) as usize) * 4;
+        for p in pixels[start..start + rect.width * 4].chunks_exact(4) {
+            premultiplied
+                .extend_from_slice(&premultiply(Color::new(p[0], p[1], p[2], p[3]).convert()));
+        }
+    }
+    layer.write_rect(rect, &premultiplied);
+}
+
+/// Groups holding a layer from the outermost to the one it sits in directly
+pub(crate) fn group_path<T: Channel>(stack: &LayerStack<T>, group: Option<usize>) -> Vec<usize> {
+    let mut path = vec![];
+    let mut current = group;
+    while let Some(index) = current {
+        /* a cycle of parents stops at the first repeated group */
+        if index >= stack.groups().len() || path.contains(&index) {
+            break;
+        }
+        path.push(index);
+        current = stack.groups()[index].parent;
+    }
+    path.reverse();
+    path
+}
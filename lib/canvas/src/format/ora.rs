@@ -0,0 +1,570 @@
+//! OpenRaster, the layered exchange format shared with Krita, MyPaint and GIMP.
+//!
+//! An .ora file is a zip archive starting with an uncompressed `mimetype` entry, followed
+//! by `stack.xml` describing the layers top to bottom, a png per layer and a flattened
+//! `mergedimage.png` with its thumbnail
+
+use std::io::{Read, Seek, Write};
+
+use quick_xml::Reader;
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use super::{Error, Result, group_path, place_rgba, png};
+use crate::Canvas;
+use crate::blend::BlendMode;
+use crate::canvas::Rect;
+use crate::color::Channel;
+use crate::layer::{Layer, LayerGroup, LayerStack};
+
+const MIMETYPE: &str = "image/openraster";
+
+/// Largest side of the thumbnail stored in the file
+const THUMBNAIL_SIZE: usize = 256;
+
+/// Name of the composite op OpenRaster uses for a blend mode
+pub fn composite_op(mode: BlendMode) -> &'static str {
+    match mode {
+        BlendMode::Normal => "svg:src-over",
+        BlendMode::Multiply => "svg:multiply",
+        BlendMode::Screen => "svg:screen",
+        BlendMode::Overlay => "svg:overlay",
+        BlendMode::Darken => "svg:darken",
+        BlendMode::Lighten => "svg:lighten",
+        BlendMode::ColorDodge => "svg:color-dodge",
+        BlendMode::ColorBurn => "svg:color-burn",
+        BlendMode::HardLight => "svg:hard-light",
+        BlendMode::SoftLight => "svg:soft-light",
+        BlendMode::Difference => "svg:difference",
+        BlendMode::Exclusion => "svg:exclusion",
+        BlendMode::Hue => "svg:hue",
+        BlendMode::Saturation => "svg:saturation",
+        BlendMode::Color => "svg:color",
+        BlendMode::Luminosity => "svg:luminosity",
+    }
+}
+
+/// Blend mode of an OpenRaster composite op, `None` for ops we can not represent
+pub fn blend_mode(composite_op: &str) -> Option<BlendMode> {
+    BlendMode::ALL
+        .iter()
+        .copied()
+        .find(|&mode| self::composite_op(mode) == composite_op)
+}
+
+/// Writes every layer of the canvas along with the merged image and its thumbnail
+pub fn export<T: Channel>(canvas: &Canvas<T>, writer: impl Write + Seek) -> Result<()> {
+    let mut zip = ZipWriter::new(writer);
+    /* the mimetype has to be readable without decompressing, the pngs are compressed already */
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(MIMETYPE.as_bytes())?;
+
+    let layers = canvas.layers();
+    let active = layers.active_index();
+    let mut stack = String::new();
+    /* groups around the previous layer, outermost first */
+    let mut open: Vec<usize> = vec![];
+    /* stack.xml lists the top most layer first */
+    for (index, layer) in layers.iter().enumerate().rev() {
+        /* leave the groups the layer is not part of, then enter the ones it is */
+        let path = group_path(layers, layer.group());
+        while !path.starts_with(&open) {
+            open.pop();
+            stack.push_str(&format!("{}</stack>\n", indent(open.len())));
+        }
+        for &group in &path[open.len()..] {
+            let group_properties = &layers.groups()[group];
+            stack.push_str(&format!(
+                "{}<stack name=\"{}\" opacity=\"{}\" visibility=\"{}\" composite-op=\"{}\" isolation=\"{}\">\n",
+                indent(open.len()),
+                escape(&group_properties.name),
+                group_properties.opacity,
+                visibility(group_properties.visible),
+                composite_op(group_properties.blend_mode.unwrap_or_default()),
+                if group_properties.blend_mode.is_some() { "isolate" } else { "auto" },
+            ));
+            open.push(group);
+        }
+
+        let src = format!("data/layer{index}.png");
+        let bounds = layer.content_bounds().unwrap_or(Rect::new(0, 0, 1, 1));
+        let pixels = layer.read_rgba(bounds);
+
+        zip.start_file(src.as_str(), stored)?;
+        png::encode(bounds.width, bounds.height, &pixels, &mut zip)?;
+
+        stack.push_str(&format!(
+            "{}<layer name=\"{}\" src=\"{src}\" x=\"{}\" y=\"{}\" opacity=\"{}\" visibility=\"{}\" composite-op=\"{}\" edit-locked=\"{}\" alpha-preserve=\"{}\" selected=\"{}\"/>\n",
+            indent(open.len()),
+            escape(layer.name()),
+            bounds.x,
+            bounds.y,
+            layer.opacity(),
+            visibility(layer.visible()),
+            composite_op(layer.blend_mode()),
+            layer.locked(),
+            layer.alpha_locked(),
+            index == active,
+        ));
+    }
+    while open.pop().is_some() {
+        stack.push_str(&format!("{}</stack>\n", indent(open.len())));
+    }
+
+    zip.start_file("stack.xml", SimpleFileOptions::default())?;
+    write!(
+        zip,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<image version=\"0.0.5\" w=\"{}\" h=\"{}\">\n  <stack>\n{stack}  </stack>\n</image>\n",
+        canvas.width(),
+        canvas.height(),
+    )?;
+
+    let merged = canvas.composite();
+    zip.start_file("mergedimage.png", stored)?;
+    png::encode(canvas.width(), canvas.height(), &merged, &mut zip)?;
+
+    let (width, height, thumbnail) = thumbnail(canvas.width(), canvas.height(), &merged);
+    zip.start_file("Thumbnails/thumbnail.png", stored)?;
+    png::encode(width, height, &thumbnail, &mut zip)?;
+
+    zip.finish()?;
+    Ok(())
+}
+
+/// Reads the layers of an .ora file into a new canvas.
+///
+/// Nested stacks become [`LayerGroup`]s, stacks isolated from the layers below them
+/// keep their composite op while the others pass their layers through
+pub fn import<T: Channel>(reader: impl Read + Seek) -> Result<Canvas<T>> {
+    let mut zip = ZipArchive::new(reader)?;
+
+    let mut xml = String::new();
+    zip.by_name("stack.xml")?.read_to_string(&mut xml)?;
+    let Stack {
+        width,
+        height,
+        entries,
+        groups,
+    } = parse_stack(&xml)?;
+    if width == 0 || height == 0 {
+        return Err(Error::Unsupported("image has no size".into()));
+    }
+
+    let mut layers = vec![];
+    let mut active = None;
+    /* stack.xml lists the top most layer first */
+    for (index, entry) in entries.into_iter().rev().enumerate() {
+        let mut data = vec![];
+        zip.by_name(&entry.src)?.read_to_end(&mut data)?;
+        let (png_width, png_height, pixels) = png::decode(data.as_slice())?;
+
+        let mut layer = Layer::new(entry.name, width, height);
+        place_rgba(&mut layer, entry.x, entry.y, png_width, png_height, &pixels);
+        layer.set_opacity(entry.opacity);
+        layer.set_visible(entry.visible);
+        layer.set_blend_mode(blend_mode(&entry.composite_op).unwrap_or_default());
+        layer.set_locked(entry.locked);
+        layer.set_alpha_locked(entry.alpha_locked);
+        layer.set_group(entry.group);
+        if entry.selected {
+            active = Some(index);
+        }
+        layers.push(layer);
+    }
+
+    let active = active.unwrap_or(layers.len().saturating_sub(1));
+    let mut layers = LayerStack::from_layers(layers, active)
+        .ok_or_else(|| Error::Unsupported("image has no layers".into()))?;
+    for group in groups {
+        layers.add_group(group);
+    }
+    Ok(Canvas::from_layers(layers))
+}
+
+/// Contents of stack.xml
+struct Stack {
+    width: usize,
+    height: usize,
+    /// Layers in document order, top most first
+    entries: Vec<StackEntry>,
+    groups: Vec<LayerGroup>,
+}
+
+/// A `<layer>` element of stack.xml
+struct StackEntry {
+    name: String,
+    src: String,
+    x: i64,
+    y: i64,
+    opacity: f32,
+    visible: bool,
+    composite_op: String,
+    locked: bool,
+    alpha_locked: bool,
+    selected: bool,
+    /// Index of the nested stack holding the layer
+    group: Option<usize>,
+}
+
+/// Parses stack.xml into the image size, its layers and the groups of its nested stacks
+fn parse_stack(xml: &str) -> Result<Stack> {
+    let mut reader = Reader::from_str(xml);
+    let mut size = (0, 0);
+    let mut entries = vec![];
+    let mut groups: Vec<LayerGroup> = vec![];
+    /* group of every open `<stack>`, innermost last. The root stack is not a group */
+    let mut open: Vec<Option<usize>> = vec![];
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) if element.name().as_ref() == b"stack" => {
+                if open.is_empty() {
+                    open.push(None);
+                    continue;
+                }
+                let mut group = LayerGroup::new(attribute(&element, "name")?.unwrap_or_default());
+                group.parent = open.last().copied().flatten();
+                group.opacity = attribute(&element, "opacity")?
+                    .and_then(|v| v.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                group.visible = attribute(&element, "visibility")?.as_deref() != Some("hidden");
+                /* only isolated stacks are composited on their own before blending */
+                if attribute(&element, "isolation")?.as_deref() == Some("isolate") {
+                    let op = attribute(&element, "composite-op")?.unwrap_or_default();
+                    group.blend_mode = Some(blend_mode(&op).unwrap_or_default());
+                }
+                groups.push(group);
+                open.push(Some(groups.len() - 1));
+            }
+            Event::End(element) if element.name().as_ref() == b"stack" => {
+                open.pop();
+            }
+            Event::Start(element) | Event::Empty(element) => match element.name().as_ref() {
+                b"image" => {
+                    size = (
+                        attribute(&element, "w")?
+                            .and_then(|w| w.parse().ok())
+                            .unwrap_or(0),
+                        attribute(&element, "h")?
+                            .and_then(|h| h.parse().ok())
+                            .unwrap_or(0),
+                    );
+                }
+                b"layer" => {
+                    let mut entry = parse_layer(&element)?;
+                    entry.group = open.last().copied().flatten();
+                    entries.push(entry);
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(Stack {
+        width: size.0,
+        height: size.1,
+        entries,
+        groups,
+    })
+}
+
+fn parse_layer(element: &BytesStart) -> Result<StackEntry> {
+    let src =
+        attribute(element, "src")?.ok_or_else(|| Error::Unsupported("layer without src".into()))?;
+    let number = |name: &str| -> Result<Option<f64>> {
+        Ok(attribute(element, name)?.and_then(|v| v.trim().parse().ok()))
+    };
+    let flag =
+        |name: &str| -> Result<bool> { Ok(attribute(element, name)?.as_deref() == Some("true")) };
+
+    Ok(StackEntry {
+        name: attribute(element, "name")?.unwrap_or_default(),
+        src,
+        x: number("x")?.unwrap_or(0.0) as i64,
+        y: number("y")?.unwrap_or(0.0) as i64,
+        opacity: number("opacity")?.unwrap_or(1.0) as f32,
+        visible: attribute(element, "visibility")?.as_deref() != Some("hidden"),
+        composite_op: attribute(element, "composite-op")?.unwrap_or_default(),
+        locked: flag("edit-locked")?,
+        alpha_locked: flag("alpha-preserve")?,
+        selected: flag("selected")?,
+        group: None,
+    })
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Result<Option<String>> {
+    let Some(attribute) = element
+        .try_get_attribute(name)
+        .map_err(quick_xml::Error::from)?
+    else {
+        return Ok(None);
+    };
+    Ok(Some(attribute.unescape_value()?.into_owned()))
+}
+
+fn visibility(visible: bool) -> &'static str {
+    if visible { "visible" } else { "hidden" }
+}
+
+/// Indentation of an element inside `depth` nested stacks
+fn indent(depth: usize) -> String {
+    "  ".repeat(depth + 2)
+}
+
+/// Scales straight alpha RGBA pixels down to fit into [`THUMBNAIL_SIZE`]
+fn thumbnail(width: usize, height: usize, pixels: &[u8]) -> (usize, usize, Vec<u8>) {
+    let scale = (THUMBNAIL_SIZE as f32 / width.max(height) as f32).min(1.0);
+    let thumb_width = ((width as f32 * scale).round() as usize).max(1);
+    let thumb_height = ((height as f32 * scale).round() as usize).max(1);
+
+    let mut output = Vec::with_capacity(thumb_width * thumb_height * 4);
+    for y in 0..thumb_height {
+        let source_y = (y * height / thumb_height).min(height - 1);
+        for x in 0..thumb_width {
+            let source_x = (x * width / thumb_width).min(width - 1);
+            let start = (source_y * width + source_x) * 4;
+            output.extend_from_slice(&pixels[start..start + 4]);
+        }
+    }
+    (thumb_width, thumb_height, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::Color;
+
+    fn encode_png(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+        let mut data = vec![];
+        png::encode(width, height, pixels, &mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn maps_every_blend_mode() {
+        for mode in BlendMode::ALL {
+            assert_eq!(blend_mode(composite_op(mode)), Some(mode));
+        }
+        assert_eq!(blend_mode("krita:dissolve"), None);
+    }
+
+    #[test]
+    fn round_trips_layers() {
+        let mut canvas: Canvas = Canvas::new(300, 200);
+        canvas.add_layer();
+        canvas.draw_pixel(150, 100, Color::new(255, 0, 0, 255));
+        canvas.draw_pixel(151, 140, Color::new(0, 255, 0, 128));
+        canvas.layers_mut().active_mut().set_name("Ink & \"lines\"");
+        canvas.layers_mut().active_mut().set_opacity(0.25);
+        canvas
+            .layers_mut()
+            .active_mut()
+            .set_blend_mode(BlendMode::Multiply);
+        canvas.add_layer();
+        canvas.layers_mut().active_mut().set_visible(false);
+        canvas.layers_mut().set_active(1);
+
+        let mut file = Cursor::new(vec![]);
+        export(&canvas, &mut file).unwrap();
+
+        let mut zip = ZipArchive::new(&mut file).unwrap();
+        assert_eq!(zip.by_index(0).unwrap().name(), "mimetype");
+        assert_eq!(
+            zip.by_index(0).unwrap().compression(),
+            CompressionMethod::Stored
+        );
+        let mut thumbnail = vec![];
+        zip.by_name("Thumbnails/thumbnail.png")
+            .unwrap()
+            .read_to_end(&mut thumbnail)
+            .unwrap();
+        let (width, height, _) = png::decode(thumbnail.as_slice()).unwrap();
+        assert_eq!((width, height), (256, 171));
+        drop(zip);
+
+        let imported: Canvas = import(file).unwrap();
+        assert_eq!(imported.layers().len(), 3);
+        assert_eq!(imported.layers().active_index(), 1);
+
+        let ink = imported.layers().get(1).unwrap();
+        assert_eq!(ink.name(), "Ink & \"lines\"");
+        assert_eq!(ink.opacity(), 0.25);
+        assert_eq!(ink.blend_mode(), BlendMode::Multiply);
+        assert_eq!(ink.pixel(150, 100), Some(Color::new(255, 0, 0, 255)));
+        assert_eq!(ink.pixel(151, 140), Some(Color::new(0, 255, 0, 128)));
+        assert!(!imported.layers().get(2).unwrap().visible());
+        assert_eq!(imported.composite(), canvas.composite());
+    }
+
+    #[test]
+    fn reads_hand_built_fixture() {
+        let stack = r#"<?xml version="1.0" encoding="UTF-8"?>
+<image w="4" h="3" version="0.0.3">
+  <stack>
+    <layer name="top" src="data/top.png" x="-1" y="2" composite-op="svg:screen" opacity="0.5"/>
+    <stack name="group">
+      <layer name="hidden" src="data/hidden.png" visibility="hidden"/>
+    </stack>
+    <layer name="bottom" src="data/bottom.png" composite-op="krita:unknown"/>
+  </stack>
+</image>"#;
+
+        let mut file = Cursor::new(vec![]);
+        let mut zip = ZipWriter::new(&mut file);
+        let options = SimpleFileOptions::default();
+        zip.start_file("mimetype", options).unwrap();
+        zip.write_all(MIMETYPE.as_bytes()).unwrap();
+        zip.start_file("stack.xml", options).unwrap();
+        zip.write_all(stack.as_bytes()).unwrap();
+        zip.start_file("data/top.png", options).unwrap();
+        zip.write_all(&encode_png(2, 2, &[10, 20, 30, 255].repeat(4)))
+            .unwrap();
+        zip.start_file("data/hidden.png", options).unwrap();
+        zip.write_all(&encode_png(1, 1, &[0, 0, 0, 255])).unwrap();
+        zip.start_file("data/bottom.png", options).unwrap();
+        zip.write_all(&encode_png(4, 3, &[200, 200, 200, 255].repeat(12)))
+            .unwrap();
+        zip.finish().unwrap();
+
+        let canvas: Canvas = import(file).unwrap();
+        assert_eq!((canvas.width(), canvas.height()), (4, 3));
+
+        let names: Vec<&str> = canvas.layers().iter().map(Layer::name).collect();
+        assert_eq!(names, ["bottom", "hidden", "top"]);
+        assert_eq!(canvas.layers().active_index(), 2);
+
+        let bottom = canvas.layers().get(0).unwrap();
+        assert_eq!(bottom.blend_mode(), BlendMode::Normal);
+        assert!(!canvas.layers().get(1).unwrap().visible());
+
+        /* the top layer is shifted one pixel left and two down, clipping its left column */
+        let top = canvas.layers().get(2).unwrap();
+        assert_eq!(top.blend_mode(), BlendMode::Screen);
+        assert_eq!(top.opacity(), 0.5);
+        assert_eq!(top.pixel(0, 2), Some(Color::new(10, 20, 30, 255)));
+        assert_eq!(top.pixel(1, 2), Some(Color::new(0, 0, 0, 0)));
+        assert_eq!(top.pixel(0, 1), Some(Color::new(0, 0, 0, 0)));
+    }
+
+    #[test]
+    fn round_trips_groups() {
+        let mut canvas: Canvas = Canvas::new(4, 4);
+        let outer = canvas.layers_mut().add_group(LayerGroup {
+            opacity: 0.5,
+            ..LayerGroup::new("outer")
+        });
+        let inner = canvas.layers_mut().add_group(LayerGroup {
+            parent: Some(outer),
+            blend_mode: Some(BlendMode::Multiply),
+            ..LayerGroup::new("inner")
+        });
+        let hidden = canvas.layers_mut().add_group(LayerGroup {
+            visible: false,
+            ..LayerGroup::new("hidden")
+        });
+
+        /* bottom to top: background, outer [a, inner [b], c], hidden [d], top */
+        for (group, color) in [
+            (Some(outer), Color::new(255, 0, 0, 255)),
+            (Some(inner), Color::new(0, 255, 0, 255)),
+            (Some(outer), Color::new(0, 0, 255, 128)),
+            (Some(hidden), Color::new(255, 255, 0, 255)),
+            (None, Color::new(0, 0, 0, 64)),
+        ] {
+            canvas.add_layer();
+            canvas.layers_mut().active_mut().set_group(group);
+            canvas.draw_pixel(1, 1, color);
+        }
+
+        let mut file = Cursor::new(vec![]);
+        export(&canvas, &mut file).unwrap();
+        let imported: Canvas = import(file).unwrap();
+
+        let layers = imported.layers();
+        let groups: Vec<Option<&str>> = layers
+            .iter()
+            .map(|layer| layer.group().map(|g| layers.groups()[g].name.as_str()))
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                None,
+                Some("outer"),
+                Some("inner"),
+                Some("outer"),
+                Some("hidden"),
+                None
+            ]
+        );
+
+        let inner = &layers.groups()[layers.get(2).unwrap().group().unwrap()];
+        assert_eq!(inner.blend_mode, Some(BlendMode::Multiply));
+        let outer = &layers.groups()[inner.parent.unwrap()];
+        assert_eq!((outer.opacity, outer.blend_mode), (0.5, None));
+
+        /* the layer keeps its own visibility, the group hides it */
+        let in_hidden = layers.get(4).unwrap();
+        assert!(in_hidden.visible());
+        assert!(!layers.groups()[in_hidden.group().unwrap()].visible);
+
+        assert_eq!(imported.composite(), canvas.composite());
+    }
+
+    #[test]
+    fn nested_stacks_become_groups() {
+        let stack = r#"<image w="2" h="2">
+  <stack>
+    <stack name="outer" opacity="0.5">
+      <layer name="half" src="a.png" opacity="0.5"/>
+      <stack name="inner" visibility="hidden" composite-op="svg:multiply" isolation="isolate">
+        <layer name="hidden" src="b.png"/>
+      </stack>
+      <stack/>
+      <layer name="after" src="c.png"/>
+    </stack>
+    <layer name="outside" src="d.png" opacity="0.8"/>
+  </stack>
+</image>"#;
+
+        let stack = parse_stack(stack).unwrap();
+        let layers: Vec<_> = stack
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.opacity, entry.group))
+            .collect();
+        assert_eq!(
+            layers,
+            [
+                ("half", 0.5, Some(0)),
+                ("hidden", 1.0, Some(1)),
+                ("after", 1.0, Some(0)),
+                ("outside", 0.8, None),
+            ]
+        );
+
+        let outer = &stack.groups[0];
+        assert_eq!(
+            (
+                outer.name.as_str(),
+                outer.parent,
+                outer.opacity,
+                outer.visible,
+                outer.blend_mode
+            ),
+            ("outer", None, 0.5, true, None)
+        );
+        let inner = &stack.groups[1];
+        assert_eq!(
+            (inner.parent, inner.visible, inner.blend_mode),
+            (Some(0), false, Some(BlendMode::Multiply))
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use std::io::{Read, Write};
+
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+
+use super::{Error, Result};
+use crate::blend;
+use crate::canvas::Rect;
+use crate::color::{Channel, PixelFormat, linear_to_srgb};
+use crate::{Canvas, Color, Layer};
+
+/// Options for [`export`]
+#[derive(Debug, Clone, Copy)]
+pub struct ExportOptions {
+    /// Write 16 bits per channel instead of 8
+    pub sixteen_bit: bool,
+    /// Keep the transparency of the canvas, otherwise it is flattened onto white
+    pub transparent_background: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            sixteen_bit: false,
+            transparent_background: true,
+        }
+    }
+}
+
+/// Writes the flattened canvas as an RGBA png
+pub fn export<T: Channel>(
+    canvas: &Canvas<T>,
+    options: ExportOptions,
+    writer: impl Write,
+) -> Result<()> {
+    if options.sixteen_bit && T::LINEAR {
+        return export_linear(canvas, options, writer);
+    }
+
+    let mut pixels = canvas.composite();
+    if !options.transparent_background {
+        flatten_onto(&mut pixels, Color::new(255, 255, 255, 255));
+    }
+
+    if !options.sixteen_bit {
+        return encode(canvas.width(), canvas.height(), &pixels, writer);
+    }
+
+    let mut encoder = Encoder::new(writer, canvas.width() as u32, canvas.height() as u32);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Sixteen);
+    /* png stores 16 bit samples big endian, 257 maps 255 onto 65535 */
+    let pixels: Vec<u8> = pixels
+        .iter()
+        .flat_map(|&channel| (channel as u16 * 257).to_be_bytes())
+        .collect();
+    encoder.write_header()?.write_image_data(&pixels)?;
+    Ok(())
+}
+
+/// Writes a 16 bit png of a linear canvas without going through 8 bits, flattening
+/// in linear light like the canvas blends
+fn export_linear<T: Channel>(
+    canvas: &Canvas<T>,
+    options: ExportOptions,
+    writer: impl Write,
+) -> Result<()> {
+    let bounds = Rect::new(0, 0, canvas.width(), canvas.height());
+    let mut pixels = canvas.composite_rect_linear(bounds);
+    if !options.transparent_background {
+        for pixel in pixels.chunks_exact_mut(4) {
+            let alpha = pixel[3];
+            for channel in &mut pixel[..3] {
+                *channel = *channel * alpha + 1.0 - alpha;
+            }
+            pixel[3] = 1.0;
+        }
+    }
+
+    let mut encoder = Encoder::new(writer, canvas.width() as u32, canvas.height() as u32);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Sixteen);
+    let pixels: Vec<u8> = pixels
+        .chunks_exact(4)
+        .flat_map(|pixel| {
+            let [red, green, blue, alpha] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+            [
+                linear_to_srgb(red),
+                linear_to_srgb(green),
+                linear_to_srgb(blue),
+                alpha,
+            ]
+        })
+        .flat_map(|channel| ((channel.clamp(0.0, 1.0) * 65535.0).round() as u16).to_be_bytes())
+        .collect();
+    encoder.write_header()?.write_image_data(&pixels)?;
+    Ok(())
+}
+
+/// Writes tightly packed straight alpha RGBA8 pixels as a png
+pub fn encode(width: usize, height: usize, pixels: &[u8], writer: impl Write) -> Result<()> {
+    let mut encoder = Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+    encoder.write_header()?.write_image_data(pixels)?;
+    Ok(())
+}
+
+/// Reads a png of any color type into a new canvas holding it as its only layer.
+///
+/// Canvases wider than 8 bits per channel decode the file at 16 bits so deep pngs keep
+/// their precision
+pub fn import<T: Channel>(reader: impl Read) -> Result<Canvas<T>> {
+    if T::FORMAT == PixelFormat::Srgb8 {
+        let (width, height, pixels) = decode(reader)?;
+        let layer = Layer::from_rgba("Background", width, height, &pixels);
+        return Ok(Canvas::from_layer(layer));
+    }
+
+    let (width, height, pixels) = decode16(reader)?;
+    let mut layer = Layer::new("Background", width, height);
+    let premultiplied: Vec<T> = pixels
+        .chunks_exact(4)
+        .flat_map(|p| {
+            let srgb = |c: u16| T::from_srgb(c as f32 / 65535.0);
+            let alpha = T::from_normalized(p[3] as f32 / 65535.0);
+            blend::premultiply(Color::new(srgb(p[0]), srgb(p[1]), srgb(p[2]), alpha))
+        })
+        .collect();
+    layer.write_rect(layer.bounds(), &premultiplied);
+    Ok(Canvas::from_layer(layer))
+}
+
+/// Decodes a png into straight alpha RGBA8 pixels, returning its width and height along them
+pub fn decode(reader: impl Read) -> Result<(usize, usize, Vec<u8>)> {
+    let mut decoder = Decoder::new(reader);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    let pixels = expand_to_rgba(info.color_type, &buffer[..info.buffer_size()], 255)?;
+
+    Ok((info.width as usize, info.height as usize, pixels))
+}
+
+/// Decodes a png into straight alpha sRGB RGBA16 pixels, scaling up files with fewer bits
+/// per channel, returning its width and height along them
+pub fn decode16(reader: impl Read) -> Result<(usize, usize, Vec<u16>)> {
+    let mut decoder = Decoder::new(reader);
+    decoder.set_transformations(Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    let buffer = &buffer[..info.buffer_size()];
+
+    /* png stores 16 bit samples big endian, 257 maps 255 onto 65535 */
+    let samples: Vec<u16> = match info.bit_depth {
+        BitDepth::Sixteen => buffer
+            .chunks_exact(2)
+            .map(|s| u16::from_be_bytes([s[0], s[1]]))
+            .collect(),
+        _ => buffer.iter().map(|&s| s as u16 * 257).collect(),
+    };
+    let pixels = expand_to_rgba(info.color_type, &samples, u16::MAX)?;
+
+    Ok((info.width as usize, info.height as usize, pixels))
+}
+
+/// Expands decoded samples of any non indexed color type into RGBA, `opaque` being the
+/// alpha of color types without one
+fn expand_to_rgba<S: Copy>(color_type: ColorType, samples: &[S], opaque: S) -> Result<Vec<S>> {
+    Ok(match color_type {
+        ColorType::Rgba => samples.to_vec(),
+        ColorType::Rgb => samples
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], opaque])
+            .collect(),
+        ColorType::GrayscaleAlpha => samples
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        ColorType::Grayscale => samples.iter().flat_map(|&g| [g, g, g, opaque]).collect(),
+        ColorType::Indexed => {
+            return Err(Error::Unsupported("indexed png was not expanded".into()));
+        }
+    })
+}
+
+/// Composites straight alpha RGBA `pixels` over an opaque `background`
+fn flatten_onto(pixels: &mut [u8], background: Color<u8>) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let mut flat = blend::premultiply(background);
+        blend::source_over(
+            &mut flat,
+            blend::premultiply(Color::new(pixel[0], pixel[1], pixel[2], pixel[3])),
+            1.0,
+        );
+        pixel.copy_from_slice(&flat);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_eight_bit() {
+        let mut canvas: Canvas = Canvas::new(3, 2);
+        canvas.draw_pixel(1, 1, Color::new(10, 20, 30, 255));
+
+        let mut file = vec![];
+        export(&canvas, ExportOptions::default(), &mut file).unwrap();
+
+        let imported: Canvas = import(file.as_slice()).unwrap();
+        assert_eq!((imported.width(), imported.height()), (3, 2));
+        assert_eq!(imported.composite(), canvas.composite());
+    }
+
+    #[test]
+    fn sixteen_bit_is_reduced_on_import() {
+        let mut canvas: Canvas = Canvas::new(2, 2);
+        canvas.layers_mut().active_mut().set_visible(false);
+        canvas.add_layer();
+        canvas.draw_pixel(0, 0, Color::new(200, 100, 50, 255));
+
+        let options = ExportOptions {
+            sixteen_bit: true,
+            transparent_background: false,
+        };
+        let mut file = vec![];
+        export(&canvas, options, &mut file).unwrap();
+
+        let (_, _, pixels) = decode(file.as_slice()).unwrap();
+        assert_eq!(&pixels[0..4], &[200, 100, 50, 255]);
+        /* the hidden background leaves the canvas transparent, which is flattened onto white */
+        assert_eq!(&pixels[4..8], &[255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn sixteen_bit_round_trips_into_deep_canvases() {
+        let mut canvas: Canvas<u16> = Canvas::new(2, 1);
+        /* a value between two 8 bit steps, which a reduction to 8 bits would lose */
+        let color = Color::new(30_000, 12_345, 54_321, 65_535);
+        canvas.draw_pixel(0, 0, color);
+
+        let options = ExportOptions {
+            sixteen_bit: true,
+            transparent_background: true,
+        };
+        let mut file = vec![];
+        export(&canvas, options, &mut file).unwrap();
+
+        let imported: Canvas<u16> = import(file.as_slice()).unwrap();
+        let pixel = imported.layers().active().pixel(0, 0).unwrap();
+        for (imported, original) in [pixel.r, pixel.g, pixel.b]
+            .into_iter()
+            .zip([color.r, color.g, color.b])
+        {
+            /* sRGB quantization at 16 bits stays well below one 8 bit step of 257 */
+            assert!(imported.abs_diff(original) < 16, "{imported} != {original}");
+        }
+        assert_eq!(pixel.a, 65_535);
+    }
+}
@@ -0,0 +1,408 @@
+//! Native project files, a zip archive holding a json manifest and the raw
+//! premultiplied pixels of every allocated layer tile, stored little endian in the
+//! working format of the canvas
+
+use std::io::{Read, Seek, Write};
+
+use serde::{Deserialize, Serialize};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use super::{Error, Result};
+use crate::Canvas;
+use crate::blend::BlendMode;
+use crate::brush::BrushSettings;
+use crate::color::{Channel, ColorHistory, PixelFormat};
+use crate::layer::{Layer, LayerGroup, LayerStack};
+use crate::tile::{Tile, TiledImage};
+
+/// Version written into new manifests, files of this or any older version can be opened.
+/// Version 2 added working formats other than 8 bit sRGB
+pub const FORMAT_VERSION: u32 = 2;
+
+const MANIFEST: &str = "manifest.json";
+
+/// Everything stored in a project file
+pub struct Project<T: Channel = u8> {
+    pub canvas: Canvas<T>,
+    pub brush: BrushSettings,
+    pub color_history: ColorHistory,
+}
+
+/* fields added after the first version need a serde default so older files keep loading */
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    width: usize,
+    height: usize,
+    /// Working format of the stored tiles
+    #[serde(default)]
+    format: PixelFormat,
+    #[serde(default)]
+    view: ViewState,
+    layers: Vec<LayerEntry>,
+    #[serde(default)]
+    groups: Vec<LayerGroup>,
+    #[serde(default)]
+    active_layer: usize,
+    #[serde(default)]
+    brush: BrushSettings,
+    #[serde(default)]
+    color_history: ColorHistory,
+}
+
+#[derive(Deserialize)]
+struct ManifestVersion {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct ManifestFormat {
+    #[serde(default)]
+    format: PixelFormat,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ViewState {
+    zoom: f32,
+    rotation: f32,
+    offset_x: f32,
+    offset_y: f32,
+    #[serde(default)]
+    mirror_x: bool,
+    #[serde(default)]
+    mirror_y: bool,
+}
+
+impl Default for ViewState {
+    fn default() -> Self {
+        Self {
+            zoom: 1.0,
+            rotation: 0.0,
+            offset_x: 0.0,
+            offset_y: 0.0,
+            mirror_x: false,
+            mirror_y: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LayerEntry {
+    name: String,
+    opacity: f32,
+    blend_mode: BlendMode,
+    visible: bool,
+    locked: bool,
+    alpha_locked: bool,
+    #[serde(default)]
+    group: Option<usize>,
+    /// Premultiplied raw channel values of every tile that is not stored
+    fill: [f32; 4],
+    /// Column and row of every stored tile
+    tiles: Vec<(usize, usize)>,
+}
+
+fn tile_path(layer: usize, column: usize, row: usize) -> String {
+    format!("layers/{layer}/{column}_{row}")
+}
+
+/// Writes the canvas along with the editor state that belongs to the document
+pub fn save<T: Channel>(
+    canvas: &Canvas<T>,
+    brush: &BrushSettings,
+    color_history: &ColorHistory,
+    writer: impl Write + Seek,
+) -> Result<()> {
+    let mut zip = ZipWriter::new(writer);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut layers = vec![];
+    for (index, layer) in canvas.layers().iter().enumerate() {
+        let mut tiles = vec![];
+        for tile in layer.pixels().tiles() {
+            let Some(pixels) = tile.tile else {
+                continue;
+            };
+            let mut bytes = Vec::with_capacity(pixels.byte_size());
+            for &channel in pixels.pixels() {
+                channel.write_le(&mut bytes);
+            }
+            zip.start_file(tile_path(index, tile.column, tile.row), options)?;
+            zip.write_all(&bytes)?;
+            tiles.push((tile.column, tile.row));
+        }
+
+        layers.push(LayerEntry {
+            name: layer.name().to_string(),
+            opacity: layer.opacity(),
+            blend_mode: layer.blend_mode(),
+            visible: layer.visible(),
+            locked: layer.locked(),
+            alpha_locked: layer.alpha_locked(),
+            group: layer.group(),
+            fill: layer.pixels().fill().map(Channel::to_f32),
+            tiles,
+        });
+    }
+
+    let manifest = Manifest {
+        version: FORMAT_VERSION,
+        width: canvas.width(),
+        height: canvas.height(),
+        format: T::FORMAT,
+        view: ViewState {
+            zoom: canvas.zoom(),
+            rotation: canvas.rotation(),
+            offset_x: canvas.offset().x,
+            offset_y: canvas.offset().y,
+            mirror_x: canvas.view().mirrored().0,
+            mirror_y: canvas.view().mirrored().1,
+        },
+        layers,
+        groups: canvas.layers().groups().to_vec(),
+        active_layer: canvas.layers().active_index(),
+        brush: brush.clone(),
+        color_history: color_history.clone(),
+    };
+
+    zip.start_file(MANIFEST, options)?;
+    serde_json::to_writer_pretty(&mut zip, &manifest)?;
+    zip.finish()?;
+    Ok(())
+}
+
+/// Reads a project file written by [`save`] with this or an older format version,
+/// converting it into the working format `T`
+pub fn open<T: Channel>(reader: impl Read + Seek) -> Result<Project<T>> {
+    let mut zip = ZipArchive::new(reader)?;
+
+    let mut json = vec![];
+    zip.by_name(MANIFEST)?.read_to_end(&mut json)?;
+
+    let ManifestVersion { version } = serde_json::from_slice(&json)?;
+    if version > FORMAT_VERSION {
+        return Err(Error::Unsupported(format!(
+            "project format version {version} is newer than {FORMAT_VERSION}"
+        )));
+    }
+    let manifest: Manifest = serde_json::from_slice(&json)?;
+
+    let mut layers = vec![];
+    for (index, entry) in manifest.layers.into_iter().enumerate() {
+        let (width, height) = (manifest.width, manifest.height);
+        let image = match manifest.format {
+            PixelFormat::Srgb8 => read_image::<u8, T>(&mut zip, index, &entry, width, height),
+            PixelFormat::Linear16 => read_image::<u16, T>(&mut zip, index, &entry, width, height),
+            PixelFormat::LinearF32 => read_image::<f32, T>(&mut zip, index, &entry, width, height),
+        }?;
+
+        let mut layer = Layer::from_image(entry.name, image);
+        layer.set_opacity(entry.opacity);
+        layer.set_blend_mode(entry.blend_mode);
+        layer.set_visible(entry.visible);
+        layer.set_locked(entry.locked);
+        layer.set_alpha_locked(entry.alpha_locked);
+        layer.set_group(entry.group);
+        layers.push(layer);
+    }
+
+    let mut layers = LayerStack::from_layers(layers, manifest.active_layer)
+        .ok_or_else(|| Error::Unsupported("project has no layers".into()))?;
+    for group in manifest.groups {
+        layers.add_group(group);
+    }
+
+    let mut canvas = Canvas::from_layers(layers);
+    canvas.set_zoom(manifest.view.zoom);
+    canvas.set_rotation(manifest.view.rotation);
+    canvas.set_offset(manifest.view.offset_x, manifest.view.offset_y);
+    canvas
+        .view_mut()
+        .set_mirrored(manifest.view.mirror_x, manifest.view.mirror_y);
+
+    Ok(Project {
+        canvas,
+        brush: manifest.brush,
+        color_history: manifest.color_history,
+    })
+}
+
+/// The working format a project file was saved in, so it can be opened without converting
+pub fn stored_format(reader: impl Read + Seek) -> Result<PixelFormat> {
+    let mut zip = ZipArchive::new(reader)?;
+
+    let mut json = vec![];
+    zip.by_name(MANIFEST)?.read_to_end(&mut json)?;
+    let ManifestFormat { format } = serde_json::from_slice(&json)?;
+    Ok(format)
+}
+
+/// Reads the tiles of a layer stored in the working format `S`, converted into `T`
+fn read_image<S: Channel, T: Channel>(
+    zip: &mut ZipArchive<impl Read + Seek>,
+    index: usize,
+    entry: &LayerEntry,
+    width: usize,
+    height: usize,
+) -> Result<TiledImage<T>> {
+    let mut image = TiledImage::<S>::new(width, height, entry.fill.map(S::from_f32));
+    for &(column, row) in &entry.tiles {
+        let mut bytes = vec![];
+        zip.by_name(&tile_path(index, column, row))?
+            .read_to_end(&mut bytes)?;
+        let size = std::mem::size_of::<S>();
+        let pixels = bytes.chunks_exact(size).map(S::read_le).collect();
+        let tile = Tile::from_pixels(pixels)
+            .filter(|_| bytes.len().is_multiple_of(size))
+            .ok_or_else(|| Error::Unsupported(format!("corrupt tile {column}_{row}")))?;
+        image.replace_tile(column, row, Some(tile));
+    }
+    Ok(image.convert())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::Color;
+    use crate::brush::Airbrush;
+
+    #[test]
+    fn round_trips_the_document() {
+        let mut canvas: Canvas = Canvas::new(100, 70);
+        canvas.add_layer();
+        canvas.draw_pixel(80, 65, Color::new(1, 2, 3, 128));
+        canvas.layers_mut().active_mut().set_opacity(0.5);
+        canvas
+            .layers_mut()
+            .active_mut()
+            .set_blend_mode(BlendMode::Screen);
+        let group = canvas.layers_mut().add_group(LayerGroup::new("inks"));
+        canvas.layers_mut().active_mut().set_group(Some(group));
+        canvas.layers_mut().set_active(0);
+        canvas.set_zoom(2.5);
+        canvas.set_offset(-3.0, 4.0);
+        canvas.view_mut().set_mirrored(true, false);
+
+        let brush = BrushSettings::Airbrush(Airbrush {
+            size: 7.0,
+            ..Default::default()
+        });
+        let mut colors = ColorHistory::default();
+        colors.push(Color::new(9, 8, 7, 255));
+
+        let mut file = Cursor::new(vec![]);
+        save(&canvas, &brush, &colors, &mut file).unwrap();
+        let project = open::<u8>(file).unwrap();
+
+        let opened = &project.canvas;
+        assert_eq!((opened.width(), opened.height()), (100, 70));
+        assert_eq!(opened.zoom(), 2.5);
+        assert_eq!(opened.offset().y, 4.0);
+        assert_eq!(opened.view().mirrored(), (true, false));
+        assert_eq!(opened.layers().len(), 2);
+        assert_eq!(opened.layers().active_index(), 0);
+
+        let layer = opened.layers().get(1).unwrap();
+        assert_eq!(layer.opacity(), 0.5);
+        assert_eq!(layer.blend_mode(), BlendMode::Screen);
+        assert_eq!(layer.group(), Some(0));
+        assert_eq!(opened.layers().groups(), [LayerGroup::new("inks")]);
+        assert_eq!(
+            layer.memory_size(),
+            canvas.layers().get(1).unwrap().memory_size()
+        );
+        assert_eq!(opened.composite(), canvas.composite());
+
+        assert!(matches!(
+            project.brush,
+            BrushSettings::Airbrush(Airbrush { size: 7.0, .. })
+        ));
+        assert_eq!(
+            project.color_history.iter().next(),
+            Some(&Color::new(9, 8, 7, 255))
+        );
+    }
+
+    #[test]
+    fn loads_manifests_without_optional_fields() {
+        let mut file = Cursor::new(vec![]);
+        let mut zip = ZipWriter::new(&mut file);
+        zip.start_file(MANIFEST, SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(
+            br#"{"version": 1, "width": 4, "height": 4, "view": {
+                "zoom": 2.0, "rotation": 0.0, "offset_x": 0.0, "offset_y": 0.0
+            }, "layers": [{
+                "name": "Background", "opacity": 1.0, "blend_mode": "Normal", "visible": true,
+                "locked": false, "alpha_locked": false, "fill": [255, 0, 0, 255], "tiles": []
+            }]}"#,
+        )
+        .unwrap();
+        zip.finish().unwrap();
+
+        file.set_position(0);
+        assert_eq!(stored_format(&mut file).unwrap(), PixelFormat::Srgb8);
+        file.set_position(0);
+        let project = open::<u8>(file).unwrap();
+        assert_eq!(project.canvas.zoom(), 2.0);
+        assert_eq!(project.canvas.view().mirrored(), (false, false));
+        assert!(project.color_history.is_empty());
+        assert_eq!(&project.canvas.composite()[..4], &[255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let mut file = Cursor::new(vec![]);
+        let mut zip = ZipWriter::new(&mut file);
+        zip.start_file(MANIFEST, SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(br#"{"version": 99}"#).unwrap();
+        zip.finish().unwrap();
+
+        assert!(matches!(open::<u8>(file), Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn keeps_the_working_format() {
+        let mut canvas = Canvas::<u16>::new(70, 70);
+        canvas.add_layer();
+        canvas.draw_pixel(65, 3, Color::new(1000, 20000, 65535, 40000));
+
+        let mut file = Cursor::new(vec![]);
+        save(
+            &canvas,
+            &BrushSettings::default(),
+            &ColorHistory::default(),
+            &mut file,
+        )
+        .unwrap();
+
+        file.set_position(0);
+        assert_eq!(stored_format(&mut file).unwrap(), PixelFormat::Linear16);
+        file.set_position(0);
+        let linear = open::<u16>(&mut file).unwrap().canvas;
+        assert_eq!(
+            linear.layers().get(1).unwrap().pixel(65, 3),
+            canvas.layers().get(1).unwrap().pixel(65, 3)
+        );
+
+        file.set_position(0);
+        let srgb = open::<u8>(&mut file).unwrap().canvas;
+        assert_eq!(
+            srgb.layers().get(0).unwrap().pixel(0, 0),
+            Some(Color::new(255, 255, 255, 255))
+        );
+        assert_eq!(
+            srgb.layers().get(1).unwrap().pixel(65, 3),
+            canvas
+                .layers()
+                .get(1)
+                .unwrap()
+                .pixel(65, 3)
+                .map(Color::convert)
+        );
+    }
+}
@@ -0,0 +1,1191 @@
+//! Photoshop documents, limited to 8 bit RGB.
+//!
+//! Raster layers keep their names, opacity, visibility, locks and blend modes, and groups
+//! are read into nested [`LayerGroup`]s. Anything else that can not be represented, like
+//! adjustment layers or layer effects, is skipped and reported in a list of warnings
+
+use std::io::{Read, Write};
+
+use super::{Error, Result, group_path, place_rgba};
+use crate::Canvas;
+use crate::blend::BlendMode;
+use crate::canvas::Rect;
+use crate::color::Channel;
+use crate::layer::{Layer, LayerGroup, LayerStack};
+
+const SIGNATURE: &[u8; 4] = b"8BPS";
+const RGB_MODE: u16 = 3;
+
+/// Additional layer information keys of adjustment and fill layers
+const ADJUSTMENT_KEYS: [&[u8; 4]; 20] = [
+    b"levl", b"curv", b"brit", b"blnc", b"hue ", b"hue2", b"selc", b"thrs", b"nvrt", b"post",
+    b"mixr", b"grdm", b"phfl", b"expA", b"vibA", b"blwh", b"clrL", b"SoCo", b"GdFl", b"PtFl",
+];
+
+/// Additional layer information keys of layer effects
+const EFFECT_KEYS: [&[u8; 4]; 3] = [b"lrFX", b"lfx2", b"lmfx"];
+
+/// Section divider types of group layers
+const OPEN_FOLDER: u32 = 1;
+const CLOSED_FOLDER: u32 = 2;
+const BOUNDING_DIVIDER: u32 = 3;
+
+/// Blend key of groups that pass their layers through
+const PASS_THROUGH: &[u8; 4] = b"pass";
+
+/// Name Photoshop gives the bounding divider records of groups
+const DIVIDER_NAME: &str = "</Layer group>";
+
+/// A document read by [`import`]
+pub struct PsdImport<T: Channel = u8> {
+    pub canvas: Canvas<T>,
+    /// Parts of the document that could not be represented on the canvas
+    pub warnings: Vec<String>,
+}
+
+/// Photoshop key of a blend mode
+pub fn blend_key(mode: BlendMode) -> &'static [u8; 4] {
+    match mode {
+        BlendMode::Normal => b"norm",
+        BlendMode::Multiply => b"mul ",
+        BlendMode::Screen => b"scrn",
+        BlendMode::Overlay => b"over",
+        BlendMode::Darken => b"dark",
+        BlendMode::Lighten => b"lite",
+        BlendMode::ColorDodge => b"div ",
+        BlendMode::ColorBurn => b"idiv",
+        BlendMode::HardLight => b"hLit",
+        BlendMode::SoftLight => b"sLit",
+        BlendMode::Difference => b"diff",
+        BlendMode::Exclusion => b"smud",
+        BlendMode::Hue => b"hue ",
+        BlendMode::Saturation => b"sat ",
+        BlendMode::Color => b"colr",
+        BlendMode::Luminosity => b"lum ",
+    }
+}
+
+/// Blend mode of a Photoshop blend key, `None` for modes we can not represent
+pub fn blend_mode(key: &[u8; 4]) -> Option<BlendMode> {
+    BlendMode::ALL
+        .iter()
+        .copied()
+        .find(|&mode| blend_key(mode) == key)
+}
+
+/// Reads the layers of a psd into a new canvas, falling back to the merged
+/// composite for documents without layers
+pub fn import<T: Channel>(mut reader: impl Read) -> Result<PsdImport<T>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let mut reader = ByteReader::new(&data);
+
+    if reader.bytes(4)? != SIGNATURE {
+        return Err(Error::Unsupported("not a photoshop document".into()));
+    }
+    if reader.u16()? != 1 {
+        return Err(Error::Unsupported(
+            "large documents (psb) are not supported".into(),
+        ));
+    }
+    reader.skip(6)?;
+    let channels = reader.u16()? as usize;
+    let height = reader.u32()? as usize;
+    let width = reader.u32()? as usize;
+    let depth = reader.u16()?;
+    let mode = reader.u16()?;
+    if depth != 8 || mode != RGB_MODE {
+        return Err(Error::Unsupported(format!(
+            "only 8 bit rgb documents are supported, got {depth} bit color mode {mode}"
+        )));
+    }
+
+    /* color mode data and image resources */
+    for _ in 0..2 {
+        let length = reader.u32()? as usize;
+        reader.skip(length)?;
+    }
+
+    let mut warnings = vec![];
+    let length = reader.u32()? as usize;
+    let (mut layers, groups) = read_layers(reader.sub(length)?, width, height, &mut warnings)?;
+
+    if layers.is_empty() {
+        let pixels = read_merged(&mut reader, width, height, channels)?;
+        layers.push(Layer::from_rgba("Background", width, height, &pixels));
+    }
+
+    let active = layers.len() - 1;
+    let mut layers = LayerStack::from_layers(layers, active)
+        .ok_or_else(|| Error::Unsupported("document has no layers".into()))?;
+    for group in groups {
+        layers.add_group(group);
+    }
+    Ok(PsdImport {
+        canvas: Canvas::from_layers(layers),
+        warnings,
+    })
+}
+
+/// Writes every layer and group of the canvas along with the merged composite
+pub fn export<T: Channel>(canvas: &Canvas<T>, mut writer: impl Write) -> Result<()> {
+    let (width, height) = (canvas.width(), canvas.height());
+    let mut out = vec![];
+
+    out.extend_from_slice(SIGNATURE);
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&[0; 6]);
+    out.extend_from_slice(&4u16.to_be_bytes());
+    out.extend_from_slice(&(height as u32).to_be_bytes());
+    out.extend_from_slice(&(width as u32).to_be_bytes());
+    out.extend_from_slice(&8u16.to_be_bytes());
+    out.extend_from_slice(&RGB_MODE.to_be_bytes());
+
+    /* no color mode data or image resources */
+    out.extend_from_slice(&0u32.to_be_bytes());
+    out.extend_from_slice(&0u32.to_be_bytes());
+
+    let stack = canvas.layers();
+    let mut records = vec![];
+    let mut channel_data = vec![];
+    let mut count = 0;
+    /* groups around the previous layer, outermost first */
+    let mut open: Vec<usize> = vec![];
+    for layer in stack.iter() {
+        /* leave the groups the layer is not part of, then enter the ones it is */
+        let path = group_path(stack, layer.group());
+        while !path.starts_with(&open) {
+            let group = open.pop().unwrap_or_default();
+            write_folder(&mut records, &mut channel_data, &stack.groups()[group]);
+            count += 1;
+        }
+        for &group in &path[open.len()..] {
+            write_divider(&mut records, &mut channel_data);
+            open.push(group);
+            count += 1;
+        }
+
+        let bounds = layer.content_bounds().unwrap_or(Rect::new(0, 0, 0, 0));
+        let pixels = layer.read_rgba(bounds);
+
+        let channels: Vec<(i16, Vec<u8>)> = [(-1, 3), (0, 0), (1, 1), (2, 2)]
+            .into_iter()
+            .map(|(id, channel)| {
+                let plane: Vec<u8> = pixels.iter().skip(channel).step_by(4).copied().collect();
+                (id, encode_channel(&plane, bounds.width, bounds.height))
+            })
+            .collect();
+
+        let mut blocks = vec![(*b"luni", unicode_name(layer.name()))];
+        if layer.locked() {
+            blocks.push((*b"lspf", 0x8000_0000u32.to_be_bytes().to_vec()));
+        }
+
+        let mut flags = 0;
+        if layer.alpha_locked() {
+            flags |= 0x01;
+        }
+        if !layer.visible() {
+            flags |= 0x02;
+        }
+
+        write_record(
+            &mut records,
+            &RecordHeader {
+                top: bounds.y as i32,
+                left: bounds.x as i32,
+                bottom: bounds.bottom() as i32,
+                right: bounds.right() as i32,
+                blend_key: *blend_key(layer.blend_mode()),
+                opacity: (layer.opacity() * 255.0).round() as u8,
+                flags,
+                name: layer.name(),
+            },
+            &channels,
+            &blocks,
+        );
+        for (_, data) in channels {
+            channel_data.extend_from_slice(&data);
+        }
+        count += 1;
+    }
+    while let Some(group) = open.pop() {
+        write_folder(&mut records, &mut channel_data, &stack.groups()[group]);
+        count += 1;
+    }
+
+    let count = i16::try_from(count).map_err(|_| {
+        Error::Unsupported(format!(
+            "{count} layers and groups are more than a psd can hold"
+        ))
+    })?;
+    let mut layer_info = vec![];
+    /* a negative count marks the first alpha channel of the merged image as its transparency */
+    layer_info.extend_from_slice(&(-count).to_be_bytes());
+    layer_info.extend_from_slice(&records);
+    layer_info.extend_from_slice(&channel_data);
+    if !layer_info.len().is_multiple_of(2) {
+        layer_info.push(0);
+    }
+
+    let section_length = 4 + layer_info.len() + 4;
+    out.extend_from_slice(&(section_length as u32).to_be_bytes());
+    out.extend_from_slice(&(layer_info.len() as u32).to_be_bytes());
+    out.extend_from_slice(&layer_info);
+    /* no global layer mask */
+    out.extend_from_slice(&0u32.to_be_bytes());
+
+    write_merged(&mut out, &canvas.composite(), width, height);
+
+    writer.write_all(&out)?;
+    Ok(())
+}
+
+/// A layer record read from the layer info section
+struct LayerRecord {
+    top: i32,
+    left: i32,
+    bottom: i32,
+    right: i32,
+    channels: Vec<(i16, usize)>,
+    blend_key: [u8; 4],
+    opacity: u8,
+    clipping: u8,
+    flags: u8,
+    name: String,
+    divider: Option<u32>,
+    /// Blend key stored with the section divider, it replaces the one of the record
+    divider_blend_key: Option<[u8; 4]>,
+    keys: Vec<[u8; 4]>,
+    protection: u32,
+}
+
+impl LayerRecord {
+    /// Width and height of the layer, layers larger than the whole document are rejected
+    /// before anything is allocated for their pixels
+    fn size(&self, document_width: usize, document_height: usize) -> Result<(usize, usize)> {
+        let width = (self.right as i64 - self.left as i64).max(0);
+        let height = (self.bottom as i64 - self.top as i64).max(0);
+        let document_area = (document_width as i64).checked_mul(document_height as i64);
+        match width.checked_mul(height).zip(document_area) {
+            Some((area, document_area)) if area <= document_area => {
+                Ok((width as usize, height as usize))
+            }
+            _ => Err(Error::Unsupported(format!(
+                "layer \"{}\" of {width}x{height} pixels is larger than the document",
+                self.name
+            ))),
+        }
+    }
+}
+
+fn read_layers<T: Channel>(
+    mut reader: ByteReader,
+    width: usize,
+    height: usize,
+    warnings: &mut Vec<String>,
+) -> Result<(Vec<Layer<T>>, Vec<LayerGroup>)> {
+    if reader.remaining() < 4 {
+        return Ok((vec![], vec![]));
+    }
+    let length = reader.u32()? as usize;
+    if length == 0 {
+        return Ok((vec![], vec![]));
+    }
+    let mut reader = reader.sub(length)?;
+
+    let count = reader.i16()?.unsigned_abs() as usize;
+    let records = (0..count)
+        .map(|_| read_record(&mut reader))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut layers: Vec<Layer<T>> = vec![];
+    let mut groups: Vec<LayerGroup> = vec![];
+    /* index of every group that is still open, innermost last */
+    let mut open = vec![];
+
+    /* records are stored bottom to top, so a group starts with its bounding divider
+     * and its properties only follow after the layers it holds */
+    for record in records {
+        let (layer_width, layer_height) = record.size(width, height)?;
+        let mut channels = [None, None, None, None];
+        for &(id, length) in &record.channels {
+            let data = reader.bytes(length)?;
+            let slot = match id {
+                -1 => 3,
+                0..=2 => id as usize,
+                _ => continue,
+            };
+            channels[slot] = Some(decode_channel(data, layer_width, layer_height)?);
+        }
+
+        match record.divider {
+            Some(BOUNDING_DIVIDER) => {
+                let mut group = LayerGroup::new("Group");
+                group.parent = open.last().copied();
+                groups.push(group);
+                open.push(groups.len() - 1);
+                continue;
+            }
+            Some(OPEN_FOLDER | CLOSED_FOLDER) => {
+                /* a folder without a divider below it holds no layers */
+                let index = open.pop().unwrap_or_else(|| {
+                    let mut group = LayerGroup::new("Group");
+                    group.parent = open.last().copied();
+                    groups.push(group);
+                    groups.len() - 1
+                });
+
+                let key = record.divider_blend_key.unwrap_or(record.blend_key);
+                let blend_mode = if &key == PASS_THROUGH {
+                    None
+                } else {
+                    Some(blend_mode(&key).unwrap_or_else(|| {
+                        warnings.push(format!(
+                            "group \"{}\" uses the unsupported blend mode \"{}\"",
+                            record.name,
+                            String::from_utf8_lossy(&key)
+                        ));
+                        BlendMode::Normal
+                    }))
+                };
+
+                let group = &mut groups[index];
+                group.name = record.name;
+                group.opacity = record.opacity as f32 / 255.0;
+                group.visible = record.flags & 0x02 == 0;
+                group.blend_mode = blend_mode;
+                continue;
+            }
+            _ => {}
+        }
+
+        if record.keys.iter().any(|key| ADJUSTMENT_KEYS.contains(&key)) {
+            warnings.push(format!("skipped adjustment layer \"{}\"", record.name));
+            continue;
+        }
+        if record.keys.iter().any(|key| EFFECT_KEYS.contains(&key)) {
+            warnings.push(format!("ignored the effects of layer \"{}\"", record.name));
+        }
+        if record.keys.contains(b"TySh") {
+            warnings.push(format!("text layer \"{}\" was rasterized", record.name));
+        }
+        if record.channels.iter().any(|&(id, _)| id < -1) {
+            warnings.push(format!("ignored the mask of layer \"{}\"", record.name));
+        }
+        if record.clipping != 0 {
+            warnings.push(format!("layer \"{}\" is no longer clipped", record.name));
+        }
+
+        let blend_mode = blend_mode(&record.blend_key).unwrap_or_else(|| {
+            warnings.push(format!(
+                "layer \"{}\" uses the unsupported blend mode \"{}\"",
+                record.name,
+                String::from_utf8_lossy(&record.blend_key)
+            ));
+            BlendMode::Normal
+        });
+
+        let pixel_count = layer_width * layer_height;
+        let mut pixels = Vec::with_capacity(pixel_count * 4);
+        for i in 0..pixel_count {
+            for (channel, data) in channels.iter().enumerate() {
+                let default = if channel == 3 { 255 } else { 0 };
+                pixels.push(data.as_ref().map_or(default, |d| d[i]));
+            }
+        }
+
+        let (left, top) = (record.left as i64, record.top as i64);
+        let mut layer = Layer::new(record.name, width, height);
+        place_rgba(&mut layer, left, top, layer_width, layer_height, &pixels);
+        layer.set_opacity(record.opacity as f32 / 255.0);
+        layer.set_blend_mode(blend_mode);
+        layer.set_visible(record.flags & 0x02 == 0);
+        layer.set_alpha_locked(record.flags & 0x01 != 0);
+        layer.set_locked(record.protection & 0x8000_0002 != 0);
+        layer.set_group(open.last().copied());
+        layers.push(layer);
+    }
+
+    Ok((layers, groups))
+}
+
+fn read_record(reader: &mut ByteReader) -> Result<LayerRecord> {
+    let top = reader.i32()?;
+    let left = reader.i32()?;
+    let bottom = reader.i32()?;
+    let right = reader.i32()?;
+
+    let channel_count = reader.u16()? as usize;
+    let channels = (0..channel_count)
+        .map(|_| Ok((reader.i16()?, reader.u32()? as usize)))
+        .collect::<Result<Vec<_>>>()?;
+
+    if reader.bytes(4)? != b"8BIM" {
+        return Err(Error::Unsupported("invalid blend mode signature".into()));
+    }
+    let blend_key = reader.key()?;
+    let opacity = reader.u8()?;
+    let clipping = reader.u8()?;
+    let flags = reader.u8()?;
+    reader.skip(1)?;
+
+    let length = reader.u32()? as usize;
+    let mut extra = reader.sub(length)?;
+
+    /* layer mask and blending ranges */
+    for _ in 0..2 {
+        let length = extra.u32()? as usize;
+        extra.skip(length)?;
+    }
+
+    /* pascal string padded to a multiple of 4 bytes, length byte included */
+    let name_length = extra.u8()? as usize;
+    let mut name: String = extra
+        .bytes(name_length)?
+        .iter()
+        .map(|&b| b as char)
+        .collect();
+    extra.skip((4 - (name_length + 1) % 4) % 4)?;
+
+    let mut divider = None;
+    let mut divider_blend_key = None;
+    let mut keys = vec![];
+    let mut protection = 0;
+    while extra.remaining() >= 12 {
+        let signature = extra.bytes(4)?;
+        if signature != b"8BIM" && signature != b"8B64" {
+            break;
+        }
+        let key = extra.key()?;
+        let length = extra.u32()? as usize;
+        let mut block = extra.sub(length)?;
+
+        match &key {
+            b"luni" => {
+                let count = block.u32()? as usize;
+                let units = (0..count)
+                    .map(|_| block.u16())
+                    .collect::<Result<Vec<_>>>()?;
+                name = String::from_utf16_lossy(&units)
+                    .trim_end_matches('\0')
+                    .to_string();
+            }
+            b"lsct" => {
+                divider = Some(block.u32()?);
+                if block.remaining() >= 8 && block.bytes(4)? == b"8BIM" {
+                    divider_blend_key = Some(block.key()?);
+                }
+            }
+            b"lspf" => protection = block.u32()?,
+            _ => {}
+        }
+        keys.push(key);
+    }
+
+    Ok(LayerRecord {
+        top,
+        left,
+        bottom,
+        right,
+        channels,
+        blend_key,
+        opacity,
+        clipping,
+        flags,
+        name,
+        divider,
+        divider_blend_key,
+        keys,
+        protection,
+    })
+}
+
+/// Reads the merged composite as straight RGBA pixels
+fn read_merged(
+    reader: &mut ByteReader,
+    width: usize,
+    height: usize,
+    channels: usize,
+) -> Result<Vec<u8>> {
+    if channels == 0 {
+        return Err(Error::Unsupported("document has no channels".into()));
+    }
+    let compression = reader.u16()?;
+    let used = channels.min(4);
+
+    let planes: Vec<Vec<u8>> = match compression {
+        0 => (0..used)
+            .map(|_| Ok(reader.bytes(width * height)?.to_vec()))
+            .collect::<Result<_>>()?,
+        1 => {
+            let counts = (0..channels * height)
+                .map(|_| Ok(reader.u16()? as usize))
+                .collect::<Result<Vec<_>>>()?;
+            let mut planes = vec![];
+            for channel in 0..used {
+                let mut plane = Vec::with_capacity(width * height);
+                for &count in &counts[channel * height..(channel + 1) * height] {
+                    plane.extend(unpack_bits(reader.bytes(count)?, width));
+                }
+                planes.push(plane);
+            }
+            planes
+        }
+        _ => {
+            return Err(Error::Unsupported(format!(
+                "compression method {compression}"
+            )));
+        }
+    };
+
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for i in 0..width * height {
+        for channel in 0..4 {
+            pixels.push(match planes.get(channel) {
+                Some(plane) => plane[i],
+                None if channel == 3 => 255,
+                None => planes[0][i],
+            });
+        }
+    }
+    Ok(pixels)
+}
+
+/// Decodes the data of a single layer channel, compression method included
+fn decode_channel(data: &[u8], width: usize, height: usize) -> Result<Vec<u8>> {
+    let mut reader = ByteReader::new(data);
+    if reader.remaining() < 2 {
+        return Ok(vec![0; width * height]);
+    }
+
+    match reader.u16()? {
+        0 => {
+            let mut plane = reader.bytes(reader.remaining())?.to_vec();
+            plane.resize(width * height, 0);
+            Ok(plane)
+        }
+        1 => {
+            let counts = (0..height)
+                .map(|_| Ok(reader.u16()? as usize))
+                .collect::<Result<Vec<_>>>()?;
+            let mut plane = Vec::with_capacity(width * height);
+            for count in counts {
+                plane.extend(unpack_bits(reader.bytes(count)?, width));
+            }
+            Ok(plane)
+        }
+        compression => Err(Error::Unsupported(format!(
+            "compression method {compression}"
+        ))),
+    }
+}
+
+/// Encodes a channel with packbits compression, compression method included
+fn encode_channel(plane: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut counts = Vec::with_capacity(height * 2);
+    let mut rows = vec![];
+    for row in plane.chunks_exact(width.max(1)).take(height) {
+        let start = rows.len();
+        pack_bits(row, &mut rows);
+        counts.extend_from_slice(&((rows.len() - start) as u16).to_be_bytes());
+    }
+
+    let mut data = 1u16.to_be_bytes().to_vec();
+    data.extend_from_slice(&counts);
+    data.extend_from_slice(&rows);
+    data
+}
+
+/// Writes the straight alpha composite as packbits compressed RGBA planes
+fn write_merged(out: &mut Vec<u8>, pixels: &[u8], width: usize, height: usize) {
+    let mut counts = vec![];
+    let mut rows = vec![];
+    for channel in 0..4 {
+        let plane: Vec<u8> = pixels.iter().skip(channel).step_by(4).copied().collect();
+        for row in plane.chunks_exact(width.max(1)).take(height) {
+            let start = rows.len();
+            pack_bits(row, &mut rows);
+            counts.extend_from_slice(&((rows.len() - start) as u16).to_be_bytes());
+        }
+    }
+
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&counts);
+    out.extend_from_slice(&rows);
+}
+
+/// Properties written into the header of a layer record
+struct RecordHeader<'a> {
+    top: i32,
+    left: i32,
+    bottom: i32,
+    right: i32,
+    blend_key: [u8; 4],
+    opacity: u8,
+    flags: u8,
+    name: &'a str,
+}
+
+/// Writes a layer record, `channels` holds the id and encoded data of every channel
+/// and `blocks` the additional layer information
+fn write_record(
+    out: &mut Vec<u8>,
+    header: &RecordHeader,
+    channels: &[(i16, Vec<u8>)],
+    blocks: &[([u8; 4], Vec<u8>)],
+) {
+    for value in [header.top, header.left, header.bottom, header.right] {
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+    out.extend_from_slice(&(channels.len() as u16).to_be_bytes());
+    for (id, data) in channels {
+        out.extend_from_slice(&id.to_be_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    }
+
+    out.extend_from_slice(b"8BIM");
+    out.extend_from_slice(&header.blend_key);
+    out.extend_from_slice(&[header.opacity, 0, header.flags, 0]);
+
+    let mut extra = vec![];
+    /* empty layer mask and blending ranges */
+    extra.extend_from_slice(&0u32.to_be_bytes());
+    extra.extend_from_slice(&0u32.to_be_bytes());
+
+    let name: Vec<u8> = header
+        .name
+        .chars()
+        .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+        .take(255)
+        .collect();
+    extra.push(name.len() as u8);
+    extra.extend_from_slice(&name);
+    extra.resize(extra.len() + (4 - (name.len() + 1) % 4) % 4, 0);
+
+    for (key, data) in blocks {
+        extra.extend_from_slice(b"8BIM");
+        extra.extend_from_slice(key);
+        extra.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        extra.extend_from_slice(data);
+    }
+
+    out.extend_from_slice(&(extra.len() as u32).to_be_bytes());
+    out.extend_from_slice(&extra);
+}
+
+/// Empty channels written for the section records of groups
+fn section_channels() -> Vec<(i16, Vec<u8>)> {
+    [-1, 0, 1, 2]
+        .into_iter()
+        .map(|id| (id, encode_channel(&[], 0, 0)))
+        .collect()
+}
+
+/// Writes the bounding divider that sits below the layers of a group
+fn write_divider(records: &mut Vec<u8>, channel_data: &mut Vec<u8>) {
+    let channels = section_channels();
+    write_record(
+        records,
+        &RecordHeader {
+            top: 0,
+            left: 0,
+            bottom: 0,
+            right: 0,
+            blend_key: *blend_key(BlendMode::Normal),
+            opacity: 255,
+            flags: 0,
+            name: DIVIDER_NAME,
+        },
+        &channels,
+        &[
+            (*b"luni", unicode_name(DIVIDER_NAME)),
+            (*b"lsct", BOUNDING_DIVIDER.to_be_bytes().to_vec()),
+        ],
+    );
+    for (_, data) in channels {
+        channel_data.extend_from_slice(&data);
+    }
+}
+
+/// Writes the folder record holding the properties of a group, above its layers
+fn write_folder(records: &mut Vec<u8>, channel_data: &mut Vec<u8>, group: &LayerGroup) {
+    let key = group.blend_mode.map_or(PASS_THROUGH, blend_key);
+    let mut section = OPEN_FOLDER.to_be_bytes().to_vec();
+    section.extend_from_slice(b"8BIM");
+    section.extend_from_slice(key);
+
+    let channels = section_channels();
+    write_record(
+        records,
+        &RecordHeader {
+            top: 0,
+            left: 0,
+            bottom: 0,
+            right: 0,
+            blend_key: *key,
+            opacity: (group.opacity.clamp(0.0, 1.0) * 255.0).round() as u8,
+            flags: if group.visible { 0 } else { 0x02 },
+            name: &group.name,
+        },
+        &channels,
+        &[(*b"luni", unicode_name(&group.name)), (*b"lsct", section)],
+    );
+    for (_, data) in channels {
+        channel_data.extend_from_slice(&data);
+    }
+}
+
+/// Data of a `luni` block holding the full unicode layer name
+fn unicode_name(name: &str) -> Vec<u8> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let mut data = (units.len() as u32).to_be_bytes().to_vec();
+    for unit in units {
+        data.extend_from_slice(&unit.to_be_bytes());
+    }
+    if !data.len().is_multiple_of(2) {
+        data.push(0);
+    }
+    data
+}
+
+/// Compresses a row with the packbits run length encoding
+fn pack_bits(row: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < row.len() {
+        let mut run = 1;
+        while i + run < row.len() && run < 128 && row[i + run] == row[i] {
+            run += 1;
+        }
+
+        if run >= 2 {
+            out.push((1 - run as i16) as u8);
+            out.push(row[i]);
+            i += run;
+            continue;
+        }
+
+        /* literals continue until the next run of equal bytes starts */
+        let start = i;
+        i += 1;
+        while i < row.len() && i - start < 128 && !(i + 1 < row.len() && row[i] == row[i + 1]) {
+            i += 1;
+        }
+        out.push((i - start - 1) as u8);
+        out.extend_from_slice(&row[start..i]);
+    }
+}
+
+/// Expands a packbits compressed row, padding or cutting it to `width` bytes
+fn unpack_bits(data: &[u8], width: usize) -> Vec<u8> {
+    let mut row = Vec::with_capacity(width);
+    let mut i = 0;
+    while row.len() < width && i < data.len() {
+        let header = data[i] as i8;
+        i += 1;
+
+        if header >= 0 {
+            let end = (i + header as usize + 1).min(data.len());
+            row.extend_from_slice(&data[i..end]);
+            i = end;
+        } else if header != -128 && i < data.len() {
+            let count = (1 - header as isize) as usize;
+            row.resize(row.len() + count, data[i]);
+            i += 1;
+        }
+    }
+    row.resize(width, 0);
+    row
+}
+
+/// Big endian reader over a byte slice that errors instead of panicking on truncated data
+struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+        if count > self.remaining() {
+            return Err(Error::Unsupported("truncated photoshop document".into()));
+        }
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, count: usize) -> Result<()> {
+        self.bytes(count).map(|_| ())
+    }
+
+    /// Splits off the next `count` bytes into their own reader
+    fn sub(&mut self, count: usize) -> Result<ByteReader<'a>> {
+        Ok(ByteReader::new(self.bytes(count)?))
+    }
+
+    fn key(&mut self) -> Result<[u8; 4]> {
+        let mut key = [0; 4];
+        key.copy_from_slice(self.bytes(4)?);
+        Ok(key)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(self.u32()? as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    #[test]
+    fn packbits_round_trips() {
+        let row: Vec<u8> = [
+            vec![7; 200],
+            (0..150).map(|i| i as u8).collect(),
+            vec![1, 1, 2],
+        ]
+        .concat();
+        let mut packed = vec![];
+        pack_bits(&row, &mut packed);
+        assert!(packed.len() < row.len());
+        assert_eq!(unpack_bits(&packed, row.len()), row);
+    }
+
+    #[test]
+    fn round_trips_layers() {
+        let mut canvas: Canvas = Canvas::new(70, 50);
+        canvas.add_layer();
+        canvas.draw_pixel(65, 40, Color::new(255, 128, 0, 200));
+        canvas.draw_pixel(3, 2, Color::new(0, 0, 255, 255));
+        let layer = canvas.layers_mut().active_mut();
+        layer.set_name("Lineart ✏");
+        layer.set_opacity(0.6);
+        layer.set_blend_mode(BlendMode::ColorBurn);
+        layer.set_alpha_locked(true);
+        canvas.add_layer();
+        canvas.layers_mut().active_mut().set_visible(false);
+        canvas.layers_mut().active_mut().set_locked(true);
+
+        let mut file = vec![];
+        export(&canvas, &mut file).unwrap();
+        let imported: PsdImport = import(file.as_slice()).unwrap();
+        assert!(imported.warnings.is_empty(), "{:?}", imported.warnings);
+
+        let layers = imported.canvas.layers();
+        assert_eq!(layers.len(), 3);
+        let lineart = layers.get(1).unwrap();
+        assert_eq!(lineart.name(), "Lineart ✏");
+        assert_eq!(lineart.opacity(), 153.0 / 255.0);
+        assert_eq!(lineart.blend_mode(), BlendMode::ColorBurn);
+        assert!(lineart.alpha_locked());
+        assert_eq!(lineart.pixel(65, 40), Some(Color::new(255, 128, 0, 200)));
+        assert_eq!(lineart.pixel(3, 2), Some(Color::new(0, 0, 255, 255)));
+        assert_eq!(lineart.pixel(4, 2), Some(Color::new(0, 0, 0, 0)));
+
+        let top = layers.get(2).unwrap();
+        assert!(!top.visible());
+        assert!(top.locked());
+        assert_eq!(top.memory_size(), 0);
+    }
+
+    #[test]
+    fn round_trips_nested_groups() {
+        let mut canvas: Canvas = Canvas::new(4, 4);
+        let outer = canvas.layers_mut().add_group(LayerGroup {
+            opacity: 0.2,
+            ..LayerGroup::new("outer")
+        });
+        let inner = canvas.layers_mut().add_group(LayerGroup {
+            parent: Some(outer),
+            blend_mode: Some(BlendMode::Multiply),
+            ..LayerGroup::new("inner ✏")
+        });
+        let hidden = canvas.layers_mut().add_group(LayerGroup {
+            visible: false,
+            ..LayerGroup::new("hidden")
+        });
+
+        /* bottom to top: background, outer [a, inner [b], c], hidden [d], top */
+        for (group, color) in [
+            (Some(outer), Color::new(255, 0, 0, 255)),
+            (Some(inner), Color::new(0, 255, 0, 255)),
+            (Some(outer), Color::new(0, 0, 255, 128)),
+            (Some(hidden), Color::new(255, 255, 0, 255)),
+            (None, Color::new(0, 0, 0, 64)),
+        ] {
+            canvas.add_layer();
+            canvas.layers_mut().active_mut().set_group(group);
+            canvas.draw_pixel(1, 1, color);
+        }
+
+        let mut file = vec![];
+        export(&canvas, &mut file).unwrap();
+        let imported: PsdImport = import(file.as_slice()).unwrap();
+        assert!(imported.warnings.is_empty(), "{:?}", imported.warnings);
+
+        let layers = imported.canvas.layers();
+        let groups: Vec<Option<&str>> = layers
+            .iter()
+            .map(|layer| layer.group().map(|g| layers.groups()[g].name.as_str()))
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                None,
+                Some("outer"),
+                Some("inner ✏"),
+                Some("outer"),
+                Some("hidden"),
+                None
+            ]
+        );
+
+        let inner = &layers.groups()[layers.get(2).unwrap().group().unwrap()];
+        assert_eq!(inner.blend_mode, Some(BlendMode::Multiply));
+        let outer = &layers.groups()[inner.parent.unwrap()];
+        assert_eq!((outer.opacity, outer.blend_mode), (0.2, None));
+        let hidden = &layers.groups()[layers.get(4).unwrap().group().unwrap()];
+        assert!(!hidden.visible);
+
+        assert_eq!(imported.canvas.composite(), canvas.composite());
+    }
+
+    #[test]
+    fn rejects_documents_without_channels() {
+        let mut file = SIGNATURE.to_vec();
+        file.extend_from_slice(&1u16.to_be_bytes());
+        file.extend_from_slice(&[0; 6]);
+        file.extend_from_slice(&0u16.to_be_bytes());
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(&8u16.to_be_bytes());
+        file.extend_from_slice(&RGB_MODE.to_be_bytes());
+        file.extend_from_slice(&[0; 12]);
+        file.extend_from_slice(&0u16.to_be_bytes());
+
+        assert!(matches!(
+            import::<u8>(file.as_slice()),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    /// Builds a raw compressed channel of a single value
+    fn solid_channel(value: u8, pixels: usize) -> Vec<u8> {
+        let mut data = 0u16.to_be_bytes().to_vec();
+        data.resize(2 + pixels, value);
+        data
+    }
+
+    /// Builds an RGBA document holding `count` layer records and a blank merged image
+    fn layered_document(
+        width: u32,
+        height: u32,
+        count: i16,
+        records: &[u8],
+        channel_data: &[u8],
+    ) -> Vec<u8> {
+        let mut layer_info = (-count).to_be_bytes().to_vec();
+        layer_info.extend_from_slice(records);
+        layer_info.extend_from_slice(channel_data);
+        if !layer_info.len().is_multiple_of(2) {
+            layer_info.push(0);
+        }
+
+        let mut file = SIGNATURE.to_vec();
+        file.extend_from_slice(&1u16.to_be_bytes());
+        file.extend_from_slice(&[0; 6]);
+        file.extend_from_slice(&4u16.to_be_bytes());
+        file.extend_from_slice(&height.to_be_bytes());
+        file.extend_from_slice(&width.to_be_bytes());
+        file.extend_from_slice(&8u16.to_be_bytes());
+        file.extend_from_slice(&RGB_MODE.to_be_bytes());
+        file.extend_from_slice(&[0; 8]);
+        file.extend_from_slice(&((layer_info.len() + 8) as u32).to_be_bytes());
+        file.extend_from_slice(&(layer_info.len() as u32).to_be_bytes());
+        file.extend_from_slice(&layer_info);
+        file.extend_from_slice(&[0; 4]);
+        file.extend_from_slice(&0u16.to_be_bytes());
+        file.extend_from_slice(&vec![0; (width * height * 4) as usize]);
+        file
+    }
+
+    #[test]
+    fn rejects_layers_larger_than_the_document() {
+        for (top, left, bottom, right) in [
+            (i32::MIN, i32::MIN, i32::MAX, i32::MAX),
+            (0, 0, 3, 2),
+            (-1, -1, 2, 2),
+        ] {
+            let mut records = vec![];
+            let header = RecordHeader {
+                top,
+                left,
+                bottom,
+                right,
+                blend_key: *b"norm",
+                opacity: 255,
+                flags: 0,
+                name: "huge",
+            };
+            write_record(&mut records, &header, &[(0, vec![])], &[]);
+
+            let file = layered_document(2, 2, 1, &records, &[]);
+            assert!(
+                matches!(import::<u8>(file.as_slice()), Err(Error::Unsupported(_))),
+                "{top} {left} {bottom} {right}"
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_to_export_more_layers_than_a_psd_holds() {
+        let mut canvas: Canvas = Canvas::new(1, 1);
+        for _ in 0..i16::MAX {
+            canvas.layers_mut().add_layer();
+        }
+        assert!(matches!(
+            export(&canvas, &mut vec![]),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn reads_groups_and_reports_skipped_layers() {
+        let mut records = vec![];
+        let mut channel_data = vec![];
+        let mut add = |header: RecordHeader, size: usize, blocks: &[([u8; 4], Vec<u8>)]| {
+            let channels: Vec<(i16, Vec<u8>)> = [(-1, 255), (0, 10), (1, 20), (2, 30)]
+                .into_iter()
+                .map(|(id, value)| (id, solid_channel(value, size)))
+                .collect();
+            write_record(&mut records, &header, &channels, blocks);
+            for (_, data) in channels {
+                channel_data.extend_from_slice(&data);
+            }
+        };
+        let header = |name, top, left, bottom, right, opacity, flags| RecordHeader {
+            top,
+            left,
+            bottom,
+            right,
+            blend_key: *b"norm",
+            opacity,
+            flags,
+            name,
+        };
+
+        /* bottom to top: a layer, a hidden half opacity group holding two layers, an adjustment */
+        add(header("base", 0, 0, 2, 2, 255, 0), 4, &[]);
+        add(
+            header("</Layer group>", 0, 0, 0, 0, 255, 0),
+            0,
+            &[(*b"lsct", BOUNDING_DIVIDER.to_be_bytes().to_vec())],
+        );
+        add(
+            header("shifted", 1, -1, 3, 1, 255, 0),
+            4,
+            &[(*b"lfx2", vec![0; 4])],
+        );
+        let mut dissolve = header("dissolve", 0, 0, 1, 1, 128, 0);
+        dissolve.blend_key = *b"diss";
+        add(dissolve, 1, &[]);
+        add(
+            header("group", 0, 0, 0, 0, 128, 0x02),
+            0,
+            &[(*b"lsct", OPEN_FOLDER.to_be_bytes().to_vec())],
+        );
+        add(
+            header("levels", 0, 0, 0, 0, 255, 0),
+            0,
+            &[(*b"levl", vec![0; 2])],
+        );
+
+        let file = layered_document(2, 2, 6, &records, &channel_data);
+        let imported: PsdImport = import(file.as_slice()).unwrap();
+        let names: Vec<&str> = imported.canvas.layers().iter().map(Layer::name).collect();
+        assert_eq!(names, ["base", "shifted", "dissolve"]);
+
+        let groups = imported.canvas.layers().groups();
+        assert_eq!(
+            groups,
+            [LayerGroup {
+                name: "group".into(),
+                parent: None,
+                opacity: 128.0 / 255.0,
+                visible: false,
+                blend_mode: Some(BlendMode::Normal),
+            }]
+        );
+        assert_eq!(imported.canvas.layers().get(0).unwrap().group(), None);
+
+        let shifted = imported.canvas.layers().get(1).unwrap();
+        assert_eq!(shifted.group(), Some(0));
+        assert!(shifted.visible());
+        assert_eq!(shifted.pixel(0, 1), Some(Color::new(10, 20, 30, 255)));
+        assert_eq!(shifted.pixel(1, 1), Some(Color::new(0, 0, 0, 0)));
+
+        let dissolve = imported.canvas.layers().get(2).unwrap();
+        assert_eq!(dissolve.group(), Some(0));
+        assert_eq!(dissolve.blend_mode(), BlendMode::Normal);
+        assert_eq!(dissolve.opacity(), 128.0 / 255.0);
+
+        /* the hidden group hides both of its layers */
+        assert_eq!(imported.canvas.composite()[..4], [10, 20, 30, 255]);
+
+        assert_eq!(
+            imported.warnings,
+            [
+                "ignored the effects of layer \"shifted\"",
+                "layer \"dissolve\" uses the unsupported blend mode \"diss\"",
+                "skipped adjustment layer \"levels\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_the_merged_image_of_flat_documents() {
+        let mut file = SIGNATURE.to_vec();
+        file.extend_from_slice(&1u16.to_be_bytes());
+        file.extend_from_slice(&[0; 6]);
+        file.extend_from_slice(&3u16.to_be_bytes());
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(&2u32.to_be_bytes());
+        file.extend_from_slice(&8u16.to_be_bytes());
+        file.extend_from_slice(&RGB_MODE.to_be_bytes());
+        file.extend_from_slice(&[0; 12]);
+        file.extend_from_slice(&0u16.to_be_bytes());
+        file.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+
+        let imported: PsdImport = import(file.as_slice()).unwrap();
+        assert_eq!(imported.canvas.layers().len(), 1);
+        assert_eq!(imported.canvas.composite(), [1, 3, 5, 255, 2, 4, 6, 255]);
+    }
+}
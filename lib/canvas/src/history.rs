@@ -0,0 +1,407 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::canvas::Rect;
+use crate::color::Channel;
+use crate::dirty::DirtyRegion;
+use crate::layer::{Layer, LayerStack};
+use crate::tile::{TILE_SIZE, Tile};
+
+/// Default amount of bytes the undo history may hold before dropping the oldest steps
+const DEFAULT_MEMORY_LIMIT: usize = 256 * 1024 * 1024;
+
+/// A tile of a layer saved by the history.
+///
+/// Tiles are copy-on-write, so saving one only shares it with the layer until
+/// the layer paints over it
+#[derive(Debug, Clone)]
+pub struct Patch<T: Channel = u8> {
+    column: usize,
+    row: usize,
+    tile: Option<Tile<T>>,
+}
+
+/// A reversible edit to the canvas.
+///
+/// Every command stores only the state that is *not* currently on the canvas,
+/// applying a command swaps that state with the canvas so the same command can
+/// be used for both undo and redo.
+#[derive(Debug, Clone)]
+pub enum Command<T: Channel = u8> {
+    /// Pixels painted on a layer, storing the touched patches
+    Paint {
+        layer: usize,
+        patches: Vec<Patch<T>>,
+    },
+    /// A layer that was added, `layer` is `None` while it lives in the stack
+    AddLayer {
+        index: usize,
+        layer: Option<Layer<T>>,
+    },
+    /// A layer that was removed, `layer` is `None` while it lives in the stack
+    RemoveLayer {
+        index: usize,
+        layer: Option<Layer<T>>,
+    },
+    /// A layer that was moved in the stack
+    MoveLayer { from: usize, to: usize },
+    /// Two layers that were merged, storing the pair that is not on the canvas
+    MergeDown {
+        index: usize,
+        upper: Option<Layer<T>>,
+        lower: Layer<T>,
+    },
+}
+
+impl<T: Channel> Command<T> {
+    /// Amount of pixel memory held by this command
+    fn size(&self) -> usize {
+        let layer_size = |layer: &Option<Layer<T>>| layer.as_ref().map_or(0, Layer::memory_size);
+        match self {
+            Command::Paint { patches, .. } => patches
+                .iter()
+                .filter_map(|p| p.tile.as_ref())
+                .map(Tile::byte_size)
+                .sum(),
+            Command::AddLayer { layer, .. } | Command::RemoveLayer { layer, .. } => {
+                layer_size(layer)
+            }
+            Command::MoveLayer { .. } => 0,
+            Command::MergeDown { upper, lower, .. } => layer_size(upper) + lower.memory_size(),
+        }
+    }
+
+    /// Reverts the command if it was last applied, or re-applies it if it was last reverted,
+    /// marking the areas it changed in `dirty`
+    fn swap(&mut self, layers: &mut LayerStack<T>, dirty: &mut DirtyRegion) {
+        if !matches!(self, Command::Paint { .. }) {
+            dirty.mark_all();
+        }
+
+        match self {
+            Command::Paint { layer, patches } => {
+                let Some(layer) = layers.get_mut(*layer) else {
+                    return;
+                };
+                for patch in patches {
+                    dirty.mark(layer.pixels().tile_rect(patch.column, patch.row));
+                    patch.tile =
+                        layer
+                            .pixels_mut()
+                            .replace_tile(patch.column, patch.row, patch.tile.take());
+                }
+            }
+            Command::AddLayer { index, layer } | Command::RemoveLayer { index, layer } => {
+                match layer.take() {
+                    Some(l) => {
+                        layers.insert(*index, l);
+                    }
+                    None => *layer = layers.remove(*index),
+                }
+            }
+            Command::MoveLayer { from, to } => {
+                layers.move_layer(*to, *from);
+                std::mem::swap(from, to);
+            }
+            Command::MergeDown {
+                index,
+                upper,
+                lower,
+            } => match upper.take() {
+                /* currently merged, split them back up */
+                Some(u) => {
+                    if let Some(merged) = layers.get_mut(*index - 1) {
+                        std::mem::swap(merged, lower);
+                    }
+                    layers.insert(*index, u);
+                }
+                /* currently split, merge them again */
+                None => {
+                    *upper = layers.remove(*index);
+                    if let Some(l) = layers.get_mut(*index - 1) {
+                        std::mem::swap(l, lower);
+                    }
+                    layers.set_active(*index - 1);
+                }
+            },
+        }
+    }
+}
+
+/// Paint edit that is still being recorded
+struct PendingPaint<T: Channel> {
+    layer: usize,
+    patches: HashMap<(usize, usize), Patch<T>>,
+}
+
+/// Bounded undo and redo stacks of [`Command`]s
+pub struct History<T: Channel = u8> {
+    undo: VecDeque<Command<T>>,
+    redo: Vec<Command<T>>,
+    pending: Option<PendingPaint<T>>,
+    memory_used: usize,
+    memory_limit: usize,
+}
+
+impl<T: Channel> Default for History<T> {
+    fn default() -> Self {
+        Self::with_memory_limit(DEFAULT_MEMORY_LIMIT)
+    }
+}
+
+impl<T: Channel> History<T> {
+    /// Creates a history that drops its oldest steps once it holds more than `memory_limit` bytes
+    pub fn with_memory_limit(memory_limit: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: vec![],
+            pending: None,
+            memory_used: 0,
+            memory_limit,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+
+    /// Records a command that has already been applied to the canvas, clearing the redo stack
+    pub fn push(&mut self, command: Command<T>) {
+        for command in self.redo.drain(..) {
+            self.memory_used -= command.size();
+        }
+
+        self.memory_used += command.size();
+        self.undo.push_back(command);
+
+        while self.memory_used > self.memory_limit && self.undo.len() > 1 {
+            if let Some(dropped) = self.undo.pop_front() {
+                self.memory_used -= dropped.size();
+            }
+        }
+    }
+
+    /// Starts recording a paint edit on a layer
+    pub fn begin_paint(&mut self, layer: usize) {
+        self.pending = Some(PendingPaint {
+            layer,
+            patches: HashMap::new(),
+        });
+    }
+
+    /// Saves the before-pixels of the tiles overlapping `rect` that were not touched yet
+    /// in the current paint edit, this has to be called before the pixels are changed
+    pub fn record_paint(&mut self, rect: Rect, layer: &Layer<T>) {
+        let Some(pending) = &mut self.pending else {
+            return;
+        };
+        let Some(rect) = rect.intersect(&layer.bounds()) else {
+            return;
+        };
+
+        for row in rect.y / TILE_SIZE..=(rect.bottom() - 1) / TILE_SIZE {
+            for column in rect.x / TILE_SIZE..=(rect.right() - 1) / TILE_SIZE {
+                pending
+                    .patches
+                    .entry((column, row))
+                    .or_insert_with(|| Patch {
+                        column,
+                        row,
+                        tile: layer.pixels().tile(column, row).cloned(),
+                    });
+            }
+        }
+    }
+
+    /// Finishes the current paint edit and pushes it onto the undo stack
+    pub fn end_paint(&mut self) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        if pending.patches.is_empty() {
+            return;
+        }
+
+        self.push(Command::Paint {
+            layer: pending.layer,
+            patches: pending.patches.into_values().collect(),
+        });
+    }
+
+    /// Reverts the most recent command, returns `false` if there was nothing to undo
+    pub fn undo(&mut self, layers: &mut LayerStack<T>, dirty: &mut DirtyRegion) -> bool {
+        self.end_paint();
+        let Some(mut command) = self.undo.pop_back() else {
+            return false;
+        };
+
+        self.memory_used -= command.size();
+        command.swap(layers, dirty);
+        self.memory_used += command.size();
+        self.redo.push(command);
+        true
+    }
+
+    /// Re-applies the most recently undone command, returns `false` if there was nothing to redo
+    pub fn redo(&mut self, layers: &mut LayerStack<T>, dirty: &mut DirtyRegion) -> bool {
+        self.end_paint();
+        let Some(mut command) = self.redo.pop() else {
+            return false;
+        };
+
+        self.memory_used -= command.size();
+        command.swap(layers, dirty);
+        self.memory_used += command.size();
+        self.undo.push_back(command);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    /// Bytes held by one allocated tile of 8 bit pixels
+    const TILE_BYTES: usize = TILE_SIZE * TILE_SIZE * 4;
+
+    const RED: Color<u8> = Color {
+        r: 255,
+        g: 0,
+        b: 0,
+        a: 255,
+    };
+
+    struct Fixture {
+        history: History,
+        layers: LayerStack,
+        dirty: DirtyRegion,
+    }
+
+    impl Fixture {
+        fn new(memory_limit: usize) -> Self {
+            Self {
+                history: History::with_memory_limit(memory_limit),
+                layers: LayerStack::new(Layer::new("base", 128, 128)),
+                dirty: DirtyRegion::new(128, 128),
+            }
+        }
+
+        /// Paints a single pixel on the active layer as one undo step
+        fn paint(&mut self, x: usize, y: usize, color: Color<u8>) {
+            self.history.begin_paint(self.layers.active_index());
+            self.history
+                .record_paint(Rect::new(x, y, 1, 1), self.layers.active());
+            self.layers.active_mut().draw_pixel(x, y, color);
+            self.history.end_paint();
+        }
+
+        fn pixel(&self, x: usize, y: usize) -> Color<u8> {
+            self.layers.active().pixel(x, y).unwrap()
+        }
+
+        fn undo(&mut self) -> bool {
+            self.history.undo(&mut self.layers, &mut self.dirty)
+        }
+
+        fn redo(&mut self) -> bool {
+            self.history.redo(&mut self.layers, &mut self.dirty)
+        }
+    }
+
+    #[test]
+    fn undoes_and_redoes_in_order() {
+        let mut fixture = Fixture::new(DEFAULT_MEMORY_LIMIT);
+        let clear = Color::new(0, 0, 0, 0);
+        let blue = Color::new(0, 0, 255, 255);
+        fixture.paint(0, 0, RED);
+        fixture.paint(0, 0, blue);
+
+        assert!(fixture.undo());
+        assert_eq!(fixture.pixel(0, 0), RED);
+        assert!(fixture.undo());
+        assert_eq!(fixture.pixel(0, 0), clear);
+        assert!(!fixture.undo());
+
+        assert!(fixture.redo());
+        assert_eq!(fixture.pixel(0, 0), RED);
+        assert!(fixture.redo());
+        assert_eq!(fixture.pixel(0, 0), blue);
+        assert!(!fixture.redo());
+        assert!(!fixture.dirty.is_empty());
+    }
+
+    #[test]
+    fn new_edits_clear_the_redo_stack() {
+        let mut fixture = Fixture::new(DEFAULT_MEMORY_LIMIT);
+        fixture.paint(0, 0, RED);
+        fixture.paint(1, 0, RED);
+        assert!(fixture.undo());
+        assert!(fixture.history.can_redo());
+
+        fixture.paint(2, 0, RED);
+        assert!(!fixture.history.can_redo());
+        assert!(!fixture.redo());
+        assert_eq!(fixture.pixel(1, 0), Color::new(0, 0, 0, 0));
+
+        /* undoing twice now skips the discarded edit */
+        assert!(fixture.undo());
+        assert!(fixture.undo());
+        assert_eq!(fixture.pixel(0, 0), Color::new(0, 0, 0, 0));
+    }
+
+    #[test]
+    fn layer_commands_swap_back_and_forth() {
+        let mut fixture = Fixture::new(DEFAULT_MEMORY_LIMIT);
+        let index = fixture.layers.add_layer();
+        fixture
+            .history
+            .push(Command::AddLayer { index, layer: None });
+        fixture.paint(0, 0, RED);
+
+        assert!(fixture.undo());
+        assert!(fixture.undo());
+        assert_eq!(fixture.layers.len(), 1);
+        assert!(fixture.redo());
+        assert!(fixture.redo());
+        assert_eq!(fixture.layers.len(), 2);
+        assert_eq!(fixture.layers.get(1).unwrap().pixel(0, 0), Some(RED));
+    }
+
+    #[test]
+    fn drops_the_oldest_steps_over_the_memory_limit() {
+        /* every step after the first saves one allocated tile */
+        let mut fixture = Fixture::new(2 * TILE_BYTES);
+        for x in 0..5 {
+            fixture.paint(x, 0, RED);
+        }
+        assert!(fixture.history.memory_used() <= 2 * TILE_BYTES);
+
+        let mut steps = 0;
+        while fixture.undo() {
+            steps += 1;
+        }
+        assert_eq!(steps, 2);
+        /* the dropped steps stay painted */
+        assert_eq!(fixture.pixel(2, 0), RED);
+        assert_eq!(fixture.pixel(3, 0), Color::new(0, 0, 0, 0));
+    }
+
+    #[test]
+    fn keeps_the_latest_step_even_over_the_limit() {
+        let mut fixture = Fixture::new(0);
+        fixture.paint(0, 0, RED);
+        fixture.paint(1, 0, RED);
+        assert!(fixture.undo());
+        assert!(!fixture.undo());
+        assert_eq!(fixture.pixel(0, 0), RED);
+        assert_eq!(fixture.pixel(1, 0), Color::new(0, 0, 0, 0));
+    }
+}
@@ -0,0 +1,941 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Color;
+use crate::blend::{self, BlendMode, premultiply};
+use crate::canvas::Rect;
+use crate::color::Channel;
+use crate::tile::{TILE_SIZE, TiledImage};
+
+/// A single raster layer, storing its own premultiplied RGBA pixels in sparse
+/// tiles along with the properties used when compositing the stack
+#[derive(Debug, Clone)]
+pub struct Layer<T: Channel = u8> {
+    name: String,
+    width: usize,
+    height: usize,
+    pixels: TiledImage<T>,
+    opacity: f32,
+    blend_mode: BlendMode,
+    visible: bool,
+    locked: bool,
+    alpha_locked: bool,
+    /// Index of the group in the [`LayerStack`] holding the layer
+    group: Option<usize>,
+}
+
+impl<T: Channel> Layer<T> {
+    /// Creates a new fully transparent layer
+    pub fn new(name: impl Into<String>, width: usize, height: usize) -> Self {
+        Self::filled(name, width, height, Color::new(0, 0, 0, 0))
+    }
+
+    /// Creates a new layer where every pixel is set to the sRGB `color`,
+    /// no tiles are allocated until the layer is painted on
+    pub fn filled(name: impl Into<String>, width: usize, height: usize, color: Color<u8>) -> Self {
+        Self {
+            name: name.into(),
+            width,
+            height,
+            pixels: TiledImage::new(width, height, premultiply(color.convert())),
+            opacity: 1.0,
+            blend_mode: BlendMode::Normal,
+            visible: true,
+            locked: false,
+            alpha_locked: false,
+            group: None,
+        }
+    }
+
+    /// Creates a new layer holding `pixels`, the layer takes the size of the image
+    pub fn from_image(name: impl Into<String>, pixels: TiledImage<T>) -> Self {
+        let mut layer = Self::new(name, 0, 0);
+        layer.width = pixels.width();
+        layer.height = pixels.height();
+        layer.pixels = pixels;
+        layer
+    }
+
+    /// Creates a new layer from tightly packed straight alpha sRGB RGBA8 pixels
+    pub fn from_rgba(name: impl Into<String>, width: usize, height: usize, pixels: &[u8]) -> Self {
+        let mut layer = Self::new(name, width, height);
+        let premultiplied: Vec<T> = pixels
+            .chunks_exact(4)
+            .flat_map(|p| premultiply(Color::new(p[0], p[1], p[2], p[3]).convert()))
+            .collect();
+        layer.write_rect(layer.bounds(), &premultiplied);
+        layer
+    }
+
+    /// Copy of the layer in another working format
+    pub fn convert<U: Channel>(&self) -> Layer<U> {
+        Layer {
+            name: self.name.clone(),
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.convert(),
+            opacity: self.opacity,
+            blend_mode: self.blend_mode,
+            visible: self.visible,
+            locked: self.locked,
+            alpha_locked: self.alpha_locked,
+            group: self.group,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        self.name = name.into();
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    /// Sets the layer opacity, clamped to `0.0..=1.0`
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = if opacity.is_nan() {
+            0.0
+        } else {
+            opacity.clamp(0.0, 1.0)
+        };
+    }
+
+    /// How the layer is blended onto the layers below it
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    /// A locked layer ignores all pixel writes
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+    }
+
+    /// An alpha locked layer only lets color through, keeping its existing transparency
+    pub fn alpha_locked(&self) -> bool {
+        self.alpha_locked
+    }
+
+    pub fn set_alpha_locked(&mut self, alpha_locked: bool) {
+        self.alpha_locked = alpha_locked;
+    }
+
+    /// Index of the innermost group holding the layer, see [`LayerStack::groups`]
+    pub fn group(&self) -> Option<usize> {
+        self.group
+    }
+
+    pub fn set_group(&mut self, group: Option<usize>) {
+        self.group = group;
+    }
+
+    /// The tiles holding the premultiplied RGBA pixels of the layer
+    pub fn pixels(&self) -> &TiledImage<T> {
+        &self.pixels
+    }
+
+    /// Mutable access to the tiles of the layer, this ignores the lock flags
+    pub fn pixels_mut(&mut self) -> &mut TiledImage<T> {
+        &mut self.pixels
+    }
+
+    /// Reads a pixel as a straight alpha color in the working format
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Color<T>> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        Some(blend::unpremultiply(self.pixels.pixel(x, y)))
+    }
+
+    /// Overwrites a pixel with a straight alpha color, respecting the lock flags of the layer
+    pub fn draw_pixel(&mut self, x: usize, y: usize, color: Color<T>) {
+        if self.locked || x >= self.width || y >= self.height {
+            return;
+        }
+
+        let pixel = self.pixels.pixel_mut(x, y);
+        let alpha = pixel[3];
+        pixel.copy_from_slice(&premultiply(color));
+        if self.alpha_locked {
+            blend::with_alpha(pixel, alpha);
+        }
+    }
+
+    /// Composites a straight alpha `color` onto a pixel using `mode`,
+    /// `coverage` scales the alpha of the color. Respects the lock flags of the layer
+    pub fn blend_pixel(
+        &mut self,
+        x: usize,
+        y: usize,
+        color: Color<T>,
+        coverage: f32,
+        mode: BlendMode,
+    ) {
+        if self.locked || x >= self.width || y >= self.height {
+            return;
+        }
+
+        let pixel = self.pixels.pixel_mut(x, y);
+        let alpha = pixel[3];
+        blend::blend(pixel, premultiply(color), coverage, mode);
+        if self.alpha_locked {
+            blend::with_alpha(pixel, alpha);
+        }
+    }
+
+    /// Moves a pixel towards the straight alpha `color` by `amount`, lowering its alpha
+    /// when the color is more transparent. Respects the lock flags of the layer
+    pub fn smudge_pixel(&mut self, x: usize, y: usize, color: Color<T>, amount: f32) {
+        if self.locked || x >= self.width || y >= self.height {
+            return;
+        }
+
+        let amount = amount.clamp(0.0, 1.0);
+        let pixel = self.pixels.pixel_mut(x, y);
+        let alpha = pixel[3];
+        for (channel, target) in pixel.iter_mut().zip(premultiply(color)) {
+            let value = channel.to_f32();
+            *channel = T::from_f32(value + (target.to_f32() - value) * amount);
+        }
+        if self.alpha_locked {
+            blend::with_alpha(pixel, alpha);
+        }
+    }
+
+    /// Removes `amount` of the alpha of a pixel, respecting the lock flags of the layer
+    pub fn erase_pixel(&mut self, x: usize, y: usize, amount: f32) {
+        if self.locked || self.alpha_locked || x >= self.width || y >= self.height {
+            return;
+        }
+
+        let keep = 1.0 - amount.clamp(0.0, 1.0);
+        for channel in self.pixels.pixel_mut(x, y) {
+            *channel = T::from_f32(channel.to_f32() * keep);
+        }
+    }
+
+    /// Copies the pixels inside `rect` into a tightly packed premultiplied RGBA buffer,
+    /// the rect is clipped to the layer bounds
+    pub fn read_rect(&self, rect: Rect) -> Vec<T> {
+        self.pixels.read_rect(rect)
+    }
+
+    /// Copies the pixels inside `rect` as straight alpha sRGB RGBA8, the counterpart of
+    /// [`Layer::from_rgba`]
+    pub fn read_rgba(&self, rect: Rect) -> Vec<u8> {
+        self.read_rect(rect)
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let color = blend::unpremultiply([p[0], p[1], p[2], p[3]]).convert::<u8>();
+                [color.r, color.g, color.b, color.a]
+            })
+            .collect()
+    }
+
+    /// Overwrites the pixels inside `rect` with a tightly packed RGBA buffer
+    /// previously produced by [`Layer::read_rect`]. This ignores the lock flags
+    pub fn write_rect(&mut self, rect: Rect, pixels: &[T]) {
+        self.pixels.write_rect(rect, pixels);
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    /// Smallest rect containing every pixel of the layer that is not fully transparent
+    pub fn content_bounds(&self) -> Option<Rect> {
+        let pixels = &self.pixels;
+        if pixels.fill()[3] != T::default() {
+            return Some(self.bounds());
+        }
+
+        let mut bounds: Option<Rect> = None;
+        for tile in pixels.tiles() {
+            let Some(data) = tile.tile else {
+                continue;
+            };
+
+            let mut tile_bounds: Option<(usize, usize, usize, usize)> = None;
+            for y in 0..tile.rect.height {
+                for x in 0..tile.rect.width {
+                    if data.pixels()[(y * TILE_SIZE + x) * 4 + 3] == T::default() {
+                        continue;
+                    }
+                    tile_bounds = Some(match tile_bounds {
+                        Some((left, top, right, bottom)) => {
+                            (left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1))
+                        }
+                        None => (x, y, x + 1, y + 1),
+                    });
+                }
+            }
+
+            if let Some((left, top, right, bottom)) = tile_bounds {
+                let rect = Rect::new(
+                    tile.rect.x + left,
+                    tile.rect.y + top,
+                    right - left,
+                    bottom - top,
+                );
+                bounds = Some(bounds.map_or(rect, |b| b.union(&rect)));
+            }
+        }
+        bounds
+    }
+
+    /// Amount of bytes held by the allocated tiles of the layer
+    pub fn memory_size(&self) -> usize {
+        self.pixels.allocated_bytes()
+    }
+
+    /// Composites the area `rect` of this layer over the premultiplied `dst` using its
+    /// opacity and blend mode, `dst` is a tightly packed buffer the size of `rect`.
+    /// `opacity` scales the opacity of the layer, used for the groups holding it
+    pub(crate) fn composite_rect_onto(&self, rect: Rect, dst: &mut [T], opacity: f32) {
+        let opacity = self.opacity * opacity;
+        if !self.visible || opacity <= 0.0 {
+            return;
+        }
+
+        let Some(clipped) = rect.intersect(&self.bounds()) else {
+            return;
+        };
+        let transparent_fill = self.pixels.fill()[3] == T::default();
+
+        for row in clipped.y / TILE_SIZE..=(clipped.bottom() - 1) / TILE_SIZE {
+            for column in clipped.x / TILE_SIZE..=(clipped.right() - 1) / TILE_SIZE {
+                let tile = self.pixels.tile(column, row);
+                /* blending a transparent pixel never changes the destination */
+                if tile.is_none() && transparent_fill {
+                    continue;
+                }
+                let Some(area) = self.pixels.tile_rect(column, row).intersect(&clipped) else {
+                    continue;
+                };
+
+                for y in area.y..area.bottom() {
+                    for x in area.x..area.right() {
+                        let src = match tile {
+                            Some(tile) => tile.pixel(x % TILE_SIZE, y % TILE_SIZE),
+                            None => self.pixels.fill(),
+                        };
+                        let position = ((y - rect.y) * rect.width + (x - rect.x)) * 4;
+                        blend::blend(
+                            &mut dst[position..position + 4],
+                            src,
+                            opacity,
+                            self.blend_mode,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A folder of layers composited as a unit. The layers of a group sit next to each
+/// other in the stack and refer to it through [`Layer::group`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayerGroup {
+    pub name: String,
+    /// Index of the group this one is nested in
+    pub parent: Option<usize>,
+    pub opacity: f32,
+    pub visible: bool,
+    /// `None` passes the layers straight through onto the layers below the group,
+    /// with a blend mode they are composited on their own first and the result is
+    /// blended like a single layer
+    pub blend_mode: Option<BlendMode>,
+}
+
+impl LayerGroup {
+    /// Creates a visible pass through group
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            parent: None,
+            opacity: 1.0,
+            visible: true,
+            blend_mode: None,
+        }
+    }
+}
+
+/// Ordered stack of layers, index 0 being the bottom most layer
+#[derive(Debug, Clone)]
+pub struct LayerStack<T: Channel = u8> {
+    width: usize,
+    height: usize,
+    layers: Vec<Layer<T>>,
+    groups: Vec<LayerGroup>,
+    active: usize,
+    created: usize,
+    /// copy of a layer with pixels that are shown in its place without touching it,
+    /// used to preview filters
+    preview: Option<(usize, Layer<T>)>,
+}
+
+impl<T: Channel> LayerStack<T> {
+    /// Creates a stack holding a single layer
+    pub fn new(base: Layer<T>) -> Self {
+        Self {
+            width: base.width,
+            height: base.height,
+            layers: vec![base],
+            groups: vec![],
+            active: 0,
+            created: 1,
+            preview: None,
+        }
+    }
+
+    /// Creates a stack from layers ordered bottom to top, all of the size of the first one.
+    /// Returns `None` if there are no layers or they differ in size
+    pub fn from_layers(layers: Vec<Layer<T>>, active: usize) -> Option<Self> {
+        let first = layers.first()?;
+        let (width, height) = (first.width, first.height);
+        if layers
+            .iter()
+            .any(|l| l.width != width || l.height != height)
+        {
+            return None;
+        }
+
+        Some(Self {
+            width,
+            height,
+            active: active.min(layers.len() - 1),
+            created: layers.len(),
+            layers,
+            groups: vec![],
+            preview: None,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Iterates the layers from bottom to top
+    pub fn iter(&self) -> std::slice::Iter<'_, Layer<T>> {
+        self.layers.iter()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Layer<T>> {
+        self.layers.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Layer<T>> {
+        self.layers.get_mut(index)
+    }
+
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    pub fn set_active(&mut self, index: usize) {
+        if index < self.layers.len() {
+            self.active = index;
+        }
+    }
+
+    pub fn active(&self) -> &Layer<T> {
+        &self.layers[self.active]
+    }
+
+    pub fn active_mut(&mut self) -> &mut Layer<T> {
+        &mut self.layers[self.active]
+    }
+
+    /// Groups of the stack, layers and nested groups refer to a group by its index
+    pub fn groups(&self) -> &[LayerGroup] {
+        &self.groups
+    }
+
+    pub fn group_mut(&mut self, index: usize) -> Option<&mut LayerGroup> {
+        self.groups.get_mut(index)
+    }
+
+    /// Adds a group without any layers, returning its index
+    pub fn add_group(&mut self, group: LayerGroup) -> usize {
+        self.groups.push(group);
+        self.groups.len() - 1
+    }
+
+    /// Adds a new transparent layer above the active one and makes it active,
+    /// returning its index. The layer joins the group of the active layer
+    pub fn add_layer(&mut self) -> usize {
+        self.created += 1;
+        let mut layer = Layer::new(format!("Layer {}", self.created), self.width, self.height);
+        layer.group = self.active().group;
+        self.insert(self.active + 1, layer)
+    }
+
+    /// Inserts a layer at `index` and makes it active, returning the index it ended up at
+    pub fn insert(&mut self, index: usize, layer: Layer<T>) -> usize {
+        let index = index.min(self.layers.len());
+        self.layers.insert(index, layer);
+        self.active = index;
+        index
+    }
+
+    /// Removes the layer at `index`, the last remaining layer can not be removed
+    pub fn remove(&mut self, index: usize) -> Option<Layer<T>> {
+        if self.layers.len() <= 1 || index >= self.layers.len() {
+            return None;
+        }
+
+        let layer = self.layers.remove(index);
+        if self.active >= index && self.active > 0 {
+            self.active -= 1;
+        }
+        Some(layer)
+    }
+
+    /// Moves a layer to a new position in the stack, the active layer follows the move
+    pub fn move_layer(&mut self, from: usize, to: usize) -> Option<usize> {
+        if from >= self.layers.len() {
+            return None;
+        }
+
+        let active_is_moved = self.active == from;
+        let layer = self.layers.remove(from);
+        let to = to.min(self.layers.len());
+        self.layers.insert(to, layer);
+
+        if active_is_moved {
+            self.active = to;
+        } else if from < self.active && to >= self.active {
+            self.active -= 1;
+        } else if from > self.active && to <= self.active {
+            self.active += 1;
+        }
+        Some(to)
+    }
+
+    /// Duplicates a layer and places the copy directly above it as the active layer
+    pub fn duplicate(&mut self, index: usize) -> Option<usize> {
+        let mut copy = self.layers.get(index)?.clone();
+        copy.set_name(format!("{} copy", copy.name));
+        Some(self.insert(index + 1, copy))
+    }
+
+    /// Merges the layer at `index` into the one below it, returning the index of the merged layer.
+    ///
+    /// Only visible layers of the same group are merged, so the groups around them
+    /// composite the result like they did the two layers and no hidden pixels are lost
+    pub fn merge_down(&mut self, index: usize) -> Option<usize> {
+        if index == 0 || index >= self.layers.len() {
+            return None;
+        }
+        let (lower, upper) = (&self.layers[index - 1], &self.layers[index]);
+        if lower.group != upper.group || !lower.visible || !upper.visible {
+            return None;
+        }
+
+        let upper = self.layers.remove(index);
+        let lower = &mut self.layers[index - 1];
+        let transparent_fill = upper.pixels.fill()[3] == T::default();
+        for tile in upper.pixels.tiles() {
+            if tile.tile.is_none() && transparent_fill {
+                continue;
+            }
+            let mut pixels = lower.read_rect(tile.rect);
+            upper.composite_rect_onto(tile.rect, &mut pixels, 1.0);
+            lower.write_rect(tile.rect, &pixels);
+        }
+
+        self.active = index - 1;
+        Some(index - 1)
+    }
+
+    /// Copy of the stack in another working format
+    pub fn convert<U: Channel>(&self) -> LayerStack<U> {
+        LayerStack {
+            width: self.width,
+            height: self.height,
+            layers: self.layers.iter().map(Layer::convert).collect(),
+            groups: self.groups.clone(),
+            active: self.active,
+            created: self.created,
+            preview: None,
+        }
+    }
+
+    /// Shows `pixels` in place of the pixels of the layer at `index` when compositing,
+    /// the layer itself is left as it is
+    pub(crate) fn set_preview(&mut self, index: usize, pixels: TiledImage<T>) {
+        let Some(layer) = self.layers.get(index) else {
+            return;
+        };
+        let mut preview = layer.clone();
+        preview.pixels = pixels;
+        self.preview = Some((index, preview));
+    }
+
+    /// Stops showing the preview, returning its pixels
+    pub(crate) fn take_preview(&mut self) -> Option<TiledImage<T>> {
+        self.preview.take().map(|(_, layer)| layer.pixels)
+    }
+
+    /// Flattens every visible layer into a single straight alpha sRGB RGBA8 buffer
+    pub fn composite(&self) -> Vec<u8> {
+        self.composite_rect(Rect::new(0, 0, self.width, self.height))
+    }
+
+    /// Flattens the area `rect` of every visible layer into a tightly packed straight
+    /// alpha sRGB RGBA8 buffer, the rect is clipped to the stack bounds
+    pub fn composite_rect(&self, rect: Rect) -> Vec<u8> {
+        self.composite_premultiplied(rect)
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let color = blend::unpremultiply([p[0], p[1], p[2], p[3]]).convert::<u8>();
+                [color.r, color.g, color.b, color.a]
+            })
+            .collect()
+    }
+
+    /// Like [`LayerStack::composite_rect`] but keeping linear light at full precision,
+    /// channels range from 0 to 1
+    pub fn composite_rect_linear(&self, rect: Rect) -> Vec<f32> {
+        self.composite_premultiplied(rect)
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let color = blend::unpremultiply([p[0], p[1], p[2], p[3]]);
+                [
+                    color.r.to_linear(),
+                    color.g.to_linear(),
+                    color.b.to_linear(),
+                    color.a.normalized(),
+                ]
+            })
+            .collect()
+    }
+
+    /// Flattens the area `rect` of every visible layer into premultiplied pixels in the
+    /// working format
+    pub(crate) fn composite_premultiplied(&self, rect: Rect) -> Vec<T> {
+        let Some(rect) = rect.intersect(&Rect::new(0, 0, self.width, self.height)) else {
+            return vec![];
+        };
+
+        let layers: Vec<&Layer<T>> = self
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(index, layer)| match &self.preview {
+                Some((preview_index, preview)) if *preview_index == index => preview,
+                _ => layer,
+            })
+            .collect();
+
+        let mut output = vec![T::default(); rect.width * rect.height * 4];
+        self.composite_group(None, &layers, rect, 1.0, &mut output);
+        output
+    }
+
+    /// Composites `layers`, which all sit inside `group`, over `dst`. Runs of layers in
+    /// a nested group are handed to that group
+    fn composite_group(
+        &self,
+        group: Option<usize>,
+        layers: &[&Layer<T>],
+        rect: Rect,
+        opacity: f32,
+        dst: &mut [T],
+    ) {
+        let mut start = 0;
+        while start < layers.len() {
+            let Some(child) = self.child_group(layers[start], group) else {
+                layers[start].composite_rect_onto(rect, dst, opacity);
+                start += 1;
+                continue;
+            };
+
+            let end = start
+                + layers[start..]
+                    .iter()
+                    .take_while(|layer| self.child_group(layer, group) == Some(child))
+                    .count();
+            let run = &layers[start..end];
+            start = end;
+
+            let properties = &self.groups[child];
+            let opacity = opacity * properties.opacity;
+            if !properties.visible || opacity <= 0.0 {
+                continue;
+            }
+            match properties.blend_mode {
+                None => self.composite_group(Some(child), run, rect, opacity, dst),
+                Some(mode) => {
+                    let mut isolated = vec![T::default(); dst.len()];
+                    self.composite_group(Some(child), run, rect, 1.0, &mut isolated);
+                    for (dst, src) in dst.chunks_exact_mut(4).zip(isolated.chunks_exact(4)) {
+                        blend::blend(dst, [src[0], src[1], src[2], src[3]], opacity, mode);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The group directly inside `group` that holds `layer`, `None` when the layer
+    /// sits in `group` itself
+    fn child_group(&self, layer: &Layer<T>, group: Option<usize>) -> Option<usize> {
+        let mut current = layer.group?;
+        /* the walk is bounded so a cycle of parents can not hang the compositor */
+        for _ in 0..=self.groups.len() {
+            if Some(current) == group {
+                return None;
+            }
+            let parent = self.groups.get(current)?.parent;
+            if parent == group {
+                return Some(current);
+            }
+            current = parent?;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color<u8> = Color {
+        r: 255,
+        g: 0,
+        b: 0,
+        a: 255,
+    };
+
+    /// A stack of `count` 4x4 layers named after their index, with the top one active
+    fn stack(count: usize) -> LayerStack {
+        let layers = (0..count)
+            .map(|i| Layer::new(i.to_string(), 4, 4))
+            .collect();
+        LayerStack::from_layers(layers, count - 1).unwrap()
+    }
+
+    fn names(stack: &LayerStack) -> Vec<&str> {
+        stack.iter().map(Layer::name).collect()
+    }
+
+    #[test]
+    fn adds_above_the_active_layer() {
+        let mut stack = stack(3);
+        stack.set_active(0);
+        assert_eq!(stack.add_layer(), 1);
+        assert_eq!(stack.active_index(), 1);
+        assert_eq!(names(&stack), ["0", "Layer 4", "1", "2"]);
+
+        /* inserting past the end lands on top */
+        let index = stack.insert(10, Layer::new("top", 4, 4));
+        assert_eq!((index, stack.active_index()), (4, 4));
+    }
+
+    #[test]
+    fn removing_keeps_the_active_layer_in_range() {
+        let mut stack = stack(3);
+        assert_eq!(stack.remove(2).unwrap().name(), "2");
+        assert_eq!(stack.active_index(), 1);
+
+        /* removing below the active layer shifts it down with its layer */
+        stack.insert(2, Layer::new("2", 4, 4));
+        assert!(stack.remove(0).is_some());
+        assert_eq!(stack.active().name(), "2");
+
+        assert!(stack.remove(5).is_none());
+        assert!(stack.remove(0).is_some());
+        assert!(stack.remove(0).is_none(), "the last layer stays");
+        assert_eq!(stack.active_index(), 0);
+    }
+
+    #[test]
+    fn active_layer_follows_moves() {
+        let mut stack = stack(4);
+        stack.set_active(1);
+        assert_eq!(stack.move_layer(1, 3), Some(3));
+        assert_eq!(names(&stack), ["0", "2", "3", "1"]);
+        assert_eq!(stack.active().name(), "1");
+
+        /* moving another layer across the active one keeps the same layer active */
+        stack.set_active(1);
+        assert_eq!(stack.move_layer(0, 3), Some(3));
+        assert_eq!(stack.active().name(), "2");
+        stack.move_layer(3, 0);
+        assert_eq!(stack.active().name(), "2");
+
+        assert_eq!(stack.move_layer(8, 0), None);
+        assert_eq!(stack.move_layer(0, 8), Some(3));
+    }
+
+    #[test]
+    fn duplicates_above_the_original() {
+        let mut stack = stack(2);
+        stack.get_mut(0).unwrap().draw_pixel(1, 1, RED);
+        assert_eq!(stack.duplicate(0), Some(1));
+        assert_eq!(names(&stack), ["0", "0 copy", "1"]);
+        assert_eq!(stack.active_index(), 1);
+        assert_eq!(stack.active().pixel(1, 1), Some(RED));
+        assert_eq!(stack.duplicate(3), None);
+    }
+
+    #[test]
+    fn merges_down_with_opacity() {
+        let mut stack = stack(3);
+        let upper = stack.get_mut(2).unwrap();
+        upper.draw_pixel(0, 0, RED);
+        upper.set_opacity(0.5);
+        stack
+            .get_mut(1)
+            .unwrap()
+            .draw_pixel(0, 0, Color::new(0, 0, 255, 255));
+
+        assert_eq!(stack.merge_down(2), Some(1));
+        assert_eq!(names(&stack), ["0", "1"]);
+        assert_eq!(stack.active_index(), 1);
+        assert_eq!(
+            stack.active().pixel(0, 0),
+            Some(Color::new(128, 0, 128, 255))
+        );
+        assert_eq!(stack.active().pixel(1, 0), Some(Color::new(0, 0, 0, 0)));
+
+        assert_eq!(stack.merge_down(0), None);
+        assert_eq!(stack.merge_down(2), None);
+    }
+
+    #[test]
+    fn merges_down_only_inside_one_group() {
+        let mut stack = stack(3);
+        let group = stack.add_group(LayerGroup {
+            opacity: 0.5,
+            ..LayerGroup::new("group")
+        });
+        stack.get_mut(1).unwrap().set_group(Some(group));
+        stack.get_mut(2).unwrap().set_group(Some(group));
+        stack.get_mut(2).unwrap().draw_pixel(0, 0, RED);
+
+        /* the bottom layer sits outside the group, which would drop its opacity */
+        assert_eq!(stack.merge_down(1), None);
+        assert_eq!(stack.len(), 3);
+
+        let before = stack.composite();
+        assert_eq!(stack.merge_down(2), Some(1));
+        assert_eq!(stack.get(1).unwrap().group(), Some(group));
+        assert_eq!(stack.composite(), before);
+    }
+
+    #[test]
+    fn hidden_layers_are_not_merged() {
+        let mut stack = stack(3);
+        let upper = stack.get_mut(2).unwrap();
+        upper.draw_pixel(0, 0, RED);
+        upper.set_visible(false);
+        assert_eq!(stack.merge_down(2), None);
+        assert_eq!(stack.get(2).unwrap().pixel(0, 0), Some(RED));
+
+        stack.get_mut(2).unwrap().set_visible(true);
+        stack.get_mut(1).unwrap().set_visible(false);
+        assert_eq!(stack.merge_down(2), None);
+        assert_eq!(stack.len(), 3);
+    }
+
+    #[test]
+    fn groups_composite_their_layers() {
+        let blue = Color::new(0, 0, 255, 255);
+        let white = Color::new(255, 255, 255, 255);
+        let mut stack = stack(3);
+        stack.get_mut(0).unwrap().draw_pixel(0, 0, white);
+        stack.get_mut(1).unwrap().draw_pixel(0, 0, RED);
+        stack.get_mut(2).unwrap().draw_pixel(0, 0, blue);
+
+        let group = stack.add_group(LayerGroup {
+            opacity: 0.5,
+            ..LayerGroup::new("group")
+        });
+        stack.get_mut(1).unwrap().set_group(Some(group));
+        stack.get_mut(2).unwrap().set_group(Some(group));
+
+        /* passing through, every layer fades on its own and the red shows */
+        assert_eq!(
+            stack.composite_rect(Rect::new(0, 0, 1, 1)),
+            [128, 64, 192, 255]
+        );
+
+        /* isolated, the group is flattened first so only the blue fades */
+        stack.group_mut(group).unwrap().blend_mode = Some(BlendMode::Normal);
+        assert_eq!(
+            stack.composite_rect(Rect::new(0, 0, 1, 1)),
+            [128, 128, 255, 255]
+        );
+
+        stack.group_mut(group).unwrap().visible = false;
+        assert_eq!(
+            stack.composite_rect(Rect::new(0, 0, 1, 1)),
+            [255, 255, 255, 255]
+        );
+
+        /* new layers join the group of the active layer */
+        stack.set_active(1);
+        let index = stack.add_layer();
+        assert_eq!(stack.get(index).unwrap().group(), Some(group));
+    }
+
+    #[test]
+    fn locks_reject_writes() {
+        let mut layer: Layer = Layer::new("locked", 2, 2);
+        layer.set_locked(true);
+        layer.draw_pixel(0, 0, RED);
+        assert_eq!(layer.pixel(0, 0), Some(Color::new(0, 0, 0, 0)));
+
+        /* alpha lock paints color but keeps the transparency */
+        layer.set_locked(false);
+        layer.draw_pixel(1, 1, Color::new(0, 0, 255, 128));
+        layer.set_alpha_locked(true);
+        layer.draw_pixel(0, 0, RED);
+        layer.draw_pixel(1, 1, RED);
+        assert_eq!(layer.pixel(0, 0).unwrap().a, 0);
+        let tinted = layer.pixel(1, 1).unwrap();
+        assert_eq!((tinted.r, tinted.b, tinted.a), (255, 0, 128));
+    }
+
+    #[test]
+    fn stacks_need_layers_of_one_size() {
+        assert!(LayerStack::<u8>::from_layers(vec![], 0).is_none());
+        let mixed = vec![Layer::new("a", 4, 4), Layer::new("b", 4, 5)];
+        assert!(LayerStack::<u8>::from_layers(mixed, 0).is_none());
+        let stack = LayerStack::<u8>::from_layers(vec![Layer::new("a", 4, 4)], 7).unwrap();
+        assert_eq!(stack.active_index(), 0);
+    }
+}
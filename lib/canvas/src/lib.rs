@@ -1,7 +1,25 @@
+pub mod blend;
 pub mod brush;
 pub mod canvas;
 pub mod color;
+pub mod dirty;
+pub mod fill;
+pub mod filter;
+pub mod format;
+pub mod history;
+pub mod layer;
+pub mod picker;
+mod random;
+pub mod selection;
+pub mod tile;
+pub mod transform;
+pub mod view;
 
-pub use color::Color;
+#[cfg(test)]
+mod testing;
 
+pub use color::{Channel, Color, PixelFormat};
+
+pub use blend::BlendMode;
 pub use canvas::Canvas;
+pub use layer::{Layer, LayerGroup, LayerStack};
@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Canvas;
+use crate::blend::unpremultiply;
+use crate::canvas::Rect;
+use crate::color::{Channel, Color};
+use crate::fill::FillSource;
+
+/// Area the eyedropper averages around the picked pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "size", rename_all = "camelCase")]
+pub enum SampleSize {
+    /// Only the pixel under the pointer
+    #[default]
+    Point,
+    /// 3x3 pixels centred on the pointer
+    Average3x3,
+    /// 5x5 pixels centred on the pointer
+    Average5x5,
+    /// Every pixel whose centre is at most `radius` pixels away
+    Radius { radius: usize },
+}
+
+impl SampleSize {
+    fn radius(self) -> usize {
+        match self {
+            SampleSize::Point => 0,
+            SampleSize::Average3x3 => 1,
+            SampleSize::Average5x5 => 2,
+            SampleSize::Radius { radius } => radius,
+        }
+    }
+}
+
+/// Reads the sRGB color at canvas pixel `x`, `y`, averaged over `size`. Samples outside
+/// the canvas are left out, `None` if the pixel itself is outside
+pub fn pick_color<T: Channel>(
+    canvas: &Canvas<T>,
+    x: usize,
+    y: usize,
+    source: FillSource,
+    size: SampleSize,
+) -> Option<Color<u8>> {
+    if x >= canvas.width() || y >= canvas.height() {
+        return None;
+    }
+
+    /* a radius past the far corner samples the same pixels, clamping keeps the math small */
+    let radius = size.radius().min(canvas.width() + canvas.height());
+    let (left, top) = (x.saturating_sub(radius), y.saturating_sub(radius));
+    let area = Rect::new(left, top, x + radius + 1 - left, y + radius + 1 - top)
+        .intersect(&Rect::new(0, 0, canvas.width(), canvas.height()))?;
+
+    /* averaged premultiplied so transparent pixels don't darken the result */
+    let pixels = match source {
+        FillSource::Layer => canvas.layers().active().read_rect(area),
+        FillSource::Merged => canvas.layers().composite_premultiplied(area),
+    };
+
+    let round = matches!(size, SampleSize::Radius { .. });
+    let mut sum = [0.0f64; 4];
+    let mut count = 0.0f64;
+    for (i, pixel) in pixels.chunks_exact(4).enumerate() {
+        let dx = (area.x + i % area.width).abs_diff(x);
+        let dy = (area.y + i / area.width).abs_diff(y);
+        if round && dx * dx + dy * dy > radius * radius {
+            continue;
+        }
+        for (total, &channel) in sum.iter_mut().zip(pixel) {
+            *total += channel.to_f32() as f64;
+        }
+        count += 1.0;
+    }
+
+    let average = sum.map(|total| T::from_f32((total / count) as f32));
+    Some(unpremultiply(average).convert())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_only_the_sampled_area() {
+        let mut canvas: Canvas = Canvas::new(10, 10);
+        canvas.begin_paint();
+        canvas.draw_pixel(5, 5, Color::new(0, 0, 0, 255));
+        canvas.end_paint();
+
+        let point = pick_color(&canvas, 5, 5, FillSource::Layer, SampleSize::Point);
+        assert_eq!(point, Some(Color::new(0, 0, 0, 255)));
+
+        let square = pick_color(&canvas, 5, 5, FillSource::Merged, SampleSize::Average3x3);
+        assert_eq!(square, Some(Color::new(227, 227, 227, 255)));
+
+        /* the corners of the 3x3 square are outside a radius of one */
+        let round = pick_color(
+            &canvas,
+            5,
+            5,
+            FillSource::Layer,
+            SampleSize::Radius { radius: 1 },
+        );
+        assert_eq!(round, Some(Color::new(204, 204, 204, 255)));
+
+        assert_eq!(
+            pick_color(&canvas, 10, 0, FillSource::Layer, SampleSize::Point),
+            None
+        );
+    }
+
+    #[test]
+    fn huge_radii_sample_the_whole_canvas() {
+        let mut canvas: Canvas = Canvas::new(2, 1);
+        canvas.begin_paint();
+        canvas.draw_pixel(0, 0, Color::new(0, 0, 0, 255));
+        canvas.end_paint();
+
+        let picked = pick_color(
+            &canvas,
+            1,
+            0,
+            FillSource::Layer,
+            SampleSize::Radius { radius: usize::MAX },
+        );
+        assert_eq!(picked, Some(Color::new(128, 128, 128, 255)));
+    }
+}
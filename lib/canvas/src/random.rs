@@ -0,0 +1,23 @@
+//! splitmix64, the random numbers behind brush jitter and filter noise. It is seeded
+//! explicitly everywhere, so the same settings always paint the same pixels
+
+/// Step of the splitmix64 sequence
+pub(crate) const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Scrambles `z` into a well spread 64 bit value, the output function of splitmix64
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Uniform number between 0 and 1 from the top 24 bits of `z`
+pub(crate) fn unit(z: u64) -> f32 {
+    (z >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Uniform random number between 0 and 1 from the sequence in `state`
+pub(crate) fn next_random(state: &mut u64) -> f32 {
+    *state = state.wrapping_add(GAMMA);
+    unit(mix(*state))
+}
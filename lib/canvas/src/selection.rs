@@ -0,0 +1,538 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Canvas;
+use crate::canvas::Rect;
+use crate::color::Channel;
+use crate::fill::{self, FillOptions};
+
+/// How a new selection is combined with the current one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SelectionMode {
+    #[default]
+    Replace,
+    Add,
+    Subtract,
+    Intersect,
+}
+
+/// Vertical samples taken per pixel row when rasterizing polygons
+const POLYGON_SUBSAMPLES: usize = 4;
+/// Samples taken per pixel along each axis when rasterizing ellipses
+const ELLIPSE_SUBSAMPLES: usize = 4;
+
+/// An 8 bit mask over the canvas, 255 is fully selected and 0 not selected at all.
+/// Values in between come from anti-aliased edges and feathering and scale how
+/// strongly painting affects a pixel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    width: usize,
+    height: usize,
+    mask: Vec<u8>,
+}
+
+impl Selection {
+    /// Creates a selection with nothing selected
+    pub fn empty(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            mask: vec![0; width * height],
+        }
+    }
+
+    /// Creates a selection covering the whole canvas
+    pub fn all(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            mask: vec![255; width * height],
+        }
+    }
+
+    /// Selects the pixels of `rect`
+    pub fn rectangle(width: usize, height: usize, rect: Rect) -> Self {
+        let mut selection = Self::empty(width, height);
+        if let Some(rect) = rect.intersect(&Rect::new(0, 0, width, height)) {
+            for y in rect.y..rect.bottom() {
+                selection.mask[y * width + rect.x..y * width + rect.right()].fill(255);
+            }
+        }
+        selection
+    }
+
+    /// Selects the ellipse inscribed in `rect`, with anti-aliased edges
+    pub fn ellipse(width: usize, height: usize, rect: Rect) -> Self {
+        let mut selection = Self::empty(width, height);
+        let Some(area) = rect.intersect(&Rect::new(0, 0, width, height)) else {
+            return selection;
+        };
+
+        let radius_x = rect.width as f32 / 2.0;
+        let radius_y = rect.height as f32 / 2.0;
+        let center_x = rect.x as f32 + radius_x;
+        let center_y = rect.y as f32 + radius_y;
+        let step = 1.0 / ELLIPSE_SUBSAMPLES as f32;
+
+        for y in area.y..area.bottom() {
+            for x in area.x..area.right() {
+                let mut inside = 0;
+                for sy in 0..ELLIPSE_SUBSAMPLES {
+                    for sx in 0..ELLIPSE_SUBSAMPLES {
+                        let dx = (x as f32 + (sx as f32 + 0.5) * step - center_x) / radius_x;
+                        let dy = (y as f32 + (sy as f32 + 0.5) * step - center_y) / radius_y;
+                        inside += (dx * dx + dy * dy <= 1.0) as usize;
+                    }
+                }
+                selection.mask[y * width + x] =
+                    (inside * 255 / (ELLIPSE_SUBSAMPLES * ELLIPSE_SUBSAMPLES)) as u8;
+            }
+        }
+        selection
+    }
+
+    /// Selects the inside of a closed polygon in canvas coordinates using the even-odd
+    /// rule, with anti-aliased edges. Used for lasso selections
+    pub fn polygon(width: usize, height: usize, points: &[(f32, f32)]) -> Self {
+        let mut selection = Self::empty(width, height);
+        if points.len() < 3 {
+            return selection;
+        }
+
+        let mut coverage = vec![0.0f32; width];
+        let mut crossings = vec![];
+        for y in 0..height {
+            coverage.fill(0.0);
+            for sample in 0..POLYGON_SUBSAMPLES {
+                let sample_y = y as f32 + (sample as f32 + 0.5) / POLYGON_SUBSAMPLES as f32;
+
+                crossings.clear();
+                for (i, &(x0, y0)) in points.iter().enumerate() {
+                    let (x1, y1) = points[(i + 1) % points.len()];
+                    if (y0 <= sample_y) != (y1 <= sample_y) {
+                        crossings.push(x0 + (sample_y - y0) / (y1 - y0) * (x1 - x0));
+                    }
+                }
+                crossings.sort_by(f32::total_cmp);
+
+                /* spans between pairs of crossings are inside, partially covered
+                 * pixels at their ends get the covered fraction */
+                for span in crossings.chunks_exact(2) {
+                    let start = span[0].clamp(0.0, width as f32);
+                    let end = span[1].clamp(0.0, width as f32);
+                    let mut x = start;
+                    while x < end {
+                        let pixel = x.floor();
+                        let next = (pixel + 1.0).min(end);
+                        coverage[pixel as usize] += next - x;
+                        x = next;
+                    }
+                }
+            }
+
+            for (value, covered) in selection.mask[y * width..(y + 1) * width]
+                .iter_mut()
+                .zip(&coverage)
+            {
+                *value = (covered / POLYGON_SUBSAMPLES as f32 * 255.0)
+                    .round()
+                    .clamp(0.0, 255.0) as u8;
+            }
+        }
+        selection
+    }
+
+    /// Selects the pixels a fill started at canvas pixel `x`, `y` would cover
+    pub fn magic_wand<T: Channel>(
+        canvas: &Canvas<T>,
+        x: usize,
+        y: usize,
+        options: &FillOptions,
+    ) -> Option<Self> {
+        let mask = fill::fill_mask(canvas, x, y, options)?;
+        let (width, height) = (canvas.width(), canvas.height());
+        let mut selection = Self::empty(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                if mask.contains(x, y) {
+                    selection.mask[y * width + x] = 255;
+                }
+            }
+        }
+        Some(selection)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// How strongly a pixel is selected, 0 outside the canvas
+    pub fn coverage(&self, x: usize, y: usize) -> u8 {
+        if x >= self.width || y >= self.height {
+            return 0;
+        }
+        self.mask[y * self.width + x]
+    }
+
+    /// The mask, one byte per pixel row by row
+    pub fn mask(&self) -> &[u8] {
+        &self.mask
+    }
+
+    /// Overwrites the coverage of `rect` with `values`, one byte per pixel
+    pub(crate) fn set_rect(&mut self, rect: Rect, values: &[u8]) {
+        for (row, values) in values.chunks_exact(rect.width.max(1)).enumerate() {
+            let start = (rect.y + row) * self.width + rect.x;
+            self.mask[start..start + rect.width].copy_from_slice(values);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mask.iter().all(|&m| m == 0)
+    }
+
+    /// Smallest rect containing every selected pixel, `None` if nothing is selected
+    pub fn bounds(&self) -> Option<Rect> {
+        let mut bounds: Option<Rect> = None;
+        for (y, row) in self.mask.chunks_exact(self.width.max(1)).enumerate() {
+            let Some(left) = row.iter().position(|&m| m > 0) else {
+                continue;
+            };
+            let right = row.iter().rposition(|&m| m > 0).unwrap_or(left);
+            let rect = Rect::new(left, y, right - left + 1, 1);
+            bounds = Some(bounds.map_or(rect, |b| b.union(&rect)));
+        }
+        bounds
+    }
+
+    /// Combines `other` into this selection, both have to be the same size
+    pub fn combine(&mut self, other: &Selection, mode: SelectionMode) {
+        if mode == SelectionMode::Replace {
+            self.mask.copy_from_slice(&other.mask);
+            return;
+        }
+
+        for (a, &b) in self.mask.iter_mut().zip(&other.mask) {
+            *a = match mode {
+                SelectionMode::Replace => b,
+                SelectionMode::Add => (*a).max(b),
+                SelectionMode::Subtract => mul(*a, 255 - b),
+                SelectionMode::Intersect => mul(*a, b),
+            };
+        }
+    }
+
+    pub fn invert(&mut self) {
+        for value in &mut self.mask {
+            *value = 255 - *value;
+        }
+    }
+
+    /// Softens the edges of the selection over roughly `radius` pixels
+    pub fn feather(&mut self, radius: usize) {
+        if radius == 0 {
+            return;
+        }
+
+        /* three box blurs come close to a gaussian blur, each covering a third */
+        let box_radius = radius.div_ceil(3);
+        for _ in 0..3 {
+            self.filter(|src, dst| box_line(src, dst, box_radius));
+        }
+    }
+
+    /// Expands the selection by `pixels` in every direction
+    pub fn grow(&mut self, pixels: usize) {
+        if pixels > 0 {
+            self.filter(|src, dst| extreme_line(src, dst, pixels, 0, u8::max));
+        }
+    }
+
+    /// Contracts the selection by `pixels` in every direction
+    pub fn shrink(&mut self, pixels: usize) {
+        if pixels > 0 {
+            self.filter(|src, dst| extreme_line(src, dst, pixels, 255, u8::min));
+        }
+    }
+
+    /// Runs `line` over every row, then over every column of the mask
+    fn filter(&mut self, line: impl Fn(&[u8], &mut [u8])) {
+        let (width, height) = (self.width, self.height);
+        let mut src = vec![];
+        let mut dst = vec![0; width.max(height)];
+        for y in 0..height {
+            src.clear();
+            src.extend_from_slice(&self.mask[y * width..(y + 1) * width]);
+            line(&src, &mut self.mask[y * width..(y + 1) * width]);
+        }
+        for x in 0..width {
+            src.clear();
+            src.extend((0..height).map(|y| self.mask[y * width + x]));
+            line(&src, &mut dst[..height]);
+            for (y, &value) in dst[..height].iter().enumerate() {
+                self.mask[y * width + x] = value;
+            }
+        }
+    }
+
+    /// Line segments in canvas coordinates along the pixel edges between selected and
+    /// unselected pixels, a pixel counts as selected from half coverage on. Neighbouring
+    /// segments on the same edge are merged
+    pub fn outline(&self) -> Vec<[[f32; 2]; 2]> {
+        let inside = |x: isize, y: isize| {
+            x >= 0
+                && y >= 0
+                && (x as usize) < self.width
+                && (y as usize) < self.height
+                && self.mask[y as usize * self.width + x as usize] >= 128
+        };
+
+        let mut segments = vec![];
+        /* horizontal edges above every row, including the bottom edge of the canvas */
+        for y in 0..=self.height as isize {
+            let mut start = None;
+            for x in 0..=self.width as isize {
+                let edge = x < self.width as isize && inside(x, y - 1) != inside(x, y);
+                match (edge, start) {
+                    (true, None) => start = Some(x),
+                    (false, Some(from)) => {
+                        segments.push([[from as f32, y as f32], [x as f32, y as f32]]);
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for x in 0..=self.width as isize {
+            let mut start = None;
+            for y in 0..=self.height as isize {
+                let edge = y < self.height as isize && inside(x - 1, y) != inside(x, y);
+                match (edge, start) {
+                    (true, None) => start = Some(y),
+                    (false, Some(from)) => {
+                        segments.push([[x as f32, from as f32], [x as f32, y as f32]]);
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        segments
+    }
+}
+
+/// Average of a window of `radius` pixels to each side using a running sum, windows
+/// are cut off at the ends of the line
+fn box_line(src: &[u8], dst: &mut [u8], radius: usize) {
+    let length = src.len();
+    let mut sum: usize = src[..(radius + 1).min(length)]
+        .iter()
+        .map(|&v| v as usize)
+        .sum();
+    for x in 0..length {
+        let count = (x + radius + 1).min(length) - x.saturating_sub(radius);
+        dst[x] = (sum / count) as u8;
+        if let Some(&entering) = src.get(x + radius + 1) {
+            sum += entering as usize;
+        }
+        if x >= radius {
+            sum -= src[x - radius] as usize;
+        }
+    }
+}
+
+/// Maximum or minimum, picked by `pick`, of a window of `radius` pixels to each side.
+///
+/// Uses the van Herk/Gil-Werman algorithm: the line is split into blocks the size of a
+/// window, and every window is covered by the tail of one block and the head of the
+/// next, so each pixel costs three comparisons whatever the radius. `identity` pads the
+/// ends, cutting the windows off there
+fn extreme_line(src: &[u8], dst: &mut [u8], radius: usize, identity: u8, pick: fn(u8, u8) -> u8) {
+    /* past the length of the line every window covers all of it */
+    let radius = radius.min(src.len());
+    let size = 2 * radius + 1;
+
+    let mut padded = vec![identity; src.len() + 2 * radius];
+    padded[radius..radius + src.len()].copy_from_slice(src);
+
+    /* running extremes from the start of each block and from the end of each block */
+    let mut head = padded.clone();
+    let mut tail = padded.clone();
+    for i in 1..padded.len() {
+        if i % size != 0 {
+            head[i] = pick(head[i - 1], padded[i]);
+        }
+    }
+    for i in (0..padded.len().saturating_sub(1)).rev() {
+        if (i + 1) % size != 0 {
+            tail[i] = pick(tail[i + 1], padded[i]);
+        }
+    }
+
+    for (x, value) in dst.iter_mut().enumerate() {
+        *value = pick(tail[x], head[x + 2 * radius]);
+    }
+}
+
+fn mul(a: u8, b: u8) -> u8 {
+    ((a as u32 * b as u32 + 127) / 255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlendMode, Color};
+    use proptest::prelude::*;
+
+    /// Applies `reduce` to every window of `radius` pixels to each side, cut off at the ends
+    fn naive_line(src: &[u8], radius: usize, reduce: impl Fn(&[u8]) -> u8) -> Vec<u8> {
+        (0..src.len())
+            .map(|x| reduce(&src[x.saturating_sub(radius)..(x + radius + 1).min(src.len())]))
+            .collect()
+    }
+
+    #[test]
+    fn shapes_combine_with_modes() {
+        let mut selection = Selection::rectangle(20, 20, Rect::new(2, 2, 10, 10));
+        selection.combine(
+            &Selection::rectangle(20, 20, Rect::new(8, 8, 10, 10)),
+            SelectionMode::Add,
+        );
+        assert_eq!(selection.bounds(), Some(Rect::new(2, 2, 16, 16)));
+
+        selection.combine(
+            &Selection::rectangle(20, 20, Rect::new(0, 0, 20, 5)),
+            SelectionMode::Subtract,
+        );
+        assert_eq!(selection.bounds(), Some(Rect::new(2, 5, 16, 13)));
+
+        selection.combine(
+            &Selection::ellipse(20, 20, Rect::new(0, 0, 20, 20)),
+            SelectionMode::Intersect,
+        );
+        assert_eq!(selection.coverage(10, 10), 255);
+        assert_eq!(selection.coverage(17, 17), 0);
+
+        selection.invert();
+        assert_eq!(selection.coverage(0, 0), 255);
+    }
+
+    #[test]
+    fn polygons_are_anti_aliased() {
+        let square = [(2.0, 2.0), (6.5, 2.0), (6.5, 6.0), (2.0, 6.0)];
+        let selection = Selection::polygon(10, 10, &square);
+        assert_eq!(selection.coverage(3, 3), 255);
+        assert_eq!(selection.coverage(6, 3), 128);
+        assert_eq!(selection.coverage(7, 3), 0);
+        assert_eq!(selection.bounds(), Some(Rect::new(2, 2, 5, 4)));
+    }
+
+    #[test]
+    fn grow_shrink_and_feather() {
+        let mut selection = Selection::rectangle(30, 30, Rect::new(10, 10, 10, 10));
+        selection.grow(2);
+        assert_eq!(selection.bounds(), Some(Rect::new(8, 8, 14, 14)));
+        selection.shrink(4);
+        assert_eq!(selection.bounds(), Some(Rect::new(12, 12, 6, 6)));
+
+        let mut selection = Selection::rectangle(30, 30, Rect::new(5, 5, 20, 20));
+        selection.feather(3);
+        assert_eq!(selection.coverage(15, 15), 255);
+        let edge = selection.coverage(5, 15);
+        assert!(edge > 0 && edge < 255);
+    }
+
+    proptest! {
+        #[test]
+        fn line_filters_match_their_windows(
+            line in prop::collection::vec(any::<u8>(), 1..40),
+            radius in 0usize..50,
+        ) {
+            let mut dst = vec![0; line.len()];
+
+            extreme_line(&line, &mut dst, radius, 0, u8::max);
+            prop_assert_eq!(&dst, &naive_line(&line, radius, |w| *w.iter().max().unwrap()));
+
+            extreme_line(&line, &mut dst, radius, 255, u8::min);
+            prop_assert_eq!(&dst, &naive_line(&line, radius, |w| *w.iter().min().unwrap()));
+
+            box_line(&line, &mut dst, radius);
+            let average = |w: &[u8]| (w.iter().map(|&v| v as usize).sum::<usize>() / w.len()) as u8;
+            prop_assert_eq!(&dst, &naive_line(&line, radius, average));
+        }
+    }
+
+    #[test]
+    fn outline_follows_the_selected_pixels() {
+        let selection = Selection::rectangle(10, 10, Rect::new(1, 2, 3, 4));
+        let mut outline = selection.outline();
+        outline.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(
+            outline,
+            [
+                [[1.0, 2.0], [1.0, 6.0]],
+                [[1.0, 2.0], [4.0, 2.0]],
+                [[1.0, 6.0], [4.0, 6.0]],
+                [[4.0, 2.0], [4.0, 6.0]],
+            ]
+        );
+    }
+
+    #[test]
+    fn painting_is_limited_to_the_selection() {
+        let mut canvas: Canvas = Canvas::new(10, 10);
+        canvas.select(
+            Selection::rectangle(10, 10, Rect::new(0, 0, 5, 10)),
+            SelectionMode::Replace,
+        );
+        canvas.select(
+            Selection::rectangle(10, 10, Rect::new(0, 0, 2, 10)),
+            SelectionMode::Subtract,
+        );
+
+        let red = Color::new(255, 0, 0, 255);
+        for x in 0..10 {
+            canvas.blend_pixel(x, 0, red, 1.0, BlendMode::Normal);
+        }
+        let row: Vec<_> = (0..10)
+            .map(|x| canvas.layers().active().pixel(x, 0))
+            .collect();
+        assert_eq!(row.iter().filter(|&&p| p == Some(red)).count(), 3);
+        assert_eq!(row[2], Some(red));
+
+        canvas.invert_selection();
+        canvas.select_none();
+        assert!(canvas.selection().is_none());
+    }
+
+    #[test]
+    fn drawn_pixels_fade_with_partial_coverage() {
+        let mut canvas: Canvas = Canvas::new(10, 10);
+        canvas.select(
+            Selection::polygon(10, 10, &[(0.0, 0.0), (2.5, 0.0), (2.5, 1.0), (0.0, 1.0)]),
+            SelectionMode::Replace,
+        );
+        let coverage = canvas.selection().unwrap().coverage(2, 0);
+        assert!((100..156).contains(&coverage), "{coverage}");
+
+        let red = Color::new(255, 0, 0, 255);
+        for x in 0..4 {
+            canvas.draw_pixel(x, 0, red);
+        }
+        let layer = canvas.layers().active();
+        assert_eq!(layer.pixel(1, 0), Some(red));
+        assert_eq!(layer.pixel(3, 0), Some(Color::new(255, 255, 255, 255)));
+
+        /* the white background only moves towards red by the coverage */
+        let faded = layer.pixel(2, 0).unwrap();
+        assert_eq!((faded.r, faded.a), (255, 255));
+        assert!(
+            faded.g.abs_diff(255 - coverage) <= 1,
+            "{faded:?} {coverage}"
+        );
+    }
+}
@@ -0,0 +1,48 @@
+//! Helpers shared by the unit tests
+
+use std::fs::File;
+use std::path::PathBuf;
+
+use crate::format::png;
+
+/// Path of `name` inside `testdata/<dir>` of the crate
+pub(crate) fn testdata(dir: &str, name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "testdata", dir, name]
+        .iter()
+        .collect()
+}
+
+/// Compares the straight alpha RGBA8 `pixels` of an image `width` by `height` with the
+/// png `testdata/<dir>/<name>.png`, every channel may be off by up to `tolerance`.
+/// Setting `UPDATE_GOLDEN` writes `pixels` as the new golden image instead
+pub(crate) fn assert_golden(
+    dir: &str,
+    name: &str,
+    (width, height): (usize, usize),
+    pixels: &[u8],
+    tolerance: u8,
+) {
+    let path = testdata(dir, &format!("{name}.png"));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        png::encode(width, height, pixels, File::create(&path).unwrap()).unwrap();
+        return;
+    }
+
+    let (golden_width, golden_height, golden) = png::decode(File::open(&path).unwrap()).unwrap();
+    assert_eq!(
+        (golden_width, golden_height),
+        (width, height),
+        "{name}: size"
+    );
+    for (i, (&a, &b)) in pixels.iter().zip(&golden).enumerate() {
+        assert!(
+            a.abs_diff(b) <= tolerance,
+            "{name}: channel {} of pixel ({}, {}) is {a}, expected {b}",
+            i % 4,
+            i / 4 % width,
+            i / 4 / width
+        );
+    }
+}
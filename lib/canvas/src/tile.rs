@@ -0,0 +1,347 @@
+use std::sync::Arc;
+
+use crate::blend;
+use crate::canvas::Rect;
+use crate::color::Channel;
+
+/// Width and height of a tile in pixels
+pub const TILE_SIZE: usize = 64;
+
+/// Channels in a whole tile
+const TILE_CHANNELS: usize = TILE_SIZE * TILE_SIZE * 4;
+
+/// A square block of premultiplied RGBA pixels.
+///
+/// Cloning a tile is cheap, the pixels are shared until one of the clones is written to
+#[derive(Debug, Clone)]
+pub struct Tile<T: Channel = u8> {
+    pixels: Arc<Vec<T>>,
+}
+
+impl<T: Channel> Tile<T> {
+    fn filled(fill: [T; 4]) -> Self {
+        let pixels: Vec<T> = fill.iter().cycle().take(TILE_CHANNELS).cloned().collect();
+        Self {
+            pixels: Arc::new(pixels),
+        }
+    }
+
+    /// Wraps the pixels of a whole tile, `None` if there are not exactly
+    /// `TILE_SIZE * TILE_SIZE * 4` channels
+    pub fn from_pixels(pixels: Vec<T>) -> Option<Self> {
+        (pixels.len() == TILE_CHANNELS).then(|| Self {
+            pixels: Arc::new(pixels),
+        })
+    }
+
+    /// The pixels of the whole tile, rows are `TILE_SIZE * 4` channels long even for
+    /// tiles that stick out of the image
+    pub fn pixels(&self) -> &[T] {
+        &self.pixels
+    }
+
+    /// Reads a pixel in tile local coordinates
+    pub fn pixel(&self, x: usize, y: usize) -> [T; 4] {
+        let position = (y * TILE_SIZE + x) * 4;
+        let mut pixel = [T::default(); 4];
+        pixel.copy_from_slice(&self.pixels[position..position + 4]);
+        pixel
+    }
+
+    /// Whether both tiles share the same pixel memory
+    pub fn shares_pixels(&self, other: &Tile<T>) -> bool {
+        Arc::ptr_eq(&self.pixels, &other.pixels)
+    }
+
+    /// Amount of bytes held by the pixels of the tile
+    pub fn byte_size(&self) -> usize {
+        std::mem::size_of_val(self.pixels.as_slice())
+    }
+
+    fn pixels_mut(&mut self) -> &mut [T] {
+        Arc::make_mut(&mut self.pixels).as_mut_slice()
+    }
+}
+
+/// A tile of an image handed out by [`TiledImage::tiles`]
+pub struct TileRef<'a, T: Channel = u8> {
+    pub column: usize,
+    pub row: usize,
+    /// Area of the image covered by the tile, clipped to the image bounds
+    pub rect: Rect,
+    /// `None` if the tile was never written to, every pixel of it is then the image fill
+    pub tile: Option<&'a Tile<T>>,
+}
+
+/// Sparse image made of [`Tile`]s that are only allocated once they are written to
+#[derive(Debug, Clone)]
+pub struct TiledImage<T: Channel = u8> {
+    width: usize,
+    height: usize,
+    columns: usize,
+    rows: usize,
+    tiles: Vec<Option<Tile<T>>>,
+    fill: [T; 4],
+}
+
+impl<T: Channel> TiledImage<T> {
+    /// Creates an image where every pixel reads as the premultiplied `fill`
+    /// without allocating any tiles
+    pub fn new(width: usize, height: usize, fill: [T; 4]) -> Self {
+        let columns = width.div_ceil(TILE_SIZE);
+        let rows = height.div_ceil(TILE_SIZE);
+
+        Self {
+            width,
+            height,
+            columns,
+            rows,
+            tiles: vec![None; columns * rows],
+            fill,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// The value of every pixel in an unallocated tile
+    pub fn fill(&self) -> [T; 4] {
+        self.fill
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    pub fn tile(&self, column: usize, row: usize) -> Option<&Tile<T>> {
+        if column >= self.columns || row >= self.rows {
+            return None;
+        }
+        self.tiles[row * self.columns + column].as_ref()
+    }
+
+    /// Area of the image covered by a tile, clipped to the image bounds
+    pub fn tile_rect(&self, column: usize, row: usize) -> Rect {
+        tile_rect(column, row, self.width, self.height)
+    }
+
+    /// Swaps a tile out of the image, used to restore snapshots taken with [`TiledImage::tile`]
+    pub fn replace_tile(
+        &mut self,
+        column: usize,
+        row: usize,
+        tile: Option<Tile<T>>,
+    ) -> Option<Tile<T>> {
+        if column >= self.columns || row >= self.rows {
+            return tile;
+        }
+        std::mem::replace(&mut self.tiles[row * self.columns + column], tile)
+    }
+
+    /// Iterates every tile of the image row by row, allocated or not
+    pub fn tiles(&self) -> impl Iterator<Item = TileRef<'_, T>> {
+        (0..self.rows).flat_map(move |row| {
+            (0..self.columns).map(move |column| TileRef {
+                column,
+                row,
+                rect: self.tile_rect(column, row),
+                tile: self.tiles[row * self.columns + column].as_ref(),
+            })
+        })
+    }
+
+    /// Amount of bytes held by allocated tiles, shared tiles are counted in full
+    pub fn allocated_bytes(&self) -> usize {
+        self.tiles.iter().flatten().map(Tile::byte_size).sum()
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [T; 4] {
+        match self.tile(x / TILE_SIZE, y / TILE_SIZE) {
+            Some(tile) => tile.pixel(x % TILE_SIZE, y % TILE_SIZE),
+            None => self.fill,
+        }
+    }
+
+    /// Mutable access to a pixel, allocating or unsharing its tile
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> &mut [T] {
+        let position = ((y % TILE_SIZE) * TILE_SIZE + x % TILE_SIZE) * 4;
+        let pixels = self.tile_pixels_mut(x / TILE_SIZE, y / TILE_SIZE);
+        &mut pixels[position..position + 4]
+    }
+
+    /// Mutable access to the pixels of a whole tile, allocating or unsharing it
+    pub fn tile_pixels_mut(&mut self, column: usize, row: usize) -> &mut [T] {
+        let fill = self.fill;
+        self.tiles[row * self.columns + column]
+            .get_or_insert_with(|| Tile::filled(fill))
+            .pixels_mut()
+    }
+
+    /// Copies the pixels inside `rect` into a tightly packed buffer,
+    /// the rect is clipped to the image bounds
+    pub fn read_rect(&self, rect: Rect) -> Vec<T> {
+        let Some(rect) = rect.intersect(&self.bounds()) else {
+            return vec![];
+        };
+
+        let mut output = Vec::with_capacity(rect.width * rect.height * 4);
+        for y in rect.y..rect.bottom() {
+            let mut x = rect.x;
+            while x < rect.right() {
+                let span = (TILE_SIZE - x % TILE_SIZE).min(rect.right() - x);
+                match self.tile(x / TILE_SIZE, y / TILE_SIZE) {
+                    Some(tile) => {
+                        let start = ((y % TILE_SIZE) * TILE_SIZE + x % TILE_SIZE) * 4;
+                        output.extend_from_slice(&tile.pixels()[start..start + span * 4]);
+                    }
+                    None => {
+                        for _ in 0..span {
+                            output.extend_from_slice(&self.fill);
+                        }
+                    }
+                }
+                x += span;
+            }
+        }
+        output
+    }
+
+    /// Overwrites the pixels inside `rect` with a tightly packed buffer the size of `rect`
+    pub fn write_rect(&mut self, rect: Rect, pixels: &[T]) {
+        let Some(clipped) = rect.intersect(&self.bounds()) else {
+            return;
+        };
+
+        for y in clipped.y..clipped.bottom() {
+            let mut x = clipped.x;
+            while x < clipped.right() {
+                let span = (TILE_SIZE - x % TILE_SIZE).min(clipped.right() - x);
+                let source = ((y - rect.y) * rect.width + (x - rect.x)) * 4;
+                let start = ((y % TILE_SIZE) * TILE_SIZE + x % TILE_SIZE) * 4;
+
+                self.tile_pixels_mut(x / TILE_SIZE, y / TILE_SIZE)[start..start + span * 4]
+                    .copy_from_slice(&pixels[source..source + span * 4]);
+                x += span;
+            }
+        }
+    }
+
+    /// Copy of the image in another working format, unallocated tiles stay unallocated
+    pub fn convert<U: Channel>(&self) -> TiledImage<U> {
+        let convert = |pixels: &[T]| -> Vec<U> {
+            pixels
+                .chunks_exact(4)
+                .flat_map(|p| blend::convert_pixel([p[0], p[1], p[2], p[3]]))
+                .collect()
+        };
+
+        TiledImage {
+            width: self.width,
+            height: self.height,
+            columns: self.columns,
+            rows: self.rows,
+            tiles: self
+                .tiles
+                .iter()
+                .map(|tile| {
+                    tile.as_ref().map(|t| Tile {
+                        pixels: Arc::new(convert(t.pixels())),
+                    })
+                })
+                .collect(),
+            fill: blend::convert_pixel(self.fill),
+        }
+    }
+}
+
+/// Area covered by the tile at `column` and `row` of an image, clipped to the image bounds
+pub fn tile_rect(column: usize, row: usize, width: usize, height: usize) -> Rect {
+    let x = column * TILE_SIZE;
+    let y = row * TILE_SIZE;
+    Rect::new(
+        x,
+        y,
+        TILE_SIZE.min(width.saturating_sub(x)),
+        TILE_SIZE.min(height.saturating_sub(y)),
+    )
+}
+
+/// Iterates the rects of every tile of an image of the given size, row by row
+pub fn tile_rects(width: usize, height: usize) -> impl Iterator<Item = Rect> {
+    let columns = width.div_ceil(TILE_SIZE);
+    let rows = height.div_ceil(TILE_SIZE);
+    (0..rows)
+        .flat_map(move |row| (0..columns).map(move |column| tile_rect(column, row, width, height)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_are_allocated_on_first_write() {
+        let mut image: TiledImage = TiledImage::new(150, 100, [1u8, 2, 3, 4]);
+        assert_eq!((image.columns(), image.rows()), (3, 2));
+        assert_eq!(image.allocated_bytes(), 0);
+        assert_eq!(image.pixel(149, 99), [1, 2, 3, 4]);
+
+        image.pixel_mut(130, 70).copy_from_slice(&[9, 9, 9, 9]);
+        assert_eq!(image.allocated_bytes(), TILE_CHANNELS);
+        assert!(image.tile(2, 1).is_some());
+        assert_eq!(image.pixel(130, 70), [9, 9, 9, 9]);
+        assert_eq!(image.pixel(131, 70), [1, 2, 3, 4]);
+        assert_eq!(image.tile_rect(2, 1), Rect::new(128, 64, 22, 36));
+    }
+
+    #[test]
+    fn cloned_tiles_are_copied_on_write() {
+        let mut image: TiledImage = TiledImage::new(64, 64, [0u8; 4]);
+        image.pixel_mut(0, 0).copy_from_slice(&[1, 1, 1, 1]);
+
+        let snapshot = image.clone();
+        assert!(
+            image
+                .tile(0, 0)
+                .unwrap()
+                .shares_pixels(snapshot.tile(0, 0).unwrap())
+        );
+
+        image.pixel_mut(1, 0).copy_from_slice(&[2, 2, 2, 2]);
+        assert!(
+            !image
+                .tile(0, 0)
+                .unwrap()
+                .shares_pixels(snapshot.tile(0, 0).unwrap())
+        );
+        assert_eq!(snapshot.pixel(1, 0), [0; 4]);
+        assert_eq!(image.pixel(1, 0), [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn rects_span_tile_borders() {
+        let mut image: TiledImage = TiledImage::new(200, 200, [0u8; 4]);
+        let rect = Rect::new(60, 60, 10, 10);
+        let pixels: Vec<u8> = (0..rect.width * rect.height * 4).map(|i| i as u8).collect();
+
+        image.write_rect(rect, &pixels);
+        assert_eq!(image.allocated_bytes(), 4 * TILE_CHANNELS);
+        assert_eq!(image.read_rect(rect), pixels);
+        assert_eq!(
+            image.read_rect(Rect::new(190, 190, 20, 20)).len(),
+            10 * 10 * 4
+        );
+    }
+}
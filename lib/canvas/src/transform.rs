@@ -0,0 +1,480 @@
+use glam::{DMat3, DVec3};
+use serde::{Deserialize, Serialize};
+
+use crate::blend::{self, BlendMode};
+use crate::canvas::Rect;
+use crate::color::Channel;
+use crate::selection::Selection;
+use crate::tile::TiledImage;
+
+/// How pixels are sampled when they are transformed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Resampling {
+    /// Weighted average of the four nearest pixels
+    #[default]
+    Bilinear,
+    /// Cubic interpolation of the sixteen nearest pixels, sharper when scaling up
+    Bicubic,
+}
+
+/// A projective transform of canvas coordinates, covering moving, scaling, rotating,
+/// flipping, skewing and four corner perspective distortion
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    matrix: DMat3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self {
+            matrix: DMat3::IDENTITY,
+        }
+    }
+
+    pub fn translate(dx: f64, dy: f64) -> Self {
+        Self {
+            matrix: DMat3::from_translation((dx, dy).into()),
+        }
+    }
+
+    /// Scales around `pivot`, negative factors flip
+    pub fn scale(sx: f64, sy: f64, pivot: (f64, f64)) -> Self {
+        Self::around(DMat3::from_scale((sx, sy).into()), pivot)
+    }
+
+    /// Rotates by `angle` radians around `pivot`
+    pub fn rotate(angle: f64, pivot: (f64, f64)) -> Self {
+        Self::around(DMat3::from_angle(angle), pivot)
+    }
+
+    /// Mirrors horizontally and or vertically around `pivot`
+    pub fn flip(horizontal: bool, vertical: bool, pivot: (f64, f64)) -> Self {
+        let sign = |flip: bool| if flip { -1.0 } else { 1.0 };
+        Self::scale(sign(horizontal), sign(vertical), pivot)
+    }
+
+    /// Shears by the tangents `kx` along x and `ky` along y, around `pivot`
+    pub fn skew(kx: f64, ky: f64, pivot: (f64, f64)) -> Self {
+        let shear = DMat3::from_cols(DVec3::new(1.0, ky, 0.0), DVec3::new(kx, 1.0, 0.0), DVec3::Z);
+        Self::around(shear, pivot)
+    }
+
+    /// Maps the corners of `rect` onto `corners`, given as top left, top right,
+    /// bottom right and bottom left. Any other quad gives a perspective distortion
+    pub fn from_corners(rect: Rect, corners: [(f64, f64); 4]) -> Option<Self> {
+        if rect.is_empty() {
+            return None;
+        }
+
+        let [(x0, y0), (x1, y1), (x2, y2), (x3, y3)] = corners;
+        let (dx1, dx2, dx3) = (x1 - x2, x3 - x2, x0 - x1 + x2 - x3);
+        let (dy1, dy2, dy3) = (y1 - y2, y3 - y2, y0 - y1 + y2 - y3);
+
+        /* unit square to quad, see Heckbert's "Fundamentals of Texture Mapping" */
+        let det = dx1 * dy2 - dx2 * dy1;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let g = (dx3 * dy2 - dx2 * dy3) / det;
+        let h = (dx1 * dy3 - dx3 * dy1) / det;
+        let square_to_quad = DMat3::from_cols(
+            DVec3::new(x1 - x0 + g * x1, y1 - y0 + g * y1, g),
+            DVec3::new(x3 - x0 + h * x3, y3 - y0 + h * y3, h),
+            DVec3::new(x0, y0, 1.0),
+        );
+        let rect_to_square =
+            DMat3::from_scale((1.0 / rect.width as f64, 1.0 / rect.height as f64).into())
+                * DMat3::from_translation((-(rect.x as f64), -(rect.y as f64)).into());
+
+        let matrix = square_to_quad * rect_to_square;
+        (matrix.determinant().abs() > 1e-12).then_some(Self { matrix })
+    }
+
+    /// Applies `self` and then `other`
+    pub fn then(self, other: Transform) -> Self {
+        Self {
+            matrix: other.matrix * self.matrix,
+        }
+    }
+
+    pub fn inverse(&self) -> Option<Self> {
+        (self.matrix.determinant().abs() > 1e-12).then(|| Self {
+            matrix: self.matrix.inverse(),
+        })
+    }
+
+    /// Transforms a point, `None` when it ends up behind the perspective horizon
+    pub fn apply(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let point = self.matrix * DVec3::new(x, y, 1.0);
+        (point.z > 1e-9).then(|| (point.x / point.z, point.y / point.z))
+    }
+
+    /// Where the corners of `rect` end up, top left, top right, bottom right, bottom left
+    pub fn corners(&self, rect: Rect) -> Option<[(f64, f64); 4]> {
+        let (left, top) = (rect.x as f64, rect.y as f64);
+        let (right, bottom) = (rect.right() as f64, rect.bottom() as f64);
+        Some([
+            self.apply(left, top)?,
+            self.apply(right, top)?,
+            self.apply(right, bottom)?,
+            self.apply(left, bottom)?,
+        ])
+    }
+
+    fn around(matrix: DMat3, (x, y): (f64, f64)) -> Self {
+        Self {
+            matrix: DMat3::from_translation((x, y).into())
+                * matrix
+                * DMat3::from_translation((-x, -y).into()),
+        }
+    }
+}
+
+/// Premultiplied RGBA pixels covering `rect` of the canvas
+#[derive(Debug, Clone)]
+pub(crate) struct Floating<T: Channel> {
+    pub rect: Rect,
+    pub pixels: Vec<T>,
+}
+
+impl<T: Channel> Floating<T> {
+    fn texel(&self, x: isize, y: isize) -> [f32; 4] {
+        if x < 0 || y < 0 || x as usize >= self.rect.width || y as usize >= self.rect.height {
+            return [0.0; 4];
+        }
+        let i = (y as usize * self.rect.width + x as usize) * 4;
+        let p = &self.pixels[i..i + 4];
+        [p[0].to_f32(), p[1].to_f32(), p[2].to_f32(), p[3].to_f32()]
+    }
+
+    /// Samples at canvas position `x`, `y`, pixels outside the floating area are transparent
+    fn sample(&self, x: f64, y: f64, resampling: Resampling) -> [T; 4] {
+        /* pixel centres sit at half coordinates */
+        let x = (x - self.rect.x as f64 - 0.5) as f32;
+        let y = (y - self.rect.y as f64 - 0.5) as f32;
+        let (ix, iy) = (x.floor() as isize, y.floor() as isize);
+        let (fx, fy) = (x - x.floor(), y - y.floor());
+
+        let mut sum = [0.0f32; 4];
+        let mut add = |dx: isize, dy: isize, weight: f32| {
+            if weight != 0.0 {
+                let texel = self.texel(ix + dx, iy + dy);
+                for (s, t) in sum.iter_mut().zip(texel) {
+                    *s += t * weight;
+                }
+            }
+        };
+        match resampling {
+            Resampling::Bilinear => {
+                add(0, 0, (1.0 - fx) * (1.0 - fy));
+                add(1, 0, fx * (1.0 - fy));
+                add(0, 1, (1.0 - fx) * fy);
+                add(1, 1, fx * fy);
+            }
+            Resampling::Bicubic => {
+                for dy in -1..=2 {
+                    for dx in -1..=2 {
+                        add(dx, dy, cubic(dx as f32 - fx) * cubic(dy as f32 - fy));
+                    }
+                }
+            }
+        }
+
+        /* cubic overshoot could leave colors above the alpha of a premultiplied pixel */
+        let alpha = T::from_f32(sum[3]);
+        let channel = |v: f32| T::from_f32(v.min(alpha.to_f32()));
+        [channel(sum[0]), channel(sum[1]), channel(sum[2]), alpha]
+    }
+}
+
+/// Keys cubic convolution kernel with `a = -0.5`
+fn cubic(x: f32) -> f32 {
+    let x = x.abs();
+    if x < 1.0 {
+        (1.5 * x - 2.5) * x * x + 1.0
+    } else if x < 2.0 {
+        ((-0.5 * x + 2.5) * x - 4.0) * x + 2.0
+    } else {
+        0.0
+    }
+}
+
+/// A transform in progress on one layer. The layer shows a preview while the
+/// untouched pixels are kept around to cancel or to record the undo step on commit
+pub(crate) struct TransformSession<T: Channel> {
+    pub layer: usize,
+    /// the layer pixels before the transform started
+    pub original: TiledImage<T>,
+    /// the layer with the transformed pixels cut out
+    pub base: TiledImage<T>,
+    pub floating: Floating<T>,
+    /// the selection before the transform, moved along with the pixels
+    pub selection: Option<Selection>,
+    pub transform: Transform,
+    pub resampling: Resampling,
+    /// area covered by the current preview
+    pub preview: Option<Rect>,
+}
+
+impl<T: Channel> TransformSession<T> {
+    /// Lifts the pixels of `image` inside `selection`, or all of its content when there
+    /// is no selection, off the layer
+    pub fn new(
+        layer: usize,
+        image: &TiledImage<T>,
+        content: Rect,
+        selection: Option<&Selection>,
+    ) -> Self {
+        let mut base = image.clone();
+        let mut pixels = image.read_rect(content);
+
+        match selection {
+            Some(selection) => {
+                let mut remaining = pixels.clone();
+                for (i, (lifted, left)) in pixels
+                    .chunks_exact_mut(4)
+                    .zip(remaining.chunks_exact_mut(4))
+                    .enumerate()
+                {
+                    let x = content.x + i % content.width;
+                    let y = content.y + i / content.width;
+                    let coverage = selection.coverage(x, y) as f32 / 255.0;
+                    for (l, r) in lifted.iter_mut().zip(left.iter_mut()) {
+                        let value = l.to_f32();
+                        *l = T::from_f32(value * coverage);
+                        *r = T::from_f32(value - l.to_f32());
+                    }
+                }
+                base.write_rect(content, &remaining);
+            }
+            None => {
+                base = TiledImage::new(image.width(), image.height(), [T::default(); 4]);
+            }
+        }
+
+        Self {
+            layer,
+            original: image.clone(),
+            base,
+            floating: Floating {
+                rect: content,
+                pixels,
+            },
+            selection: selection.cloned(),
+            transform: Transform::identity(),
+            resampling: Resampling::default(),
+            preview: None,
+        }
+    }
+
+    /// Canvas area the transformed pixels cover, clipped to `bounds`
+    pub fn destination(&self, bounds: Rect) -> Option<Rect> {
+        let Some(corners) = self.transform.corners(self.floating.rect) else {
+            /* part of the quad is behind the horizon, it may reach anywhere */
+            return Some(bounds);
+        };
+
+        let (mut left, mut top) = (f64::MAX, f64::MAX);
+        let (mut right, mut bottom) = (f64::MIN, f64::MIN);
+        for (x, y) in corners {
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x);
+            bottom = bottom.max(y);
+        }
+
+        let clamp = |v: f64, max: usize| v.clamp(0.0, max as f64) as usize;
+        let left = clamp(left.floor(), bounds.width);
+        let top = clamp(top.floor(), bounds.height);
+        let right = clamp(right.ceil(), bounds.width);
+        let bottom = clamp(bottom.ceil(), bounds.height);
+        Rect::new(
+            left,
+            top,
+            right.saturating_sub(left),
+            bottom.saturating_sub(top),
+        )
+        .intersect(&bounds)
+    }
+
+    /// Composites the transformed pixels over the base inside `area`, returning them
+    pub fn render(&self, image: &TiledImage<T>, area: Rect) -> Vec<T> {
+        let mut pixels = image.read_rect(area);
+        let Some(inverse) = self.transform.inverse() else {
+            return pixels;
+        };
+
+        for (i, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+            let x = (area.x + i % area.width) as f64 + 0.5;
+            let y = (area.y + i / area.width) as f64 + 0.5;
+            let Some((sx, sy)) = inverse.apply(x, y) else {
+                continue;
+            };
+            let source = self.floating.sample(sx, sy, self.resampling);
+            if source[3] != T::default() {
+                blend::blend(pixel, source, 1.0, BlendMode::Normal);
+            }
+        }
+        pixels
+    }
+
+    /// The selection moved along with the pixels
+    pub fn transformed_selection(&self) -> Option<Selection> {
+        let selection = self.selection.as_ref()?;
+        let inverse = self.transform.inverse()?;
+        let (width, height) = (selection.width(), selection.height());
+        let mask = Floating {
+            rect: Rect::new(0, 0, width, height),
+            pixels: selection.mask().iter().flat_map(|&m| [m; 4]).collect(),
+        };
+
+        let mut moved = Selection::empty(width, height);
+        let area = self.destination(Rect::new(0, 0, width, height))?;
+        let mut coverage = vec![0; area.width * area.height];
+        for (i, value) in coverage.iter_mut().enumerate() {
+            let x = (area.x + i % area.width) as f64 + 0.5;
+            let y = (area.y + i / area.width) as f64 + 0.5;
+            if let Some((sx, sy)) = inverse.apply(x, y) {
+                *value = mask.sample(sx, sy, Resampling::Bilinear)[3];
+            }
+        }
+        moved.set_rect(area, &coverage);
+        Some(moved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection::SelectionMode;
+    use crate::{Canvas, Color};
+
+    fn close(a: (f64, f64), b: (f64, f64)) -> bool {
+        (a.0 - b.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6
+    }
+
+    #[test]
+    fn corners_round_trip_through_the_homography() {
+        let rect = Rect::new(10, 20, 40, 30);
+        let corners = [(5.0, 5.0), (60.0, 12.0), (70.0, 80.0), (0.0, 60.0)];
+        let transform = Transform::from_corners(rect, corners).unwrap();
+        let mapped = transform.corners(rect).unwrap();
+        for (a, b) in mapped.into_iter().zip(corners) {
+            assert!(close(a, b), "{a:?} != {b:?}");
+        }
+
+        let inverse = transform.inverse().unwrap();
+        assert!(close(inverse.apply(70.0, 80.0).unwrap(), (50.0, 50.0)));
+    }
+
+    #[test]
+    fn simple_transforms_compose() {
+        let transform = Transform::rotate(std::f64::consts::FRAC_PI_2, (0.0, 0.0))
+            .then(Transform::translate(10.0, 0.0))
+            .then(Transform::flip(true, false, (10.0, 0.0)));
+        assert!(close(transform.apply(1.0, 0.0).unwrap(), (10.0, 1.0)));
+
+        let skew = Transform::skew(1.0, 0.0, (0.0, 0.0));
+        assert!(close(skew.apply(0.0, 2.0).unwrap(), (2.0, 2.0)));
+    }
+
+    #[test]
+    fn identity_resampling_keeps_pixels() {
+        let floating = Floating {
+            rect: Rect::new(0, 0, 3, 1),
+            pixels: vec![10u8, 20, 30, 255, 0, 0, 0, 0, 200, 100, 50, 255],
+        };
+        for resampling in [Resampling::Bilinear, Resampling::Bicubic] {
+            assert_eq!(floating.sample(0.5, 0.5, resampling), [10, 20, 30, 255]);
+            assert_eq!(floating.sample(2.5, 0.5, resampling), [200, 100, 50, 255]);
+        }
+        assert_eq!(
+            floating.sample(1.0, 0.5, Resampling::Bilinear),
+            [5, 10, 15, 128]
+        );
+    }
+
+    #[test]
+    fn canvas_transform_commits_and_cancels() {
+        let red = Color::new(255, 0, 0, 255);
+        let clear = Color::new(0, 0, 0, 0);
+        let mut canvas: Canvas = Canvas::new(20, 20);
+        canvas.add_layer();
+        canvas.begin_paint();
+        canvas.draw_pixel(2, 3, red);
+        canvas.draw_pixel(12, 3, red);
+        canvas.end_paint();
+        canvas.select(
+            Selection::rectangle(20, 20, Rect::new(0, 0, 10, 10)),
+            SelectionMode::Replace,
+        );
+
+        assert!(canvas.begin_transform());
+        canvas.update_transform(Transform::translate(5.0, 1.0), Resampling::Bilinear);
+        assert_eq!(canvas.layers().active().pixel(2, 3), Some(clear));
+        canvas.cancel_transform();
+        assert_eq!(canvas.layers().active().pixel(2, 3), Some(red));
+
+        assert!(canvas.begin_transform());
+        canvas.update_transform(Transform::translate(5.0, 1.0), Resampling::Bicubic);
+        canvas.commit_transform();
+        let layer = canvas.layers().active();
+        assert_eq!(layer.pixel(7, 4), Some(red));
+        assert_eq!(layer.pixel(2, 3), Some(clear));
+        assert_eq!(layer.pixel(12, 3), Some(red));
+        assert_eq!(
+            canvas.selection().and_then(Selection::bounds),
+            Some(Rect::new(5, 1, 10, 10))
+        );
+
+        assert!(canvas.undo());
+        assert_eq!(canvas.layers().active().pixel(2, 3), Some(red));
+        assert_eq!(canvas.layers().active().pixel(7, 4), Some(clear));
+    }
+
+    #[test]
+    fn layer_edits_commit_the_transform_first() {
+        let red = Color::new(255, 0, 0, 255);
+        let clear = Color::new(0, 0, 0, 0);
+        let mut canvas: Canvas = Canvas::new(20, 20);
+        canvas.add_layer();
+        canvas.begin_paint();
+        canvas.draw_pixel(2, 3, red);
+        canvas.end_paint();
+
+        assert!(canvas.begin_transform());
+        canvas.update_transform(Transform::translate(5.0, 0.0), Resampling::Bilinear);
+        canvas.add_layer();
+        assert!(!canvas.is_transforming());
+
+        /* cancelling afterwards can no longer touch the new layer at the same index */
+        canvas.cancel_transform();
+        let moved = canvas.layers().get(1).unwrap();
+        assert_eq!(moved.pixel(7, 3), Some(red));
+        assert_eq!(moved.pixel(2, 3), Some(clear));
+        assert_eq!(canvas.layers().get(2).unwrap().memory_size(), 0);
+
+        /* removing the layer below the one being transformed */
+        canvas.layers_mut().set_active(1);
+        assert!(canvas.begin_transform());
+        canvas.update_transform(Transform::translate(0.0, 5.0), Resampling::Bilinear);
+        assert!(canvas.remove_layer(0));
+        canvas.cancel_transform();
+        let moved = canvas.layers().get(0).unwrap();
+        assert_eq!(moved.pixel(7, 8), Some(red));
+        assert_eq!(moved.pixel(7, 3), Some(clear));
+
+        /* the transform and the removal are undone one after the other */
+        assert!(canvas.undo());
+        assert_eq!(canvas.layers().len(), 3);
+        assert!(canvas.undo());
+        assert_eq!(canvas.layers().get(1).unwrap().pixel(7, 3), Some(red));
+    }
+}
@@ -0,0 +1,283 @@
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::canvas::Point;
+
+/// Smallest zoom the view allows
+pub const MIN_ZOOM: f32 = 0.05;
+/// Largest zoom the view allows
+pub const MAX_ZOOM: f32 = 64.0;
+
+/// Step the view rotation snaps to
+pub const ROTATION_SNAP: f32 = std::f32::consts::PI / 12.0;
+
+/// Rounds an angle in radians to the nearest multiple of [`ROTATION_SNAP`]
+pub fn snap_angle(angle: f32) -> f32 {
+    (angle / ROTATION_SNAP).round() * ROTATION_SNAP
+}
+
+/// How the canvas is placed on screen.
+///
+/// The canvas is rotated and mirrored around its centre, then scaled by the zoom and
+/// moved by the negated offset. Every change that takes a screen anchor keeps the canvas
+/// point under that anchor in place, which is what zooming or rotating under the cursor needs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewTransform {
+    width: f32,
+    height: f32,
+    zoom: f32,
+    rotation: f32,
+    mirror_x: bool,
+    mirror_y: bool,
+    offset: Point,
+}
+
+impl ViewTransform {
+    /// Creates an untransformed view of a canvas of the given size
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width: width as f32,
+            height: height as f32,
+            zoom: 1.0,
+            rotation: 0.0,
+            mirror_x: false,
+            mirror_y: false,
+            offset: Point { x: 0.0, y: 0.0 },
+        }
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    /// Sets the zoom, clamped to [`MIN_ZOOM`] and [`MAX_ZOOM`]
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    /// Sets the zoom while keeping the canvas point under the screen position `x`, `y` in place
+    pub fn zoom_around(&mut self, zoom: f32, x: f32, y: f32) {
+        self.keep_in_place(x, y, |view| view.set_zoom(zoom));
+    }
+
+    /// Shows the canvas at its actual size, anchored at the screen position `x`, `y`
+    pub fn actual_size(&mut self, x: f32, y: f32) {
+        self.zoom_around(1.0, x, y);
+    }
+
+    /// Zooms so the whole canvas, as it is currently rotated, fits centred in a
+    /// viewport of the given size
+    pub fn fit(&mut self, viewport_width: f32, viewport_height: f32) {
+        let (sin, cos) = self.rotation.sin_cos();
+        let width = (self.width * cos).abs() + (self.height * sin).abs();
+        let height = (self.width * sin).abs() + (self.height * cos).abs();
+        if width <= 0.0 || height <= 0.0 {
+            return;
+        }
+
+        self.set_zoom((viewport_width / width).min(viewport_height / height));
+        /* the canvas centre is the pivot, so it only moves with zoom and offset */
+        self.offset = Point {
+            x: self.zoom * self.width / 2.0 - viewport_width / 2.0,
+            y: self.zoom * self.height / 2.0 - viewport_height / 2.0,
+        };
+    }
+
+    /// Rotation of the view in radians
+    pub fn rotation(&self) -> f32 {
+        self.rotation
+    }
+
+    /// Sets the view rotation in radians, turning around the canvas centre
+    pub fn set_rotation(&mut self, rotation: f32) {
+        self.rotation = rotation;
+    }
+
+    /// Sets the view rotation in radians while keeping the canvas point under the
+    /// screen position `x`, `y` in place
+    pub fn rotate_around(&mut self, rotation: f32, x: f32, y: f32) {
+        self.keep_in_place(x, y, |view| view.rotation = rotation);
+    }
+
+    /// Rounds the view rotation to the nearest multiple of [`ROTATION_SNAP`]
+    pub fn snap_rotation(&mut self) {
+        self.rotation = snap_angle(self.rotation);
+    }
+
+    pub fn reset_rotation(&mut self) {
+        self.rotation = 0.0;
+    }
+
+    /// Whether the view is mirrored horizontally and vertically
+    pub fn mirrored(&self) -> (bool, bool) {
+        (self.mirror_x, self.mirror_y)
+    }
+
+    /// Mirrors the view around the canvas centre, the pixels of the layers are left untouched
+    pub fn set_mirrored(&mut self, horizontal: bool, vertical: bool) {
+        self.mirror_x = horizontal;
+        self.mirror_y = vertical;
+    }
+
+    pub fn offset(&self) -> &Point {
+        &self.offset
+    }
+
+    pub fn set_offset(&mut self, x: f32, y: f32) {
+        self.offset = Point { x, y };
+    }
+
+    /// Moves the offset by `dx`, `dy` screen pixels
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        self.offset.x += dx;
+        self.offset.y += dy;
+    }
+
+    /// Matrix taking canvas coordinates to screen coordinates
+    pub fn matrix(&self) -> Mat4 {
+        let translation = Mat4::from_translation(Vec3::new(-self.offset.x, -self.offset.y, 0.0));
+        let scale = Mat4::from_scale(Vec3::new(self.zoom, self.zoom, 1.0));
+        let rotation = Mat4::from_rotation_z(self.rotation);
+        let mirror = Mat4::from_scale(Vec3::new(
+            if self.mirror_x { -1.0 } else { 1.0 },
+            if self.mirror_y { -1.0 } else { 1.0 },
+            1.0,
+        ));
+        let pivot = Mat4::from_translation(Vec3::new(self.width / 2.0, self.height / 2.0, 0.0));
+
+        translation * scale * pivot * rotation * mirror * pivot.inverse()
+    }
+
+    pub fn screen_to_canvas(&self, x: f32, y: f32) -> (f32, f32) {
+        let point = self.matrix().inverse() * Vec4::new(x, y, 0.0, 1.0);
+        (point.x, point.y)
+    }
+
+    pub fn canvas_to_screen(&self, x: f32, y: f32) -> (f32, f32) {
+        let point = self.matrix() * Vec4::new(x, y, 0.0, 1.0);
+        (point.x, point.y)
+    }
+
+    /// Turns a direction on screen into the same direction on the canvas, undoing the
+    /// rotation and mirroring of the view but keeping its length
+    pub fn screen_to_canvas_direction(&self, x: f32, y: f32) -> (f32, f32) {
+        let direction = self.matrix().inverse() * Vec4::new(x, y, 0.0, 0.0);
+        (direction.x * self.zoom, direction.y * self.zoom)
+    }
+
+    /// Applies a change to the view, then moves the offset so the canvas point that was
+    /// under the screen position `x`, `y` ends up there again
+    fn keep_in_place(&mut self, x: f32, y: f32, change: impl FnOnce(&mut Self)) {
+        let (canvas_x, canvas_y) = self.screen_to_canvas(x, y);
+        change(self);
+        let (moved_x, moved_y) = self.canvas_to_screen(canvas_x, canvas_y);
+        self.offset.x += moved_x - x;
+        self.offset.y += moved_y - y;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn view() -> impl Strategy<Value = ViewTransform> {
+        (
+            (1usize..4000, 1usize..4000),
+            MIN_ZOOM..MAX_ZOOM,
+            -10.0f32..10.0,
+            any::<(bool, bool)>(),
+            (-5000.0f32..5000.0, -5000.0f32..5000.0),
+        )
+            .prop_map(|((width, height), zoom, rotation, mirror, offset)| {
+                let mut view = ViewTransform::new(width, height);
+                view.set_zoom(zoom);
+                view.set_rotation(rotation);
+                view.set_mirrored(mirror.0, mirror.1);
+                view.set_offset(offset.0, offset.1);
+                view
+            })
+    }
+
+    /// Screen positions compared with a tolerance relative to their magnitude
+    fn assert_close(a: (f32, f32), b: (f32, f32)) {
+        let tolerance = 1e-3 * (1.0 + a.0.abs().max(a.1.abs()));
+        assert!(
+            (a.0 - b.0).abs() < tolerance && (a.1 - b.1).abs() < tolerance,
+            "{a:?} != {b:?}"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn screen_to_canvas_round_trips(view in view(), x in -4000.0f32..4000.0, y in -4000.0f32..4000.0) {
+            let (canvas_x, canvas_y) = view.screen_to_canvas(x, y);
+            assert_close(view.canvas_to_screen(canvas_x, canvas_y), (x, y));
+        }
+
+        #[test]
+        fn anchored_changes_keep_the_anchor_in_place(
+            mut view in view(),
+            zoom in MIN_ZOOM..MAX_ZOOM,
+            rotation in -10.0f32..10.0,
+            x in -4000.0f32..4000.0,
+            y in -4000.0f32..4000.0,
+        ) {
+            let (canvas_x, canvas_y) = view.screen_to_canvas(x, y);
+
+            view.zoom_around(zoom, x, y);
+            assert_close(view.canvas_to_screen(canvas_x, canvas_y), (x, y));
+
+            view.rotate_around(rotation, x, y);
+            assert_close(view.canvas_to_screen(canvas_x, canvas_y), (x, y));
+        }
+
+        #[test]
+        fn fit_centres_the_canvas_inside_the_viewport(
+            mut view in view(),
+            viewport in (100.0f32..4000.0, 100.0f32..4000.0),
+        ) {
+            view.fit(viewport.0, viewport.1);
+            if view.zoom() > MIN_ZOOM {
+                for (x, y) in [(0.0, 0.0), (view.width, 0.0), (0.0, view.height), (view.width, view.height)] {
+                    let (screen_x, screen_y) = view.canvas_to_screen(x, y);
+                    prop_assert!(screen_x > -0.5 && screen_x < viewport.0 + 0.5);
+                    prop_assert!(screen_y > -0.5 && screen_y < viewport.1 + 0.5);
+                }
+            }
+            assert_close(
+                view.canvas_to_screen(view.width / 2.0, view.height / 2.0),
+                (viewport.0 / 2.0, viewport.1 / 2.0),
+            );
+        }
+    }
+
+    #[test]
+    fn zoom_is_clamped_and_rotation_snaps() {
+        let mut view = ViewTransform::new(300, 200);
+        view.set_zoom(1000.0);
+        assert_eq!(view.zoom(), MAX_ZOOM);
+
+        view.set_rotation(0.7);
+        view.snap_rotation();
+        assert!((view.rotation() - 3.0 * ROTATION_SNAP).abs() < 1e-6);
+    }
+
+    #[test]
+    fn directions_turn_with_the_view() {
+        let mut view = ViewTransform::new(300, 200);
+        view.set_zoom(4.0);
+        view.set_rotation(std::f32::consts::FRAC_PI_2);
+        assert_close(view.screen_to_canvas_direction(2.0, 0.0), (0.0, -2.0));
+
+        view.set_rotation(0.0);
+        view.set_mirrored(true, false);
+        assert_close(view.screen_to_canvas_direction(2.0, 1.0), (-2.0, 1.0));
+    }
+
+    #[test]
+    fn mirroring_flips_the_view_around_the_centre() {
+        let mut view = ViewTransform::new(300, 200);
+        view.set_mirrored(true, true);
+        assert_close(view.screen_to_canvas(10.0, 20.0), (290.0, 180.0));
+    }
+}
@@ -16,7 +16,7 @@ mod plugin;
 mod utils;
 
 pub use error::{Error, Result};
-pub use plugin::{AppHandleExt, Builder as CanvasPluginBuilder};
+pub use plugin::{AppHandleExt, Builder as CanvasPluginBuilder, WorkingCanvas};
 
 #[cfg(desktop)]
 use desktop::Canvas;
@@ -2,12 +2,16 @@ mod renderer;
 
 use crate::utils::*;
 use anyhow::{Error, Result};
-use canvas::Canvas;
+use canvas::{Canvas, PixelFormat};
 use renderer::RenderState;
 use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread::Thread;
+use std::time::Duration;
 use tauri::{AppHandle, Manager};
+use tauri_runtime::window::WindowId;
 use tauri_runtime::UserEvent;
 use tauri_runtime_wry::tao::event::Event;
 use tauri_runtime_wry::tao::event::WindowEvent as TaoWindowEvent;
@@ -17,6 +21,72 @@ use tauri_runtime_wry::{Message, Plugin, PluginBuilder};
 
 type CanvasWindowMap = Arc<Mutex<HashMap<String, CanvasWindow>>>;
 
+/// A canvas in the working format picked for its document when it was created or opened.
+/// Linear light keeps soft brush edges free of dark fringes and glazes free of banding,
+/// see [`canvas::Channel`]
+pub enum WorkingCanvas {
+    Srgb8(Canvas<u8>),
+    Linear16(Canvas<u16>),
+    LinearF32(Canvas<f32>),
+}
+
+/// Evaluates `$body` with `$canvas` bound to the canvas inside a [`WorkingCanvas`],
+/// whatever its working format. Matching on a reference binds a reference
+#[macro_export]
+macro_rules! with_canvas {
+    ($working:expr, $canvas:ident => $body:expr) => {
+        match $working {
+            $crate::WorkingCanvas::Srgb8($canvas) => $body,
+            $crate::WorkingCanvas::Linear16($canvas) => $body,
+            $crate::WorkingCanvas::LinearF32($canvas) => $body,
+        }
+    };
+}
+
+impl WorkingCanvas {
+    /// Creates a canvas with a white background in `format`
+    pub fn new(width: usize, height: usize, format: PixelFormat) -> Self {
+        match format {
+            PixelFormat::Srgb8 => Self::Srgb8(Canvas::new(width, height)),
+            PixelFormat::Linear16 => Self::Linear16(Canvas::new(width, height)),
+            PixelFormat::LinearF32 => Self::LinearF32(Canvas::new(width, height)),
+        }
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        with_canvas!(self, canvas => canvas.format())
+    }
+
+    pub fn width(&self) -> usize {
+        with_canvas!(self, canvas => canvas.width())
+    }
+
+    pub fn height(&self) -> usize {
+        with_canvas!(self, canvas => canvas.height())
+    }
+}
+
+impl From<Canvas<u8>> for WorkingCanvas {
+    fn from(canvas: Canvas<u8>) -> Self {
+        Self::Srgb8(canvas)
+    }
+}
+
+impl From<Canvas<u16>> for WorkingCanvas {
+    fn from(canvas: Canvas<u16>) -> Self {
+        Self::Linear16(canvas)
+    }
+}
+
+impl From<Canvas<f32>> for WorkingCanvas {
+    fn from(canvas: Canvas<f32>) -> Self {
+        Self::LinearF32(canvas)
+    }
+}
+
+/// Time between the frames drawn only to move the marching ants of a selection
+const ANTS_FRAME_TIME: Duration = Duration::from_millis(50);
+
 pub struct Builder {
     app: AppHandle,
 }
@@ -70,16 +140,14 @@ impl<T: UserEvent> Plugin<T> for CanvasRendererPlugin<T> {
                 if let Some(label) = get_label_from_tao_id(window_id, &context) {
                     if let Some(canvas_win) = windows.get_mut(&label) {
                         /* hackiest of hacks but whateves */
-                        canvas_win.tao_id = Some(window_id.clone());
+                        canvas_win.tao_id = Some(*window_id);
                         match event {
                             TaoWindowEvent::Resized(size) => {
                                 if let Some(canvas) = &canvas_win.canvas {
                                     let canvas = canvas.lock().unwrap();
-                                    canvas_win.renderer.change_size(
-                                        size.width,
-                                        size.height,
-                                        &canvas,
-                                    );
+                                    with_canvas!(&*canvas, canvas => canvas_win
+                                        .renderer
+                                        .change_size(size.width, size.height, canvas));
                                     return false;
                                 }
                             }
@@ -115,9 +183,21 @@ impl<T: UserEvent> Plugin<T> for CanvasRendererPlugin<T> {
                 };
 
                 if let Some(canvas) = &canvas_win.canvas {
-                    let canvas = canvas.lock().unwrap();
-                    canvas_win.renderer.update(&*canvas);
+                    let mut canvas = canvas.lock().unwrap();
+                    with_canvas!(&mut *canvas, canvas => canvas_win.renderer.update(canvas));
                     canvas_win.renderer.render();
+
+                    /* the ants only move when a frame is drawn, so keep asking for frames
+                     * while they are shown */
+                    let selected = with_canvas!(&*canvas, canvas => canvas.selection().is_some());
+                    if canvas_win.ants_timer.is_none() && selected {
+                        if let Some(id) = get_id_from_tao_id(window_id, &context) {
+                            canvas_win.ants_timer = Some(AntsTimer::spawn(id, proxy.clone()));
+                        }
+                    }
+                    if let Some(timer) = &canvas_win.ants_timer {
+                        timer.set_running(selected);
+                    }
                 };
             }
             &_ => {}
@@ -127,16 +207,72 @@ impl<T: UserEvent> Plugin<T> for CanvasRendererPlugin<T> {
     }
 }
 
+/// A thread requesting a redraw of a window every [`ANTS_FRAME_TIME`] while it runs,
+/// parked while it doesn't. The thread ends once the timer is dropped
+struct AntsTimer {
+    running: Arc<AtomicBool>,
+    thread: Thread,
+}
+
+impl AntsTimer {
+    fn spawn<T: UserEvent>(id: WindowId, proxy: EventLoopProxy<Message<T>>) -> Self {
+        let running = Arc::new(AtomicBool::new(false));
+        let weak = Arc::downgrade(&running);
+        let handle = std::thread::spawn(move || {
+            /* the timer going away drops the only strong reference */
+            while let Some(running) = weak.upgrade() {
+                if !running.load(Ordering::Acquire) {
+                    drop(running);
+                    std::thread::park();
+                    continue;
+                }
+                drop(running);
+
+                std::thread::sleep(ANTS_FRAME_TIME);
+                let request = Message::Window(id, tauri_runtime_wry::WindowMessage::RequestRedraw);
+                if proxy.send_event(request).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            running,
+            thread: handle.thread().clone(),
+        }
+    }
+
+    /// Starts or stops requesting redraws
+    fn set_running(&self, running: bool) {
+        if !self.running.swap(running, Ordering::AcqRel) && running {
+            self.thread.unpark();
+        }
+    }
+}
+
+impl Drop for AntsTimer {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        self.thread.unpark();
+    }
+}
+
 struct CanvasWindow {
     tao_id: Option<TaoWindowId>,
-    canvas: Option<Arc<Mutex<Canvas>>>,
+    canvas: Option<Arc<Mutex<WorkingCanvas>>>,
     renderer: RenderState,
+    /// Keeps the marching ants moving while a selection is shown, started with the first one
+    ants_timer: Option<AntsTimer>,
 }
 
 pub trait AppHandleExt {
     fn start_renderer_for_window(&self, label: &str) -> Result<()>;
 
-    fn attach_canvas_for_window(&self, label: &str, canvas: Arc<Mutex<Canvas>>) -> Result<()>;
+    fn attach_canvas_for_window(
+        &self,
+        label: &str,
+        canvas: Arc<Mutex<WorkingCanvas>>,
+    ) -> Result<()>;
 
     fn send_redraw_request_for_window(&self, label: &str) -> Result<()>;
 }
@@ -160,13 +296,18 @@ impl AppHandleExt for AppHandle {
                 canvas: None,
                 tao_id: None,
                 renderer,
+                ants_timer: None,
             },
         );
 
         Ok(())
     }
 
-    fn attach_canvas_for_window(&self, label: &str, canvas: Arc<Mutex<Canvas>>) -> Result<()> {
+    fn attach_canvas_for_window(
+        &self,
+        label: &str,
+        canvas: Arc<Mutex<WorkingCanvas>>,
+    ) -> Result<()> {
         let canvas_windows = self
             .try_state::<CanvasWindowMap>()
             .ok_or(Error::msg("TauriPluginCanvasRenderer is not initialized"))?;
@@ -182,8 +323,8 @@ impl AppHandleExt for AppHandle {
         };
 
         window.canvas = Some(canvas.clone());
-        let canvas = canvas.lock().unwrap();
-        window.renderer.attach_canvas(&canvas);
+        let mut canvas = canvas.lock().unwrap();
+        with_canvas!(&mut *canvas, canvas => window.renderer.attach_canvas(canvas));
 
         Ok(())
     }
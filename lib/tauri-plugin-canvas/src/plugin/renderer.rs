@@ -1,16 +1,18 @@
+mod selection;
 mod texture;
 mod vertex;
 
 use std::sync::Mutex;
 
 use anyhow::Result;
-use canvas::Canvas;
+use canvas::{Canvas, Channel};
 use tauri::async_runtime::block_on;
 use wgpu::{
     Device, Queue, RenderPipeline, RequestAdapterOptions, Surface, SurfaceConfiguration,
     SurfaceTarget,
 };
 
+use crate::plugin::renderer::selection::SelectionOutline;
 use crate::plugin::renderer::texture::CanvasTexture;
 
 use vertex::Vertex;
@@ -27,6 +29,7 @@ pub(crate) struct RenderState {
 
     pipeline: Mutex<Option<RenderPipeline>>,
     texture: Mutex<Option<CanvasTexture>>,
+    selection: Mutex<Option<SelectionOutline>>,
 }
 
 impl RenderState {
@@ -65,8 +68,8 @@ impl RenderState {
             .formats
             .iter()
             .find(|f| f.is_srgb())
-            .unwrap()
-            .clone();
+            .copied()
+            .unwrap();
         println!(
             "{:?} texture format: {:?}",
             surface_capabilities.formats, texture_format
@@ -92,15 +95,16 @@ impl RenderState {
             config: Mutex::new(config),
             pipeline: Mutex::new(None),
             texture: Mutex::new(None),
+            selection: Mutex::new(None),
         })
     }
 
-    pub fn attach_canvas(&self, canvas: &Canvas) {
+    pub fn attach_canvas<T: Channel>(&self, canvas: &mut Canvas<T>) {
         let config = self.config.lock().unwrap_or_else(|p| p.into_inner());
 
         let texture = CanvasTexture::new(
             &self.device,
-            &canvas,
+            canvas,
             config.width as f32,
             config.height as f32,
         );
@@ -163,13 +167,22 @@ impl RenderState {
                 cache: None,
             });
 
+        let outline = SelectionOutline::new(
+            &self.device,
+            config.format,
+            &texture.uniform_bind_group_layout,
+        );
+
         let mut pipeline = self.pipeline.lock().unwrap();
         *pipeline = Some(render_pipeline);
+        *self.selection.lock().unwrap() = Some(outline);
         let mut ctexture = self.texture.lock().unwrap();
         *ctexture = Some(texture);
+        /* the new textures start out empty */
+        canvas.mark_all_dirty();
     }
 
-    pub fn change_size(&self, width: u32, height: u32, canvas: &Canvas) {
+    pub fn change_size<T: Channel>(&self, width: u32, height: u32, canvas: &Canvas<T>) {
         let mut config = self.config.lock().unwrap();
         config.width = if width > 0 { width } else { 1 };
         config.height = if height > 0 { height } else { 1 };
@@ -181,12 +194,17 @@ impl RenderState {
         }
     }
 
-    pub fn update(&self, canvas: &Canvas) {
+    pub fn update<T: Channel>(&self, canvas: &mut Canvas<T>) {
         let mut texture = self.texture.lock().unwrap();
 
         if let Some(c) = &mut *texture {
             c.update(&self.queue, canvas);
         }
+
+        let mut selection = self.selection.lock().unwrap();
+        if let Some(outline) = &mut *selection {
+            outline.update(&self.device, &self.queue, canvas);
+        }
     }
 
     pub fn render(&self) {
@@ -200,6 +218,7 @@ impl RenderState {
         let Some(texture) = &*texture else {
             return;
         };
+        let selection = self.selection.lock().unwrap();
 
         let frame = self
             .surface
@@ -209,8 +228,6 @@ impl RenderState {
             ..Default::default()
         });
 
-        let num_vertices = texture.vertices.len() as u32;
-
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -239,11 +256,16 @@ impl RenderState {
                 timestamp_writes: None,
                 occlusion_query_set: None,
             });
-            rpass.set_pipeline(&pipeline);
-            rpass.set_vertex_buffer(0, texture.vertex_buffer.slice(..));
-            rpass.set_bind_group(0, &texture.diffuse_bind_group, &[]);
+            rpass.set_pipeline(pipeline);
             rpass.set_bind_group(1, &texture.uniform_bind_group, &[]);
-            rpass.draw(0..num_vertices, 0..1);
+            for chunk in &texture.chunks {
+                rpass.set_vertex_buffer(0, chunk.vertex_buffer.slice(..));
+                rpass.set_bind_group(0, &chunk.diffuse_bind_group, &[]);
+                rpass.draw(0..chunk.vertices.len() as u32, 0..1);
+            }
+            if let Some(outline) = &*selection {
+                outline.draw(&mut rpass, &texture.uniform_bind_group);
+            }
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
@@ -0,0 +1,153 @@
+use std::time::Instant;
+
+use canvas::{Canvas, Channel};
+use wgpu::util::DeviceExt;
+
+/// Screen pixels the marching ants move per second
+const ANTS_SPEED: f32 = 8.0;
+
+/// Draws the outline of the canvas selection as marching ants on top of the canvas
+pub struct SelectionOutline {
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: Option<wgpu::Buffer>,
+    vertex_count: u32,
+    /// selection revision of the canvas the vertex buffer was built from
+    revision: Option<u64>,
+    phase_buffer: wgpu::Buffer,
+    phase_bind_group: wgpu::BindGroup,
+    started: Instant,
+}
+
+impl SelectionOutline {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        uniform_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Selection Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("./selection.wgsl").into()),
+        });
+
+        let phase_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("selection phase buffer"),
+            contents: bytemuck::cast_slice(&[0.0f32; 4]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let phase_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("selection phase layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let phase_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("selection phase bind group"),
+            layout: &phase_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: phase_buffer.as_entire_binding(),
+            }],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Selection Pipeline layout"),
+            bind_group_layouts: &[uniform_bind_group_layout, &phase_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Selection Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2],
+                }],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            vertex_buffer: None,
+            vertex_count: 0,
+            revision: None,
+            phase_buffer,
+            phase_bind_group,
+            started: Instant::now(),
+        }
+    }
+
+    /// Rebuilds the outline when the selection of the canvas changed and moves the ants along
+    pub fn update<T: Channel>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        canvas: &Canvas<T>,
+    ) {
+        if self.revision != Some(canvas.selection_revision()) {
+            self.revision = Some(canvas.selection_revision());
+
+            let vertices: Vec<[f32; 2]> = canvas
+                .selection()
+                .map(|s| s.outline().into_iter().flatten().collect())
+                .unwrap_or_default();
+            self.vertex_count = vertices.len() as u32;
+            self.vertex_buffer = (!vertices.is_empty()).then(|| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("selection outline buffer"),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                })
+            });
+        }
+
+        let phase = self.started.elapsed().as_secs_f32() * ANTS_SPEED;
+        queue.write_buffer(
+            &self.phase_buffer,
+            0,
+            bytemuck::cast_slice(&[phase, 0.0, 0.0, 0.0]),
+        );
+    }
+
+    /// Draws the outline, `uniform_bind_group` holds the projection and view transform
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass<'_>, uniform_bind_group: &wgpu::BindGroup) {
+        let Some(vertex_buffer) = &self.vertex_buffer else {
+            return;
+        };
+
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, uniform_bind_group, &[]);
+        rpass.set_bind_group(1, &self.phase_bind_group, &[]);
+        rpass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        rpass.draw(0..self.vertex_count, 0..1);
+    }
+}
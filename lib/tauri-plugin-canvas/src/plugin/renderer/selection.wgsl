@@ -0,0 +1,32 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+struct Uniform {
+    projection: mat4x4<f32>,
+    transformation: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> uni: Uniform;
+
+/* x holds how far the ants moved, in screen pixels */
+@group(1) @binding(0)
+var<uniform> phase: vec4<f32>;
+
+const DASH_LENGTH: f32 = 4.0;
+
+@vertex
+fn vs_main(@location(0) position: vec2<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = uni.projection * uni.transformation * vec4<f32>(position, 0.0, 1.0);
+    return out;
+}
+
+/* dashes run diagonally in screen space so they keep their size at any zoom */
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dash = floor((in.clip_position.x + in.clip_position.y + phase.x) / DASH_LENGTH);
+    let shade = select(1.0, 0.0, dash % 2.0 < 1.0);
+    return vec4<f32>(shade, shade, shade, 1.0);
+}
@@ -1,44 +1,55 @@
 use super::vertex::Vertex;
 
-use canvas::Canvas;
-use wgpu;
+use canvas::canvas::Rect;
+use canvas::tile::TILE_SIZE;
+use canvas::{Canvas, Channel};
+use half::f16;
 use wgpu::util::DeviceExt;
 
+/// Largest side of a single gpu texture, big canvases are split into several of these
+const MAX_CHUNK_SIZE: usize = 4096;
+
+/// A part of the canvas living in its own gpu texture, so canvases bigger than the
+/// maximum texture size of the device can still be displayed
 #[allow(unused)]
-pub struct CanvasTexture {
-    pub size: wgpu::Extent3d,
+pub struct TextureChunk {
+    pub rect: Rect,
     pub texture: wgpu::Texture,
     pub texture_view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
     pub vertices: [Vertex; 6],
     pub diffuse_bind_group: wgpu::BindGroup,
-    pub texture_bind_group_layout: wgpu::BindGroupLayout,
     pub vertex_buffer: wgpu::Buffer,
+}
+
+#[allow(unused)]
+pub struct CanvasTexture {
+    pub chunk_size: usize,
+    pub chunk_columns: usize,
+    pub chunks: Vec<TextureChunk>,
+    pub sampler: wgpu::Sampler,
+    pub texture_bind_group_layout: wgpu::BindGroupLayout,
     pub uniform_buffer: wgpu::Buffer,
     pub uniform_bind_group: wgpu::BindGroup,
     pub uniform_bind_group_layout: wgpu::BindGroupLayout,
 }
 
-impl CanvasTexture {
-    pub fn new(device: &wgpu::Device, canvas: &Canvas, w: f32, h: f32) -> Self {
-        let size = wgpu::Extent3d {
-            width: canvas.width() as u32,
-            height: canvas.height() as u32,
-            ..Default::default()
-        };
-
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Canvas Rendering"),
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb, // might change to non srgb
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
+/// Texture format holding the composited pixels of a canvas in the working format `T`.
+/// 8 bit canvases stay sRGB encoded, linear ones keep their precision as half floats
+fn texture_format<T: Channel>() -> wgpu::TextureFormat {
+    if T::LINEAR {
+        wgpu::TextureFormat::Rgba16Float
+    } else {
+        wgpu::TextureFormat::Rgba8UnormSrgb
+    }
+}
 
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+impl CanvasTexture {
+    pub fn new<T: Channel>(device: &wgpu::Device, canvas: &Canvas<T>, w: f32, h: f32) -> Self {
+        /* chunks are a multiple of the tile size so every tile is uploaded into a single chunk */
+        let max_dimension = device.limits().max_texture_dimension_2d as usize;
+        let chunk_size = (max_dimension.min(MAX_CHUNK_SIZE) / TILE_SIZE).max(1) * TILE_SIZE;
+        let chunk_columns = canvas.width().div_ceil(chunk_size);
+        let chunk_rows = canvas.height().div_ceil(chunk_size);
 
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -50,42 +61,6 @@ impl CanvasTexture {
             ..Default::default()
         });
 
-        let width: f32 = canvas.width() as f32;
-        let height: f32 = canvas.height() as f32;
-
-        let vertices = [
-            Vertex {
-                // Top Right
-                position: [width, 0.0], // 200, 0.0
-                tex_coords: [1.0, 0.0],
-            },
-            Vertex {
-                // Top Left
-                position: [0.0, 0.0], // 0.0, 0.0
-                tex_coords: [0.0, 0.0],
-            },
-            Vertex {
-                // Bottom Left
-                position: [0.0, height], // 0.0, 200
-                tex_coords: [0.0, 1.0],
-            },
-            Vertex {
-                // Top Right
-                position: [width, 0.0], // 200, 0.0
-                tex_coords: [1.0, 0.0],
-            },
-            Vertex {
-                // Bottom Left
-                position: [0.0, height], // 0.0, 200
-                tex_coords: [0.0, 1.0],
-            },
-            Vertex {
-                // Bottom Right
-                position: [width, height], // 200, 200
-                tex_coords: [1.0, 1.0],
-            },
-        ];
-
         /* bind group stuff */
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -110,20 +85,26 @@ impl CanvasTexture {
                 label: Some("Texture bind group layout"),
             });
 
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
-            label: Some("diffuse bind group"),
-        });
+        let chunks = (0..chunk_rows)
+            .flat_map(|row| (0..chunk_columns).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let x = column * chunk_size;
+                let y = row * chunk_size;
+                let rect = Rect::new(
+                    x,
+                    y,
+                    chunk_size.min(canvas.width() - x),
+                    chunk_size.min(canvas.height() - y),
+                );
+                TextureChunk::new(
+                    device,
+                    rect,
+                    texture_format::<T>(),
+                    &texture_bind_group_layout,
+                    &sampler,
+                )
+            })
+            .collect();
 
         let ortho = glam::Mat4::orthographic_lh(0.0, w, h, 0.0, 0.0, 1.0);
         let trans_mat = glam::Mat4::IDENTITY;
@@ -163,43 +144,38 @@ impl CanvasTexture {
             }],
         });
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
         Self {
-            size,
-            texture,
-            texture_view,
+            chunk_size,
+            chunk_columns,
+            chunks,
             sampler,
-            vertices,
-            diffuse_bind_group,
             texture_bind_group_layout,
-            vertex_buffer,
             uniform_buffer,
             uniform_bind_group,
             uniform_bind_group_layout,
         }
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue, canvas: &Canvas) {
-        queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: &self.texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &canvas.pixels().as_slice(),
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * canvas.width() as u32),
-                rows_per_image: Some(canvas.height() as u32),
-            },
-            self.size,
-        );
+    /// Uploads the areas of the canvas that changed since the last update along with the
+    /// view transform, pure pan and zoom changes only touch the uniform buffer
+    pub fn update<T: Channel>(&mut self, queue: &wgpu::Queue, canvas: &mut Canvas<T>) {
+        for rect in canvas.take_dirty_rects() {
+            let column = rect.x / self.chunk_size;
+            let row = rect.y / self.chunk_size;
+            let Some(chunk) = self.chunks.get(row * self.chunk_columns + column) else {
+                continue;
+            };
+            if T::LINEAR {
+                let pixels: Vec<u8> = canvas
+                    .composite_rect_linear(rect)
+                    .into_iter()
+                    .flat_map(|channel| f16::from_f32(channel).to_le_bytes())
+                    .collect();
+                chunk.upload(queue, rect, &pixels, 8);
+            } else {
+                chunk.upload(queue, rect, &canvas.composite_rect(rect), 4);
+            }
+        }
 
         let offset = std::mem::size_of::<[[f32; 4]; 4]>();
         queue.write_buffer(
@@ -209,12 +185,12 @@ impl CanvasTexture {
         );
     }
 
-    pub fn update_uniform(
+    pub fn update_uniform<T: Channel>(
         &mut self,
         queue: &wgpu::Queue,
         width: f32,
         height: f32,
-        canvas: &Canvas,
+        canvas: &Canvas<T>,
     ) {
         let ortho = glam::Mat4::orthographic_lh(0.0, width, height, 0.0, 0.0, 1.0);
         let uniforms = Uniforms {
@@ -226,6 +202,131 @@ impl CanvasTexture {
     }
 }
 
+impl TextureChunk {
+    fn new(
+        device: &wgpu::Device,
+        rect: Rect,
+        format: wgpu::TextureFormat,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: rect.width as u32,
+            height: rect.height as u32,
+            ..Default::default()
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Canvas Rendering"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let left = rect.x as f32;
+        let top = rect.y as f32;
+        let right = rect.right() as f32;
+        let bottom = rect.bottom() as f32;
+
+        let vertices = [
+            Vertex {
+                // Top Right
+                position: [right, top],
+                tex_coords: [1.0, 0.0],
+            },
+            Vertex {
+                // Top Left
+                position: [left, top],
+                tex_coords: [0.0, 0.0],
+            },
+            Vertex {
+                // Bottom Left
+                position: [left, bottom],
+                tex_coords: [0.0, 1.0],
+            },
+            Vertex {
+                // Top Right
+                position: [right, top],
+                tex_coords: [1.0, 0.0],
+            },
+            Vertex {
+                // Bottom Left
+                position: [left, bottom],
+                tex_coords: [0.0, 1.0],
+            },
+            Vertex {
+                // Bottom Right
+                position: [right, bottom],
+                tex_coords: [1.0, 1.0],
+            },
+        ];
+
+        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("diffuse bind group"),
+        });
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        Self {
+            rect,
+            texture,
+            texture_view,
+            vertices,
+            diffuse_bind_group,
+            vertex_buffer,
+        }
+    }
+
+    /// Writes tightly packed RGBA `pixels` covering the canvas area `rect` into the chunk,
+    /// each pixel taking `pixel_size` bytes in the format of the chunk texture
+    fn upload(&self, queue: &wgpu::Queue, rect: Rect, pixels: &[u8], pixel_size: usize) {
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: (rect.x - self.rect.x) as u32,
+                    y: (rect.y - self.rect.y) as u32,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            pixels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some((pixel_size * rect.width) as u32),
+                rows_per_image: Some(rect.height as u32),
+            },
+            wgpu::Extent3d {
+                width: rect.width as u32,
+                height: rect.height as u32,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
 /* for later use */
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
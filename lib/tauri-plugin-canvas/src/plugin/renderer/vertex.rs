@@ -1,6 +1,3 @@
-use bytemuck;
-use wgpu;
-
 /* Vertex Stuff */
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -1,8 +1,10 @@
-use canvas::brush::stroke::StrokeManager;
+use canvas::{brush::stroke::StrokeManager, color::ColorHistory};
 
 /// The current state of the application, right now it only holds the canvas
+/// and the colors recently painted with
 pub struct AppState {
     _stroke_manager: StrokeManager,
+    pub color_history: ColorHistory,
 }
 
 impl AppState {}
@@ -11,6 +13,7 @@ impl Default for AppState {
     fn default() -> Self {
         Self {
             _stroke_manager: StrokeManager::new(),
+            color_history: ColorHistory::default(),
         }
     }
 }
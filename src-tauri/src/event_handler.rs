@@ -1,16 +1,31 @@
 pub mod canvas_input;
 
-use canvas::{brush::stroke::StrokeManager, Canvas};
-use canvas_input::CanvasInput;
+use canvas::{
+    brush::{
+        stroke::{StrokeManager, StrokePositionalData},
+        Airbrush, BrushSettings, Eraser, RoundBrush, SmudgeBrush,
+    },
+    fill::{FillOptions, FillSource},
+    picker::{pick_color, SampleSize},
+    selection::{Selection, SelectionMode},
+    transform::{Resampling, Transform},
+    view::snap_angle,
+    Canvas, Channel, Color,
+};
+use canvas_input::{BrushKind, CanvasInput, SelectionShape};
 use std::sync::{Arc, Mutex};
-use tauri::Window;
-use tauri_plugin_canvas::AppHandleExt;
+use tauri::{Emitter, Window};
+use tauri_plugin_canvas::{AppHandleExt, WorkingCanvas};
+
+use crate::appstate::AppState;
+use crate::strokes::WorkingStrokeManager;
 
 #[tauri::command]
 pub fn process_canvas_input(
     input: CanvasInput,
-    canvas: tauri::State<Arc<Mutex<Canvas>>>,
-    stroke_manager: tauri::State<Mutex<StrokeManager>>,
+    canvas: tauri::State<Arc<Mutex<WorkingCanvas>>>,
+    stroke_manager: tauri::State<Mutex<WorkingStrokeManager>>,
+    app_state: tauri::State<Mutex<AppState>>,
     app: tauri::AppHandle,
     window: Window,
 ) {
@@ -18,29 +33,279 @@ pub fn process_canvas_input(
     let mut stroke_manager = stroke_manager.lock().unwrap();
     // println!("Received {input} input");
 
+    stroke_manager.set_format(canvas.format());
+    match (&mut *canvas, &mut *stroke_manager) {
+        (WorkingCanvas::Srgb8(canvas), WorkingStrokeManager::Srgb8(stroke_manager)) => {
+            handle_input(input, canvas, stroke_manager, &app_state, &window)
+        }
+        (WorkingCanvas::Linear16(canvas), WorkingStrokeManager::Linear16(stroke_manager)) => {
+            handle_input(input, canvas, stroke_manager, &app_state, &window)
+        }
+        (WorkingCanvas::LinearF32(canvas), WorkingStrokeManager::LinearF32(stroke_manager)) => {
+            handle_input(input, canvas, stroke_manager, &app_state, &window)
+        }
+        /* set_format above keeps the two in the same working format */
+        _ => {}
+    }
+
+    app.send_redraw_request_for_window(window.label()).ok();
+}
+
+fn handle_input<T: Channel>(
+    input: CanvasInput,
+    canvas: &mut Canvas<T>,
+    stroke_manager: &mut StrokeManager<T>,
+    app_state: &Mutex<AppState>,
+    window: &Window,
+) {
     match input {
-        CanvasInput::ZoomCanvas { zoom } => handle_zoom(zoom, &mut canvas),
-        CanvasInput::PanCanvas { offset_x, offset_y } => {
-            handle_pan(offset_x, offset_y, &mut canvas)
+        CanvasInput::ZoomCanvas { zoom } => handle_zoom(zoom, canvas),
+        CanvasInput::PanCanvas { offset_x, offset_y } => handle_pan(offset_x, offset_y, canvas),
+        CanvasInput::PanCanvasBy { delta_x, delta_y } => canvas.view_mut().pan(delta_x, delta_y),
+        CanvasInput::ZoomCanvasAround {
+            zoom,
+            anchor_x,
+            anchor_y,
+        } => canvas.view_mut().zoom_around(zoom, anchor_x, anchor_y),
+        CanvasInput::FitCanvas {
+            viewport_width,
+            viewport_height,
+        } => canvas.view_mut().fit(viewport_width, viewport_height),
+        CanvasInput::ActualSize { anchor_x, anchor_y } => {
+            canvas.view_mut().actual_size(anchor_x, anchor_y)
         }
+        CanvasInput::RotateCanvas {
+            angle,
+            pivot_x,
+            pivot_y,
+            snap,
+        } => handle_rotate(angle, pivot_x.zip(pivot_y), snap, canvas),
+        CanvasInput::ResetRotation => canvas.view_mut().reset_rotation(),
+        CanvasInput::MirrorCanvas {
+            horizontal,
+            vertical,
+        } => canvas.view_mut().set_mirrored(horizontal, vertical),
         CanvasInput::BeginStroke(event) => {
-            stroke_manager.begin_stroke(event.into(), &mut canvas);
+            let point: StrokePositionalData = event.into();
+            if !matches!(stroke_manager.brush(), BrushSettings::Eraser(_)) {
+                app_state.lock().unwrap().color_history.push(point.color);
+            }
+            stroke_manager.begin_stroke(point, canvas);
         }
         CanvasInput::ContinueStroke(event) => {
-            stroke_manager.continue_stroke(event.into(), &mut canvas);
+            stroke_manager.continue_stroke(event.into(), canvas);
         }
         CanvasInput::EndStroke(event) => {
-            stroke_manager.end_stroke(event.into(), &mut canvas);
+            stroke_manager.end_stroke(event.into(), canvas);
+        }
+        CanvasInput::Undo => {
+            canvas.undo();
+        }
+        CanvasInput::Redo => {
+            canvas.redo();
         }
+        CanvasInput::SelectBrush { brush, size } => {
+            handle_select_brush(brush, size, stroke_manager)
+        }
+        CanvasInput::SetDynamics { dynamics } => stroke_manager.set_dynamics(dynamics),
+        CanvasInput::Fill {
+            pos_x,
+            pos_y,
+            color,
+            options,
+        } => handle_fill(pos_x, pos_y, color, &options, canvas),
+        CanvasInput::PickColor {
+            pos_x,
+            pos_y,
+            source,
+            sample,
+        } => handle_pick_color(pos_x, pos_y, source, sample, canvas, window),
+        CanvasInput::Select { shape, mode } => handle_select(shape, mode, canvas),
+        CanvasInput::SelectAll => canvas.select_all(),
+        CanvasInput::SelectNone => canvas.select_none(),
+        CanvasInput::InvertSelection => canvas.invert_selection(),
+        CanvasInput::FeatherSelection { radius } => canvas.feather_selection(radius),
+        CanvasInput::GrowSelection { pixels } => canvas.grow_selection(pixels),
+        CanvasInput::SetSmoothing {
+            stabilizer,
+            interpolation,
+        } => stroke_manager.set_smoothing(stabilizer, interpolation),
+        CanvasInput::BeginTransform => {
+            canvas.begin_transform();
+        }
+        CanvasInput::UpdateTransform {
+            corners,
+            resampling,
+        } => handle_update_transform(corners, resampling, canvas),
+        CanvasInput::CommitTransform => canvas.commit_transform(),
+        CanvasInput::CancelTransform => canvas.cancel_transform(),
+        CanvasInput::PreviewFilter { filter } => {
+            canvas.preview_filter(&filter);
+        }
+        CanvasInput::CommitFilter => canvas.commit_filter(),
+        CanvasInput::CancelFilter => canvas.cancel_filter(),
     }
-
-    app.send_redraw_request_for_window(window.label()).ok();
 }
 
-fn handle_zoom(zoom: f32, canvas: &mut Canvas) {
+fn handle_zoom<T: Channel>(zoom: f32, canvas: &mut Canvas<T>) {
     canvas.set_zoom(zoom);
 }
 
-fn handle_pan(offset_x: f32, offset_y: f32, canvas: &mut Canvas) {
+fn handle_pan<T: Channel>(offset_x: f32, offset_y: f32, canvas: &mut Canvas<T>) {
     canvas.set_offset(offset_x, offset_y);
 }
+
+fn handle_rotate<T: Channel>(
+    angle: f32,
+    pivot: Option<(f32, f32)>,
+    snap: bool,
+    canvas: &mut Canvas<T>,
+) {
+    let angle = if snap { snap_angle(angle) } else { angle };
+
+    match pivot {
+        Some((x, y)) => canvas.view_mut().rotate_around(angle, x, y),
+        None => canvas.set_rotation(angle),
+    }
+}
+
+fn handle_fill<T: Channel>(
+    pos_x: f32,
+    pos_y: f32,
+    color: (f32, f32, f32, f32),
+    options: &FillOptions,
+    canvas: &mut Canvas<T>,
+) {
+    let (x, y) = canvas.translate_screen_to_canvas(pos_x, pos_y);
+    if x < 0.0 || y < 0.0 {
+        return;
+    }
+    let color = Color::new_f32(color.0, color.1, color.2, color.3);
+    canvas.fill(x as usize, y as usize, color, options);
+}
+
+fn handle_pick_color<T: Channel>(
+    pos_x: f32,
+    pos_y: f32,
+    source: FillSource,
+    sample: SampleSize,
+    canvas: &Canvas<T>,
+    window: &Window,
+) {
+    let (x, y) = canvas.translate_screen_to_canvas(pos_x, pos_y);
+    if x < 0.0 || y < 0.0 {
+        return;
+    }
+    if let Some(color) = pick_color(canvas, x as usize, y as usize, source, sample) {
+        window.emit("color-picked", color).ok();
+    }
+}
+
+/// Segments used to approximate ellipses drawn on a rotated view
+const ELLIPSE_SEGMENTS: usize = 64;
+
+/// Selections are drawn in screen space, so with a rotated or mirrored view every shape
+/// turns into a polygon on the canvas
+fn handle_select<T: Channel>(shape: SelectionShape, mode: SelectionMode, canvas: &mut Canvas<T>) {
+    let (width, height) = (canvas.width(), canvas.height());
+    let to_canvas = |(x, y): (f32, f32)| canvas.translate_screen_to_canvas(x, y);
+
+    let selection = match shape {
+        SelectionShape::Rectangle {
+            start_x,
+            start_y,
+            end_x,
+            end_y,
+        } => {
+            let corners = [
+                (start_x, start_y),
+                (end_x, start_y),
+                (end_x, end_y),
+                (start_x, end_y),
+            ];
+            Selection::polygon(width, height, &corners.map(to_canvas))
+        }
+        SelectionShape::Ellipse {
+            start_x,
+            start_y,
+            end_x,
+            end_y,
+        } => {
+            let center = ((start_x + end_x) / 2.0, (start_y + end_y) / 2.0);
+            let radius = ((end_x - start_x) / 2.0, (end_y - start_y) / 2.0);
+            let points: Vec<_> = (0..ELLIPSE_SEGMENTS)
+                .map(|i| {
+                    let angle = i as f32 / ELLIPSE_SEGMENTS as f32 * std::f32::consts::TAU;
+                    to_canvas((
+                        center.0 + radius.0 * angle.cos(),
+                        center.1 + radius.1 * angle.sin(),
+                    ))
+                })
+                .collect();
+            Selection::polygon(width, height, &points)
+        }
+        SelectionShape::Lasso { points } => {
+            let points: Vec<_> = points.into_iter().map(to_canvas).collect();
+            Selection::polygon(width, height, &points)
+        }
+        SelectionShape::MagicWand {
+            pos_x,
+            pos_y,
+            options,
+        } => {
+            let (x, y) = to_canvas((pos_x, pos_y));
+            if x < 0.0 || y < 0.0 {
+                return;
+            }
+            let Some(selection) = Selection::magic_wand(canvas, x as usize, y as usize, &options)
+            else {
+                return;
+            };
+            selection
+        }
+    };
+
+    canvas.select(selection, mode);
+}
+
+fn handle_update_transform<T: Channel>(
+    corners: [(f32, f32); 4],
+    resampling: Resampling,
+    canvas: &mut Canvas<T>,
+) {
+    let Some(source) = canvas.transform_source() else {
+        return;
+    };
+    let corners = corners.map(|(x, y)| {
+        let (x, y) = canvas.translate_screen_to_canvas(x, y);
+        (x as f64, y as f64)
+    });
+
+    /* degenerate quads (folded or collapsed handles) keep the last good preview */
+    if let Some(transform) = Transform::from_corners(source, corners) {
+        canvas.update_transform(transform, resampling);
+    }
+}
+
+fn handle_select_brush<T: Channel>(
+    brush: BrushKind,
+    size: f32,
+    stroke_manager: &mut StrokeManager<T>,
+) {
+    let brush = match brush {
+        BrushKind::Round => BrushSettings::Round(RoundBrush {
+            size,
+            ..Default::default()
+        }),
+        BrushKind::Airbrush => BrushSettings::Airbrush(Airbrush {
+            size,
+            ..Default::default()
+        }),
+        BrushKind::Eraser => BrushSettings::Eraser(Eraser {
+            size,
+            ..Default::default()
+        }),
+        BrushKind::Smudge(brush) => BrushSettings::Smudge(SmudgeBrush { size, ..brush }),
+    };
+    stroke_manager.set_brush(brush);
+}
@@ -1,6 +1,16 @@
 use std::fmt::Display;
 
-use canvas::{brush::stroke::StrokePositionalData, Color};
+use canvas::{
+    brush::{
+        stroke::StrokePositionalData, Interpolation, PressureDynamics, SmudgeBrush, Stabilizer,
+    },
+    fill::{FillOptions, FillSource},
+    filter::Filter,
+    picker::SampleSize,
+    selection::SelectionMode,
+    transform::Resampling,
+    Color,
+};
 use serde::{Deserialize, Serialize};
 
 /// All of the different actions the user can perform on the canvas
@@ -11,15 +21,192 @@ pub enum CanvasInput {
     /// we want the canvas. Mouse_x and Mouse_y is to zoom relative to point, probably should
     /// make a seperate action specifically for zooming relateive to point
     #[serde(rename_all = "camelCase")]
-    ZoomCanvas { zoom: f32 },
+    ZoomCanvas {
+        zoom: f32,
+    },
     #[serde(rename_all = "camelCase")]
-    PanCanvas { offset_x: f32, offset_y: f32 },
+    PanCanvas {
+        offset_x: f32,
+        offset_y: f32,
+    },
+    /// Moves the view by a distance in screen pixels, so the ui doesn't need to track
+    /// the offset of the canvas
+    #[serde(rename_all = "camelCase")]
+    PanCanvasBy {
+        delta_x: f32,
+        delta_y: f32,
+    },
+    /// Zooms while keeping the canvas point under the screen anchor in place, the zoom
+    /// is clamped by the canvas so the ui doesn't need to know the view transform
+    #[serde(rename_all = "camelCase")]
+    ZoomCanvasAround {
+        zoom: f32,
+        anchor_x: f32,
+        anchor_y: f32,
+    },
+    /// Zooms and centres the canvas so it fits inside the viewport
+    #[serde(rename_all = "camelCase")]
+    FitCanvas {
+        viewport_width: f32,
+        viewport_height: f32,
+    },
+    /// Shows the canvas at 100% around the screen anchor
+    #[serde(rename_all = "camelCase")]
+    ActualSize {
+        anchor_x: f32,
+        anchor_y: f32,
+    },
+    /// Turns the view to an absolute `angle` in radians. With a pivot the canvas point
+    /// under it stays in place, otherwise the view turns around the canvas centre.
+    /// `snap` rounds the angle to steps of 15°
+    #[serde(rename_all = "camelCase")]
+    RotateCanvas {
+        angle: f32,
+        pivot_x: Option<f32>,
+        pivot_y: Option<f32>,
+        #[serde(default)]
+        snap: bool,
+    },
+    /// Turns the view back upright
+    ResetRotation,
+    /// Mirrors the view without touching the pixels of the canvas
+    #[serde(rename_all = "camelCase")]
+    MirrorCanvas {
+        horizontal: bool,
+        vertical: bool,
+    },
     #[serde(rename_all = "camelCase")]
     BeginStroke(PointerEvent),
     #[serde(rename_all = "camelCase")]
     ContinueStroke(PointerEvent),
     #[serde(rename_all = "camelCase")]
     EndStroke(PointerEvent),
+    /// Reverts the last stroke or layer edit
+    Undo,
+    /// Re-applies the last undone stroke or layer edit
+    Redo,
+    /// Switches the brush engine used for the following strokes
+    #[serde(rename_all = "camelCase")]
+    SelectBrush {
+        brush: BrushKind,
+        size: f32,
+    },
+    /// Changes how pen pressure affects the size and opacity of the active brush
+    #[serde(rename_all = "camelCase")]
+    SetDynamics {
+        dynamics: PressureDynamics,
+    },
+    /// Paint bucket fill at a screen position
+    #[serde(rename_all = "camelCase")]
+    Fill {
+        pos_x: f32,
+        pos_y: f32,
+        color: (f32, f32, f32, f32),
+        #[serde(default)]
+        options: FillOptions,
+    },
+    /// Samples the color at a screen position, the result is emitted to the window as a
+    /// `color-picked` event. Sending this on every pointer move streams the colors
+    #[serde(rename_all = "camelCase")]
+    PickColor {
+        pos_x: f32,
+        pos_y: f32,
+        #[serde(default)]
+        source: FillSource,
+        #[serde(default)]
+        sample: SampleSize,
+    },
+    /// Creates a selection and combines it with the current one
+    #[serde(rename_all = "camelCase")]
+    Select {
+        shape: SelectionShape,
+        #[serde(default)]
+        mode: SelectionMode,
+    },
+    SelectAll,
+    SelectNone,
+    InvertSelection,
+    /// Softens the selection edge over `radius` canvas pixels
+    #[serde(rename_all = "camelCase")]
+    FeatherSelection {
+        radius: usize,
+    },
+    /// Grows the selection by `pixels`, negative values shrink it
+    #[serde(rename_all = "camelCase")]
+    GrowSelection {
+        pixels: isize,
+    },
+    /// Changes how pointer samples are steadied and joined, starting with the next stroke
+    #[serde(rename_all = "camelCase")]
+    SetSmoothing {
+        stabilizer: Stabilizer,
+        interpolation: Interpolation,
+    },
+    /// Lifts the selected pixels, or the whole active layer, off for free transform
+    BeginTransform,
+    /// Previews the lifted pixels stretched onto `corners`, given in screen coordinates
+    /// clockwise from the top left corner of the source
+    #[serde(rename_all = "camelCase")]
+    UpdateTransform {
+        corners: [(f32, f32); 4],
+        #[serde(default)]
+        resampling: Resampling,
+    },
+    /// Applies the previewed transform as a single undo step
+    CommitTransform,
+    /// Puts the lifted pixels back where they were
+    CancelTransform,
+    /// Shows the filter on the selection or active layer, replacing any earlier preview
+    #[serde(rename_all = "camelCase")]
+    PreviewFilter {
+        filter: Filter,
+    },
+    /// Applies the previewed filter as a single undo step
+    CommitFilter,
+    /// Drops the previewed filter
+    CancelFilter,
+}
+
+/// Shapes a selection can be made from, all positions are in screen coordinates
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "shape", rename_all = "camelCase")]
+pub enum SelectionShape {
+    #[serde(rename_all = "camelCase")]
+    Rectangle {
+        start_x: f32,
+        start_y: f32,
+        end_x: f32,
+        end_y: f32,
+    },
+    /// Ellipse inscribed in the rectangle between the two corners
+    #[serde(rename_all = "camelCase")]
+    Ellipse {
+        start_x: f32,
+        start_y: f32,
+        end_x: f32,
+        end_y: f32,
+    },
+    /// Closed polygon through the points, used for lasso selections
+    #[serde(rename_all = "camelCase")]
+    Lasso { points: Vec<(f32, f32)> },
+    #[serde(rename_all = "camelCase")]
+    MagicWand {
+        pos_x: f32,
+        pos_y: f32,
+        #[serde(default)]
+        options: FillOptions,
+    },
+}
+
+/// The brush engines the ui can choose from. Engines with settings beyond the size
+/// carry them along, the size of [`CanvasInput::SelectBrush`] replaces theirs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BrushKind {
+    Round,
+    Airbrush,
+    Eraser,
+    Smudge(SmudgeBrush),
 }
 
 impl Display for CanvasInput {
@@ -31,11 +218,113 @@ impl Display for CanvasInput {
             CanvasInput::PanCanvas { offset_x, offset_y } => {
                 write!(f, "PanCanvas(offset_x: {offset_x}, offset_y: {offset_y})")
             }
+            CanvasInput::PanCanvasBy { delta_x, delta_y } => {
+                write!(f, "PanCanvasBy(delta_x: {delta_x}, delta_y: {delta_y})")
+            }
+            CanvasInput::ZoomCanvasAround {
+                zoom,
+                anchor_x,
+                anchor_y,
+            } => {
+                write!(
+                    f,
+                    "ZoomCanvasAround(zoom: {zoom}, anchor: ({anchor_x}, {anchor_y}))"
+                )
+            }
+            CanvasInput::FitCanvas {
+                viewport_width,
+                viewport_height,
+            } => {
+                write!(f, "FitCanvas(viewport: {viewport_width}x{viewport_height})")
+            }
+            CanvasInput::ActualSize { anchor_x, anchor_y } => {
+                write!(f, "ActualSize(anchor: ({anchor_x}, {anchor_y}))")
+            }
+            CanvasInput::RotateCanvas {
+                angle,
+                pivot_x,
+                pivot_y,
+                snap,
+            } => {
+                write!(
+                    f,
+                    "RotateCanvas(angle: {angle}, pivot: ({pivot_x:?}, {pivot_y:?}), snap: {snap})"
+                )
+            }
+            CanvasInput::ResetRotation => write!(f, "ResetRotation"),
+            CanvasInput::MirrorCanvas {
+                horizontal,
+                vertical,
+            } => {
+                write!(
+                    f,
+                    "MirrorCanvas(horizontal: {horizontal}, vertical: {vertical})"
+                )
+            }
             CanvasInput::BeginStroke(event) => {
                 write!(f, "StartStroke({event})")
             }
             CanvasInput::ContinueStroke(event) => write!(f, "ContinueStroke({event})"),
             CanvasInput::EndStroke(event) => write!(f, "EndStroke({event})"),
+            CanvasInput::Undo => write!(f, "Undo"),
+            CanvasInput::Redo => write!(f, "Redo"),
+            CanvasInput::SelectBrush { brush, size } => {
+                write!(f, "SelectBrush(brush: {brush:?}, size: {size})")
+            }
+            CanvasInput::SetDynamics { dynamics } => write!(f, "SetDynamics({dynamics:?})"),
+            CanvasInput::Fill {
+                pos_x,
+                pos_y,
+                options,
+                ..
+            } => {
+                write!(f, "Fill(pos: ({pos_x}, {pos_y}), options: {options:?})")
+            }
+            CanvasInput::PickColor {
+                pos_x,
+                pos_y,
+                source,
+                sample,
+            } => {
+                write!(
+                    f,
+                    "PickColor(pos: ({pos_x}, {pos_y}), source: {source:?}, sample: {sample:?})"
+                )
+            }
+            CanvasInput::Select { shape, mode } => {
+                write!(f, "Select(shape: {shape:?}, mode: {mode:?})")
+            }
+            CanvasInput::SelectAll => write!(f, "SelectAll"),
+            CanvasInput::SelectNone => write!(f, "SelectNone"),
+            CanvasInput::InvertSelection => write!(f, "InvertSelection"),
+            CanvasInput::FeatherSelection { radius } => {
+                write!(f, "FeatherSelection(radius: {radius})")
+            }
+            CanvasInput::GrowSelection { pixels } => write!(f, "GrowSelection(pixels: {pixels})"),
+            CanvasInput::SetSmoothing {
+                stabilizer,
+                interpolation,
+            } => {
+                write!(
+                    f,
+                    "SetSmoothing(stabilizer: {stabilizer:?}, interpolation: {interpolation:?})"
+                )
+            }
+            CanvasInput::BeginTransform => write!(f, "BeginTransform"),
+            CanvasInput::UpdateTransform {
+                corners,
+                resampling,
+            } => {
+                write!(
+                    f,
+                    "UpdateTransform(corners: {corners:?}, resampling: {resampling:?})"
+                )
+            }
+            CanvasInput::CommitTransform => write!(f, "CommitTransform"),
+            CanvasInput::CancelTransform => write!(f, "CancelTransform"),
+            CanvasInput::PreviewFilter { filter } => write!(f, "PreviewFilter({filter:?})"),
+            CanvasInput::CommitFilter => write!(f, "CommitFilter"),
+            CanvasInput::CancelFilter => write!(f, "CancelFilter"),
         }
     }
 }
@@ -46,6 +335,14 @@ pub struct PointerEvent {
     pos_x: f32,
     pos_y: f32,
     pressure: f32,
+    /// Pen tilt in degrees, as reported by the browser pointer event
+    #[serde(default)]
+    tilt_x: f32,
+    #[serde(default)]
+    tilt_y: f32,
+    /// Milliseconds since the page loaded, the `timeStamp` of the pointer event
+    #[serde(default)]
+    time_stamp: f64,
     color: (f32, f32, f32, f32),
 }
 impl Display for PointerEvent {
@@ -63,6 +360,9 @@ impl From<PointerEvent> for StrokePositionalData {
             x: value.pos_x,
             y: value.pos_y,
             pressure: value.pressure,
+            tilt_x: value.tilt_x,
+            tilt_y: value.tilt_y,
+            time: value.time_stamp / 1000.0,
             color: Color::new_f32(value.color.0, value.color.1, value.color.2, value.color.3),
         }
     }
@@ -2,11 +2,21 @@ use tauri::{AppHandle, Manager};
 
 mod appstate;
 mod event_handler;
+mod strokes;
 use appstate::AppState;
-use canvas::{brush::stroke::StrokeManager, Canvas};
+use canvas::{
+    brush::{BrushSettings, Grain, GrayImage, ImageBrush, MyPaintBrush},
+    color::ColorHistory,
+    format::{png, project},
+    Canvas, Channel, PixelFormat,
+};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Seek};
 use std::sync::{Arc, Mutex};
+use strokes::WorkingStrokeManager;
 
-use tauri_plugin_canvas::{AppHandleExt, CanvasPluginBuilder};
+use tauri_plugin_canvas::{with_canvas, AppHandleExt, CanvasPluginBuilder, WorkingCanvas};
 
 #[tauri::command]
 async fn show_snap_overlay() {
@@ -28,30 +38,229 @@ async fn show_snap_overlay() {
     }
 }
 
+/// Creates a new canvas in the working format picked for the document
 #[tauri::command]
-fn attach_canvas(width: usize, height: usize, app: tauri::AppHandle, window: tauri::Window) {
+fn attach_canvas(
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+    app: tauri::AppHandle,
+    window: tauri::Window,
+) {
     let label = window.label();
 
     println!("{label} sent this call");
-    let canvas = Arc::new(Mutex::new(Canvas::new(width, height)));
-    app.attach_canvas_for_window(label, canvas.clone()).ok();
+    replace_canvas(WorkingCanvas::new(width, height, format), &app, label);
+}
+
+#[tauri::command]
+fn export_png(
+    path: String,
+    sixteen_bit: bool,
+    transparent_background: bool,
+    canvas: tauri::State<Arc<Mutex<WorkingCanvas>>>,
+) -> Result<(), String> {
+    let mut canvas = canvas.lock().unwrap();
+    let file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+    let options = png::ExportOptions {
+        sixteen_bit,
+        transparent_background,
+    };
+
+    with_canvas!(&mut *canvas, canvas => {
+        canvas.commit_previews();
+        png::export(canvas, options, file)
+    })
+    .map_err(|e| e.to_string())
+}
+
+/// Opens a png as a new canvas in the working format `format`, returning its width and height
+#[tauri::command]
+fn import_png(
+    path: String,
+    format: PixelFormat,
+    app: AppHandle,
+    window: tauri::Window,
+) -> Result<(usize, usize), String> {
+    let file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let imported: WorkingCanvas = match format {
+        PixelFormat::Srgb8 => png::import::<u8>(file).map(Into::into),
+        PixelFormat::Linear16 => png::import::<u16>(file).map(Into::into),
+        PixelFormat::LinearF32 => png::import::<f32>(file).map(Into::into),
+    }
+    .map_err(|e| e.to_string())?;
+    let size = (imported.width(), imported.height());
+
+    replace_canvas(imported, &app, window.label());
+    Ok(size)
+}
+
+/// Paper texture of an image brush, read from a png
+#[derive(Deserialize)]
+struct GrainFile {
+    path: String,
+    scale: f32,
+    strength: f32,
+}
+
+/// Switches to an image brush whose tip is read from a png. The rest of the settings
+/// come from `brush`, whose tip and grain are replaced by the ones read from files
+#[tauri::command]
+fn select_image_brush(
+    path: String,
+    brush: ImageBrush,
+    grain: Option<GrainFile>,
+    stroke_manager: tauri::State<Mutex<WorkingStrokeManager>>,
+) -> Result<(), String> {
+    let tip = read_gray_image(&path)?;
+    let grain = match grain {
+        Some(grain) => Some(Grain {
+            texture: read_gray_image(&grain.path)?,
+            scale: grain.scale,
+            strength: grain.strength,
+        }),
+        None => None,
+    };
+
+    stroke_manager
+        .lock()
+        .unwrap()
+        .set_brush(BrushSettings::Image(ImageBrush {
+            tip,
+            grain,
+            ..brush
+        }));
+    Ok(())
+}
+
+fn read_gray_image(path: &str) -> Result<GrayImage, String> {
+    let file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    GrayImage::from_png(file).map_err(|e| e.to_string())
+}
+
+/// Switches to a brush read from a MyPaint `.myb` file
+#[tauri::command]
+fn select_mypaint_brush(
+    path: String,
+    stroke_manager: tauri::State<Mutex<WorkingStrokeManager>>,
+) -> Result<(), String> {
+    let file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let brush = MyPaintBrush::from_myb(file).map_err(|e| e.to_string())?;
+
+    stroke_manager
+        .lock()
+        .unwrap()
+        .set_brush(BrushSettings::MyPaint(brush));
+    Ok(())
+}
+
+#[tauri::command]
+fn save_project(
+    path: String,
+    canvas: tauri::State<Arc<Mutex<WorkingCanvas>>>,
+    stroke_manager: tauri::State<Mutex<WorkingStrokeManager>>,
+    app_state: tauri::State<Mutex<AppState>>,
+) -> Result<(), String> {
+    let mut canvas = canvas.lock().unwrap();
+    let stroke_manager = stroke_manager.lock().unwrap();
+    let app_state = app_state.lock().unwrap();
+    let file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+
+    with_canvas!(&mut *canvas, canvas => {
+        canvas.commit_previews();
+        project::save(canvas, stroke_manager.brush(), &app_state.color_history, file)
+    })
+    .map_err(|e| e.to_string())
+}
+
+/// Opens a project file, restoring its canvas, brush and color history. The canvas is
+/// converted to `format` if given and kept in the format it was saved in otherwise.
+/// Returns the width and height of the canvas
+#[tauri::command]
+fn open_project(
+    path: String,
+    format: Option<PixelFormat>,
+    stroke_manager: tauri::State<Mutex<WorkingStrokeManager>>,
+    app_state: tauri::State<Mutex<AppState>>,
+    app: AppHandle,
+    window: tauri::Window,
+) -> Result<(usize, usize), String> {
+    let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let format = match format {
+        Some(format) => format,
+        None => project::stored_format(&mut file).map_err(|e| e.to_string())?,
+    };
+    file.rewind().map_err(|e| e.to_string())?;
+    let (canvas, brush, color_history) = match format {
+        PixelFormat::Srgb8 => project::open::<u8>(file).map(into_working),
+        PixelFormat::Linear16 => project::open::<u16>(file).map(into_working),
+        PixelFormat::LinearF32 => project::open::<f32>(file).map(into_working),
+    }
+    .map_err(|e| e.to_string())?;
+    let size = (canvas.width(), canvas.height());
+
+    stroke_manager.lock().unwrap().set_brush(brush);
+    app_state.lock().unwrap().color_history = color_history;
+    replace_canvas(canvas, &app, window.label());
+    Ok(size)
+}
+
+/// Splits an opened project, wrapping its canvas
+fn into_working<T: Channel>(
+    project: project::Project<T>,
+) -> (WorkingCanvas, BrushSettings, ColorHistory)
+where
+    WorkingCanvas: From<Canvas<T>>,
+{
+    (project.canvas.into(), project.brush, project.color_history)
+}
+
+/// Swaps the managed canvas for a new one and hands it to the renderer of the window
+fn replace_canvas(new_canvas: WorkingCanvas, app: &AppHandle, label: &str) {
+    let canvas = match app.try_state::<Arc<Mutex<WorkingCanvas>>>() {
+        Some(canvas) => {
+            *canvas.lock().unwrap() = new_canvas;
+            canvas.inner().clone()
+        }
+        None => {
+            let canvas = Arc::new(Mutex::new(new_canvas));
+            app.manage(canvas.clone());
+            canvas
+        }
+    };
+
+    app.attach_canvas_for_window(label, canvas).ok();
     app.send_redraw_request_for_window(label).ok();
-    app.manage(canvas);
 }
 
 #[tauri::command]
 fn set_view(offset_x: f32, offset_y: f32, app: AppHandle, window: tauri::Window) {
-    let canvas = app.try_state::<Arc<Mutex<Canvas>>>();
+    let canvas = app.try_state::<Arc<Mutex<WorkingCanvas>>>();
 
     let Some(canvas) = canvas else {
         return;
     };
     let mut canvas = canvas.lock().unwrap();
 
-    canvas.set_offset(offset_x, offset_y);
+    with_canvas!(&mut *canvas, canvas => canvas.set_offset(offset_x, offset_y));
     app.send_redraw_request_for_window(window.label()).ok();
 }
 
+/// Screen positions of the corners of the pixels being transformed, top left, top right,
+/// bottom right and bottom left, `None` when no transform is in progress
+#[tauri::command]
+fn transform_handles(app: AppHandle) -> Option<[(f32, f32); 4]> {
+    let canvas = app.try_state::<Arc<Mutex<WorkingCanvas>>>()?;
+    let canvas = canvas.lock().unwrap();
+
+    with_canvas!(&*canvas, canvas => {
+        let corners = canvas
+            .current_transform()?
+            .corners(canvas.transform_source()?)?;
+        Some(corners.map(|(x, y)| canvas.view().canvas_to_screen(x as f32, y as f32)))
+    })
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
@@ -81,7 +290,7 @@ pub fn run() {
             let state = AppState::default();
             app.manage(Mutex::new(state));
 
-            let stroke_manager = StrokeManager::new();
+            let stroke_manager = WorkingStrokeManager::default();
             app.manage(Mutex::new(stroke_manager));
 
             println!("Finished!");
@@ -91,9 +300,16 @@ pub fn run() {
         .plugin(tauri_plugin_opener::init())
         .invoke_handler(tauri::generate_handler![
             attach_canvas,
+            export_png,
+            import_png,
+            select_image_brush,
+            select_mypaint_brush,
+            save_project,
+            open_project,
             event_handler::process_canvas_input,
             show_snap_overlay,
             set_view,
+            transform_handles,
         ])
         .run(tauri::generate_context!())
         .expect("error while building tauri application");
@@ -0,0 +1,62 @@
+use canvas::{
+    brush::{stroke::StrokeManager, BrushSettings},
+    PixelFormat,
+};
+
+/// The stroke manager in the working format of the canvas it paints on, brush engines
+/// keep state like the picked up smudge color in that format
+pub enum WorkingStrokeManager {
+    Srgb8(StrokeManager<u8>),
+    Linear16(StrokeManager<u16>),
+    LinearF32(StrokeManager<f32>),
+}
+
+/// Evaluates `$body` with `$manager` bound to the stroke manager inside a
+/// [`WorkingStrokeManager`], whatever its working format
+macro_rules! with_strokes {
+    ($working:expr, $manager:ident => $body:expr) => {
+        match $working {
+            WorkingStrokeManager::Srgb8($manager) => $body,
+            WorkingStrokeManager::Linear16($manager) => $body,
+            WorkingStrokeManager::LinearF32($manager) => $body,
+        }
+    };
+}
+
+impl Default for WorkingStrokeManager {
+    fn default() -> Self {
+        Self::Srgb8(StrokeManager::new())
+    }
+}
+
+impl WorkingStrokeManager {
+    pub fn format(&self) -> PixelFormat {
+        match self {
+            Self::Srgb8(_) => PixelFormat::Srgb8,
+            Self::Linear16(_) => PixelFormat::Linear16,
+            Self::LinearF32(_) => PixelFormat::LinearF32,
+        }
+    }
+
+    /// Switches to the working format of a new canvas, keeping the brush and smoothing
+    pub fn set_format(&mut self, format: PixelFormat) {
+        if self.format() == format {
+            return;
+        }
+
+        let manager = std::mem::take(self);
+        *self = with_strokes!(manager, manager => match format {
+            PixelFormat::Srgb8 => Self::Srgb8(manager.convert()),
+            PixelFormat::Linear16 => Self::Linear16(manager.convert()),
+            PixelFormat::LinearF32 => Self::LinearF32(manager.convert()),
+        });
+    }
+
+    pub fn brush(&self) -> &BrushSettings {
+        with_strokes!(self, manager => manager.brush())
+    }
+
+    pub fn set_brush(&mut self, brush: BrushSettings) {
+        with_strokes!(self, manager => manager.set_brush(brush))
+    }
+}
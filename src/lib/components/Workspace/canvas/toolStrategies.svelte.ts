@@ -1,14 +1,15 @@
 import { invoke } from "@tauri-apps/api/core";
-import { vec2 } from "gl-matrix";
 import { Tool } from "$lib/context/toolContext";
 import { appState } from "$lib/state/AppState.svelte";
 
-/* maybe seperate this variables into their own file */
-export let scale = 1.0;
-export let offsetX = 0.0;
-export let offsetY = 0.0;
-export let mouseX = 0.0;
-export let mouseY = 0.0;
+/* same limits as canvas::view, the canvas clamps the zoom itself but the
+ * zoom kept here has to stop at them too so zooming back responds at once */
+const MIN_ZOOM = 0.05;
+const MAX_ZOOM = 64.0;
+
+/* the canvas owns the view transform, only the zoom is tracked here because
+ * zoomCanvasAround takes an absolute zoom */
+let zoom = 1.0;
 
 let isPointerDown = $state(false);
 export function getIsPointerDown(): boolean {
@@ -19,6 +20,9 @@ export function setIsPointerDown(bool: boolean) {
 }
 
 abstract class ToolStrategy {
+    /* called whenever the tool becomes the active one */
+    activate(): void {}
+
     handlePointerDown(event: PointerEvent): void {
         console.log(
             `${this.constructor.name} does not support handlePointerDown`,
@@ -38,7 +42,39 @@ abstract class ToolStrategy {
     }
 }
 
+/* the brush tool whose brush the stroke manager holds right now */
+let selectedBrushTool: BrushToolStrategy | null = null;
+
 class BrushToolStrategy extends ToolStrategy {
+    brush = "round";
+    size = 12;
+
+    /* selects the brush of this tool, replaced when another brush is loaded */
+    private selectBrush: () => Promise<unknown> = () =>
+        invoke("process_canvas_input", {
+            input: {
+                type: "selectBrush",
+                brush: this.brush,
+                size: this.size,
+            },
+        });
+
+    /* the brush only changes when switching between brush tools, so the
+     * stroke manager keeps its state from one stroke to the next */
+    activate(): void {
+        if (selectedBrushTool === this) return;
+        selectedBrushTool = this;
+        this.selectBrush();
+    }
+
+    /* replaces the brush of this tool, like one loaded from files, and selects
+     * it right away, so a file that can't be read rejects the returned promise */
+    async loadBrush(select: () => Promise<unknown>) {
+        await select();
+        this.selectBrush = select;
+        selectedBrushTool = this;
+    }
+
     handlePointerDown(event: PointerEvent): void {
         let dpr = window.devicePixelRatio;
         invoke("process_canvas_input", {
@@ -47,6 +83,9 @@ class BrushToolStrategy extends ToolStrategy {
                 posX: event.pageX * dpr,
                 posY: event.pageY * dpr,
                 pressure: event.pointerType === "mouse" ? 1.0 : event.pressure,
+                tiltX: event.tiltX,
+                tiltY: event.tiltY,
+                timeStamp: event.timeStamp,
                 color: appState.getColor().toRGB(),
             },
         });
@@ -62,6 +101,9 @@ class BrushToolStrategy extends ToolStrategy {
                 posX: event.pageX * dpr,
                 posY: event.pageY * dpr,
                 pressure: event.pointerType === "mouse" ? 1.0 : event.pressure,
+                tiltX: event.tiltX,
+                tiltY: event.tiltY,
+                timeStamp: event.timeStamp,
                 color: appState.getColor().toRGB(),
             },
         });
@@ -74,50 +116,56 @@ class BrushToolStrategy extends ToolStrategy {
                 type: "endStroke",
                 posX: event.pageX * dpr,
                 posY: event.pageY * dpr,
-                pressure: event.pressure,
+                pressure: event.pointerType === "mouse" ? 1.0 : event.pressure,
+                tiltX: event.tiltX,
+                tiltY: event.tiltY,
+                timeStamp: event.timeStamp,
                 color: appState.getColor().toRGB(),
             },
         });
     }
 }
 
+class EraserToolStrategy extends BrushToolStrategy {
+    brush = "eraser";
+    size = 20;
+}
+
 class PanToolStrategy extends ToolStrategy {
     handlePointerDown(event: PointerEvent): void {}
 
     handlePointerMove(event: PointerEvent): void {
         if (!isPointerDown) return;
         let dpr = window.devicePixelRatio;
-        offsetX -= event.movementX * dpr;
-        offsetY -= event.movementY * dpr;
-
-        invoke("process_canvas_input", {
-            input: {
-                type: "panCanvas",
-                offsetX,
-                offsetY,
-            },
-        });
+        panBy(-event.movementX * dpr, -event.movementY * dpr);
     }
     handlePointerUp(event: PointerEvent): void {}
 }
 
-function zoomRelativeToPoint(
-    zoom: number,
-    mouseX: number,
-    mouseY: number,
-): [number, number] {
-    const offset = vec2.fromValues(offsetX, offsetY);
-    const mousePos = vec2.fromValues(mouseX, mouseY);
+/* zooms by `factor` keeping the canvas point under the anchor, given in
+ * physical pixels, in place */
+function zoomAround(factor: number, anchorX: number, anchorY: number) {
+    zoom = Math.min(Math.max(zoom * factor, MIN_ZOOM), MAX_ZOOM);
 
-    const result = vec2.create();
-    vec2.add(result, mousePos, offset);
-    vec2.scale(result, result, 1 / scale);
-
-    const newCoords = vec2.create();
-    vec2.scale(newCoords, result, zoom);
-    vec2.sub(newCoords, newCoords, mousePos);
+    invoke("process_canvas_input", {
+        input: {
+            type: "zoomCanvasAround",
+            zoom,
+            anchorX,
+            anchorY,
+        },
+    });
+}
 
-    return [newCoords[0], newCoords[1]];
+/* moves the view by physical pixels */
+function panBy(deltaX: number, deltaY: number) {
+    invoke("process_canvas_input", {
+        input: {
+            type: "panCanvasBy",
+            deltaX,
+            deltaY,
+        },
+    });
 }
 
 class MagnifyToolStrategy extends ToolStrategy {
@@ -133,33 +181,11 @@ class MagnifyToolStrategy extends ToolStrategy {
         if (!isPointerDown) return;
 
         const dpr = window.devicePixelRatio;
-
-        const zoomFactor = 1 + event.movementX * 0.01;
-        let newScale = scale * zoomFactor;
-
-        newScale = Math.min(Math.max(newScale, 0.3), 5.0);
-        const [newOffsetX, newOffsetY] = zoomRelativeToPoint(
-            newScale,
+        zoomAround(
+            1 + event.movementX * 0.01,
             this.startPosX * dpr,
             this.startPosY * dpr,
         );
-        scale = newScale;
-        offsetX = newOffsetX;
-        offsetY = newOffsetY;
-
-        invoke("process_canvas_input", {
-            input: {
-                type: "zoomCanvas",
-                zoom: scale,
-            },
-        });
-        invoke("process_canvas_input", {
-            input: {
-                type: "panCanvas",
-                offsetX,
-                offsetY,
-            },
-        });
     }
 
     handlePointerUp(event: PointerEvent): void {
@@ -168,66 +194,110 @@ class MagnifyToolStrategy extends ToolStrategy {
     }
 }
 
+/* samples the canvas under the pointer, the color comes back as a
+ * `color-picked` event */
+class ColorPickerToolStrategy extends ToolStrategy {
+    handlePointerDown(event: PointerEvent): void {
+        this.pick(event);
+    }
+
+    handlePointerMove(event: PointerEvent): void {
+        if (!isPointerDown) return;
+        this.pick(event);
+    }
+
+    handlePointerUp(event: PointerEvent): void {}
+
+    private pick(event: PointerEvent) {
+        let dpr = window.devicePixelRatio;
+        invoke("process_canvas_input", {
+            input: {
+                type: "pickColor",
+                posX: event.pageX * dpr,
+                posY: event.pageY * dpr,
+                source: "merged",
+            },
+        });
+    }
+}
+
 class UnimplementedToolStrategy extends ToolStrategy {}
+const brushTool = new BrushToolStrategy();
 export const ToolStrategies: Record<Tool, ToolStrategy> = {
-    [Tool.Brush]: new BrushToolStrategy(),
+    [Tool.Brush]: brushTool,
     [Tool.Pan]: new PanToolStrategy(),
-    [Tool.ColorPicker]: new UnimplementedToolStrategy(),
-    [Tool.Eraser]: new UnimplementedToolStrategy(),
+    [Tool.ColorPicker]: new ColorPickerToolStrategy(),
+    [Tool.Eraser]: new EraserToolStrategy(),
     [Tool.Lasso]: new UnimplementedToolStrategy(),
     [Tool.Magnify]: new MagnifyToolStrategy(),
 } as const;
 
-export function handleMagnifyGesture(event: WheelEvent) {
-    const dpr = window.devicePixelRatio;
-    const zoomFactor = 1 - event.deltaY * 0.01;
-    let newScale = scale * zoomFactor;
-    console.log(
-        "zoomFactor: ",
-        zoomFactor,
-        "deltaY",
-        event.deltaY,
-        "new_scale",
-        newScale,
+/* settings of an image brush, named like the fields of canvas::brush::ImageBrush */
+export type ImageBrushOptions = {
+    size: number;
+    spacing: number;
+    opacity: number;
+    angle: number;
+    follow_direction: boolean;
+    scatter: number;
+    size_jitter: number;
+    angle_jitter: number;
+    opacity_jitter: number;
+};
+
+/* paper texture of an image brush, read from a png */
+export type GrainFile = {
+    path: string;
+    scale: number;
+    strength: number;
+};
+
+/* gives the brush tool an image brush whose tip is read from the png at `path` */
+export function loadImageBrush(
+    path: string,
+    brush: ImageBrushOptions,
+    grain: GrainFile | null,
+): Promise<void> {
+    return brushTool.loadBrush(() =>
+        invoke("select_image_brush", { path, brush, grain }),
     );
-    /* clamp the value Definitely needs to be calculated before hand*/
-    newScale = Math.min(Math.max(newScale, 0.3), 5.0);
-    const [newOffsetX, newOffsetY] = zoomRelativeToPoint(
-        newScale,
-        event.pageX * dpr,
-        event.pageY * dpr,
+}
+
+/* settings of a smudge brush, named like the fields of canvas::brush::SmudgeBrush */
+export type SmudgeBrushOptions = {
+    size: number;
+    hardness: number;
+    opacity: number;
+    length: number;
+    wetness: number;
+    mix_mode: "additive" | "pigment";
+};
+
+/* gives the brush tool a smudge brush */
+export function loadSmudgeBrush(brush: SmudgeBrushOptions): Promise<void> {
+    return brushTool.loadBrush(() =>
+        invoke("process_canvas_input", {
+            input: {
+                type: "selectBrush",
+                brush: { smudge: brush },
+                size: brush.size,
+            },
+        }),
     );
+}
 
-    scale = newScale;
-    offsetX = newOffsetX;
-    offsetY = newOffsetY;
+/* gives the brush tool a brush read from a MyPaint .myb preset */
+export function loadMyPaintBrush(path: string): Promise<void> {
+    return brushTool.loadBrush(() => invoke("select_mypaint_brush", { path }));
+}
 
-    invoke("process_canvas_input", {
-        input: {
-            type: "zoomCanvas",
-            zoom: scale,
-        },
-    });
-    invoke("process_canvas_input", {
-        input: {
-            type: "panCanvas",
-            offsetX,
-            offsetY,
-        },
-    });
+export function handleMagnifyGesture(event: WheelEvent) {
+    const dpr = window.devicePixelRatio;
+    zoomAround(1 - event.deltaY * 0.01, event.pageX * dpr, event.pageY * dpr);
 }
 
 export function handlePanGesture(event: WheelEvent) {
-    offsetX += event.deltaX * 2.0;
-    offsetY += event.deltaY * 2.0;
-
-    invoke("process_canvas_input", {
-        input: {
-            type: "panCanvas",
-            offsetX: offsetX,
-            offsetY: offsetY,
-        },
-    });
+    panBy(event.deltaX * 2.0, event.deltaY * 2.0);
 }
 
 /* To be Moved
@@ -240,14 +310,12 @@ export function handlePanGesture(event: WheelEvent) {
 export function fitToView(view: HTMLDivElement) {
     let boundingRect = view.getBoundingClientRect();
     let dpr = window.devicePixelRatio;
-    offsetX = -boundingRect.x * dpr;
-    offsetY = -boundingRect.y * dpr;
 
     invoke("process_canvas_input", {
         input: {
             type: "panCanvas",
-            offsetX: offsetX,
-            offsetY: offsetY,
+            offsetX: -boundingRect.x * dpr,
+            offsetY: -boundingRect.y * dpr,
         },
     });
 }
@@ -6,6 +6,25 @@ export default class Color {
         private alpha: number,
     ) {}
 
+    /* builds a color from rgba channels in the 0 to 1 range */
+    static fromRGB(r: number, g: number, b: number, a: number): Color {
+        const max = Math.max(r, g, b);
+        const delta = max - Math.min(r, g, b);
+
+        let h = 0;
+        if (delta > 0) {
+            if (max === r) {
+                h = ((g - b) / delta + 6) % 6;
+            } else if (max === g) {
+                h = (b - r) / delta + 2;
+            } else {
+                h = (r - g) / delta + 4;
+            }
+        }
+
+        return new Color(h / 6, max > 0 ? delta / max : 0, max, a);
+    }
+
     getHue() {
         return this.hue;
     }